@@ -0,0 +1,110 @@
+// Criterion benchmarks of the payments engine, run with `cargo bench`
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rust_decimal::Decimal;
+
+use toy_payments_engine::csv::read_transactions_from;
+use toy_payments_engine::testing::generator::{self, GeneratedRow, Generator, GeneratorConfig};
+use toy_payments_engine::{PaymentsEngine, Transaction};
+
+const N: u32 = 10_000;
+
+/// Engine with one deposit of 10 for each transaction ID in `1..=N`, spread over 100 clients
+fn engine_with_deposits() -> PaymentsEngine {
+    let mut engine = PaymentsEngine::new();
+    for tx in 1..=N {
+        engine.deposit((tx % 100) as u16, tx, Decimal::new(10, 0)).unwrap();
+    }
+    engine
+}
+
+fn transaction_types(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transaction_types");
+    group.throughput(Throughput::Elements(N as u64));
+    group.bench_function("deposit", |b| b.iter_batched(
+        PaymentsEngine::new,
+        |mut engine| {
+            for tx in 1..=N {
+                engine.deposit((tx % 100) as u16, tx, Decimal::new(10, 0)).unwrap();
+            }
+            engine
+        },
+        BatchSize::SmallInput,
+    ));
+    group.bench_function("withdrawal", |b| b.iter_batched(
+        engine_with_deposits,
+        |mut engine| {
+            for tx in 1..=N {
+                engine.withdraw((tx % 100) as u16, N + tx, Decimal::new(1, 0)).unwrap();
+            }
+            engine
+        },
+        BatchSize::SmallInput,
+    ));
+    group.bench_function("dispute", |b| b.iter_batched(
+        engine_with_deposits,
+        |mut engine| {
+            for tx in 1..=N {
+                engine.dispute((tx % 100) as u16, tx).unwrap();
+            }
+            engine
+        },
+        BatchSize::SmallInput,
+    ));
+    group.bench_function("dispute_resolve", |b| b.iter_batched(
+        engine_with_deposits,
+        |mut engine| {
+            for tx in 1..=N {
+                engine.dispute((tx % 100) as u16, tx).unwrap();
+                engine.resolve((tx % 100) as u16, tx).unwrap();
+            }
+            engine
+        },
+        BatchSize::SmallInput,
+    ));
+    group.finish();
+}
+
+fn mixed_workload(c: &mut Criterion) {
+    let config = GeneratorConfig {
+        clients: 1000,
+        rows: N as u64,
+        dispute_probability: 0.05,
+        ..Default::default()
+    };
+    let transactions: Vec<Transaction> = Generator::new(config.clone())
+        .filter_map(|row| match row {
+            GeneratedRow::Valid(transaction) => Some(transaction),
+            GeneratedRow::Invalid(_) => None,
+        })
+        .collect();
+    let mut csv = Vec::new();
+    generator::write_csv(&mut csv, config).unwrap();
+
+    let mut group = c.benchmark_group("mixed_workload");
+    group.throughput(Throughput::Elements(N as u64));
+    group.bench_function("execute", |b| b.iter_batched(
+        || transactions.clone(),
+        |transactions| {
+            let mut engine = PaymentsEngine::new();
+            for transaction in transactions {
+                let _ = engine.execute(transaction);
+            }
+            engine
+        },
+        BatchSize::SmallInput,
+    ));
+    group.bench_function("parse", |b| b.iter(|| {
+        read_transactions_from(csv.as_slice()).filter(|r| r.is_ok()).count()
+    }));
+    group.bench_function("parse_and_execute", |b| b.iter(|| {
+        let mut engine = PaymentsEngine::new();
+        for transaction in read_transactions_from(csv.as_slice()).flatten() {
+            let _ = engine.execute(transaction);
+        }
+        engine
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, transaction_types, mixed_workload);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+//! Generates the protobuf types of the `proto` feature from `proto/payments.proto`
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "proto")]
+    {
+        println!("cargo:rerun-if-changed=proto/payments.proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("protoc is vendored");
+        prost_build::Config::new()
+            .protoc_executable(protoc)
+            .compile_protos(&["proto/payments.proto"], &["proto"])
+            .expect("proto/payments.proto compiles");
+    }
+}
@@ -0,0 +1,23 @@
+//! Feeds arbitrary bytes into the CSV reader, in strict and lenient mode, and executes whatever
+//! rows can be read
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use toy_payments_engine::csv::read_transactions_from;
+use toy_payments_engine::testing::invariants::InvariantChecker;
+use toy_payments_engine::PaymentsEngine;
+
+fuzz_target!(|data: &[u8]| {
+    let strict = read_transactions_from(data);
+    let lenient = read_transactions_from(data).lenient().tolerant_amounts();
+    for transactions in [strict, lenient] {
+        let mut engine = PaymentsEngine::new();
+        let mut checker = InvariantChecker::new();
+        for transaction in transactions.flatten() {
+            let _ = engine.execute(transaction);
+            if let Err(violation) = checker.check(&engine) {
+                panic!("{}", violation);
+            }
+        }
+    }
+});
@@ -0,0 +1,47 @@
+//! Executes arbitrary sequences of transactions and checks the invariants after each one
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+use toy_payments_engine::testing::invariants::InvariantChecker;
+use toy_payments_engine::{PaymentsEngine, Transaction, TransactionType};
+
+/// Transaction with few clients and transaction IDs, so that they refer to each other
+#[derive(Arbitrary, Debug)]
+struct Operation {
+    kind: u8,
+    client: u8,
+    tx: u8,
+    mantissa: i64,
+    scale: u8,
+}
+
+impl Operation {
+    fn transaction(&self) -> Transaction {
+        let kind = match self.kind % 7 {
+            0 => TransactionType::Deposit,
+            1 => TransactionType::Withdrawal,
+            2 => TransactionType::Dispute,
+            3 => TransactionType::Resolve,
+            4 => TransactionType::Chargeback,
+            5 => TransactionType::Refund,
+            _ => TransactionType::Adjustment,
+        };
+        let amount = Decimal::new(self.mantissa, u32::from(self.scale % 8));
+        let amount = matches!(kind, TransactionType::Deposit | TransactionType::Withdrawal
+            | TransactionType::Refund | TransactionType::Adjustment).then_some(amount);
+        Transaction::new(kind, u16::from(self.client % 4), u32::from(self.tx % 32), amount)
+    }
+}
+
+fuzz_target!(|operations: Vec<Operation>| {
+    let mut engine = PaymentsEngine::new();
+    let mut checker = InvariantChecker::new();
+    for operation in &operations {
+        let _ = engine.execute(operation.transaction());
+        if let Err(violation) = checker.check(&engine) {
+            panic!("{:?}: {}", operation, violation);
+        }
+    }
+});
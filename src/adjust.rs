@@ -0,0 +1,112 @@
+//! Administrative adjustments of client balances, e.g. to correct data errors
+//!
+//! Adjustments credit (positive amount) or debit (negative amount) the available funds of a client
+//! and require a reason, which is kept with the adjustment and given as the memo of adjustment
+//! transactions. Unless [crate::EngineConfig::strict_adjustments] is set, they also apply to locked
+//! accounts and may take the available funds negative.
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{PaymentError, Result};
+use crate::ledger::LedgerAccount;
+use crate::models::TransactionType;
+use crate::settlement::Movement;
+use crate::PaymentsEngine;
+
+/// Record of an executed adjustment
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Adjustment {
+    /// Sequence number of the engine operation
+    pub sequence: u64,
+    /// Client identifier
+    pub client: u16,
+    /// Transaction identifier
+    pub tx: u32,
+    /// Signed amount added to the available funds
+    pub amount: Decimal,
+    /// Reason given for the adjustment
+    pub reason: String,
+}
+
+impl PaymentsEngine {
+    /// Replaces the strictness of adjustments of the engine's configuration.
+    pub fn set_strict_adjustments(&mut self, strict: bool) {
+        self.config.strict_adjustments = strict;
+    }
+
+    /// Adds the signed amount to the client's available funds for the given reason.
+    ///
+    /// Fails if the client is blocked (see [crate::screening]), the reason is blank, or the client
+    /// account does not exist. With strict adjustments, it also fails if the account is locked or
+    /// has insufficient funds for a negative amount.
+    pub fn adjust(&mut self, client: u16, tx: u32, amount: Decimal, reason: &str) -> Result<()> {
+        self.screen(client, tx, TransactionType::Adjustment)?;
+        let sequence = self.next_sequence();
+        if reason.trim().is_empty() {
+            return Err(PaymentError::InvalidTransaction(
+                format!("Adjustment transaction {} does not specify a reason", tx)
+            ));
+        }
+        let account = self.accounts.get_mut(&client).ok_or_else(|| {
+            PaymentError::UnknownClient { client, tx_type: String::from("Adjustment") }
+        })?;
+        if self.config.strict_adjustments {
+            account.assert_not_locked(client, tx)?;
+            if account.available + amount < Decimal::ZERO {
+                return Err(PaymentError::InsufficientFunds {
+                    client,
+                    tx,
+                    available: account.available,
+                    amount: -amount,
+                });
+            }
+        }
+        account.available += amount;
+        let entry = if amount.is_sign_negative() {
+            (LedgerAccount::ClientAvailable(client), LedgerAccount::Adjustments)
+        } else {
+            (LedgerAccount::Adjustments, LedgerAccount::ClientAvailable(client))
+        };
+        self.post(tx, amount.abs(), &[entry]);
+        self.record_movement(client, Movement::Adjustment(amount));
+        let reason = reason.to_owned();
+        self.adjustments.push(Adjustment { sequence, client, tx, amount, reason });
+        Ok(())
+    }
+
+    /// Returns all executed adjustments in execution order.
+    pub fn adjustments(&self) -> &[Adjustment] {
+        &self.adjustments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EngineConfig;
+
+    #[test]
+    fn adjustments_bypass_validation_unless_strict() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        engine.dispute(1, 1).unwrap();
+        engine.chargeback(1, 1).unwrap();
+
+        assert!(engine.adjust(1, 2, Decimal::new(3, 0), " ").is_err());
+        assert!(matches!(engine.adjust(2, 2, Decimal::ONE, "typo"),
+            Err(PaymentError::UnknownClient { .. })));
+        engine.adjust(1, 2, Decimal::new(-2, 0), "duplicate payout").unwrap();
+        assert_eq!(Decimal::new(-2, 0), engine.account(1).unwrap().available);
+        assert_eq!("duplicate payout", engine.adjustments()[0].reason);
+
+        let mut strict = PaymentsEngine::with_config(EngineConfig {
+            strict_adjustments: true,
+            ..Default::default()
+        });
+        strict.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        assert!(matches!(strict.adjust(1, 2, Decimal::new(-6, 0), "typo"),
+            Err(PaymentError::InsufficientFunds { .. })));
+        strict.adjust(1, 3, Decimal::new(-5, 0), "typo").unwrap();
+        assert_eq!(Decimal::ZERO, strict.account(1).unwrap().available);
+    }
+}
@@ -0,0 +1,137 @@
+//! Mapping of external client identifiers (e.g. UUIDs or emails) to internal client IDs
+//!
+//! With an alias table (see [crate::csv::Transactions::with_aliases]), the `client` column of the
+//! input holds external identifiers. Each one is replaced by its internal ID, and identifiers not
+//! in the table are assigned the next free ID on the fly. All values of the column are treated as
+//! external identifiers, including numeric ones.
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use csv::{ByteRecord, Error};
+use serde::{Deserialize, Serialize};
+
+/// Row of an alias file
+#[derive(Deserialize, Serialize)]
+struct AliasRow {
+    alias: String,
+    client: u16,
+}
+
+/// Table of external client identifiers and their internal IDs
+#[derive(Clone, Debug, Default)]
+pub struct AliasTable {
+    clients: HashMap<String, u16>,
+    /// Next internal ID to assign, `None` if all IDs are taken
+    next: Option<u16>,
+}
+
+impl AliasTable {
+    /// Creates an empty table that assigns IDs from 0.
+    pub fn new() -> Self {
+        Self { clients: HashMap::new(), next: Some(0) }
+    }
+
+    /// Reads a table from CSV with the columns `alias` and `client`. New identifiers are assigned
+    /// IDs above the highest one read.
+    pub fn read<R: Read>(reader: R) -> Result<Self, Error> {
+        let mut table = Self::new();
+        let rows = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(reader);
+        for row in rows.into_deserialize() {
+            let AliasRow { alias, client } = row?;
+            table.insert(alias, client);
+        }
+        Ok(table)
+    }
+
+    /// Maps an external identifier to the given internal ID.
+    pub fn insert(&mut self, alias: String, client: u16) {
+        self.clients.insert(alias, client);
+        if self.next.is_some_and(|next| client >= next) {
+            self.next = client.checked_add(1);
+        }
+    }
+
+    /// Returns the internal ID of an external identifier, if known.
+    pub fn client(&self, alias: &str) -> Option<u16> {
+        self.clients.get(alias).copied()
+    }
+
+    /// Returns the internal ID of an external identifier, assigning the next free one if unknown.
+    /// Returns `None` if all IDs are taken.
+    pub fn resolve(&mut self, alias: &str) -> Option<u16> {
+        if let Some(client) = self.client(alias) {
+            return Some(client);
+        }
+        let client = self.next?;
+        self.insert(alias.to_owned(), client);
+        Some(client)
+    }
+
+    /// Returns the number of known identifiers.
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Returns true iff no identifier is known.
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Writes the table as CSV in order of internal ID.
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut rows: Vec<_> = self.clients.iter().collect();
+        rows.sort_by_key(|(alias, &client)| (client, alias.as_str()));
+        let mut writer = csv::Writer::from_writer(writer);
+        for (alias, &client) in rows {
+            writer.serialize(AliasRow { alias: alias.clone(), client })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns a copy of the record with the external identifier in the given column replaced by
+    /// its internal ID.
+    pub(crate) fn resolve_record(&mut self, record: &ByteRecord, column: usize)
+        -> Result<ByteRecord, Error>
+    {
+        let alias = String::from_utf8_lossy(record.get(column).unwrap_or_default());
+        let client = self.resolve(&alias).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("no client ID left for alias {:?}", alias))
+        })?;
+        let client = client.to_string();
+        let fields = record.iter().enumerate()
+            .map(|(i, field)| if i == column { client.as_bytes() } else { field });
+        Ok(fields.collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv::read_transactions_from;
+
+    #[test]
+    fn external_identifiers_are_mapped_and_assigned() {
+        let table = AliasTable::read("alias,client\nalice@example.com,7\n".as_bytes()).unwrap();
+        let input = "type,client,tx,amount\ndeposit,bob@example.com,1,2\n\
+                     deposit,alice@example.com,2,1\ndeposit,bob@example.com,3,1\ndeposit,42,4,1\n";
+        let mut transactions = read_transactions_from(input.as_bytes()).with_aliases(table);
+        let clients: Vec<u16> = transactions.by_ref().map(|t| t.unwrap().client).collect();
+        assert_eq!(vec![8, 7, 8, 9], clients);
+
+        let mut csv = Vec::new();
+        transactions.aliases().unwrap().write(&mut csv).unwrap();
+        assert_eq!("alias,client\nalice@example.com,7\nbob@example.com,8\n42,9\n",
+                   String::from_utf8(csv).unwrap());
+    }
+
+    #[test]
+    fn assignment_stops_at_last_id() {
+        let mut table = AliasTable::new();
+        table.insert(String::from("last"), u16::MAX);
+        assert_eq!(Some(u16::MAX), table.resolve("last"));
+        assert_eq!(None, table.resolve("new"));
+        assert_eq!(1, table.len());
+    }
+}
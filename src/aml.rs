@@ -0,0 +1,95 @@
+//! Anti-money-laundering reporting thresholds
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use rust_decimal::Decimal;
+
+use crate::anomaly::AnomalyRule;
+use crate::models::{Transaction, TransactionType};
+use crate::PaymentsEngine;
+
+/// Flags deposits that reach a reporting threshold, alone or as a structured series
+///
+/// A series consists of deposits of a client below the threshold within a window of timestamps
+/// that together reach it; the deposit completing the series is flagged and a new series begins.
+/// Deposits without timestamp are only checked individually.
+pub struct ReportingThreshold {
+    threshold: Decimal,
+    window: u64,
+    series: HashMap<u16, VecDeque<(u64, Decimal)>>,
+}
+
+impl ReportingThreshold {
+    /// Creates a rule with the given threshold and window, e.g. 10,000 within 86,400 seconds.
+    pub fn new(threshold: Decimal, window: u64) -> Self {
+        Self { threshold, window, series: HashMap::new() }
+    }
+}
+
+impl AnomalyRule for ReportingThreshold {
+    fn name(&self) -> &str {
+        "reporting-threshold"
+    }
+
+    fn check(&mut self, transaction: &Transaction, _: &PaymentsEngine) -> Option<String> {
+        if transaction.transaction_type != TransactionType::Deposit {
+            return None;
+        }
+        let amount = transaction.amount?;
+        if amount >= self.threshold {
+            return Some(format!("deposit of {} reaches {}", amount, self.threshold));
+        }
+        let timestamp = transaction.timestamp?;
+        let series = self.series.entry(transaction.client).or_default();
+        while series.front().is_some_and(|&(t, _)| t.saturating_add(self.window) <= timestamp) {
+            series.pop_front();
+        }
+        series.push_back((timestamp, amount));
+        let total: Decimal = series.iter().map(|&(_, amount)| amount).sum();
+        if total < self.threshold {
+            return None;
+        }
+        let count = series.len();
+        series.clear();
+        Some(format!("{} deposits totalling {} within {} reach {}", count, total, self.window,
+                     self.threshold))
+    }
+}
+
+impl PaymentsEngine {
+    /// Returns the IDs of the transactions flagged for review by any anomaly rule, including
+    /// [ReportingThreshold]s.
+    pub fn flagged_transactions(&self) -> BTreeSet<u32> {
+        self.anomalies.iter().map(|anomaly| anomaly.tx).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deposit(client: u16, tx: u32, amount: i64, timestamp: Option<u64>) -> Transaction {
+        Transaction { timestamp, ..Transaction::deposit(client, tx, Decimal::new(amount, 0)) }
+    }
+
+    #[test]
+    fn single_and_structured_deposits_are_flagged_without_blocking() {
+        let mut engine = PaymentsEngine::new();
+        engine.add_anomaly_rule(ReportingThreshold::new(Decimal::new(10_000, 0), 86_400));
+        for transaction in [
+            deposit(1, 1, 10_000, None),
+            deposit(2, 2, 4_000, Some(0)),
+            deposit(2, 3, 4_000, Some(3_600)),
+            deposit(3, 4, 9_000, Some(3_600)),
+            deposit(2, 5, 2_000, Some(86_399)),
+            deposit(2, 6, 9_999, Some(90_000)),
+            deposit(3, 7, 1_000, Some(100_000)),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+
+        assert_eq!(BTreeSet::from([1, 5]), engine.flagged_transactions());
+        assert_eq!("3 deposits totalling 10000 within 86400 reach 10000",
+                   engine.anomalies()[1].description);
+        assert_eq!(Decimal::new(19_999, 0), engine.account(2).unwrap().available);
+    }
+}
@@ -0,0 +1,206 @@
+//! Pluggable anomaly detection during execution
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Transaction, TransactionType};
+use crate::PaymentsEngine;
+
+/// Rule that flags suspicious transactions, see [PaymentsEngine::add_anomaly_rule]
+pub trait AnomalyRule: Send {
+    /// Name of the rule, used in reports
+    fn name(&self) -> &str;
+
+    /// Inspects an accepted transaction after its execution and returns a description of the
+    /// anomaly, if any.
+    fn check(&mut self, transaction: &Transaction, engine: &PaymentsEngine) -> Option<String>;
+}
+
+/// Transaction flagged by an [AnomalyRule]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Anomaly {
+    /// Name of the rule that flagged the transaction
+    pub rule: String,
+    /// Sequence number of the operation
+    pub sequence: u64,
+    /// Client identifier
+    pub client: u16,
+    /// Transaction identifier
+    pub tx: u32,
+    /// Description of the anomaly
+    pub description: String,
+}
+
+/// Writes the anomalies as CSV to the given writer.
+pub fn write_anomalies<'a, W, I>(writer: W, anomalies: I) -> Result<(), csv::Error>
+    where W: Write,
+          I: IntoIterator<Item=&'a Anomaly>
+{
+    let mut writer = csv::Writer::from_writer(writer);
+    for anomaly in anomalies {
+        writer.serialize(anomaly)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Flags deposits above a threshold.
+pub struct LargeDeposit {
+    pub threshold: Decimal,
+}
+
+impl AnomalyRule for LargeDeposit {
+    fn name(&self) -> &str {
+        "large-deposit"
+    }
+
+    fn check(&mut self, transaction: &Transaction, _: &PaymentsEngine) -> Option<String> {
+        if transaction.transaction_type != TransactionType::Deposit {
+            return None;
+        }
+        let amount = transaction.amount?;
+        (amount > self.threshold)
+            .then(|| format!("deposit of {} exceeds {}", amount, self.threshold))
+    }
+}
+
+/// Flags withdrawals of a client beyond the maximum number within a window of timestamps.
+///
+/// Withdrawals without timestamp are ignored.
+pub struct WithdrawalBurst {
+    max: usize,
+    window: u64,
+    recent: HashMap<u16, VecDeque<u64>>,
+}
+
+impl WithdrawalBurst {
+    /// Creates a rule allowing at most `max` withdrawals per client within `window` (e.g.
+    /// 60 for a minute with timestamps in seconds).
+    pub fn new(max: usize, window: u64) -> Self {
+        Self { max, window, recent: HashMap::new() }
+    }
+}
+
+impl AnomalyRule for WithdrawalBurst {
+    fn name(&self) -> &str {
+        "withdrawal-burst"
+    }
+
+    fn check(&mut self, transaction: &Transaction, _: &PaymentsEngine) -> Option<String> {
+        if transaction.transaction_type != TransactionType::Withdrawal {
+            return None;
+        }
+        let timestamp = transaction.timestamp?;
+        let recent = self.recent.entry(transaction.client).or_default();
+        while recent.front().is_some_and(|&t| t + self.window <= timestamp) {
+            recent.pop_front();
+        }
+        recent.push_back(timestamp);
+        (recent.len() > self.max).then(|| {
+            format!("{} withdrawals within {} (maximum {})", recent.len(), self.window, self.max)
+        })
+    }
+}
+
+/// Flags disputes of clients whose ratio of disputes to deposits exceeds the maximum.
+pub struct DisputeRate {
+    max_rate: f64,
+    counts: HashMap<u16, (u64, u64)>,
+}
+
+impl DisputeRate {
+    /// Creates a rule allowing at most `max_rate` disputes per deposit and client.
+    pub fn new(max_rate: f64) -> Self {
+        Self { max_rate, counts: HashMap::new() }
+    }
+}
+
+impl AnomalyRule for DisputeRate {
+    fn name(&self) -> &str {
+        "dispute-rate"
+    }
+
+    fn check(&mut self, transaction: &Transaction, _: &PaymentsEngine) -> Option<String> {
+        let (deposits, disputes) = self.counts.entry(transaction.client).or_default();
+        match transaction.transaction_type {
+            TransactionType::Deposit => *deposits += 1,
+            TransactionType::Dispute => *disputes += 1,
+            _ => return None,
+        }
+        let rate = *disputes as f64 / (*deposits).max(1) as f64;
+        (transaction.transaction_type == TransactionType::Dispute && rate > self.max_rate)
+            .then(|| format!("{} disputes for {} deposits", disputes, deposits))
+    }
+}
+
+impl PaymentsEngine {
+    /// Adds a rule that is evaluated for every transaction accepted by [PaymentsEngine::execute].
+    pub fn add_anomaly_rule(&mut self, rule: impl AnomalyRule + 'static) {
+        self.anomaly_rules.push(Box::new(rule));
+    }
+
+    /// Returns the transactions flagged by the anomaly rules in order of execution.
+    pub fn anomalies(&self) -> &[Anomaly] {
+        &self.anomalies
+    }
+
+    /// Evaluates the anomaly rules for an accepted transaction.
+    pub(crate) fn check_anomalies(&mut self, transaction: &Transaction) {
+        if self.anomaly_rules.is_empty() {
+            return;
+        }
+        let mut rules = std::mem::take(&mut self.anomaly_rules);
+        for rule in rules.iter_mut() {
+            if let Some(description) = rule.check(transaction, self) {
+                self.anomalies.push(Anomaly {
+                    rule: rule.name().to_owned(),
+                    sequence: self.sequence,
+                    client: transaction.client,
+                    tx: transaction.tx,
+                    description,
+                });
+            }
+        }
+        self.anomaly_rules = rules;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(kind: TransactionType, tx: u32, amount: i64, timestamp: u64) -> Transaction {
+        Transaction::fixture(kind, 1, tx, amount).with_timestamp(timestamp)
+    }
+
+    #[test]
+    fn rules_flag_accepted_transactions() {
+        let mut engine = PaymentsEngine::new();
+        engine.add_anomaly_rule(LargeDeposit { threshold: Decimal::new(100, 0) });
+        engine.add_anomaly_rule(WithdrawalBurst::new(2, 60));
+        engine.add_anomaly_rule(DisputeRate::new(0.5));
+        for transaction in [
+            row(TransactionType::Deposit, 1, 500, 0),
+            row(TransactionType::Deposit, 2, 50, 1),
+            row(TransactionType::Withdrawal, 3, 1, 10),
+            row(TransactionType::Withdrawal, 4, 1, 20),
+            row(TransactionType::Withdrawal, 5, 1000, 30),
+            row(TransactionType::Withdrawal, 6, 1, 69),
+            row(TransactionType::Withdrawal, 7, 1, 81),
+            row(TransactionType::Dispute, 2, 0, 90),
+            row(TransactionType::Resolve, 2, 0, 100),
+            row(TransactionType::Dispute, 2, 0, 110),
+        ] {
+            let _ = engine.execute(transaction);
+        }
+
+        let flagged: Vec<_> = engine.anomalies().iter()
+            .map(|anomaly| (anomaly.rule.as_str(), anomaly.tx))
+            .collect();
+        assert_eq!(vec![("large-deposit", 1), ("withdrawal-burst", 6), ("dispute-rate", 2)],
+                   flagged);
+        assert_eq!("2 disputes for 2 deposits", engine.anomalies()[2].description);
+    }
+}
@@ -0,0 +1,104 @@
+//! Custom key/value attributes of accounts, e.g. references to external systems
+//!
+//! Attributes can be set for any client, also before its first transaction, and are carried
+//! through checkpoints. Selected attributes can be added to the account report as columns.
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use serde::Deserialize;
+
+use crate::PaymentsEngine;
+
+/// Attribute of an account, as read from CSV
+#[derive(Deserialize)]
+struct AttributeRow {
+    client: u16,
+    key: String,
+    value: String,
+}
+
+/// Reads account attributes from CSV with the columns `client`, `key`, and `value`.
+pub fn read_account_attrs<R: Read>(reader: R) -> Result<Vec<(u16, String, String)>, csv::Error> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .into_deserialize()
+        .map(|row| row.map(|AttributeRow { client, key, value }| (client, key, value)))
+        .collect()
+}
+
+/// Writes the account report with a column for each of the given attribute keys as CSV to the
+/// given writer. Attributes a client does not have are left empty.
+pub fn write_accounts_with_attrs<W: Write>(
+    writer: W,
+    payments_engine: &PaymentsEngine,
+    keys: &[String],
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let header = ["client", "available", "held", "total", "locked"];
+    writer.write_record(header.iter().copied().chain(keys.iter().map(String::as_str)))?;
+    for account in payments_engine.accounts() {
+        let mut record = vec![
+            account.client.to_string(),
+            account.available.to_string(),
+            account.held.to_string(),
+            account.total.to_string(),
+            account.locked.to_string(),
+        ];
+        for key in keys {
+            let value = payments_engine.account_attr(account.client, key);
+            record.push(value.unwrap_or_default().to_owned());
+        }
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+impl PaymentsEngine {
+    /// Sets an attribute of the client's account, replacing its previous value.
+    pub fn set_account_attr<K, V>(&mut self, client: u16, key: K, value: V)
+        where K: Into<String>,
+              V: Into<String>
+    {
+        self.attributes.entry(client).or_default().insert(key.into(), value.into());
+    }
+
+    /// Returns the value of an attribute of the client's account, if set.
+    pub fn account_attr(&self, client: u16, key: &str) -> Option<&str> {
+        self.attributes.get(&client)?.get(key).map(String::as_str)
+    }
+
+    /// Returns all attributes of the client's account, if any.
+    pub fn account_attrs(&self, client: u16) -> Option<&BTreeMap<String, String>> {
+        self.attributes.get(&client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn selected_attributes_are_reported() {
+        let attrs = read_account_attrs("client,key,value\n1, crm, C-17\n1,region,EU\n2,crm,C-4\n"
+            .as_bytes()).unwrap();
+        let mut engine = PaymentsEngine::new();
+        for (client, key, value) in attrs {
+            engine.set_account_attr(client, key, value);
+        }
+        engine.deposit(1, 1, Decimal::new(15, 1)).unwrap();
+        engine.deposit(3, 2, Decimal::new(2, 0)).unwrap();
+        engine.set_account_attr(1, "crm", "C-18");
+
+        assert_eq!(2, engine.account_attrs(1).unwrap().len());
+        let mut csv = Vec::new();
+        write_accounts_with_attrs(&mut csv, &engine, &[String::from("crm")]).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("client,available,held,total,locked,crm\n"));
+        assert!(csv.contains("1,1.5,0,1.5,false,C-18\n"));
+        assert!(csv.contains("3,2,0,2,false,\n"));
+    }
+}
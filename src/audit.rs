@@ -0,0 +1,199 @@
+//! Append-only audit log of executed transactions
+//!
+//! Every accepted and rejected transaction is recorded as one JSON line (NDJSON) with a timestamp,
+//! its outcome, and the client's balances before and after. Administrative adjustments (see
+//! [crate::adjust]) stand out by their type `adjustment` and carry their reason as memo.
+//! Administrative operations such as unlocks (see [AdminOperation]) have no type but name the
+//! operation instead. The log can be exported to CSV.
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error;
+use crate::models::{Account, AdminOperation, Transaction, TransactionOutcome, TransactionType};
+
+/// Entry of the audit log
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch when the transaction was executed
+    pub timestamp: u64,
+    /// Type of the transaction, `None` for an administrative operation
+    #[serde(rename = "type")]
+    pub transaction_type: Option<TransactionType>,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<Decimal>,
+    /// True iff the transaction was accepted
+    pub accepted: bool,
+    /// Reason for rejection
+    pub error: Option<String>,
+    pub available_before: Option<Decimal>,
+    pub held_before: Option<Decimal>,
+    pub available_after: Option<Decimal>,
+    pub held_after: Option<Decimal>,
+    pub locked: Option<bool>,
+    /// Rule that automatically locked the account due to this transaction
+    #[serde(default)]
+    pub auto_lock: Option<String>,
+    /// Memo of the transaction, e.g. [crate::expiry::EXPIRY_MEMO]
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// Administrative operation applied instead of a transaction, e.g. `REPRESENT 1 2 unlock`
+    #[serde(default)]
+    pub operation: Option<String>,
+}
+
+impl AuditEntry {
+    /// Creates an entry for the given transaction and its outcome, timestamped now.
+    pub fn new(transaction: &Transaction, outcome: &TransactionOutcome) -> Self {
+        let before = outcome.balance_before.as_ref();
+        let after = outcome.balance_after.as_ref();
+        Self {
+            timestamp: now(),
+            transaction_type: Some(outcome.kind),
+            client: outcome.client,
+            tx: outcome.tx,
+            amount: transaction.amount,
+            accepted: outcome.status.is_ok(),
+            error: outcome.status.as_ref().err().map(|e| e.to_string()),
+            available_before: before.map(|a| a.available),
+            held_before: before.map(|a| a.held),
+            available_after: after.map(|a| a.available),
+            held_after: after.map(|a| a.held),
+            locked: after.map(|a| a.locked),
+            auto_lock: outcome.auto_lock.clone(),
+            memo: transaction.memo.clone(),
+            operation: None,
+        }
+    }
+
+    /// Creates an entry for the given administrative operation, its result, and the client's
+    /// account before and after, timestamped now.
+    pub fn for_operation(
+        operation: &AdminOperation,
+        status: &error::Result<()>,
+        before: Option<&Account>,
+        after: Option<&Account>,
+    ) -> Self {
+        Self {
+            timestamp: now(),
+            transaction_type: None,
+            client: operation.client(),
+            tx: operation.tx(),
+            amount: None,
+            accepted: status.is_ok(),
+            error: status.as_ref().err().map(|e| e.to_string()),
+            available_before: before.map(|a| a.available),
+            held_before: before.map(|a| a.held),
+            available_after: after.map(|a| a.available),
+            held_after: after.map(|a| a.held),
+            locked: after.map(|a| a.locked),
+            auto_lock: None,
+            memo: None,
+            operation: Some(operation.to_string()),
+        }
+    }
+}
+
+/// Returns the milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Writer appending entries to an audit log file
+pub struct AuditLog {
+    writer: BufWriter<File>,
+}
+
+impl AuditLog {
+    /// Opens the audit log at the given path for appending, creating it if necessary.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { writer: BufWriter::new(file) })
+    }
+
+    /// Appends an entry; it is only guaranteed to be written after [AuditLog::flush].
+    pub fn append(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, entry)?;
+        self.writer.write_all(b"\n")
+    }
+
+    /// Writes all appended entries to the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Returns iterator over the entries of the audit log at the given path.
+pub fn read_entries<P>(path: P) -> io::Result<impl Iterator<Item=io::Result<AuditEntry>>>
+    where P: AsRef<Path>
+{
+    let reader = BufReader::new(File::open(path)?);
+    Ok(reader.lines()
+        .filter(|line| !line.as_ref().is_ok_and(|l| l.is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?)))
+}
+
+/// Writes the given entries as CSV.
+pub fn write_csv<W, I>(writer: W, entries: I) -> io::Result<()>
+    where W: Write,
+          I: IntoIterator<Item=io::Result<AuditEntry>>
+{
+    let mut writer = csv::Writer::from_writer(writer);
+    for entry in entries {
+        writer.serialize(entry?)?;
+    }
+    writer.flush()
+}
+
+/// Writes the given entries as NDJSON.
+pub fn write_ndjson<W, I>(mut writer: W, entries: I) -> io::Result<()>
+    where W: Write,
+          I: IntoIterator<Item=io::Result<AuditEntry>>
+{
+    for entry in entries {
+        serde_json::to_writer(&mut writer, &entry?)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PaymentsEngine;
+
+    #[test]
+    fn appended_entries_can_be_exported() {
+        let path = std::env::temp_dir()
+            .join(format!("toy-payments-engine-audit-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut engine = PaymentsEngine::new();
+        let withdrawal = Transaction::withdrawal(1, 1, Decimal::new(5, 0));
+        let deposit = Transaction::deposit(1, 1, Decimal::new(5, 0));
+
+        let mut log = AuditLog::open(&path).unwrap();
+        for transaction in [withdrawal, deposit] {
+            let outcome = engine.execute_with_outcome(transaction.clone());
+            log.append(&AuditEntry::new(&transaction, &outcome)).unwrap();
+        }
+        log.flush().unwrap();
+
+        let entries: Vec<_> = read_entries(&path).unwrap().map(Result::unwrap).collect();
+        let mut csv = Vec::new();
+        write_csv(&mut csv, read_entries(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(2, entries.len());
+        assert!(!entries[0].accepted && entries[0].error.is_some());
+        assert!(entries[1].accepted && entries[1].available_before.is_none());
+        assert_eq!(Some(Decimal::new(5, 0)), entries[1].available_after);
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("timestamp,type,client,tx,amount,accepted,error,"));
+        assert!(csv.lines().nth(2).unwrap().contains(",deposit,1,1,5,true,,,,5,0,false"));
+    }
+}
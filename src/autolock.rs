@@ -0,0 +1,113 @@
+//! Automatic locking of accounts with repeated chargebacks
+use serde::{Deserialize, Serialize};
+
+use crate::PaymentsEngine;
+
+/// Thresholds beyond which an account stays locked, even if a chargeback is represented
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct AutoLock {
+    /// Number of chargebacks of a client that trigger the lock, `None` for no limit
+    pub max_chargebacks: Option<u32>,
+    /// Ratio of chargebacks to deposits of a client above which the lock is triggered, `None` for
+    /// no limit
+    pub max_chargeback_ratio: Option<f64>,
+}
+
+/// Numbers of accepted deposits and chargebacks of a client
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct ChargebackStats {
+    deposits: u32,
+    chargebacks: u32,
+}
+
+impl PaymentsEngine {
+    /// Replaces the automatic locking thresholds of the engine's configuration.
+    pub fn set_auto_lock(&mut self, auto_lock: Option<AutoLock>) {
+        self.config.auto_lock = auto_lock;
+    }
+
+    /// Returns the rule that automatically locked the client's account, if any.
+    pub fn auto_lock_reason(&self, client: u16) -> Option<&str> {
+        self.auto_locked.get(&client).map(String::as_str)
+    }
+
+    /// Counts an accepted deposit if automatic locking is configured.
+    pub(crate) fn count_deposit(&mut self, client: u16) {
+        if self.config.auto_lock.is_some() {
+            self.chargeback_stats.entry(client).or_default().deposits += 1;
+        }
+    }
+
+    /// Counts an accepted chargeback and locks the account if it reaches a configured threshold.
+    pub(crate) fn count_chargeback(&mut self, client: u16) {
+        let Some(auto_lock) = &self.config.auto_lock else {
+            return;
+        };
+        let stats = self.chargeback_stats.entry(client).or_default();
+        stats.chargebacks += 1;
+        if self.auto_locked.contains_key(&client) {
+            return;
+        }
+        let ratio = f64::from(stats.chargebacks) / f64::from(stats.deposits.max(1));
+        let reason = if auto_lock.max_chargebacks.is_some_and(|max| stats.chargebacks >= max) {
+            format!("{} chargebacks", stats.chargebacks)
+        } else if auto_lock.max_chargeback_ratio.is_some_and(|max| ratio > max) {
+            format!("chargeback ratio {:.2} ({} of {} deposits)", ratio, stats.chargebacks,
+                    stats.deposits)
+        } else {
+            return;
+        };
+        if let Some(account) = self.accounts.get_mut(&client) {
+            account.locked = true;
+        }
+        self.auto_locked.insert(client, reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::EngineConfig;
+
+    use super::*;
+
+    fn engine(max_chargebacks: Option<u32>, max_chargeback_ratio: Option<f64>) -> PaymentsEngine {
+        let auto_lock = Some(AutoLock { max_chargebacks, max_chargeback_ratio });
+        PaymentsEngine::with_config(EngineConfig { auto_lock, ..Default::default() })
+    }
+
+    fn charge_back(engine: &mut PaymentsEngine, tx: u32) {
+        engine.dispute(1, tx).unwrap();
+        engine.chargeback(1, tx).unwrap();
+    }
+
+    #[test]
+    fn account_stays_locked_after_max_chargebacks() {
+        let mut engine = engine(Some(2), None);
+        for tx in 1..=3 {
+            engine.deposit(1, tx, Decimal::ONE).unwrap();
+        }
+        charge_back(&mut engine, 1);
+        engine.represent(1, 1, true).unwrap();
+        assert!(!engine.account(1).unwrap().locked);
+        assert_eq!(None, engine.auto_lock_reason(1));
+
+        charge_back(&mut engine, 2);
+        engine.represent(1, 2, true).unwrap();
+        assert!(engine.account(1).unwrap().locked);
+        assert_eq!(Some("2 chargebacks"), engine.auto_lock_reason(1));
+    }
+
+    #[test]
+    fn account_stays_locked_above_max_chargeback_ratio() {
+        let mut engine = engine(None, Some(0.4));
+        engine.deposit(1, 1, Decimal::ONE).unwrap();
+        engine.deposit(1, 2, Decimal::ONE).unwrap();
+        charge_back(&mut engine, 1);
+        engine.represent(1, 1, true).unwrap();
+
+        assert!(engine.account(1).unwrap().locked);
+        assert_eq!(Some("chargeback ratio 0.50 (1 of 2 deposits)"), engine.auto_lock_reason(1));
+    }
+}
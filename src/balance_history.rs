@@ -0,0 +1,132 @@
+//! Time series of per-client balances
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Map;
+use crate::models::Transaction;
+use crate::PaymentsEngine;
+
+/// Balances of a client's account after a transaction
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BalanceSnapshot {
+    /// Sequence number of the latest operation included in the snapshot
+    pub sequence: u64,
+    /// Timestamp of the latest transaction included in the snapshot, if any had one
+    pub timestamp: Option<u64>,
+    /// Funds available for trading
+    pub available: Decimal,
+    /// Funds held for dispute
+    pub held: Decimal,
+    /// Total funds available or held
+    pub total: Decimal,
+    /// True iff the account is locked
+    pub locked: bool,
+}
+
+/// Snapshots of all clients, see [PaymentsEngine::enable_balance_history]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct BalanceHistory {
+    interval: Option<u64>,
+    snapshots: Map<u16, Vec<BalanceSnapshot>>,
+}
+
+impl BalanceHistory {
+    /// Removes all snapshots, keeping the interval.
+    pub(crate) fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// Appends the snapshot or, if it falls into the same interval as the client's latest one,
+    /// replaces the latter.
+    fn record(&mut self, client: u16, mut snapshot: BalanceSnapshot) {
+        let snapshots = self.snapshots.entry(client).or_default();
+        if let (Some(interval), Some(last)) = (self.interval, snapshots.last()) {
+            snapshot.timestamp = snapshot.timestamp.or(last.timestamp);
+            let bucket = |timestamp: Option<u64>| timestamp.map(|t| t / interval);
+            if bucket(last.timestamp) == bucket(snapshot.timestamp) {
+                snapshots.pop();
+            }
+        }
+        snapshots.push(snapshot);
+    }
+}
+
+impl PaymentsEngine {
+    /// Starts recording a snapshot of the client's balances after every accepted transaction.
+    ///
+    /// With an interval, only the last snapshot per interval of transaction timestamps is kept,
+    /// e.g. one per day with an interval of 86400 and timestamps in seconds. Transactions without
+    /// timestamp are attributed to the interval of the client's latest snapshot.
+    pub fn enable_balance_history(&mut self, interval: Option<u64>) {
+        let interval = interval.filter(|&interval| interval > 0);
+        self.balance_history = Some(BalanceHistory { interval, snapshots: Map::default() });
+    }
+
+    /// Returns the recorded snapshots of the client's balances in order of execution.
+    ///
+    /// Only transactions executed via [PaymentsEngine::execute] after
+    /// [PaymentsEngine::enable_balance_history] are recorded.
+    pub fn balance_history(&self, client: u16) -> &[BalanceSnapshot] {
+        self.balance_history.as_ref()
+            .and_then(|history| history.snapshots.get(&client))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Records a snapshot of the client's balances after an accepted transaction, if enabled.
+    pub(crate) fn record_balance(&mut self, transaction: &Transaction) {
+        let client = transaction.client;
+        let (Some(history), Some(account)) = (&mut self.balance_history, self.accounts.get(&client))
+        else {
+            return;
+        };
+        history.record(client, BalanceSnapshot {
+            sequence: self.sequence,
+            timestamp: transaction.timestamp,
+            available: account.available,
+            held: account.held,
+            total: account.available + account.held,
+            locked: account.locked,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deposit(tx: u32, timestamp: Option<u64>) -> Transaction {
+        Transaction { timestamp, ..Transaction::deposit(1, tx, Decimal::new(i64::from(tx), 0)) }
+    }
+
+    #[test]
+    fn snapshots_are_recorded_on_every_mutation() {
+        let mut engine = PaymentsEngine::new();
+        engine.enable_balance_history(None);
+        engine.execute(deposit(1, None)).unwrap();
+        engine.execute(deposit(2, None)).unwrap();
+        assert!(engine.execute(deposit(2, None)).is_err());
+
+        let totals: Vec<_> = engine.balance_history(1).iter().map(|s| s.total).collect();
+        assert_eq!(vec![Decimal::new(1, 0), Decimal::new(3, 0)], totals);
+        assert!(engine.balance_history(2).is_empty());
+    }
+
+    #[test]
+    fn only_last_snapshot_per_interval_is_kept() {
+        let mut engine = PaymentsEngine::new();
+        engine.enable_balance_history(Some(100));
+        let timestamps = [(1, Some(10)), (2, Some(99)), (3, Some(100)), (4, None), (5, Some(250))];
+        for (tx, timestamp) in timestamps {
+            engine.execute(deposit(tx, timestamp)).unwrap();
+        }
+
+        let snapshots: Vec<_> = engine.balance_history(1).iter()
+            .map(|s| (s.timestamp, s.total))
+            .collect();
+        assert_eq!(vec![
+            (Some(99), Decimal::new(3, 0)),
+            (Some(100), Decimal::new(10, 0)),
+            (Some(250), Decimal::new(15, 0)),
+        ], snapshots);
+    }
+}
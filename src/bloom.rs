@@ -0,0 +1,154 @@
+//! Bloom filter in front of the deposits for disputes of unknown transactions
+//!
+//! When most disputes, resolves, and chargebacks reference transactions the engine never saw, the
+//! filter rejects them without looking up the deposits. It never misses a deposit, but lets a
+//! small share of unknown transaction IDs pass (false positives). Compacted deposits (see
+//! [crate::compact]) remain in the filter.
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Map;
+use crate::PaymentsEngine;
+
+/// Lookups of deposits through the filter
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FilterStats {
+    /// Lookups rejected by the filter alone
+    pub misses: u64,
+    /// Lookups that passed the filter and found a deposit
+    pub hits: u64,
+    /// Lookups that passed the filter but found no deposit
+    pub false_positives: u64,
+}
+
+/// Bloom filter of transaction IDs
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    stats: FilterStats,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for the expected number of transaction IDs at the given
+    /// false positive rate.
+    pub fn new(expected: usize, false_positive_rate: f64) -> Self {
+        let expected = expected.max(1) as f64;
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let bits = (-expected * rate.ln() / (2f64.ln() * 2f64.ln())).ceil().max(64.0);
+        let hashes = (bits / expected * 2f64.ln()).round().max(1.0) as u32;
+        Self { bits: vec![0; (bits as usize).div_ceil(64)], hashes, stats: FilterStats::default() }
+    }
+
+    /// Adds a transaction ID.
+    pub fn insert(&mut self, tx: u32) {
+        for bit in self.bit_indices(tx) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns false if the transaction ID was definitely never inserted.
+    pub fn may_contain(&self, tx: u32) -> bool {
+        self.bit_indices(tx).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Removes all transaction IDs and resets the statistics, keeping the size.
+    pub(crate) fn clear(&mut self) {
+        self.bits.fill(0);
+        self.stats = FilterStats::default();
+    }
+
+    /// Returns the statistics of deposit lookups.
+    pub fn stats(&self) -> FilterStats {
+        self.stats
+    }
+
+    /// Derives the bit indices of a transaction ID by double hashing.
+    fn bit_indices(&self, tx: u32) -> impl Iterator<Item=usize> {
+        let hash = mix(tx as u64);
+        let (first, second) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let len = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| (first.wrapping_add(i * second) % len) as usize)
+    }
+}
+
+/// Finalizer of SplitMix64
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Looks up a deposit, asking the filter first if there is one.
+pub(crate) fn get_mut<'a, V>(
+    filter: &mut Option<BloomFilter>,
+    deposits: &'a mut Map<u32, V>,
+    tx: u32,
+) -> Option<&'a mut V> {
+    let Some(filter) = filter else {
+        return deposits.get_mut(&tx);
+    };
+    if !filter.may_contain(tx) {
+        filter.stats.misses += 1;
+        return None;
+    }
+    let deposit = deposits.get_mut(&tx);
+    match deposit {
+        Some(_) => filter.stats.hits += 1,
+        None => filter.stats.false_positives += 1,
+    }
+    deposit
+}
+
+impl PaymentsEngine {
+    /// Puts a Bloom filter sized for the expected number of deposits in front of the deposits,
+    /// adding the existing ones.
+    pub fn enable_deposit_filter(&mut self, expected_deposits: usize, false_positive_rate: f64) {
+        let expected = expected_deposits.max(self.deposits.len());
+        let mut filter = BloomFilter::new(expected, false_positive_rate);
+        for &tx in self.deposits.keys() {
+            filter.insert(tx);
+        }
+        self.deposit_filter = Some(filter);
+    }
+
+    /// Returns the statistics of the deposit filter, `None` if it is not enabled.
+    pub fn deposit_filter_stats(&self) -> Option<FilterStats> {
+        self.deposit_filter.as_ref().map(BloomFilter::stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn inserted_ids_are_never_missed() {
+        let mut filter = BloomFilter::new(1_000, 0.01);
+        for tx in (0..1_000).map(|i| i * 7) {
+            filter.insert(tx);
+        }
+        assert!((0..1_000).all(|i| filter.may_contain(i * 7)));
+        let false_positives = (10_000..20_000).filter(|&tx| filter.may_contain(tx)).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn unknown_disputes_are_counted_as_misses() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        engine.enable_deposit_filter(10, 0.01);
+        engine.deposit(1, 2, Decimal::new(3, 0)).unwrap();
+
+        assert!(engine.dispute(1, 100).is_err());
+        engine.dispute(1, 1).unwrap();
+        engine.resolve(1, 1).unwrap();
+        engine.dispute(1, 2).unwrap();
+        let stats = engine.deposit_filter_stats().unwrap();
+        assert_eq!(4, stats.misses + stats.hits + stats.false_positives);
+        assert_eq!(3, stats.hits);
+        assert_eq!(Decimal::new(3, 0), engine.account(1).unwrap().held);
+    }
+}
@@ -0,0 +1,83 @@
+//! Checkpoints for resuming interrupted runs
+//!
+//! A checkpoint consists of a snapshot of the [PaymentsEngine] and the number of input rows that
+//! had been consumed when the snapshot was taken.
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::PaymentsEngine;
+
+/// Checkpoint loaded from disk
+#[derive(Deserialize)]
+pub struct Checkpoint {
+    /// Number of input rows (including invalid ones) consumed before the snapshot was taken
+    pub rows: u64,
+    /// Snapshot of the engine state
+    pub engine: PaymentsEngine,
+}
+
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    rows: u64,
+    engine: &'a PaymentsEngine,
+}
+
+/// Writes a checkpoint to the file at the given path.
+///
+/// The checkpoint is first written to a temporary file next to the target and then renamed, so
+/// that a crash while writing never leaves a corrupted checkpoint behind.
+pub fn save<P: AsRef<Path>>(path: P, rows: u64, engine: &PaymentsEngine) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(&mut writer, &CheckpointRef { rows, engine })?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(tmp_path, path)
+}
+
+/// Reads the checkpoint from the file at the given path.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn saved_checkpoint_can_be_loaded() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(155, 1)).unwrap();
+        engine.deposit(2, 2, Decimal::new(3, 0)).unwrap();
+        engine.dispute(2, 2).unwrap();
+        let path = std::env::temp_dir()
+            .join(format!("toy-payments-engine-{}.checkpoint", std::process::id()));
+
+        save(&path, 42, &engine).unwrap();
+        let checkpoint = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(42, checkpoint.rows);
+        assert_eq!(engine.accounts, checkpoint.engine.accounts);
+        assert_eq!(engine.deposits, checkpoint.engine.deposits);
+    }
+
+    #[test]
+    fn engines_with_unknown_config_fields_can_be_loaded() {
+        let mut engine = PaymentsEngine::new();
+        engine.set_dispute_window(Some(7));
+        let mut json = serde_json::to_value(&engine).unwrap();
+        json["config"]["retired"] = serde_json::Value::Bool(true);
+
+        let engine: PaymentsEngine = serde_json::from_value(json).unwrap();
+
+        assert_eq!(Some(7), engine.config().dispute_window);
+    }
+}
@@ -0,0 +1,181 @@
+//! Compaction of deposits that can no longer be disputed
+//!
+//! Deposits are kept for potential disputes for the lifetime of the engine. Compaction drops the
+//! ones outside the dispute window and the charged back ones that can no longer be represented
+//! (see [crate::EngineConfig]), unless a dispute is open. The age of deposits is only recorded
+//! while a dispute window is configured; other deposits that were never charged back are kept.
+//! Disputes of compacted deposits fail as for unknown transactions. Only their transaction IDs
+//! are kept, as ranges of consecutive IDs, so that they are still rejected as duplicates.
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{EngineConfig, PaymentsEngine};
+
+/// Set of transaction IDs stored as ranges of consecutive IDs, by first ID, with the last ID
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct IdRanges(BTreeMap<u32, u32>);
+
+impl IdRanges {
+    pub(crate) fn contains(&self, id: u32) -> bool {
+        self.0.range(..=id).next_back().is_some_and(|(_, &last)| id <= last)
+    }
+
+    /// Adds the ID, merging it with adjacent ranges.
+    pub(crate) fn insert(&mut self, id: u32) {
+        if self.contains(id) {
+            return;
+        }
+        let last = id.checked_add(1).and_then(|next| self.0.remove(&next)).unwrap_or(id);
+        let first = self.0.range(..id).next_back()
+            .filter(|(_, &previous)| previous + 1 == id)
+            .map_or(id, |(&first, _)| first);
+        self.0.insert(first, last);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl PaymentsEngine {
+    /// Replaces the dispute window of the engine's configuration.
+    pub fn set_dispute_window(&mut self, window: Option<u64>) {
+        self.config.dispute_window = window;
+    }
+
+    /// Replaces the compaction interval of the engine's configuration.
+    pub fn set_compaction_interval(&mut self, interval: Option<u64>) {
+        self.config.compaction_interval = interval;
+    }
+
+    /// Drops the deposits that can no longer be disputed or represented and returns their number.
+    pub fn compact(&mut self) -> usize {
+        // Operations from the next one on are checked against the windows
+        let next = self.sequence + 1;
+        let EngineConfig { dispute_window, representment_window, .. } = self.config;
+        let chargebacks = &self.chargebacks;
+        let sequences = &self.deposit_sequences;
+        let compacted_deposits = &mut self.compacted_deposits;
+        let before = self.deposits.len();
+        self.deposits.retain(|tx, deposit| {
+            let keep = if deposit.is_disputed() {
+                true
+            } else if deposit.is_charged_back() {
+                chargebacks.get(tx).is_some_and(|&charged_back| {
+                    representment_window.is_none_or(|window| next - charged_back <= window)
+                })
+            } else {
+                let deposited = sequences.get(tx);
+                dispute_window.zip(deposited).is_none_or(|(window, &at)| next - at <= window)
+            };
+            if !keep {
+                compacted_deposits.insert(*tx);
+            }
+            keep
+        });
+        let compacted = before - self.deposits.len();
+        if compacted > 0 {
+            let deposits = &self.deposits;
+            self.chargebacks.retain(|tx, _| deposits.contains_key(tx));
+            self.deposit_wallets.retain(|tx, _| deposits.contains_key(tx));
+            self.deposit_sequences.retain(|tx, _| deposits.contains_key(tx));
+        }
+        compacted
+    }
+
+    /// Returns true iff a deposit with the transaction ID exists or was compacted.
+    pub(crate) fn deposit_id_used(&self, tx: u32) -> bool {
+        self.deposits.contains_key(&tx) || self.compacted_deposits.contains(tx)
+    }
+
+    /// Returns true iff the dispute window of the deposit has passed for the operation with the
+    /// given sequence number.
+    pub(crate) fn dispute_window_passed(&self, tx: u32, sequence: u64) -> bool {
+        let deposited = self.deposit_sequences.get(&tx);
+        self.config.dispute_window
+            .zip(deposited)
+            .is_some_and(|(window, &at)| sequence - at > window)
+    }
+
+    /// Compacts the deposits if the compaction interval has passed since the last compaction.
+    pub(crate) fn compact_if_due(&mut self) {
+        let due = self.config.compaction_interval
+            .is_some_and(|interval| self.sequence - self.compacted_at >= interval);
+        if due {
+            self.compact();
+            self.compacted_at = self.sequence;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::{PaymentError, RejectionReason, Transaction};
+
+    #[test]
+    fn deposits_outside_windows_are_dropped() {
+        let mut engine = PaymentsEngine::with_config(EngineConfig {
+            dispute_window: Some(3),
+            representment_window: Some(4),
+            ..EngineConfig::default()
+        });
+        engine.deposit(2, 3, Decimal::new(3, 0)).unwrap();
+        engine.dispute(2, 3).unwrap();
+        engine.chargeback(2, 3).unwrap();
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        engine.deposit(1, 2, Decimal::new(4, 0)).unwrap();
+        engine.dispute(1, 2).unwrap();
+        assert_eq!(0, engine.compact());
+
+        engine.deposit(3, 4, Decimal::new(1, 0)).unwrap();
+        let late = Transaction { client: 1, tx: 1, ..dispute() };
+        assert_eq!(vec![RejectionReason::DisputeWindowPassed], engine.explain(&late));
+        assert_eq!(2, engine.compact());
+        let txs: Vec<u32> = engine.deposits.keys().copied().collect();
+        assert!(!txs.contains(&1) && !txs.contains(&3));
+        assert!(engine.chargebacks.is_empty());
+        assert!(engine.dispute(1, 1).is_err());
+        engine.resolve(1, 2).unwrap();
+        engine.dispute(3, 4).unwrap();
+        let duplicate = Transaction::deposit(1, 1, Decimal::ONE);
+        assert_eq!(vec![RejectionReason::DuplicateTransaction], engine.explain(&duplicate));
+        assert!(matches!(
+            engine.deposit(2, 3, Decimal::ONE),
+            Err(PaymentError::DuplicateTransaction { client: 2, tx: 3 })
+        ));
+    }
+
+    #[test]
+    fn compaction_runs_automatically() {
+        let mut engine = PaymentsEngine::with_config(EngineConfig {
+            dispute_window: Some(1),
+            compaction_interval: Some(2),
+            ..EngineConfig::default()
+        });
+        let deposit = |tx| Transaction::deposit(1, tx, Decimal::ONE);
+        engine.execute(deposit(1)).unwrap();
+        assert_eq!(1, engine.deposits.len());
+        engine.execute(deposit(2)).unwrap();
+        assert_eq!(1, engine.deposits.len());
+        assert_eq!(Decimal::new(2, 0), engine.account(1).unwrap().available);
+    }
+
+    #[test]
+    fn id_ranges_merge_adjacent_ids() {
+        let mut ids = IdRanges::default();
+        for id in [5, 3, 1, 4, u32::MAX, 2] {
+            ids.insert(id);
+        }
+        assert_eq!(IdRanges(BTreeMap::from([(1, 5), (u32::MAX, u32::MAX)])), ids);
+        assert!(ids.contains(3) && ids.contains(u32::MAX));
+        assert!(!ids.contains(0) && !ids.contains(6));
+    }
+
+    fn dispute() -> Transaction {
+        Transaction::dispute(1, 1)
+    }
+}
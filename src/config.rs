@@ -0,0 +1,43 @@
+//! Configuration of the payments engine
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::autolock::AutoLock;
+use crate::expiry::DisputeExpiry;
+use crate::receivable::WithdrawnFundsPolicy;
+use crate::reorder::ReorderWindow;
+use crate::velocity::VelocityLimit;
+
+/// Configuration of the [crate::PaymentsEngine]
+///
+/// The default configuration imposes no additional limits.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    /// Maximum number of engine operations between a chargeback and its representment, `None`
+    /// for no limit
+    pub representment_window: Option<u64>,
+    /// Maximum number of engine operations between a deposit and its dispute, `None` for no limit
+    pub dispute_window: Option<u64>,
+    /// Number of engine operations between automatic compactions, `None` to compact only
+    /// explicitly, see [crate::compact]
+    pub compaction_interval: Option<u64>,
+    /// Record executed transactions for Merkle proofs, see [crate::merkle]
+    pub merkle_log: bool,
+    /// Limits on the withdrawals per client within a window of timestamps, `None` for no limit
+    pub velocity_limit: Option<VelocityLimit>,
+    /// Thresholds for locking accounts with repeated chargebacks for good, `None` for no limit
+    pub auto_lock: Option<AutoLock>,
+    /// Limits for parking disputes, resolves, and chargebacks of deposits not seen yet, `None` to
+    /// reject them right away
+    pub reorder_window: Option<ReorderWindow>,
+    /// Maximum age of open disputes with timestamps, `None` for no limit
+    pub dispute_expiry: Option<DisputeExpiry>,
+    /// Minimum available funds a withdrawal must leave, `None` for no minimum, see [crate::dust]
+    pub min_balance: Option<Decimal>,
+    /// Handling of disputes of deposits whose funds were already withdrawn, see
+    /// [crate::receivable]
+    pub withdrawn_funds: WithdrawnFundsPolicy,
+    /// Validate adjustments like withdrawals instead of bypassing validation, see [crate::adjust]
+    pub strict_adjustments: bool,
+}
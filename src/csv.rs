@@ -1,11 +1,13 @@
 //! Functions for reading and writing CSV
 use std::fs::File;
-use std::io;
+use std::io::{self, Read, Write};
 use std::path::Path;
 
-use csv::{DeserializeRecordsIntoIter, Error, Trim, Writer};
+use csv::{ByteRecord, DeserializeRecordsIntoIter, Error, Trim, Writer};
 
 use crate::{Account};
+use crate::alias::AliasTable;
+use crate::lenient;
 use crate::models::Transaction;
 
 /// Returns iterator over [Transaction]s from file at specified path or CSV error.
@@ -18,13 +20,235 @@ pub fn read_transactions<P>(path: P) -> Result<DeserializeRecordsIntoIter<File,
     Ok(reader.into_deserialize())
 }
 
+/// Returns iterator over [Transaction]s read from the given reader.
+pub fn read_transactions_from<R>(reader: R) -> Transactions<R>
+    where R: Read
+{
+    Transactions::new(reader)
+}
+
+/// Returns iterator over [Transaction]s read from the given reader after skipping the specified
+/// number of rows (not counting the header) or CSV error.
+pub fn read_transactions_skipping<R>(reader: R, rows: u64) -> Result<Transactions<R>, Error>
+    where R: Read
+{
+    Transactions::new(reader).skip_rows(rows)
+}
+
+/// Iterator over [Transaction]s read from CSV rows
+///
+/// Rows are deserialized with serde. With the `fast-csv` feature, well-formed rows are parsed by
+/// hand instead, which avoids allocations; serde remains the fallback for all other rows.
+pub struct Transactions<R> {
+    reader: csv::Reader<R>,
+    record: ByteRecord,
+    headers: Option<ByteRecord>,
+    /// Aliases of the external identifiers in the `client` column, see [crate::alias]
+    aliases: Option<AliasTable>,
+    /// Number of rows read so far, not counting the header
+    rows: u64,
+    /// Whether malformed rows are normalized, see [crate::lenient]
+    lenient: bool,
+    /// Whether amounts are parsed with [crate::lenient::parse_amount]
+    tolerant_amounts: bool,
+    progress: Option<Box<dyn FnMut(u64, u64) + Send>>,
+    #[cfg(feature = "fast-csv")]
+    columns: Option<crate::fast_csv::Columns>,
+}
+
+impl<R: Read> Transactions<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader: csv::ReaderBuilder::new().trim(Trim::All).from_reader(reader),
+            record: ByteRecord::new(),
+            headers: None,
+            aliases: None,
+            rows: 0,
+            lenient: false,
+            tolerant_amounts: false,
+            progress: None,
+            #[cfg(feature = "fast-csv")]
+            columns: None,
+        }
+    }
+
+    /// Skips the specified number of rows (not counting the header) or returns CSV error.
+    pub fn skip_rows(mut self, rows: u64) -> Result<Self, Error> {
+        for _ in 0..rows {
+            if !self.reader.read_byte_record(&mut self.record)? {
+                break;
+            }
+            self.rows += 1;
+        }
+        Ok(self)
+    }
+
+    /// Salvages malformed rows as far as possible (see [crate::lenient]). Must be called before
+    /// any rows are read.
+    pub fn lenient(mut self) -> Self {
+        debug_assert!(self.headers.is_none() && self.rows == 0, "rows were read already");
+        self.reader = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(self.reader.into_inner());
+        self.lenient = true;
+        self
+    }
+
+    /// Accepts amounts with thousands separators, decimal commas, or exponents (see
+    /// [crate::lenient::parse_amount]).
+    pub fn tolerant_amounts(mut self) -> Self {
+        self.tolerant_amounts = true;
+        self
+    }
+
+    /// Calls `on_progress` with the numbers of rows and bytes read so far after every row, e.g. to
+    /// display a progress bar. Skipped rows count as read.
+    pub fn on_progress(mut self, on_progress: impl FnMut(u64, u64) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Maps the external identifiers in the `client` column with the given alias table.
+    pub fn with_aliases(mut self, aliases: AliasTable) -> Self {
+        self.aliases = Some(aliases);
+        self
+    }
+
+    /// Returns the alias table, including the identifiers assigned so far.
+    pub fn aliases(&self) -> Option<&AliasTable> {
+        self.aliases.as_ref()
+    }
+}
+
+impl<R: Read> Iterator for Transactions<R> {
+    type Item = Result<Transaction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.headers.is_none() {
+            match self.reader.byte_headers() {
+                Ok(headers) => {
+                    #[cfg(feature = "fast-csv")]
+                    {
+                        self.columns = crate::fast_csv::Columns::from_headers(headers);
+                    }
+                    self.headers = Some(headers.clone());
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        match self.reader.read_byte_record(&mut self.record) {
+            Err(e) => Some(Err(e)),
+            Ok(false) => None,
+            Ok(true) => {
+                self.rows += 1;
+                if let Some(progress) = &mut self.progress {
+                    progress(self.rows, self.reader.position().byte());
+                }
+                let headers = self.headers.as_ref();
+                if let (true, Some(headers)) = (self.lenient, headers) {
+                    self.record = lenient::normalize_record(&self.record, headers);
+                }
+                if let (true, Some(headers)) = (self.tolerant_amounts, headers) {
+                    if let Err(e) = lenient::normalize_amount(&mut self.record, headers) {
+                        return Some(Err(e));
+                    }
+                }
+                let transaction = if let Some(aliases) = &mut self.aliases {
+                    let column = headers.and_then(|h| h.iter().position(|f| f == b"client"));
+                    let record = match column {
+                        Some(column) => aliases.resolve_record(&self.record, column),
+                        None => Ok(self.record.clone()),
+                    };
+                    record.and_then(|record| record.deserialize(headers))
+                } else {
+                    #[cfg(feature = "fast-csv")]
+                    if let Some(transaction) = self.columns.and_then(|c| c.parse(&self.record)) {
+                        return Some(Ok(transaction));
+                    }
+                    self.record.deserialize(headers)
+                };
+                Some(transaction.map_err(|e| match (self.lenient, headers) {
+                    (true, Some(headers)) => lenient::name_field(e, &self.record, headers),
+                    _ => e,
+                }))
+            }
+        }
+    }
+}
+
+/// Returns iterator over [Account]s read from the given reader, e.g. a bank statement.
+pub fn read_accounts<R>(reader: R) -> DeserializeRecordsIntoIter<R, Account>
+    where R: Read
+{
+    csv::ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(reader)
+        .into_deserialize()
+}
+
 /// Writes serialized [Account]s from iterator to stdout or returns CSV error.
 pub fn write_account_info<I>(accounts: I) -> Result<(), Error>
     where I: IntoIterator<Item=Account>
 {
-    let mut writer = Writer::from_writer(io::stdout());
+    write_accounts(io::stdout(), accounts)
+}
+
+/// Writes serialized [Account]s from iterator to the given writer or returns CSV error.
+pub fn write_accounts<W, I>(writer: W, accounts: I) -> Result<(), Error>
+    where W: Write,
+          I: IntoIterator<Item=Account>
+{
+    let mut writer = Writer::from_writer(writer);
     for account in accounts {
         writer.serialize(account)?;
     }
+    writer.flush()?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Writes serialized [Transaction]s from iterator to the given writer, with all columns in the
+/// format read by [read_transactions_from], or returns CSV error.
+pub fn write_transactions<'a, W, I>(writer: W, transactions: I) -> Result<(), Error>
+    where W: Write,
+          I: IntoIterator<Item=&'a Transaction>
+{
+    let mut writer = Writer::from_writer(writer);
+    for transaction in transactions {
+        writer.serialize(transaction)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn progress_counts_rows_and_bytes() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5\ndeposit,1,2,5\nwithdrawal,1,3,1\n";
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let reported = progress.clone();
+        let transactions = read_transactions_skipping(input.as_bytes(), 1).unwrap()
+            .on_progress(move |rows, bytes| reported.lock().unwrap().push((rows, bytes)));
+        assert_eq!(2, transactions.count());
+        assert_eq!(vec![(2, 50), (3, 67)], *progress.lock().unwrap());
+    }
+
+    #[test]
+    fn written_transactions_are_read_back() {
+        let input = "type,client,tx,amount,reason,timestamp,memo,tags\n\
+                     deposit,1,1,5.25,,10,salary,a;b\n\
+                     dispute,1,1,,fraud,20,,\n";
+        let transactions: Vec<_> = read_transactions_from(input.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        let mut output = Vec::new();
+        write_transactions(&mut output, &transactions).unwrap();
+        let read: Vec<_> = read_transactions_from(&output[..]).map(Result::unwrap).collect();
+        assert_eq!(transactions, read);
+    }
+}
@@ -0,0 +1,115 @@
+//! Differences between the account states of two engines, e.g. a [PaymentsEngine::fork] after a
+//! hypothetical batch or the same input processed by two engine versions
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+
+use crate::{Account, PaymentsEngine};
+
+/// Client account present in both engines but with different state
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AccountChange {
+    /// Client identifier
+    pub client: u16,
+    /// Account in the engine compared against
+    pub before: Account,
+    /// Account in the other engine
+    pub after: Account,
+}
+
+impl AccountChange {
+    /// Change of the available funds
+    pub fn available_delta(&self) -> Decimal {
+        (self.after.available - self.before.available).normalize()
+    }
+
+    /// Change of the held funds
+    pub fn held_delta(&self) -> Decimal {
+        (self.after.held - self.before.held).normalize()
+    }
+
+    /// Change of the total funds
+    pub fn total_delta(&self) -> Decimal {
+        (self.after.total - self.before.total).normalize()
+    }
+
+    /// New lock state, `None` if unchanged
+    pub fn locked(&self) -> Option<bool> {
+        (self.before.locked != self.after.locked).then_some(self.after.locked)
+    }
+}
+
+/// Result of [PaymentsEngine::diff], all lists in order of client ID
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StateDiff {
+    /// Accounts only present in the other engine
+    pub added: Vec<Account>,
+    /// Accounts only present in the engine compared against
+    pub removed: Vec<Account>,
+    /// Accounts present in both engines with different state
+    pub changed: Vec<AccountChange>,
+}
+
+impl StateDiff {
+    /// Returns true iff both engines have the same accounts.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl PaymentsEngine {
+    /// Lists the accounts added, removed, or changed in `other` compared to this engine.
+    pub fn diff(&self, other: &PaymentsEngine) -> StateDiff {
+        let mut before: BTreeMap<u16, Account> = self.accounts()
+            .map(|account| (account.client, account))
+            .collect();
+        let after: BTreeMap<u16, Account> = other.accounts()
+            .map(|account| (account.client, account))
+            .collect();
+
+        let mut diff = StateDiff::default();
+        for (client, account) in after {
+            match before.remove(&client) {
+                Some(previous) if previous == account => {}
+                Some(previous) => diff.changed.push(AccountChange {
+                    client,
+                    before: previous,
+                    after: account,
+                }),
+                None => diff.added.push(account),
+            }
+        }
+        diff.removed.extend(before.into_values());
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_added_removed_and_changed_accounts() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(5, 0)).unwrap();
+        let mut fork = engine.fork();
+        assert!(engine.diff(&fork).is_empty());
+
+        fork.dispute(1, 1).unwrap();
+        fork.chargeback(1, 1).unwrap();
+        fork.deposit(3, 3, Decimal::new(1, 0)).unwrap();
+        engine.deposit(4, 4, Decimal::new(2, 0)).unwrap();
+
+        let diff = engine.diff(&fork);
+        assert_eq!(vec![fork.account(3).unwrap()], diff.added);
+        assert_eq!(vec![engine.account(4).unwrap()], diff.removed);
+        assert_eq!(1, diff.changed.len());
+        let change = &diff.changed[0];
+        assert_eq!(1, change.client);
+        assert_eq!(Decimal::new(-10, 0), change.available_delta());
+        assert_eq!(Decimal::ZERO, change.held_delta());
+        assert_eq!(Decimal::new(-10, 0), change.total_delta());
+        assert_eq!(Some(true), change.locked());
+    }
+}
@@ -0,0 +1,81 @@
+//! Deterministic digest of the engine state
+//!
+//! The digest only depends on the accounts and deposits, not on the order in which they were
+//! created or on the hasher used by the engine, so two runs over the same input yield the same
+//! digest.
+use sha2::{Digest, Sha256};
+
+use crate::models::DisputeStatus;
+use crate::PaymentsEngine;
+
+impl PaymentsEngine {
+    /// Returns the SHA-256 digest of the canonically encoded account and deposit state.
+    ///
+    /// Accounts are hashed in order of client ID and deposits in order of transaction ID.
+    /// Amounts are normalized, so e.g. `1.50` and `1.5` are hashed identically.
+    pub fn state_digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        let mut accounts: Vec<_> = self.accounts.iter().collect();
+        accounts.sort_unstable_by_key(|(client, _)| **client);
+        hasher.update((accounts.len() as u64).to_be_bytes());
+        for (client, account) in accounts {
+            hasher.update(client.to_be_bytes());
+            update_decimal(&mut hasher, account.available);
+            update_decimal(&mut hasher, account.held);
+            hasher.update([u8::from(account.locked)]);
+        }
+        let mut deposits: Vec<_> = self.deposits.iter().collect();
+        deposits.sort_unstable_by_key(|(tx, _)| **tx);
+        hasher.update((deposits.len() as u64).to_be_bytes());
+        for (tx, deposit) in deposits {
+            hasher.update(tx.to_be_bytes());
+            hasher.update(deposit.client.to_be_bytes());
+            update_decimal(&mut hasher, deposit.amount());
+            hasher.update(encode_dispute(deposit.dispute));
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// Hashes the normalized decimal as length-prefixed string.
+fn update_decimal(hasher: &mut Sha256, value: rust_decimal::Decimal) {
+    let value = value.normalize().to_string();
+    hasher.update((value.len() as u8).to_be_bytes());
+    hasher.update(value);
+}
+
+/// Encodes dispute state and reason as one byte each, 0 meaning none.
+fn encode_dispute(dispute: Option<DisputeStatus>) -> [u8; 2] {
+    match dispute {
+        None => [0, 0],
+        Some(d) => [d.state as u8 + 1, d.reason.map_or(0, |r| r as u8 + 1)],
+    }
+}
+
+/// Formats the digest as lowercase hexadecimal string.
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn digest_is_independent_of_insertion_order_and_scale() {
+        let mut a = PaymentsEngine::new();
+        a.deposit(1, 1, Decimal::new(15, 1)).unwrap();
+        a.deposit(2, 2, Decimal::new(3, 0)).unwrap();
+        let mut b = PaymentsEngine::new();
+        b.deposit(2, 2, Decimal::new(300, 2)).unwrap();
+        b.deposit(1, 1, Decimal::new(150, 2)).unwrap();
+
+        assert_eq!(a.state_digest(), b.state_digest());
+
+        b.dispute(2, 2).unwrap();
+        assert_ne!(a.state_digest(), b.state_digest());
+        assert_eq!(64, to_hex(&b.state_digest()).len());
+    }
+}
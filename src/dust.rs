@@ -0,0 +1,94 @@
+//! Minimum-balance and dust-threshold policies
+//!
+//! With [crate::EngineConfig::min_balance], withdrawals that would leave less available funds
+//! fail with [crate::PaymentError::BelowMinimumBalance]. [PaymentsEngine::sweep_dust] moves small
+//! leftover balances into an internal account instead, e.g. before closing a period.
+use rust_decimal::Decimal;
+
+use crate::house::HouseAccount;
+use crate::ledger::LedgerAccount;
+use crate::models::{Transaction, TransactionOutcome, TransactionType};
+use crate::settlement::Movement;
+use crate::PaymentsEngine;
+
+/// Memo of the withdrawals executed by [PaymentsEngine::sweep_dust]
+pub const SWEEP_MEMO: &str = "dust sweep";
+
+impl PaymentsEngine {
+    /// Replaces the minimum balance of the engine's configuration.
+    pub fn set_min_balance(&mut self, min_balance: Option<Decimal>) {
+        self.config.min_balance = min_balance;
+    }
+
+    /// Zeroes the available funds of unlocked accounts without held funds whose balance is
+    /// positive but below the threshold, moving them into the internal dust account.
+    ///
+    /// Returns the sweeps as withdrawals with transaction ID 0 and the memo [SWEEP_MEMO], along
+    /// with their outcomes, ordered by client.
+    pub fn sweep_dust(&mut self, threshold: Decimal) -> Vec<(Transaction, TransactionOutcome)> {
+        let mut clients: Vec<u16> = self.accounts.iter()
+            .filter(|(_, a)| !a.locked && a.held.is_zero())
+            .filter(|(_, a)| a.available > Decimal::ZERO && a.available < threshold)
+            .map(|(&client, _)| client)
+            .collect();
+        clients.sort_unstable();
+        let mut sweeps = Vec::with_capacity(clients.len());
+        for client in clients {
+            let balance_before = self.account(client);
+            let account = self.accounts.get_mut(&client).expect("client exists");
+            let amount = account.available;
+            account.available = Decimal::ZERO;
+            self.credit_house(HouseAccount::Dust, amount);
+            self.next_sequence();
+            self.post(0, amount, &[(LedgerAccount::ClientAvailable(client), LedgerAccount::Dust)]);
+            self.record_movement(client, Movement::Withdrawal(amount));
+            let transaction = Transaction::withdrawal(client, 0, amount).with_memo(SWEEP_MEMO);
+            let outcome = TransactionOutcome {
+                client,
+                tx: 0,
+                kind: TransactionType::Withdrawal,
+                balance_before,
+                balance_after: self.account(client),
+                status: Ok(()),
+                auto_lock: None,
+            };
+            sweeps.push((transaction, outcome));
+        }
+        sweeps
+    }
+
+    /// Returns the funds swept into the internal dust account so far.
+    pub fn dust(&self) -> Decimal {
+        self.house_balance(HouseAccount::Dust)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EngineConfig, PaymentError};
+
+    #[test]
+    fn withdrawals_keep_minimum_balance_and_dust_is_swept() {
+        let mut engine = PaymentsEngine::with_config(EngineConfig {
+            min_balance: Some(Decimal::new(5, 1)),
+            ..Default::default()
+        });
+        engine.enable_ledger();
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(5, 1)).unwrap();
+        engine.deposit(3, 3, Decimal::new(3, 1)).unwrap();
+        engine.dispute(3, 3).unwrap();
+        assert!(matches!(engine.withdraw(1, 4, Decimal::new(46, 1)),
+            Err(PaymentError::BelowMinimumBalance { tx: 4, .. })));
+        engine.withdraw(1, 5, Decimal::new(42, 1)).unwrap();
+
+        let sweeps = engine.sweep_dust(Decimal::ONE);
+        assert_eq!(vec![(1, Some(Decimal::new(8, 1))), (2, Some(Decimal::new(5, 1)))],
+                   sweeps.iter().map(|(t, _)| (t.client, t.amount)).collect::<Vec<_>>());
+        assert_eq!(Decimal::ZERO, engine.account(1).unwrap().available);
+        assert_eq!(Decimal::new(13, 1), engine.dust());
+        let balances = engine.ledger().unwrap().trial_balance();
+        assert_eq!(Decimal::new(-13, 1), balances[&LedgerAccount::Dust]);
+    }
+}
@@ -1,21 +1,99 @@
 //! Payment engine
-use std::collections::hash_map::Iter;
-use std::collections::HashMap;
+use std::collections::hash_map::{Drain, Iter};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
 
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 
+use crate::adjust::Adjustment;
+use crate::anomaly::{Anomaly, AnomalyRule};
+use crate::autolock::ChargebackStats;
+use crate::compact::IdRanges;
+use crate::balance_history::BalanceHistory;
+use crate::bloom::{self, BloomFilter};
+use crate::config::EngineConfig;
 use crate::error::{PaymentError, Result};
-use crate::models::{Account, Transaction, TransactionType};
+use crate::expiry::OpenDisputes;
+use crate::house::HouseAccount;
+use crate::ledger::{Ledger, LedgerAccount};
+use crate::margin::Reservation;
+use crate::merkle::MerkleLog;
+use crate::middleware::{Middleware, Next};
+use crate::models::{
+    Account, AdminOperation, DepositInfo, DisputeReason, DisputeState, DisputeStatus,
+    RejectionReason, Transaction, TransactionOutcome, TransactionType,
+};
+use crate::receivable::{Receivable, WithdrawnFundsPolicy};
+use crate::reorder::Parked;
+use crate::screening::{BlockedTransaction, ScreeningProvider};
+use crate::settlement::{Movement, Period, Settlement};
+use crate::standing::ScheduledOrder;
+use crate::statement::History;
+use crate::subscription::{BalanceChange, Subscriptions};
+use crate::summary::Activity;
+use crate::validate::Validator;
+use crate::velocity::RecentWithdrawals;
+use crate::wallet::Wallet;
 
-#[derive(Debug, Default, PartialEq)]
-struct SparseAccount {
-    available: Decimal,
-    held: Decimal,
-    locked: bool,
+/// Hash map used for engine state
+///
+/// Keys are small integers and hash flooding is no concern for batch runs, so the `fxhash` and
+/// `ahash` features swap the default SipHash for faster hashers.
+#[cfg(feature = "fxhash")]
+pub(crate) type Map<K, V> = HashMap<K, V, fxhash::FxBuildHasher>;
+#[cfg(all(feature = "ahash", not(feature = "fxhash")))]
+pub(crate) type Map<K, V> = HashMap<K, V, ahash::RandomState>;
+#[cfg(not(any(feature = "fxhash", feature = "ahash")))]
+pub(crate) type Map<K, V> = HashMap<K, V>;
+
+/// Part of the engine that is neither serialized nor copied by [PaymentsEngine::fork], e.g.
+/// boxed extensions; copies start with the default instead
+#[derive(Default)]
+pub(crate) struct Detached<T>(T);
+
+impl<T: Default> Clone for Detached<T> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl<T> Deref for Detached<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Detached<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct SparseAccount {
+    pub(crate) available: Decimal,
+    pub(crate) held: Decimal,
+    pub(crate) locked: bool,
 }
 
 impl SparseAccount {
-    fn assert_not_locked(&self, client: u16, tx: u32) -> Result<()> {
+    fn to_account(&self, client: u16) -> Account {
+        Account {
+            client,
+            available: self.available,
+            held: self.held,
+            total: self.available + self.held,
+            locked: self.locked,
+        }
+    }
+
+    pub(crate) fn assert_not_locked(&self, client: u16, tx: u32) -> Result<()> {
         if self.locked {
             Err(PaymentError::LockedAccount { client, tx })
         } else {
@@ -24,18 +102,176 @@ impl SparseAccount {
     }
 }
 
-#[derive(Debug, Default, PartialEq)]
-struct Deposit {
-    client: u16,
-    amount: Decimal,
-    disputed: bool,
+/// Stored amount of a deposit, see [crate::fixed]
+#[cfg(feature = "fixed-point")]
+pub(crate) type DepositAmount = crate::fixed::FixedPoint;
+#[cfg(not(feature = "fixed-point"))]
+pub(crate) type DepositAmount = Decimal;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Deposit {
+    pub(crate) client: u16,
+    pub(crate) amount: DepositAmount,
+    /// Latest dispute, `None` if the deposit was never disputed
+    pub(crate) dispute: Option<DisputeStatus>,
+}
+
+#[cfg_attr(not(feature = "fixed-point"), allow(unused_variables))]
+impl Deposit {
+    /// Creates an undisputed deposit, failing if the amount cannot be stored.
+    fn new(client: u16, tx: u32, amount: Decimal) -> Result<Self> {
+        #[cfg(feature = "fixed-point")]
+        let amount = DepositAmount::try_from(amount).map_err(|e| {
+            PaymentError::InvalidTransaction(format!("Deposit transaction {} has {}", tx, e))
+        })?;
+        Ok(Self { client, amount, dispute: None })
+    }
+
+    /// Returns true iff the deposit is disputed and its amount held.
+    pub(crate) fn is_disputed(&self) -> bool {
+        self.dispute.is_some_and(|d| d.state.is_open())
+    }
+
+    pub(crate) fn is_charged_back(&self) -> bool {
+        self.dispute.is_some_and(|d| d.state == DisputeState::ChargedBack)
+    }
+
+    fn set_state(&mut self, state: DisputeState) {
+        if let Some(dispute) = &mut self.dispute {
+            dispute.state = state;
+        }
+    }
+
+    /// Returns the deposited amount.
+    pub(crate) fn amount(&self) -> Decimal {
+        #[cfg(feature = "fixed-point")]
+        return self.amount.into();
+        #[cfg(not(feature = "fixed-point"))]
+        self.amount
+    }
+}
+
+/// Withdrawal kept for potential refunds
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Withdrawal {
+    pub(crate) client: u16,
+    pub(crate) amount: Decimal,
+    /// Sum of the refunds so far
+    pub(crate) refunded: Decimal,
+}
+
+impl Withdrawal {
+    /// Returns the amount that may still be refunded.
+    fn refundable(&self) -> Decimal {
+        self.amount - self.refunded
+    }
 }
 
 /// Payments engine holding account data and deposits for potential disputes
-#[derive(Default)]
+///
+/// Clones are forks, see [PaymentsEngine::fork].
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct PaymentsEngine {
-    accounts: HashMap<u16, SparseAccount>,
-    deposits: HashMap<u32, Deposit>,
+    pub(crate) accounts: Map<u16, SparseAccount>,
+    pub(crate) deposits: Map<u32, Deposit>,
+    #[serde(default)]
+    pub(crate) adjustments: Vec<Adjustment>,
+    /// Receivables of disputes of withdrawn funds, by deposit transaction ID
+    #[serde(default)]
+    pub(crate) receivables: Map<u32, Receivable>,
+    /// Withdrawals for potential refunds, by transaction ID
+    #[serde(default)]
+    pub(crate) withdrawals: Map<u32, Withdrawal>,
+    #[serde(default)]
+    pub(crate) config: EngineConfig,
+    /// Number of operations attempted so far
+    #[serde(default)]
+    pub(crate) sequence: u64,
+    /// Sequence numbers of chargebacks that may still be represented, by transaction ID
+    #[serde(default)]
+    pub(crate) chargebacks: Map<u32, u64>,
+    #[serde(default)]
+    pub(crate) merkle: MerkleLog,
+    #[serde(default)]
+    pub(crate) ledger: Option<Ledger>,
+    #[serde(default)]
+    pub(crate) history: Option<History>,
+    #[serde(default)]
+    pub(crate) balance_history: Option<BalanceHistory>,
+    #[serde(skip)]
+    pub(crate) subscriptions: Detached<Subscriptions>,
+    #[serde(skip)]
+    pub(crate) anomaly_rules: Detached<Vec<Box<dyn AnomalyRule>>>,
+    #[serde(default)]
+    pub(crate) anomalies: Vec<Anomaly>,
+    #[serde(default)]
+    pub(crate) recent_withdrawals: RecentWithdrawals,
+    #[serde(skip)]
+    pub(crate) screening: Detached<Option<Box<dyn ScreeningProvider>>>,
+    #[serde(skip)]
+    pub(crate) validators: Detached<Vec<Box<dyn Validator>>>,
+    #[serde(skip)]
+    pub(crate) middleware: Detached<Vec<Box<dyn Middleware>>>,
+    #[serde(default)]
+    pub(crate) blocked: Vec<BlockedTransaction>,
+    #[serde(default)]
+    pub(crate) chargeback_stats: Map<u16, ChargebackStats>,
+    /// Rules that automatically locked accounts, by client
+    #[serde(default)]
+    pub(crate) auto_locked: Map<u16, String>,
+    /// Balances of named wallets, by client
+    #[serde(default)]
+    pub(crate) wallets: Map<u16, BTreeMap<String, Wallet>>,
+    /// Named wallets of deposits, by transaction ID
+    #[serde(default)]
+    pub(crate) deposit_wallets: Map<u32, String>,
+    /// Outcomes of transactions with idempotency keys, by key
+    #[serde(default)]
+    pub(crate) idempotency: Map<String, Result<()>>,
+    /// Transactions waiting for the deposit they refer to, in execution order
+    #[serde(default)]
+    pub(crate) parked: VecDeque<Parked>,
+    /// Transactions and outcomes of parked transactions retried or expired since the last call of
+    /// [PaymentsEngine::take_reordered]
+    #[serde(skip)]
+    pub(crate) reordered: Detached<Vec<(Transaction, TransactionOutcome)>>,
+    #[serde(default)]
+    pub(crate) open_disputes: OpenDisputes,
+    /// Overdraft limits, by client
+    #[serde(default)]
+    pub(crate) overdraft_limits: Map<u16, Decimal>,
+    /// Balances of the internal house accounts
+    #[serde(default)]
+    pub(crate) house: BTreeMap<HouseAccount, Decimal>,
+    /// Funds held outside the dispute flow, by reservation transaction ID
+    #[serde(default)]
+    pub(crate) reservations: Map<u32, Reservation>,
+    #[serde(default)]
+    pub(crate) standing_orders: Vec<ScheduledOrder>,
+    /// Number of transaction IDs generated for standing orders so far
+    #[serde(default)]
+    pub(crate) standing_order_txs: u32,
+    #[serde(default)]
+    pub(crate) period: Period,
+    #[serde(default)]
+    pub(crate) settlements: Vec<Settlement>,
+    /// Sequence numbers of deposits, by transaction ID, only recorded with a dispute window
+    #[serde(default)]
+    pub(crate) deposit_sequences: Map<u32, u64>,
+    #[serde(default)]
+    pub(crate) deposit_filter: Option<BloomFilter>,
+    /// Chargebacks and latest timestamps, by client
+    #[serde(default)]
+    pub(crate) activity: Map<u16, Activity>,
+    /// Custom attributes, by client
+    #[serde(default)]
+    pub(crate) attributes: Map<u16, BTreeMap<String, String>>,
+    /// Sequence number of the last automatic compaction
+    #[serde(default)]
+    pub(crate) compacted_at: u64,
+    /// Transaction IDs of compacted deposits, still rejected as duplicates
+    #[serde(default)]
+    pub(crate) compacted_deposits: IdRanges,
 }
 
 impl PaymentsEngine {
@@ -44,10 +280,139 @@ impl PaymentsEngine {
         Self::default()
     }
 
+    /// Creates new [PaymentsEngine] with room for the given numbers of clients and transactions,
+    /// avoiding rehashing while the maps grow.
+    ///
+    /// Only deposits are stored, so the transactions hint is an upper bound. The clients hint is
+    /// capped at the number of possible client IDs.
+    pub fn with_capacity(clients_hint: usize, transactions_hint: usize) -> Self {
+        let clients = clients_hint.min(usize::from(u16::MAX) + 1);
+        Self {
+            accounts: Map::with_capacity_and_hasher(clients, Default::default()),
+            deposits: Map::with_capacity_and_hasher(transactions_hint, Default::default()),
+            ..Default::default()
+        }
+    }
+
+    /// Creates new [PaymentsEngine] with the given configuration.
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self { config, ..Default::default() }
+    }
+
+    /// Returns the configuration of the engine.
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Returns an independent deep copy of the engine, e.g. to apply a hypothetical batch of
+    /// transactions without touching the live state and comparing both with
+    /// [PaymentsEngine::diff].
+    ///
+    /// Anomaly rules, the screening provider, validators, middleware, and subscriptions cannot be
+    /// copied; add them to the fork again if needed.
+    pub fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    /// Clears all state, keeping the allocated capacity for the next batch.
+    ///
+    /// The configuration, anomaly rules, screening provider, validators, middleware, subscriptions,
+    /// overdraft limits, and custom attributes are kept, as are the enabled ledger, histories, and
+    /// deposit filter, which start empty.
+    pub fn reset(&mut self) {
+        // Exhaustive, so that new fields must be handled here
+        let Self {
+            accounts, deposits, adjustments, receivables, withdrawals, config: _, sequence,
+            chargebacks, merkle, ledger, history, balance_history, subscriptions: _,
+            anomaly_rules: _, anomalies, recent_withdrawals, screening: _, validators: _,
+            middleware: _, blocked, chargeback_stats, auto_locked, wallets, deposit_wallets,
+            idempotency, parked, reordered, open_disputes, overdraft_limits: _, house, reservations,
+            standing_orders, standing_order_txs, period, settlements, deposit_sequences,
+            deposit_filter, activity, attributes: _, compacted_at, compacted_deposits,
+        } = self;
+        accounts.clear();
+        deposits.clear();
+        adjustments.clear();
+        receivables.clear();
+        withdrawals.clear();
+        *sequence = 0;
+        chargebacks.clear();
+        *merkle = MerkleLog::default();
+        if let Some(ledger) = ledger {
+            *ledger = Ledger::default();
+        }
+        if let Some(history) = history {
+            history.clear();
+        }
+        if let Some(balance_history) = balance_history {
+            balance_history.clear();
+        }
+        anomalies.clear();
+        recent_withdrawals.clear();
+        blocked.clear();
+        chargeback_stats.clear();
+        auto_locked.clear();
+        wallets.clear();
+        deposit_wallets.clear();
+        idempotency.clear();
+        parked.clear();
+        reordered.clear();
+        *open_disputes = OpenDisputes::default();
+        house.clear();
+        reservations.clear();
+        standing_orders.clear();
+        *standing_order_txs = 0;
+        *period = Period::default();
+        settlements.clear();
+        deposit_sequences.clear();
+        if let Some(filter) = deposit_filter {
+            filter.clear();
+        }
+        activity.clear();
+        *compacted_at = 0;
+        compacted_deposits.clear();
+    }
+
+    /// Removes and returns all accounts, clearing the rest of the state like
+    /// [PaymentsEngine::reset].
+    pub fn drain_accounts(&mut self) -> DrainAccounts<'_> {
+        let accounts = std::mem::take(&mut self.accounts);
+        self.reset();
+        self.accounts = accounts;
+        DrainAccounts { drain: self.accounts.drain() }
+    }
+
+    /// Posts journal entries for the current operation if the ledger is enabled.
+    pub(crate) fn post(
+        &mut self,
+        tx: u32,
+        amount: Decimal,
+        entries: &[(LedgerAccount, LedgerAccount)],
+    ) {
+        if let Some(ledger) = &mut self.ledger {
+            for &(debit, credit) in entries {
+                ledger.post(self.sequence, tx, debit, credit, amount);
+            }
+        }
+    }
+
+    /// Counts an attempted operation and returns its sequence number.
+    pub(crate) fn next_sequence(&mut self) -> u64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
     /// Transfers credit to client's account.
     ///
-    /// Fails if client account is locked.
+    /// Fails if the client is blocked (see [crate::screening]), client account is locked, or a
+    /// deposit with the same transaction ID exists.
     pub fn deposit(&mut self, client: u16, tx: u32, amount: Decimal) -> Result<()> {
+        self.screen(client, tx, TransactionType::Deposit)?;
+        let sequence = self.next_sequence();
+        if self.deposit_id_used(tx) {
+            return Err(PaymentError::DuplicateTransaction { client, tx });
+        }
+        let deposit = Deposit::new(client, tx, amount)?;
         if let Some(account) = self.accounts.get_mut(&client) {
             account.assert_not_locked(client, tx)?;
             account.available += amount;
@@ -57,134 +422,712 @@ impl PaymentsEngine {
                 ..Default::default()
             });
         }
-        self.deposits.insert(tx, Deposit { client, amount, disputed: false });
+        self.deposits.insert(tx, deposit);
+        if let Some(filter) = &mut self.deposit_filter {
+            filter.insert(tx);
+        }
+        if self.config.dispute_window.is_some() {
+            self.deposit_sequences.insert(tx, sequence);
+        }
+        self.post(tx, amount, &[(LedgerAccount::Cash, LedgerAccount::ClientAvailable(client))]);
+        self.record_movement(client, Movement::Deposit(amount));
+        self.count_deposit(client);
         Ok(())
     }
 
     /// Withdraws amount from client's account.
     ///
-    /// Fails if client account is locked, has insufficient funds or does not exist.
+    /// Fails if the client is blocked (see [crate::screening]), client account is locked, has
+    /// insufficient funds (including its overdraft limit, see [crate::overdraft]) or insufficient
+    /// funds in its default wallet (see [crate::wallet]), would fall below the minimum balance
+    /// (see [crate::dust]), or does not exist. See [PaymentsEngine::withdraw_at] for withdrawals
+    /// with timestamps, which are subject to the velocity limit.
     pub fn withdraw(&mut self, client: u16, tx: u32, amount: Decimal) -> Result<()> {
+        self.withdraw_from(client, tx, amount, None, None)
+    }
+
+    /// Withdraws amount from the client's wallet (`None` for the default wallet) like
+    /// [PaymentsEngine::withdraw], checking and counting it for the velocity limit if it has a
+    /// timestamp.
+    pub(crate) fn withdraw_from(
+        &mut self,
+        client: u16,
+        tx: u32,
+        amount: Decimal,
+        timestamp: Option<u64>,
+        wallet: Option<&str>,
+    ) -> Result<()> {
+        self.screen(client, tx, TransactionType::Withdrawal)?;
+        let shortfall = self.withdrawal_wallet_shortfall(client, wallet, amount);
+        if let Some((available, amount)) = shortfall {
+            return Err(PaymentError::InsufficientFunds { client, tx, available, amount });
+        }
+        if self.exceeds_velocity_limit(client, amount, timestamp) {
+            return Err(PaymentError::VelocityLimitExceeded { client, tx });
+        }
+        self.debit(client, tx, amount)?;
+        self.record_recent_withdrawal(client, amount, timestamp);
+        Ok(())
+    }
+
+    /// Debits the withdrawal from client's account, see [PaymentsEngine::withdraw].
+    fn debit(&mut self, client: u16, tx: u32, amount: Decimal) -> Result<()> {
+        self.next_sequence();
+        let limit = self.overdraft_limit(client);
+        let min_balance = self.config.min_balance;
         let account = self.accounts.get_mut(&client).ok_or_else(|| {
             PaymentError::InvalidTransaction(
                 format!("Account {} does not exist (transaction {})", client, tx)
             )
         })?;
         account.assert_not_locked(client, tx)?;
-        if account.available >= amount {
+        if account.available + limit < amount && limit > Decimal::ZERO {
+            let available = account.available;
+            Err(PaymentError::OverdraftExceeded { client, tx, available, limit, amount })
+        } else if account.available + limit < amount {
+            Err(PaymentError::InsufficientFunds { client, tx, available: account.available, amount })
+        } else if let Some(min_balance) = min_balance.filter(|&m| account.available - amount < m) {
+            Err(PaymentError::BelowMinimumBalance { client, tx, min_balance })
+        } else {
             account.available -= amount;
+            self.post(tx, amount, &[(LedgerAccount::ClientAvailable(client), LedgerAccount::Cash)]);
+            self.record_movement(client, Movement::Withdrawal(amount));
+            self.withdrawals.insert(tx, Withdrawal { client, amount, refunded: Decimal::ZERO });
             Ok(())
-        } else {
-            Err(PaymentError::InsufficientFunds { client, tx, available: account.available, amount })
         }
     }
 
+    /// Credits (part of) a past withdrawal back to client's account, without a dispute.
+    ///
+    /// Fails if the client is blocked (see [crate::screening]), client account is locked, the
+    /// withdrawal does not exist (for this client), or the amount exceeds the part of the
+    /// withdrawal not refunded yet.
+    pub fn refund(&mut self, client: u16, tx: u32, amount: Decimal) -> Result<()> {
+        self.screen(client, tx, TransactionType::Refund)?;
+        self.next_sequence();
+        let account = self.accounts.get_mut(&client).ok_or_else(|| {
+            PaymentError::UnknownClient { client, tx_type: String::from("Refund") }
+        })?;
+        account.assert_not_locked(client, tx)?;
+        let withdrawal = self.withdrawals.get_mut(&tx).filter(|w| w.client == client)
+            .ok_or(PaymentError::UnknownWithdrawal { client, tx })?;
+        if amount > withdrawal.refundable() {
+            return Err(PaymentError::RefundExceedsWithdrawal {
+                client,
+                tx,
+                refundable: withdrawal.refundable(),
+                amount,
+            });
+        }
+        withdrawal.refunded += amount;
+        account.available += amount;
+        self.post(tx, amount, &[(LedgerAccount::Cash, LedgerAccount::ClientAvailable(client))]);
+        self.record_movement(client, Movement::Withdrawal(-amount));
+        Ok(())
+    }
+
+    /// Charges a fee to client's account.
+    ///
+    /// Fails if client account is locked, has insufficient funds, or does not exist.
+    pub fn charge_fee(&mut self, client: u16, tx: u32, amount: Decimal) -> Result<()> {
+        self.next_sequence();
+        let account = self.accounts.get_mut(&client).ok_or_else(|| {
+            PaymentError::UnknownClient { client, tx_type: String::from("Fee") }
+        })?;
+        account.assert_not_locked(client, tx)?;
+        if account.available < amount {
+            return Err(PaymentError::InsufficientFunds {
+                client,
+                tx,
+                available: account.available,
+                amount,
+            });
+        }
+        account.available -= amount;
+        self.post(tx, amount, &[(LedgerAccount::ClientAvailable(client), LedgerAccount::Fees)]);
+        self.credit_house(HouseAccount::FeeIncome, amount);
+        self.record_movement(client, Movement::Fee(amount));
+        Ok(())
+    }
+
     /// Disputes past deposit transaction.
     ///
-    /// Fails if client account is locked, the account does not exist or has insufficient funds,
-    /// the disputed transaction does not exist, or is already disputed.
+    /// Fails if the client is blocked (see [crate::screening]), client account is locked, the
+    /// account (or the wallet of the deposit, see [crate::wallet]) does not exist or has
+    /// insufficient funds (unless allowed by [EngineConfig::withdrawn_funds]), the disputed
+    /// transaction does not exist (for this client), or is already disputed.
     pub fn dispute(&mut self, client: u16, tx: u32) -> Result<()> {
+        self.dispute_with_reason(client, tx, None)
+    }
+
+    /// Disputes past deposit transaction for the given reason (see [PaymentsEngine::dispute]).
+    pub fn dispute_with_reason(
+        &mut self,
+        client: u16,
+        tx: u32,
+        reason: Option<DisputeReason>,
+    ) -> Result<()> {
+        self.screen(client, tx, TransactionType::Dispute)?;
+        if let Some((available, amount)) = self.dispute_wallet_shortfall(client, tx) {
+            return Err(PaymentError::InsufficientFunds { client, tx, available, amount });
+        }
+        let sequence = self.next_sequence();
+        let window_passed = self.dispute_window_passed(tx, sequence);
         let account = self.accounts.get_mut(&client).ok_or_else(|| {
             PaymentError::UnknownClient { client, tx_type: "Dispute".to_string() }
         })?;
         account.assert_not_locked(client, tx)?;
-        let deposit = self.deposits.get_mut(&tx).ok_or_else(|| {
-            PaymentError::UnknownTransaction { client, tx, tx_type: String::from("Dispute") }
-        })?;
-        if deposit.disputed {
+        let deposit = bloom::get_mut(&mut self.deposit_filter, &mut self.deposits, tx)
+            .filter(|d| d.client == client)
+            .ok_or_else(|| {
+                PaymentError::UnknownTransaction { client, tx, tx_type: String::from("Dispute") }
+            })?;
+        if deposit.is_disputed() {
+            return Err(PaymentError::AlreadyDisputed { client, tx });
+        }
+        if deposit.is_charged_back() {
             return Err(PaymentError::InvalidTransaction(format!(
-                "Deposit transaction {} of client {} is already disputed, cannot be disputed twice",
+                "Deposit transaction {} of client {} was charged back, cannot be disputed",
                 tx,
                 client
             )))
         }
-        if account.available >= deposit.amount {
-            deposit.disputed = true;
-            account.available -= deposit.amount;
-            account.held += deposit.amount;
-            Ok(())
-        } else {
-            Err(PaymentError::InsufficientFunds {
-                client,
+        if window_passed {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Dispute window for transaction {} of client {} has passed",
                 tx,
-                available: account.available,
-                amount: deposit.amount,
-            })
+                client
+            )));
         }
+        let amount = deposit.amount();
+        let held = match self.config.withdrawn_funds {
+            _ if account.available >= amount => amount,
+            WithdrawnFundsPolicy::Reject => {
+                return Err(PaymentError::InsufficientFunds {
+                    client,
+                    tx,
+                    available: account.available,
+                    amount,
+                });
+            }
+            WithdrawnFundsPolicy::Negative => amount,
+            WithdrawnFundsPolicy::Receivable => account.available.max(Decimal::ZERO),
+        };
+        deposit.dispute = Some(DisputeStatus { state: DisputeState::Opened, reason });
+        account.available -= held;
+        account.held += held;
+        if held < amount {
+            self.receivables.insert(tx, Receivable { client, tx, amount: amount - held });
+        }
+        self.post(tx, held, &[
+            (LedgerAccount::ClientAvailable(client), LedgerAccount::ClientHeld(client)),
+        ]);
+        self.add_to_deposit_wallet(client, tx, -amount, amount);
+        Ok(())
     }
 
-    /// Resolves open dispute.
+    /// Marks an opened dispute as under review.
     ///
     /// Fails if client account is locked, the account does not exist, the specified transaction
-    /// does not exist or is not disputed.
+    /// does not exist or its dispute is not in the opened state.
+    pub fn review(&mut self, client: u16, tx: u32) -> Result<()> {
+        self.next_sequence();
+        let account = self.accounts.get(&client).ok_or_else(|| {
+            PaymentError::UnknownClient { client, tx_type: "Review".to_string() }
+        })?;
+        account.assert_not_locked(client, tx)?;
+        let deposit = bloom::get_mut(&mut self.deposit_filter, &mut self.deposits, tx)
+            .filter(|d| d.client == client)
+            .ok_or_else(|| {
+                PaymentError::UnknownTransaction { client, tx, tx_type: String::from("Review") }
+            })?;
+        if deposit.dispute.map(|d| d.state) != Some(DisputeState::Opened) {
+            return Err(PaymentError::InvalidTransaction(
+                format!("Transaction {} to be reviewed for client {} is not opened", tx, client)
+            ));
+        }
+        deposit.set_state(DisputeState::UnderReview);
+        self.notify_subscribers(client, BalanceChange::Review { tx }, None);
+        Ok(())
+    }
+
+    /// Resolves open dispute.
+    ///
+    /// Fails if the client is blocked (see [crate::screening]), client account is locked, the
+    /// account does not exist, the specified transaction does not exist or is not disputed.
     pub fn resolve(&mut self, client: u16, tx: u32) -> Result<()> {
+        self.screen(client, tx, TransactionType::Resolve)?;
+        self.next_sequence();
+        let shortfall = self.shortfall(tx);
         let account = self.accounts.get_mut(&client).ok_or_else(|| {
             PaymentError::UnknownClient { client, tx_type: "Resolve".to_string() }
         })?;
         account.assert_not_locked(client, tx)?;
-        let deposit = self.deposits.get_mut(&tx).ok_or_else(|| {
-            PaymentError::UnknownTransaction { client, tx, tx_type: String::from("Resolve") }
-        })?;
-        if !deposit.disputed {
+        let deposit = bloom::get_mut(&mut self.deposit_filter, &mut self.deposits, tx)
+            .filter(|d| d.client == client)
+            .ok_or_else(|| {
+                PaymentError::UnknownTransaction { client, tx, tx_type: String::from("Resolve") }
+            })?;
+        if !deposit.is_disputed() {
             return Err(PaymentError::InvalidTransaction(
                 format!("Transaction {} to be resolved for client {} is not disputed", tx, client)
             ));
         }
-        account.available += deposit.amount;
-        account.held -= deposit.amount;
-        deposit.disputed = false;
+        let deposited = deposit.amount();
+        let amount = deposited - shortfall;
+        account.available += amount;
+        account.held -= amount;
+        deposit.set_state(DisputeState::Resolved);
+        self.receivables.remove(&tx);
+        self.post(tx, amount, &[
+            (LedgerAccount::ClientHeld(client), LedgerAccount::ClientAvailable(client)),
+        ]);
+        self.add_to_deposit_wallet(client, tx, deposited, -deposited);
         Ok(())
     }
 
     /// Reverses specified transaction and locks client account.
     ///
-    /// Fails if client account does not exist, account is locked, specified transaction does not
-    /// exist or is not disputed.
+    /// Fails if the client is blocked (see [crate::screening]), client account does not exist,
+    /// account is locked, specified transaction does not exist or is not disputed.
     pub fn chargeback(&mut self, client: u16, tx: u32) -> Result<()> {
+        self.screen(client, tx, TransactionType::Chargeback)?;
+        let sequence = self.next_sequence();
+        let shortfall = self.shortfall(tx);
         let account = self.accounts.get_mut(&client).ok_or_else(|| {
             PaymentError::UnknownClient { client, tx_type: "Chargeback".to_string() }
         })?;
         account.assert_not_locked(client, tx)?;
-        let deposit = self.deposits.get_mut(&tx).ok_or_else(|| {
-            PaymentError::UnknownTransaction { client, tx, tx_type: String::from("Chargeback") }
-        })?;
-        if !deposit.disputed {
+        let deposit = bloom::get_mut(&mut self.deposit_filter, &mut self.deposits, tx)
+            .filter(|d| d.client == client)
+            .ok_or_else(|| {
+                PaymentError::UnknownTransaction { client, tx, tx_type: String::from("Chargeback") }
+            })?;
+        if !deposit.is_disputed() {
             return Err(PaymentError::InvalidTransaction(
                 format!("Transaction {} to be resolved for client {} is not disputed", tx, client)
             ));
         }
-        account.held = Decimal::new(0, 0);
+        let deposited = deposit.amount();
+        let amount = deposited - shortfall;
+        account.held -= amount;
         account.locked = true;
-        self.deposits.remove(&tx);
+        deposit.set_state(DisputeState::ChargedBack);
+        self.chargebacks.insert(tx, sequence);
+        self.post(tx, deposited, &[(LedgerAccount::ChargebackLoss, LedgerAccount::Cash)]);
+        self.post(tx, amount, &[
+            (LedgerAccount::ClientHeld(client), LedgerAccount::ChargebackLoss),
+        ]);
+        self.record_movement(client, Movement::Chargeback(amount));
+        self.credit_house(HouseAccount::ChargebackWriteOff, amount);
+        self.count_chargeback(client);
+        self.record_chargeback(client);
+        self.add_to_deposit_wallet(client, tx, Decimal::ZERO, -deposited);
+        Ok(())
+    }
+
+    /// Reverses the chargeback of the specified transaction (representment), reinstating the
+    /// charged back funds and optionally unlocking the client account. Accounts locked by
+    /// [EngineConfig::auto_lock] stay locked.
+    ///
+    /// Fails if client account does not exist, specified transaction does not exist or was not
+    /// charged back, or the representment window (see [EngineConfig]) has passed.
+    pub fn represent(&mut self, client: u16, tx: u32, unlock: bool) -> Result<()> {
+        let sequence = self.next_sequence();
+        let shortfall = self.shortfall(tx);
+        let account = self.accounts.get_mut(&client).ok_or_else(|| {
+            PaymentError::UnknownClient { client, tx_type: "Representment".to_string() }
+        })?;
+        let deposit = bloom::get_mut(&mut self.deposit_filter, &mut self.deposits, tx)
+            .filter(|d| d.client == client)
+            .ok_or_else(|| PaymentError::UnknownTransaction {
+                client,
+                tx,
+                tx_type: String::from("Representment"),
+            })?;
+        let charged_back = self.chargebacks.get(&tx).filter(|_| deposit.is_charged_back());
+        let charged_back = charged_back.ok_or_else(|| PaymentError::InvalidTransaction(format!(
+            "Transaction {} to be represented for client {} is not charged back",
+            tx,
+            client
+        )))?;
+        if self.config.representment_window.is_some_and(|window| sequence - charged_back > window) {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Representment window for transaction {} of client {} has passed",
+                tx,
+                client
+            )));
+        }
+        let deposited = deposit.amount();
+        let amount = deposited - shortfall;
+        account.available += amount;
+        if unlock && !self.auto_locked.contains_key(&client) {
+            account.locked = false;
+        }
+        deposit.set_state(DisputeState::Represented);
+        self.chargebacks.remove(&tx);
+        self.receivables.remove(&tx);
+        self.post(tx, deposited, &[(LedgerAccount::Cash, LedgerAccount::ChargebackLoss)]);
+        self.post(tx, amount, &[
+            (LedgerAccount::ChargebackLoss, LedgerAccount::ClientAvailable(client)),
+        ]);
+        self.record_movement(client, Movement::Chargeback(-amount));
+        self.credit_house(HouseAccount::ChargebackWriteOff, -amount);
+        self.notify_subscribers(client, BalanceChange::Representment { tx }, None);
+        Ok(())
+    }
+
+    /// Unlocks a locked client account, e.g. after a chargeback was settled outside of the engine.
+    ///
+    /// Fails if client account does not exist, is not locked, or was locked by
+    /// [EngineConfig::auto_lock].
+    pub fn unlock(&mut self, client: u16) -> Result<()> {
+        let account = self.accounts.get_mut(&client).ok_or_else(|| {
+            PaymentError::UnknownClient { client, tx_type: "Unlock".to_string() }
+        })?;
+        if !account.locked {
+            return Err(PaymentError::InvalidTransaction(
+                format!("Account of client {} to be unlocked is not locked", client)
+            ));
+        }
+        if let Some(rule) = self.auto_locked.get(&client) {
+            return Err(PaymentError::InvalidTransaction(
+                format!("Account of client {} is locked for good ({})", client, rule)
+            ));
+        }
+        account.locked = false;
+        self.notify_subscribers(client, BalanceChange::Unlock, None);
         Ok(())
     }
 
-    /// Executes a [Transaction].
+    /// Applies the administrative operation, see [PaymentsEngine::review],
+    /// [PaymentsEngine::represent], and [PaymentsEngine::unlock].
+    pub fn administer(&mut self, operation: AdminOperation) -> Result<()> {
+        match operation {
+            AdminOperation::Review { client, tx } => self.review(client, tx),
+            AdminOperation::Represent { client, tx, unlock } => self.represent(client, tx, unlock),
+            AdminOperation::Unlock { client } => self.unlock(client),
+        }
+    }
+
+    /// Executes a [Transaction], passing it through the middleware (see [crate::middleware]) if
+    /// any.
+    ///
+    /// If a transaction with the same idempotency key was executed before, its original outcome is
+    /// returned without executing the transaction again.
     pub fn execute(&mut self, transaction: Transaction) -> Result<()> {
-        let Transaction { transaction_type, client, tx, amount } = transaction;
-        match transaction_type {
+        if self.middleware.is_empty() {
+            return self.execute_idempotent(transaction);
+        }
+        let mut middleware = std::mem::take(&mut self.middleware);
+        let result = Next::new(&mut middleware).run(transaction, self);
+        middleware.append(&mut self.middleware);
+        self.middleware = middleware;
+        result
+    }
+
+    /// Executes a [Transaction] unless its idempotency key was seen before.
+    pub(crate) fn execute_idempotent(&mut self, transaction: Transaction) -> Result<()> {
+        let Some(key) = transaction.idempotency_key.clone() else {
+            return self.execute_once(transaction);
+        };
+        if let Some(outcome) = self.idempotency.get(&key) {
+            return outcome.clone();
+        }
+        let outcome = self.execute_once(transaction);
+        self.idempotency.insert(key, outcome.clone());
+        outcome
+    }
+
+    /// Returns the original outcome of the transaction with the idempotency key if it was executed.
+    pub fn idempotent_outcome(&self, key: &str) -> Option<&Result<()>> {
+        self.idempotency.get(key)
+    }
+
+    /// Executes the transaction, parking it if it refers to a deposit not seen yet (see
+    /// [crate::reorder]).
+    fn execute_once(&mut self, transaction: Transaction) -> Result<()> {
+        let result = self.apply(&transaction).map_err(|e| self.park(&transaction, e));
+        if result.is_ok() {
+            self.retry_parked(&transaction);
+        }
+        self.expire_parked(transaction.timestamp);
+        self.compact_if_due();
+        result
+    }
+
+    fn apply(&mut self, transaction: &Transaction) -> Result<()> {
+        if self.config.merkle_log {
+            self.merkle.record(transaction);
+        }
+        let Transaction { transaction_type, client, tx, amount, reason, timestamp, .. } =
+            *transaction;
+        self.screen(client, tx, transaction_type)?;
+        self.validate(transaction)?;
+        let result = match transaction_type {
             TransactionType::Deposit => self.deposit(client, tx, amount.ok_or_else(|| {
                 PaymentError::InvalidTransaction(
                     format!("Deposit transaction {} does not specify amount", tx)
                 )
             })?),
-            TransactionType::Withdrawal => self.withdraw(client, tx, amount.ok_or_else(|| {
+            TransactionType::Withdrawal => self.withdraw_from(client, tx, amount.ok_or_else(|| {
                 PaymentError::InvalidTransaction(
                     format!("Withdrawal transaction {} does not specify amount", tx)
                 )
-            })?),
-            TransactionType::Dispute => self.dispute(client, tx),
+            })?, timestamp, transaction.wallet.as_deref()),
+            TransactionType::Dispute => self.dispute_with_reason(client, tx, reason),
             TransactionType::Resolve => self.resolve(client, tx),
             TransactionType::Chargeback => self.chargeback(client, tx),
+            TransactionType::Refund => self.refund(client, tx, amount.ok_or_else(|| {
+                PaymentError::InvalidTransaction(
+                    format!("Refund transaction {} does not specify amount", tx)
+                )
+            })?),
+            TransactionType::Adjustment => self.adjust(client, tx, amount.ok_or_else(|| {
+                PaymentError::InvalidTransaction(
+                    format!("Adjustment transaction {} does not specify amount", tx)
+                )
+            })?, transaction.memo.as_deref().unwrap_or_default()),
+        };
+        if result.is_ok() {
+            self.record_wallet(transaction);
+            self.record_dispute_time(transaction);
+            self.record_history(transaction);
+            self.record_balance(transaction);
+            let change = BalanceChange::Transaction { tx, kind: transaction_type };
+            self.notify_subscribers(client, change, timestamp);
+            self.record_activity(transaction);
+            self.check_anomalies(transaction);
+        }
+        result
+    }
+
+    /// Executes a [Transaction] and returns its effect on the client's account.
+    pub fn execute_with_outcome(&mut self, transaction: Transaction) -> TransactionOutcome {
+        let Transaction { transaction_type: kind, client, tx, .. } = transaction;
+        let balance_before = self.account(client);
+        let auto_locked_before = self.auto_locked.contains_key(&client);
+        let status = self.execute(transaction);
+        TransactionOutcome {
+            client,
+            tx,
+            kind,
+            balance_before,
+            balance_after: self.account(client),
+            status,
+            auto_lock: self.auto_lock_reason(client)
+                .filter(|_| !auto_locked_before)
+                .map(String::from),
+        }
+    }
+
+    /// Returns all reasons why the given transaction would be rejected, without executing it.
+    ///
+    /// The result is empty iff executing the transaction would succeed.
+    pub fn explain(&self, transaction: &Transaction) -> Vec<RejectionReason> {
+        let Transaction { transaction_type, client, tx, amount, timestamp, .. } = *transaction;
+        let account = self.accounts.get(&client);
+        let deposit = self.deposits.get(&tx).filter(|d| d.client == client);
+        let mut reasons = Vec::new();
+        let mut reject = |reason| reasons.push(reason);
+        if self.is_blocked(client) {
+            reject(RejectionReason::ClientBlocked);
+        }
+        if let Some(reason) = self.validation_failure(transaction) {
+            reject(RejectionReason::ValidationFailed(reason));
+        }
+        let strict = transaction_type != TransactionType::Adjustment
+            || self.config.strict_adjustments;
+        if account.is_some_and(|a| a.locked) && strict {
+            reject(RejectionReason::LockedAccount);
         }
+        match transaction_type {
+            TransactionType::Deposit => {
+                if amount.is_none() {
+                    reject(RejectionReason::MissingAmount);
+                }
+                if self.deposit_id_used(tx) {
+                    reject(RejectionReason::DuplicateTransaction);
+                }
+            }
+            TransactionType::Withdrawal => {
+                if amount.is_none() {
+                    reject(RejectionReason::MissingAmount);
+                }
+                let limit = self.overdraft_limit(client);
+                match (account, amount) {
+                    (None, _) => reject(RejectionReason::UnknownClient),
+                    (Some(a), Some(amount)) if a.available + limit < amount => {
+                        let available = a.available;
+                        reject(if limit > Decimal::ZERO {
+                            RejectionReason::OverdraftExceeded { available, limit, amount }
+                        } else {
+                            RejectionReason::InsufficientFunds { available, amount }
+                        })
+                    }
+                    (Some(a), Some(amount)) => match self.config.min_balance {
+                        Some(min_balance) if a.available - amount < min_balance => {
+                            reject(RejectionReason::BelowMinimumBalance { min_balance })
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+                if amount.is_some_and(|a| self.exceeds_velocity_limit(client, a, timestamp)) {
+                    reject(RejectionReason::VelocityLimitExceeded);
+                }
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                if account.is_none() {
+                    reject(RejectionReason::UnknownClient);
+                }
+                let rejects_withdrawn = self.config.withdrawn_funds == WithdrawnFundsPolicy::Reject;
+                match (deposit, transaction_type) {
+                    (None, _) => reject(RejectionReason::UnknownTransaction),
+                    (Some(d), TransactionType::Dispute) if d.is_disputed() => {
+                        reject(RejectionReason::AlreadyDisputed)
+                    }
+                    (Some(d), TransactionType::Dispute) if d.is_charged_back() => {
+                        reject(RejectionReason::ChargedBack)
+                    }
+                    (Some(_), TransactionType::Dispute)
+                        if self.dispute_window_passed(tx, self.sequence + 1) => {
+                        reject(RejectionReason::DisputeWindowPassed)
+                    }
+                    (Some(d), TransactionType::Dispute) => match account {
+                        Some(a) if a.available < d.amount() && rejects_withdrawn => {
+                            reject(RejectionReason::InsufficientFunds {
+                                available: a.available,
+                                amount: d.amount(),
+                            })
+                        }
+                        _ => {}
+                    },
+                    (Some(d), _) if !d.is_disputed() => reject(RejectionReason::NotDisputed),
+                    _ => {}
+                }
+            }
+            TransactionType::Refund => {
+                if account.is_none() {
+                    reject(RejectionReason::UnknownClient);
+                }
+                match (self.withdrawals.get(&tx).filter(|w| w.client == client), amount) {
+                    (None, _) => reject(RejectionReason::UnknownTransaction),
+                    (Some(_), None) => reject(RejectionReason::MissingAmount),
+                    (Some(w), Some(amount)) if amount > w.refundable() => {
+                        reject(RejectionReason::RefundExceedsWithdrawal {
+                            refundable: w.refundable(),
+                            amount,
+                        })
+                    }
+                    _ => {}
+                }
+            }
+            TransactionType::Adjustment => {
+                if amount.is_none() {
+                    reject(RejectionReason::MissingAmount);
+                }
+                if transaction.memo.as_deref().is_none_or(|memo| memo.trim().is_empty()) {
+                    reject(RejectionReason::MissingReason);
+                }
+                match (account, amount) {
+                    (None, _) => reject(RejectionReason::UnknownClient),
+                    (Some(a), Some(amount)) if strict && a.available + amount < Decimal::ZERO => {
+                        reject(RejectionReason::InsufficientFunds {
+                            available: a.available,
+                            amount: -amount,
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if let Some((available, amount)) = self.insufficient_wallet_funds(transaction) {
+            let insufficient = |r: &_| matches!(r, RejectionReason::InsufficientFunds { .. });
+            if !reasons.iter().any(insufficient) {
+                reasons.push(RejectionReason::InsufficientFunds { available, amount });
+            }
+        }
+        reasons
+    }
+
+    /// Returns the status of the latest dispute of the given deposit, `None` if the deposit does
+    /// not exist or was never disputed.
+    pub fn dispute_status(&self, tx: u32) -> Option<DisputeStatus> {
+        self.deposits.get(&tx).and_then(|d| d.dispute)
+    }
+
+    /// Returns the deposits kept for potential disputes, in no particular order.
+    ///
+    /// Deposits removed by compaction (see [EngineConfig::compaction_interval]) are not included.
+    pub fn deposits(&self) -> impl Iterator<Item=DepositInfo> + '_ {
+        self.deposits.iter().map(|(&tx, deposit)| {
+            let locked = self.accounts.get(&deposit.client).is_some_and(|a| a.locked);
+            DepositInfo {
+                tx,
+                client: deposit.client,
+                amount: deposit.amount(),
+                disputed: deposit.is_disputed(),
+                disputable: !locked && !deposit.is_disputed() && !deposit.is_charged_back()
+                    && !self.dispute_window_passed(tx, self.sequence + 1),
+                dispute: deposit.dispute,
+            }
+        })
+    }
+
+    /// Returns the deposits of the given client, see [PaymentsEngine::deposits].
+    pub fn deposits_of(&self, client: u16) -> impl Iterator<Item=DepositInfo> + '_ {
+        self.deposits().filter(move |deposit| deposit.client == client)
+    }
+
+    /// Returns the [Account] of the given client if it exists.
+    pub fn account(&self, client: u16) -> Option<Account> {
+        self.accounts.get(&client).map(|account| account.to_account(client))
     }
 
     /// Returns iterator over [Account]s.
-    pub fn accounts(&self) -> AccountIter {
+    pub fn accounts(&self) -> AccountIter<'_> {
         AccountIter { iter: self.accounts.iter() }
     }
 }
 
+/// Iterator over the [Account]s removed by [PaymentsEngine::drain_accounts]
+pub struct DrainAccounts<'a> {
+    drain: Drain<'a, u16, SparseAccount>,
+}
+
+impl Iterator for DrainAccounts<'_> {
+    type Item = Account;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.drain.next().map(|(client, account)| account.to_account(client))
+    }
+}
+
+/// Compares the entire serializable state, i.e. everything but anomaly rules, the screening
+/// provider, validators, middleware, and outcomes not taken yet. Amounts are compared with their
+/// scale, so `1.50` differs from `1.5`.
+impl PartialEq for PaymentsEngine {
+    fn eq(&self, other: &Self) -> bool {
+        self.state() == other.state()
+    }
+}
+
+impl fmt::Debug for PaymentsEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PaymentsEngine")
+            .field("state", &self.state())
+            .finish_non_exhaustive()
+    }
+}
+
+impl PaymentsEngine {
+    /// Returns the serializable state as JSON value, which does not depend on the order of maps.
+    fn state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("engine state is serializable")
+    }
+}
+
 /// Iterator over [Account]s of the [PaymentsEngine]
 pub struct AccountIter<'a> {
     iter: Iter<'a, u16, SparseAccount>,
@@ -194,15 +1137,52 @@ impl Iterator for AccountIter<'_> {
     type Item = Account;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.iter.next().map(|(client, account)| {
-            Account {
-                client: *client,
-                available: account.available,
-                held: account.held,
-                total: account.available + account.held,
-                locked: account.locked,
-            }
-        })
+        self.iter.next().map(|(client, account)| account.to_account(*client))
+    }
+}
+
+/// Moves the engine to a dedicated thread that executes the transactions sent to the returned
+/// channel in order.
+///
+/// At most `capacity` transactions and outcomes are buffered, so senders block while the engine
+/// falls behind. The outcomes must be consumed via the [EngineHandle] (e.g. on another thread than
+/// the sender) until all senders are dropped, otherwise the engine stalls.
+pub fn channel(mut engine: PaymentsEngine, capacity: usize)
+    -> (SyncSender<Transaction>, EngineHandle)
+{
+    let (sender, transactions) = mpsc::sync_channel::<Transaction>(capacity);
+    let (outcome_sender, outcomes) = mpsc::sync_channel(capacity);
+    let worker = thread::spawn(move || {
+        for transaction in transactions {
+            let _ = outcome_sender.send(engine.execute_with_outcome(transaction));
+        }
+        engine
+    });
+    (sender, EngineHandle { outcomes, worker })
+}
+
+/// Handle of an engine running on a dedicated thread, see [channel]
+///
+/// Iterating yields the [TransactionOutcome]s in order of execution until all senders are dropped.
+pub struct EngineHandle {
+    outcomes: Receiver<TransactionOutcome>,
+    worker: JoinHandle<PaymentsEngine>,
+}
+
+impl EngineHandle {
+    /// Discards all outcomes not yet consumed, waits until all senders are dropped and the
+    /// remaining transactions are executed, and returns the engine.
+    pub fn join(self) -> PaymentsEngine {
+        drop(self.outcomes);
+        self.worker.join().expect("engine thread panicked")
+    }
+}
+
+impl Iterator for EngineHandle {
+    type Item = TransactionOutcome;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.outcomes.recv().ok()
     }
 }
 
@@ -224,11 +1204,8 @@ mod tests {
         }, account);
 
         let deposit = engine.deposits.get(&11).unwrap();
-        assert_eq!(&Deposit {
-            client: 3,
-            amount: Decimal::new(23, 1),
-            disputed: false,
-        }, deposit);
+        assert_eq!((3, Decimal::new(23, 1), None),
+                   (deposit.client, deposit.amount(), deposit.dispute));
 
         engine.deposit(3, 12, Decimal::new(13, 2)).unwrap();
 
@@ -240,6 +1217,87 @@ mod tests {
         }, account);
     }
 
+    #[test]
+    fn with_capacity_reserves_space() {
+        let engine = PaymentsEngine::with_capacity(10, 1000);
+
+        assert!(engine.accounts.capacity() >= 10);
+        assert!(engine.deposits.capacity() >= 1000);
+        assert_eq!(0, engine.accounts().count());
+    }
+
+    #[test]
+    fn outcome_contains_balances_before_and_after() {
+        let mut engine = PaymentsEngine::new();
+        let deposit = |tx, amount| Transaction::deposit(1, tx, amount);
+
+        let outcome = engine.execute_with_outcome(deposit(1, Decimal::new(2, 0)));
+        assert!(outcome.status.is_ok());
+        assert_eq!(None, outcome.balance_before);
+        assert_eq!(engine.account(1), outcome.balance_after);
+
+        let outcome = engine.execute_with_outcome(Transaction {
+            transaction_type: TransactionType::Withdrawal,
+            ..deposit(2, Decimal::new(3, 0))
+        });
+        assert!(matches!(outcome.status, Err(PaymentError::InsufficientFunds { .. })));
+        assert_eq!((TransactionType::Withdrawal, 1, 2), (outcome.kind, outcome.client, outcome.tx));
+        assert_eq!(outcome.balance_before, outcome.balance_after);
+    }
+
+    #[test]
+    fn explain_lists_all_rejection_reasons() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        engine.deposit(1, 2, Decimal::new(3, 0)).unwrap();
+        engine.dispute(1, 2).unwrap();
+        engine.chargeback(1, 2).unwrap();
+        let transaction = |kind, tx, amount| Transaction::new(kind, 1, tx, amount);
+
+        assert_eq!(vec![
+            RejectionReason::LockedAccount,
+            RejectionReason::InsufficientFunds {
+                available: Decimal::new(2, 0),
+                amount: Decimal::new(5, 0),
+            },
+        ], engine.explain(&transaction(TransactionType::Withdrawal, 3, Some(Decimal::new(5, 0)))));
+        assert_eq!(vec![
+            RejectionReason::LockedAccount,
+            RejectionReason::MissingAmount,
+            RejectionReason::DuplicateTransaction,
+        ], engine.explain(&transaction(TransactionType::Deposit, 1, None)));
+        assert_eq!(vec![RejectionReason::LockedAccount, RejectionReason::NotDisputed],
+                   engine.explain(&transaction(TransactionType::Resolve, 1, None)));
+        let dispute = Transaction { client: 2, ..transaction(TransactionType::Dispute, 1, None) };
+        assert_eq!(vec![RejectionReason::UnknownClient, RejectionReason::UnknownTransaction],
+                   engine.explain(&dispute));
+    }
+
+    #[test]
+    fn refunds_are_limited_to_withdrawn_amount() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        engine.withdraw(1, 2, Decimal::new(6, 0)).unwrap();
+
+        engine.refund(1, 2, Decimal::new(4, 0)).unwrap();
+        assert!(matches!(engine.refund(1, 2, Decimal::new(3, 0)),
+            Err(PaymentError::RefundExceedsWithdrawal { tx: 2, .. })));
+        assert!(matches!(engine.refund(2, 2, Decimal::ONE),
+            Err(PaymentError::UnknownClient { .. })));
+        assert!(matches!(engine.refund(1, 1, Decimal::ONE),
+            Err(PaymentError::UnknownWithdrawal { tx: 1, .. })));
+        engine.refund(1, 2, Decimal::new(2, 0)).unwrap();
+        assert_eq!(Decimal::new(10, 0), engine.account(1).unwrap().available);
+    }
+
+    #[test]
+    #[should_panic(expected = "DuplicateTransaction")]
+    fn deposit_with_existing_transaction_id_fails() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        engine.deposit(2, 1, Decimal::new(3, 0)).unwrap();
+    }
+
     #[test]
     #[should_panic(expected = "LockedAccount")]
     fn deposit_on_locked_account_fails() {
@@ -250,6 +1308,20 @@ mod tests {
         engine.deposit(1, 2, Decimal::new(23, 1)).unwrap();
     }
 
+    #[test]
+    #[cfg(feature = "fixed-point")]
+    fn fixed_point_deposits_are_compact() {
+        assert_eq!(16, std::mem::size_of::<(u32, Deposit)>());
+    }
+
+    #[test]
+    #[cfg(feature = "fixed-point")]
+    #[should_panic(expected = "InvalidTransaction")]
+    fn deposit_with_unrepresentable_amount_fails() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(1, 5)).unwrap();
+    }
+
     #[test]
     fn withrawal_with_sufficient_funds_succeeds() {
         let mut engine = PaymentsEngine::new();
@@ -293,6 +1365,31 @@ mod tests {
         engine.dispute(1, 2).unwrap();
     }
 
+    #[test]
+    #[should_panic(expected = "UnknownTransaction")]
+    fn dispute_of_other_clients_transaction_fails() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(2, 0)).unwrap();
+        engine.dispute(2, 1).unwrap();
+    }
+
+    #[test]
+    fn dispute_operations_on_other_clients_deposits_leave_both_accounts_unchanged() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(3, 0)).unwrap();
+        engine.dispute(1, 1).unwrap();
+        let before = engine.accounts.clone();
+
+        for result in [engine.dispute(2, 1), engine.resolve(2, 1), engine.chargeback(2, 1)] {
+            let unknown = matches!(result, Err(PaymentError::UnknownTransaction { client: 2, .. }));
+            assert!(unknown, "{:?}", result);
+        }
+        assert_eq!(before, engine.accounts);
+        assert_eq!(Some(DisputeState::Opened), engine.dispute_status(1).map(|d| d.state));
+    }
+
     #[test]
     #[should_panic(expected = "InsufficientFunds")]
     fn dispute_with_insufficient_funds_fails() {
@@ -303,7 +1400,7 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "InvalidTransaction")]
+    #[should_panic(expected = "AlreadyDisputed")]
     fn double_dispute_fails() {
        let mut engine = PaymentsEngine::new();
         engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
@@ -312,6 +1409,23 @@ mod tests {
         engine.dispute(1, 1).unwrap();
     }
 
+    #[test]
+    fn repeated_dispute_holds_amount_once() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        engine.deposit(1, 2, Decimal::new(100, 0)).unwrap();
+        engine.dispute(1, 1).unwrap();
+        let err = engine.dispute(1, 1).unwrap_err();
+        assert_eq!(PaymentError::AlreadyDisputed { client: 1, tx: 1 }, err);
+        assert_eq!("already_disputed", err.code());
+        assert_eq!(Decimal::new(10, 0), engine.account(1).unwrap().held);
+
+        engine.resolve(1, 1).unwrap();
+        let account = engine.account(1).unwrap();
+        assert_eq!(Decimal::new(110, 0), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+    }
+
     #[test]
     fn resolving_dispute_succeeds() {
         let mut engine = PaymentsEngine::new();
@@ -326,6 +1440,35 @@ mod tests {
         }, engine.accounts.get(&1).unwrap())
     }
 
+    #[test]
+    fn dispute_status_follows_lifecycle() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        assert_eq!(None, engine.dispute_status(1));
+
+        let status = |state| Some(DisputeStatus { state, reason: Some(DisputeReason::Fraud) });
+        engine.dispute_with_reason(1, 1, Some(DisputeReason::Fraud)).unwrap();
+        assert_eq!(status(DisputeState::Opened), engine.dispute_status(1));
+        engine.review(1, 1).unwrap();
+        assert_eq!(status(DisputeState::UnderReview), engine.dispute_status(1));
+        assert!(engine.review(1, 1).is_err());
+        engine.chargeback(1, 1).unwrap();
+        assert_eq!(status(DisputeState::ChargedBack), engine.dispute_status(1));
+    }
+
+    #[test]
+    fn resolved_deposit_can_be_disputed_again() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        engine.dispute_with_reason(1, 1, Some(DisputeReason::Duplicate)).unwrap();
+        engine.resolve(1, 1).unwrap();
+        assert_eq!(Some(DisputeState::Resolved), engine.dispute_status(1).map(|d| d.state));
+
+        engine.dispute(1, 1).unwrap();
+        assert_eq!(Some(DisputeStatus { state: DisputeState::Opened, reason: None }),
+                   engine.dispute_status(1));
+    }
+
     #[test]
     #[should_panic(expected = "InvalidTransaction")]
     fn resolve_without_dispute_fails() {
@@ -349,6 +1492,80 @@ mod tests {
         engine.resolve(1, 2).unwrap();
     }
 
+    #[test]
+    fn chargeback_only_removes_charged_back_amount() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        engine.deposit(1, 2, Decimal::new(3, 0)).unwrap();
+        engine.dispute(1, 1).unwrap();
+        engine.dispute(1, 2).unwrap();
+        engine.chargeback(1, 1).unwrap();
+
+        assert_eq!(&SparseAccount {
+            available: Decimal::default(),
+            held: Decimal::new(3, 0),
+            locked: true,
+        }, engine.accounts.get(&1).unwrap())
+    }
+
+    #[test]
+    fn representment_reinstates_charged_back_funds() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        engine.deposit(1, 2, Decimal::new(3, 0)).unwrap();
+        engine.dispute(1, 1).unwrap();
+        engine.chargeback(1, 1).unwrap();
+
+        engine.represent(1, 1, true).unwrap();
+
+        assert_eq!(&SparseAccount {
+            available: Decimal::new(5, 0),
+            held: Decimal::default(),
+            locked: false,
+        }, engine.accounts.get(&1).unwrap());
+        assert_eq!(Some(DisputeState::Represented), engine.dispute_status(1).map(|d| d.state));
+        assert!(engine.represent(1, 1, true).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "InvalidTransaction")]
+    fn representment_of_transaction_without_chargeback_fails() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        engine.dispute(1, 1).unwrap();
+        engine.represent(1, 1, false).unwrap();
+    }
+
+    #[test]
+    fn locked_accounts_are_unlocked() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        assert!(matches!(engine.unlock(1), Err(PaymentError::InvalidTransaction(_))));
+        engine.dispute(1, 1).unwrap();
+        engine.chargeback(1, 1).unwrap();
+
+        engine.unlock(1).unwrap();
+
+        assert!(!engine.accounts.get(&1).unwrap().locked);
+        engine.deposit(1, 2, Decimal::new(3, 0)).unwrap();
+        assert!(matches!(engine.unlock(2), Err(PaymentError::UnknownClient { .. })));
+    }
+
+    #[test]
+    fn representment_fails_after_window() {
+        let config = EngineConfig { representment_window: Some(2), ..Default::default() };
+        let mut engine = PaymentsEngine::with_config(config);
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        engine.dispute(1, 1).unwrap();
+        engine.chargeback(1, 1).unwrap();
+        engine.deposit(2, 2, Decimal::new(2, 0)).unwrap();
+        engine.deposit(2, 3, Decimal::new(2, 0)).unwrap();
+
+        assert!(matches!(engine.represent(1, 1, true), Err(PaymentError::InvalidTransaction(_))));
+        assert!(engine.accounts.get(&1).unwrap().locked);
+        assert_eq!(Some(DisputeState::ChargedBack), engine.dispute_status(1).map(|d| d.state));
+    }
+
     #[test]
     #[should_panic(expected = "InvalidTransaction")]
     fn chargeback_for_undisputed_transaction_fails() {
@@ -357,6 +1574,119 @@ mod tests {
         engine.chargeback(1, 1).unwrap();
     }
 
+    #[test]
+    fn redelivered_transactions_return_original_outcome() {
+        let csv = "type,client,tx,amount,idempotency_key\n\
+                   deposit,1,1,5,msg-1\n\
+                   withdrawal,1,2,8,msg-2\n\
+                   deposit,1,1,5,msg-1\n\
+                   deposit,1,3,5,\n\
+                   withdrawal,1,2,8,msg-2\n";
+        let mut engine = PaymentsEngine::new();
+        let statuses: Vec<_> = crate::csv::read_transactions_from(csv.as_bytes())
+            .map(|transaction| engine.execute(transaction.unwrap()).map_err(|e| e.code()))
+            .collect();
+
+        let insufficient_funds = Err("insufficient_funds");
+        assert_eq!(vec![Ok(()), insufficient_funds, Ok(()), Ok(()), insufficient_funds], statuses);
+        assert_eq!(Decimal::new(10, 0), engine.account(1).unwrap().available);
+        assert_eq!(Some(&Ok(())), engine.idempotent_outcome("msg-1"));
+        assert_eq!(None, engine.idempotent_outcome("msg-3"));
+    }
+
+    #[test]
+    fn channel_executes_transactions_in_order() {
+        let (sender, mut handle) = channel(PaymentsEngine::new(), 1);
+        let producer = thread::spawn(move || {
+            for (transaction_type, tx, amount) in [
+                (TransactionType::Deposit, 1, 5),
+                (TransactionType::Withdrawal, 2, 10),
+                (TransactionType::Deposit, 3, 3),
+            ] {
+                let amount = Some(Decimal::new(amount, 0));
+                sender.send(Transaction::new(transaction_type, 1, tx, amount)).unwrap();
+            }
+        });
+
+        let statuses: Vec<_> = handle.by_ref().map(|outcome| outcome.status.is_ok()).collect();
+        producer.join().unwrap();
+        assert_eq!(vec![true, false, true], statuses);
+        assert_eq!(Decimal::new(8, 0), handle.join().account(1).unwrap().available);
+    }
+
+    #[test]
+    fn fork_does_not_affect_original() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        let mut fork = engine.fork();
+        fork.withdraw(1, 2, Decimal::new(3, 0)).unwrap();
+        fork.deposit(2, 3, Decimal::new(1, 0)).unwrap();
+
+        assert_eq!(Decimal::new(2, 0), fork.account(1).unwrap().available);
+        assert_eq!(Decimal::new(5, 0), engine.account(1).unwrap().available);
+        assert!(engine.account(2).is_none());
+        engine.dispute(1, 1).unwrap();
+        assert!(fork.dispute(1, 1).is_err());
+    }
+
+    #[test]
+    fn deposits_show_whether_they_can_be_disputed() {
+        let mut engine = PaymentsEngine::with_config(EngineConfig {
+            dispute_window: Some(3),
+            ..Default::default()
+        });
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        engine.deposit(1, 2, Decimal::new(2, 0)).unwrap();
+        engine.deposit(2, 3, Decimal::new(7, 0)).unwrap();
+        engine.dispute(2, 3).unwrap();
+
+        let mut deposits: Vec<_> = engine.deposits()
+            .map(|d| (d.tx, d.client, d.amount, d.disputed, d.disputable))
+            .collect();
+        deposits.sort();
+        assert_eq!(vec![
+            (1, 1, Decimal::new(5, 0), false, false),
+            (2, 1, Decimal::new(2, 0), false, true),
+            (3, 2, Decimal::new(7, 0), true, false),
+        ], deposits);
+        assert_eq!(2, engine.deposits_of(1).count());
+    }
+
+    #[test]
+    fn drained_engine_equals_fresh_one() {
+        let mut engine = PaymentsEngine::new();
+        engine.enable_ledger();
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(7, 0)).unwrap();
+        engine.dispute(2, 2).unwrap();
+
+        let mut accounts: Vec<_> = engine.drain_accounts().collect();
+        accounts.sort_by_key(|a| a.client);
+        assert_eq!(vec![1, 2], accounts.iter().map(|a| a.client).collect::<Vec<_>>());
+        let mut fresh = PaymentsEngine::new();
+        fresh.enable_ledger();
+        assert_eq!(fresh, engine);
+
+        engine.deposit(1, 1, Decimal::new(3, 0)).unwrap();
+        engine.reset();
+        assert_eq!(fresh, engine);
+    }
+
+    #[test]
+    fn clones_are_equal_until_they_diverge() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(7, 0)).unwrap();
+        let mut clone = engine.clone();
+        assert_eq!(engine, clone);
+
+        clone.withdraw(1, 3, Decimal::new(1, 0)).unwrap();
+        assert_ne!(engine, clone);
+        engine.withdraw(1, 3, Decimal::new(1, 0)).unwrap();
+        assert_eq!(engine, clone);
+        assert!(format!("{:?}", engine).starts_with("PaymentsEngine { state: "));
+    }
+
     #[test]
     #[should_panic(expected = "UnknownClient")]
     fn chargeback_for_unknown_client_fails() {
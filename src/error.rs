@@ -1,9 +1,11 @@
 //! Crate-specific error handling
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// Custom error variants for this crate
-#[derive(Error, Debug)]
+#[derive(Clone, Debug, Deserialize, Error, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub enum PaymentError {
     #[error("Account of client {client:?} is locked, cannot execute transaction {tx:?}")]
     LockedAccount {
@@ -18,6 +20,30 @@ pub enum PaymentError {
         available: Decimal,
         amount: Decimal,
     },
+    #[error("Client {client:?} exceeds the overdraft limit with transaction {tx:?} (available: \
+    {available:?}, limit: {limit:?}, necessary: {amount:?})")]
+    OverdraftExceeded {
+        client: u16,
+        tx: u32,
+        available: Decimal,
+        limit: Decimal,
+        amount: Decimal,
+    },
+    #[error("Transaction {tx:?} would leave client {client:?} with less than the minimum balance \
+    {min_balance:?}")]
+    BelowMinimumBalance {
+        client: u16,
+        tx: u32,
+        min_balance: Decimal,
+    },
+    #[error("Refund of withdrawal transaction {tx:?} of client {client:?} exceeds the refundable \
+    amount (refundable: {refundable:?}, refund: {amount:?})")]
+    RefundExceedsWithdrawal {
+        client: u16,
+        tx: u32,
+        refundable: Decimal,
+        amount: Decimal,
+    },
     #[error("{tx_type:?} refers to unknown client account {client:?}")]
     UnknownClient {
         client: u16,
@@ -29,8 +55,69 @@ pub enum PaymentError {
         tx: u32,
         tx_type: String,
     },
+    #[error("Refund refers to unknown withdrawal transaction {tx:?} of client {client:?}")]
+    UnknownWithdrawal {
+        client: u16,
+        tx: u32,
+    },
+    #[error("Deposit transaction {tx:?} of client {client:?} reuses an existing transaction ID")]
+    DuplicateTransaction {
+        client: u16,
+        tx: u32,
+    },
+    #[error("Deposit transaction {tx:?} of client {client:?} is already disputed, cannot be \
+    disputed twice")]
+    AlreadyDisputed {
+        client: u16,
+        tx: u32,
+    },
+    #[error("Withdrawal transaction {tx:?} of client {client:?} exceeds the velocity limit")]
+    VelocityLimitExceeded {
+        client: u16,
+        tx: u32,
+    },
+    #[error("Client {client:?} is blocked by screening, cannot execute transaction {tx:?}")]
+    ClientBlocked {
+        client: u16,
+        tx: u32,
+    },
+    #[error("Transaction {tx:?} of client {client:?} failed validation: {reason}")]
+    ValidationFailed {
+        client: u16,
+        tx: u32,
+        reason: String,
+    },
+    #[error("Transaction {tx:?} of client {client:?} refers to a deposit not seen yet, parked for \
+    retry")]
+    Deferred {
+        client: u16,
+        tx: u32,
+    },
     #[error("`0`")]
     InvalidTransaction(String),
 }
 
+impl PaymentError {
+    /// Returns a short machine-readable code of the error variant, e.g. `insufficient_funds`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PaymentError::LockedAccount { .. } => "locked_account",
+            PaymentError::InsufficientFunds { .. } => "insufficient_funds",
+            PaymentError::OverdraftExceeded { .. } => "overdraft_exceeded",
+            PaymentError::BelowMinimumBalance { .. } => "below_minimum_balance",
+            PaymentError::RefundExceedsWithdrawal { .. } => "refund_exceeds_withdrawal",
+            PaymentError::UnknownClient { .. } => "unknown_client",
+            PaymentError::UnknownTransaction { .. } => "unknown_transaction",
+            PaymentError::UnknownWithdrawal { .. } => "unknown_withdrawal",
+            PaymentError::DuplicateTransaction { .. } => "duplicate_transaction",
+            PaymentError::AlreadyDisputed { .. } => "already_disputed",
+            PaymentError::VelocityLimitExceeded { .. } => "velocity_limit_exceeded",
+            PaymentError::ClientBlocked { .. } => "client_blocked",
+            PaymentError::ValidationFailed { .. } => "validation_failed",
+            PaymentError::Deferred { .. } => "deferred",
+            PaymentError::InvalidTransaction(_) => "invalid_transaction",
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, PaymentError>;
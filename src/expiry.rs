@@ -0,0 +1,182 @@
+//! Expiry of disputes that stay open for too long
+//!
+//! Disputes with a timestamp are tracked while they are open. [PaymentsEngine::expire_disputes]
+//! resolves or charges back those opened more than [DisputeExpiry::max_age] before the given
+//! time, as if the corresponding transaction had been received with the memo [EXPIRY_MEMO].
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Map;
+use crate::models::{Transaction, TransactionOutcome, TransactionType};
+use crate::PaymentsEngine;
+
+/// Memo of the transactions executed for expired disputes
+pub const EXPIRY_MEMO: &str = "dispute expired";
+
+/// Source of the current time, in the unit of transaction timestamps
+pub trait TimeProvider {
+    /// Returns the current time.
+    fn now(&self) -> u64;
+}
+
+/// Wall clock in seconds since the Unix epoch
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl TimeProvider for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+    }
+}
+
+/// Action taken on expired disputes
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpiryPolicy {
+    /// Release the held funds to the client
+    Resolve,
+    /// Reverse the deposit and lock the account
+    Chargeback,
+}
+
+impl FromStr for ExpiryPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resolve" => Ok(Self::Resolve),
+            "chargeback" => Ok(Self::Chargeback),
+            _ => Err(format!("unknown expiry policy {:?}, expected resolve or chargeback", s)),
+        }
+    }
+}
+
+/// Maximum age of open disputes and the action taken on expired ones
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DisputeExpiry {
+    /// Maximum time a dispute may stay open, e.g. 2592000 for 30 days with timestamps in seconds
+    pub max_age: u64,
+    /// Action taken once a dispute is older
+    pub policy: ExpiryPolicy,
+}
+
+/// Timestamps of open disputes
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct OpenDisputes {
+    /// Disputes ordered by timestamp and transaction ID
+    by_time: BTreeSet<(u64, u32)>,
+    /// Timestamps of the disputes, by transaction ID
+    by_tx: Map<u32, u64>,
+}
+
+impl PaymentsEngine {
+    /// Replaces the dispute expiry of the engine's configuration.
+    pub fn set_dispute_expiry(&mut self, expiry: Option<DisputeExpiry>) {
+        self.config.dispute_expiry = expiry;
+    }
+
+    /// Expires all disputes that are older than the configured maximum age at time `now` (see
+    /// [crate::expiry]) and returns the executed transactions and their outcomes.
+    pub fn expire_disputes(&mut self, now: u64) -> Vec<(Transaction, TransactionOutcome)> {
+        let Some(DisputeExpiry { max_age, policy }) = self.config.dispute_expiry.clone() else {
+            return Vec::new();
+        };
+        let mut expired = Vec::new();
+        while let Some(&(opened, tx)) = self.open_disputes.by_time.first() {
+            if opened.saturating_add(max_age) >= now {
+                break;
+            }
+            self.open_disputes.by_time.pop_first();
+            self.open_disputes.by_tx.remove(&tx);
+            let Some(deposit) = self.deposits.get(&tx).filter(|d| d.is_disputed()) else {
+                continue;
+            };
+            let transaction = match policy {
+                ExpiryPolicy::Resolve => Transaction::resolve(deposit.client, tx),
+                ExpiryPolicy::Chargeback => Transaction::chargeback(deposit.client, tx),
+            }.with_timestamp(now).with_memo(EXPIRY_MEMO);
+            let outcome = self.execute_with_outcome(transaction.clone());
+            expired.push((transaction, outcome));
+        }
+        expired
+    }
+
+    /// Expires disputes like [PaymentsEngine::expire_disputes] at the time of the given clock.
+    pub fn expire_disputes_at<T: TimeProvider>(&mut self, clock: &T)
+        -> Vec<(Transaction, TransactionOutcome)>
+    {
+        self.expire_disputes(clock.now())
+    }
+
+    /// Tracks the timestamps of accepted disputes while they are open if disputes expire.
+    pub(crate) fn record_dispute_time(&mut self, transaction: &Transaction) {
+        let Transaction { transaction_type, tx, timestamp, .. } = *transaction;
+        let open = &mut self.open_disputes;
+        match transaction_type {
+            TransactionType::Dispute if self.config.dispute_expiry.is_some() => {
+                if let Some(timestamp) = timestamp {
+                    open.by_time.insert((timestamp, tx));
+                    open.by_tx.insert(tx, timestamp);
+                }
+            }
+            TransactionType::Resolve | TransactionType::Chargeback => {
+                if let Some(timestamp) = open.by_tx.remove(&tx) {
+                    open.by_time.remove(&(timestamp, tx));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    fn transaction(kind: TransactionType, tx: u32, timestamp: u64) -> Transaction {
+        let amount = (kind == TransactionType::Deposit).then(|| Decimal::new(5, 0));
+        Transaction::new(kind, 1, tx, amount).with_timestamp(timestamp)
+    }
+
+    #[test]
+    fn old_disputes_expire_per_policy() {
+        let mut engine = PaymentsEngine::new();
+        engine.set_dispute_expiry(Some(DisputeExpiry {
+            max_age: 100,
+            policy: ExpiryPolicy::Chargeback,
+        }));
+        for (kind, tx, timestamp) in [
+            (TransactionType::Deposit, 1, 0),
+            (TransactionType::Deposit, 2, 0),
+            (TransactionType::Deposit, 3, 0),
+            (TransactionType::Dispute, 1, 10),
+            (TransactionType::Dispute, 2, 20),
+            (TransactionType::Resolve, 2, 30),
+            (TransactionType::Dispute, 3, 50),
+        ] {
+            engine.execute(transaction(kind, tx, timestamp)).unwrap();
+        }
+
+        assert!(engine.expire_disputes(110).is_empty());
+        let expired = engine.expire_disputes(140);
+        assert_eq!(1, expired.len());
+        let (transaction, outcome) = &expired[0];
+        assert_eq!(TransactionType::Chargeback, transaction.transaction_type);
+        assert_eq!(1, transaction.tx);
+        assert_eq!(Some(EXPIRY_MEMO), transaction.memo.as_deref());
+        assert!(outcome.status.is_ok());
+        let account = engine.account(1).unwrap();
+        assert_eq!((Decimal::new(5, 0), Decimal::new(5, 0), true),
+                   (account.available, account.held, account.locked));
+    }
+
+    #[test]
+    fn system_clock_is_after_2020() {
+        assert!(SystemClock.now() > 1_577_836_800);
+    }
+}
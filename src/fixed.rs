@@ -0,0 +1,127 @@
+//! Compact fixed-point representation of amounts
+//!
+//! Only available with the `fixed-point` feature, which stores deposit amounts as [FixedPoint]
+//! instead of [Decimal] to halve their memory footprint. Amounts are converted at the boundary,
+//! so the public interface still uses [Decimal].
+use std::fmt;
+use std::str::FromStr;
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize, Serializer};
+use thiserror::Error;
+
+/// Number of implied decimal places
+pub const SCALE: u32 = 4;
+
+/// Number of minor units in one unit
+const ONE: i64 = 10_i64.pow(SCALE);
+
+/// Amount in minor units (1/10000) stored in an `i64`
+///
+/// The alignment is reduced to 4 bytes so that the amount packs tightly next to the `u32`
+/// transaction IDs and `u16` client IDs it is stored with.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[serde(transparent)]
+#[repr(C, packed(4))]
+pub struct FixedPoint(i64);
+
+/// Reasons why an amount cannot be represented as [FixedPoint]
+#[derive(Error, Debug)]
+pub enum AmountError {
+    #[error("amount {0} has more than {SCALE} decimal places")]
+    TooPrecise(Decimal),
+    #[error("amount {0} is out of range")]
+    OutOfRange(Decimal),
+    #[error("invalid amount: {0}")]
+    Invalid(#[from] rust_decimal::Error),
+}
+
+impl FixedPoint {
+    /// Creates an amount from the given number of minor units.
+    pub const fn from_minor_units(units: i64) -> Self {
+        Self(units)
+    }
+
+    /// Returns the number of minor units.
+    pub const fn minor_units(self) -> i64 {
+        self.0
+    }
+
+    /// Adds two amounts, returning `None` on overflow.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    /// Subtracts an amount, returning `None` on overflow.
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+}
+
+impl TryFrom<Decimal> for FixedPoint {
+    type Error = AmountError;
+
+    fn try_from(amount: Decimal) -> Result<Self, Self::Error> {
+        let units = amount.checked_mul(Decimal::from(ONE)).ok_or(AmountError::OutOfRange(amount))?;
+        if !units.fract().is_zero() {
+            return Err(AmountError::TooPrecise(amount));
+        }
+        units.to_i64().map(Self).ok_or(AmountError::OutOfRange(amount))
+    }
+}
+
+impl From<FixedPoint> for Decimal {
+    fn from(amount: FixedPoint) -> Self {
+        Decimal::new(amount.minor_units(), SCALE).normalize()
+    }
+}
+
+impl Serialize for FixedPoint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.minor_units().serialize(serializer)
+    }
+}
+
+impl FromStr for FixedPoint {
+    type Err = AmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(Decimal::from_str(s)?)
+    }
+}
+
+impl fmt::Display for FixedPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Decimal::from(*self).fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimals_round_trip() {
+        for amount in [Decimal::new(0, 0), Decimal::new(15, 1), Decimal::new(-12345, 4)] {
+            assert_eq!(amount, Decimal::from(FixedPoint::try_from(amount).unwrap()));
+        }
+        assert_eq!("2", FixedPoint::from_minor_units(20_000).to_string());
+    }
+
+    #[test]
+    fn unrepresentable_amounts_are_rejected() {
+        let too_precise = FixedPoint::try_from(Decimal::new(1, 5));
+        assert!(matches!(too_precise, Err(AmountError::TooPrecise(_))));
+        assert!(matches!(FixedPoint::try_from(Decimal::MAX), Err(AmountError::OutOfRange(_))));
+        assert!(matches!("1.5x".parse::<FixedPoint>(), Err(AmountError::Invalid(_))));
+    }
+
+    #[test]
+    fn arithmetic_is_overflow_checked() {
+        let max = FixedPoint::from_minor_units(i64::MAX);
+        let one = FixedPoint::from_minor_units(ONE);
+        assert_eq!(None, max.checked_add(one));
+        assert_eq!(Some(FixedPoint::from_minor_units(i64::MAX - ONE)), max.checked_sub(one));
+    }
+}
@@ -0,0 +1,105 @@
+//! Following continuously appended input files, similar to `tail -f`
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use csv::Error;
+
+use crate::csv::read_transactions_skipping;
+use crate::models::Transaction;
+
+/// Default time to wait before checking for new data after reaching the end of the file
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Reader that never reaches the end of a file but waits for more data to be appended instead
+pub struct Follow<R> {
+    inner: R,
+    poll_interval: Duration,
+}
+
+impl<R: Read> Follow<R> {
+    /// Creates new [Follow] reader that polls for new data every [DEFAULT_POLL_INTERVAL].
+    pub fn new(inner: R) -> Self {
+        Self::with_poll_interval(inner, DEFAULT_POLL_INTERVAL)
+    }
+
+    /// Creates new [Follow] reader that polls for new data with the given interval.
+    pub fn with_poll_interval(inner: R, poll_interval: Duration) -> Self {
+        Self { inner, poll_interval }
+    }
+}
+
+impl<R: Read> Read for Follow<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.inner.read(buf)? {
+                0 => thread::sleep(self.poll_interval),
+                n => return Ok(n),
+            }
+        }
+    }
+}
+
+/// Follows the CSV file at the given path on a background thread and returns a receiver for
+/// [Transaction]s (or CSV errors) as they are appended, skipping the specified number of rows.
+///
+/// Partially written rows are only parsed once they are complete. The receiver disconnects only
+/// if reading the file fails.
+pub fn follow_transactions<P>(path: P, skip_rows: u64)
+    -> io::Result<Receiver<Result<Transaction, Error>>>
+    where P: AsRef<Path>
+{
+    let file = File::open(path)?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let rows = match read_transactions_skipping(Follow::new(file), skip_rows) {
+            Ok(rows) => rows,
+            Err(e) => {
+                let _ = sender.send(Err(e));
+                return;
+            }
+        };
+        for row in rows {
+            let is_io_error = matches!(&row, Err(e) if e.is_io_error());
+            if sender.send(row).is_err() || is_io_error {
+                break;
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn appended_rows_are_received() {
+        let path = std::env::temp_dir()
+            .join(format!("toy-payments-engine-follow-{}.csv", std::process::id()));
+        std::fs::write(&path, "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+
+        let receiver = follow_transactions(&path, 0).unwrap();
+        assert_eq!(1, receiver.recv().unwrap().unwrap().tx);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"deposit,1,2,").unwrap();
+        file.flush().unwrap();
+        thread::sleep(2 * DEFAULT_POLL_INTERVAL);
+        file.write_all(b"2.0\n").unwrap();
+        let transaction = receiver.recv().unwrap().unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(2, transaction.tx);
+        assert_eq!(Some(rust_decimal::Decimal::new(20, 1)), transaction.amount);
+    }
+}
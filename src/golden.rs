@@ -0,0 +1,147 @@
+//! Golden-file regression tests
+//!
+//! A fixture is a directory with an input file `input.csv`, the resulting account report
+//! `accounts.csv`, and the hex-encoded state digest `digest.txt`. [record] creates a fixture from
+//! an input file (see the `record` subcommand) and [verify] replays it with the current engine,
+//! failing on any difference. Changed dispute semantics thus show up as golden diffs; if a change
+//! is intended, the affected fixtures are recorded again.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::csv::{read_accounts, read_transactions_from, write_accounts};
+use crate::digest::to_hex;
+use crate::pipeline::{process_transactions, Ignore};
+use crate::reconcile::{compare, ReconciliationReport};
+use crate::{Account, PaymentsEngine};
+
+/// Name of the input file of a fixture
+pub const INPUT: &str = "input.csv";
+/// Name of the account report of a fixture
+pub const ACCOUNTS: &str = "accounts.csv";
+/// Name of the state digest of a fixture
+pub const DIGEST: &str = "digest.txt";
+
+/// Result of processing an input with a default engine
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Golden {
+    /// Accounts in order of client ID
+    pub accounts: Vec<Account>,
+    /// Hex-encoded state digest, see [PaymentsEngine::state_digest]
+    pub digest: String,
+}
+
+impl Golden {
+    /// Processes the CSV input with a default engine, ignoring invalid and rejected rows.
+    pub fn compute(input: &[u8]) -> Self {
+        let mut engine = PaymentsEngine::new();
+        process_transactions(read_transactions_from(input), &mut engine, &mut Ignore, |_, _| {});
+        let mut accounts: Vec<_> = engine.accounts().collect();
+        accounts.sort_by_key(|a| a.client);
+        Self { accounts, digest: to_hex(&engine.state_digest()) }
+    }
+
+    /// Loads the recorded result of the fixture in the given directory.
+    pub fn load(fixture: &Path) -> io::Result<Self> {
+        let accounts = read_accounts(fs::File::open(fixture.join(ACCOUNTS))?)
+            .collect::<Result<_, _>>()?;
+        let digest = fs::read_to_string(fixture.join(DIGEST))?.trim().to_string();
+        Ok(Self { accounts, digest })
+    }
+
+    /// Saves the result into the fixture in the given directory.
+    pub fn save(&self, fixture: &Path) -> io::Result<()> {
+        write_accounts(fs::File::create(fixture.join(ACCOUNTS))?, self.accounts.iter().cloned())?;
+        fs::write(fixture.join(DIGEST), format!("{}\n", self.digest))
+    }
+}
+
+/// Failure to verify a fixture
+#[derive(Debug, Error)]
+pub enum GoldenError {
+    #[error("Could not read fixture {0:?}: {1}")]
+    Io(PathBuf, #[source] io::Error),
+    #[error("Fixture {fixture:?} changed: {} accounts differ, digest {actual_digest} instead of \
+             {expected_digest}", .accounts.mismatches.len())]
+    Changed {
+        fixture: PathBuf,
+        /// Differences of the replayed accounts (actual) to the recorded ones (expected)
+        accounts: ReconciliationReport,
+        expected_digest: String,
+        actual_digest: String,
+    },
+}
+
+/// Records the given input as fixture `name` in the fixtures directory and returns the path of the
+/// fixture. An existing fixture of the same name is overwritten.
+pub fn record(input: &Path, fixtures: &Path, name: &str) -> io::Result<PathBuf> {
+    let content = fs::read(input)?;
+    let fixture = fixtures.join(name);
+    fs::create_dir_all(&fixture)?;
+    fs::write(fixture.join(INPUT), &content)?;
+    Golden::compute(&content).save(&fixture)?;
+    Ok(fixture)
+}
+
+/// Replays the input of the fixture in the given directory and compares the result with the
+/// recorded one.
+pub fn verify(fixture: &Path) -> Result<(), GoldenError> {
+    let io_error = |e| GoldenError::Io(fixture.to_path_buf(), e);
+    let expected = Golden::load(fixture).map_err(io_error)?;
+    let actual = Golden::compute(&fs::read(fixture.join(INPUT)).map_err(io_error)?);
+    if expected == actual {
+        return Ok(());
+    }
+    Err(GoldenError::Changed {
+        fixture: fixture.to_path_buf(),
+        accounts: compare(expected.accounts, actual.accounts),
+        expected_digest: expected.digest,
+        actual_digest: actual.digest,
+    })
+}
+
+/// Returns the fixtures in the given directory, i.e. its subdirectories with an input file, in
+/// order of their names.
+pub fn fixtures(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut fixtures = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.join(INPUT).is_file() {
+            fixtures.push(path);
+        }
+    }
+    fixtures.sort();
+    Ok(fixtures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_fixture_is_verified_until_it_changes() {
+        let dir = std::env::temp_dir()
+            .join(format!("toy-payments-engine-golden-{}", std::process::id()));
+        let input = dir.join("disputes.csv");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&input, "type,client,tx,amount\ndeposit,1,1,5\ndeposit,2,2,3\ndispute,1,1,\n")
+            .unwrap();
+
+        let fixture = record(&input, &dir, "disputes").unwrap();
+        let verified = verify(&fixture);
+        assert_eq!(vec![fixture.clone()], fixtures(&dir).unwrap());
+        fs::write(fixture.join(INPUT), "type,client,tx,amount\ndeposit,1,1,5\ndeposit,2,2,3\n")
+            .unwrap();
+        let changed = verify(&fixture);
+        fs::remove_dir_all(&dir).unwrap();
+
+        verified.unwrap();
+        let Err(GoldenError::Changed { accounts, .. }) = changed else {
+            panic!("fixture should have changed: {:?}", changed);
+        };
+        assert_eq!(1, accounts.mismatches.len());
+        assert_eq!(1, accounts.mismatches[0].client);
+    }
+}
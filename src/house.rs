@@ -0,0 +1,117 @@
+//! Internal house accounts collecting funds removed from clients
+//!
+//! Fees, charged back deposits, and swept dust leave the client accounts but remain accounted for
+//! in the house accounts of the engine. Representments take charged back funds out of the
+//! chargeback write-off again.
+use std::fmt;
+use std::io::Write;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::PaymentsEngine;
+
+/// Internal account of the engine
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HouseAccount {
+    /// Fees charged to clients, see [PaymentsEngine::charge_fee]
+    FeeIncome,
+    /// Deposits charged back
+    ChargebackWriteOff,
+    /// Dust balances swept from client accounts, see [crate::dust]
+    Dust,
+}
+
+impl HouseAccount {
+    /// All house accounts in report order
+    pub const ALL: [HouseAccount; 3] =
+        [HouseAccount::FeeIncome, HouseAccount::ChargebackWriteOff, HouseAccount::Dust];
+}
+
+impl fmt::Display for HouseAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HouseAccount::FeeIncome => "fee-income",
+            HouseAccount::ChargebackWriteOff => "chargeback-write-off",
+            HouseAccount::Dust => "dust",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Row of the account report for a house account, named in the `client` column
+#[derive(Serialize)]
+struct HouseAccountRow {
+    client: String,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// Writes the house accounts as CSV rows without header in the layout of the account report, so
+/// they can be appended to it.
+pub fn write_house_accounts<W, I>(writer: W, accounts: I) -> Result<(), csv::Error>
+    where W: Write,
+          I: IntoIterator<Item=(HouseAccount, Decimal)>
+{
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+    for (account, balance) in accounts {
+        writer.serialize(HouseAccountRow {
+            client: account.to_string(),
+            available: balance,
+            held: Decimal::ZERO,
+            total: balance,
+            locked: false,
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+impl PaymentsEngine {
+    /// Returns the balance of the house account.
+    pub fn house_balance(&self, account: HouseAccount) -> Decimal {
+        self.house.get(&account).copied().unwrap_or_default()
+    }
+
+    /// Returns iterator over all house accounts and their balances, see [HouseAccount::ALL].
+    pub fn house_accounts(&self) -> impl Iterator<Item=(HouseAccount, Decimal)> + '_ {
+        HouseAccount::ALL.into_iter().map(|account| (account, self.house_balance(account)))
+    }
+
+    /// Adds the amount (negative to take funds out) to the house account.
+    pub(crate) fn credit_house(&mut self, account: HouseAccount, amount: Decimal) {
+        *self.house.entry(account).or_default() += amount;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn funds_removed_from_clients_are_kept_in_house_accounts() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(5, 0)).unwrap();
+        engine.charge_fee(1, 3, Decimal::ONE).unwrap();
+        engine.dispute(2, 2).unwrap();
+        engine.chargeback(2, 2).unwrap();
+
+        let client_total: Decimal = engine.accounts().map(|a| a.total).sum();
+        let house_total: Decimal = engine.house_accounts().map(|(_, balance)| balance).sum();
+        assert_eq!(Decimal::new(15, 0), client_total + house_total);
+        assert_eq!(Decimal::new(5, 0), engine.house_balance(HouseAccount::ChargebackWriteOff));
+
+        let mut report = Vec::new();
+        write_house_accounts(&mut report, engine.house_accounts()).unwrap();
+        assert_eq!("fee-income,1,0,1,false\n\
+                    chargeback-write-off,5,0,5,false\n\
+                    dust,0,0,0,false\n", String::from_utf8(report).unwrap());
+
+        engine.represent(2, 2, true).unwrap();
+        assert_eq!(Decimal::ZERO, engine.house_balance(HouseAccount::ChargebackWriteOff));
+    }
+}
@@ -0,0 +1,241 @@
+//! Format-agnostic sources of transactions and sinks of accounts
+//!
+//! [crate::pipeline::process_source] processes any [TransactionSource], e.g. CSV
+//! ([crate::csv::Transactions]), NDJSON ([JsonTransactions]), or a custom one such as a database
+//! cursor. Accounts are written to any [AccountSink], e.g. [CsvAccounts] or [JsonAccounts].
+use std::io::{self, BufRead, BufReader, Read, StdinLock, Write};
+
+use csv::{Error, Writer};
+
+use crate::csv::{read_transactions_from, Transactions};
+use crate::models::{Account, Transaction};
+
+/// Source of transactions, one per row
+pub trait TransactionSource {
+    /// Returns the next transaction, an error if the row could not be read, or `None` at the end.
+    fn next_transaction(&mut self) -> Option<Result<Transaction, Error>>;
+
+    /// Returns an iterator over the transactions of the source.
+    fn rows(self) -> Rows<Self>
+        where Self: Sized
+    {
+        Rows(self)
+    }
+}
+
+impl<S: TransactionSource + ?Sized> TransactionSource for Box<S> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, Error>> {
+        (**self).next_transaction()
+    }
+}
+
+impl<R: Read> TransactionSource for Transactions<R> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, Error>> {
+        self.next()
+    }
+}
+
+/// Iterator over the transactions of a [TransactionSource]
+pub struct Rows<S>(S);
+
+impl<S: TransactionSource> Iterator for Rows<S> {
+    type Item = Result<Transaction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_transaction()
+    }
+}
+
+/// Returns a source of CSV transactions read from stdin.
+pub fn stdin() -> Transactions<StdinLock<'static>> {
+    read_transactions_from(io::stdin().lock())
+}
+
+/// Source of transactions read from JSON objects, one per line (NDJSON); blank lines are skipped
+pub struct JsonTransactions<R> {
+    lines: io::Lines<R>,
+    line: u64,
+}
+
+impl<R: Read> JsonTransactions<BufReader<R>> {
+    /// Reads transactions from the given reader.
+    pub fn new(reader: R) -> Self {
+        Self::from_buf_read(BufReader::new(reader))
+    }
+}
+
+impl<R: BufRead> JsonTransactions<R> {
+    /// Reads transactions from the given buffered reader, e.g. [io::stdin].
+    pub fn from_buf_read(reader: R) -> Self {
+        Self { lines: reader.lines(), line: 0 }
+    }
+}
+
+impl<R: BufRead> TransactionSource for JsonTransactions<R> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, Error>> {
+        loop {
+            self.line += 1;
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(Error::from(e))),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(serde_json::from_str(&line).map_err(|e| {
+                let message = format!("line {}: {}", self.line, e);
+                Error::from(io::Error::new(io::ErrorKind::InvalidData, message))
+            }));
+        }
+    }
+}
+
+/// Writes the transactions as JSON objects, one per line, in the format read by
+/// [JsonTransactions].
+pub fn write_transactions_ndjson<'a, W, I>(mut writer: W, transactions: I) -> io::Result<()>
+    where W: Write,
+          I: IntoIterator<Item=&'a Transaction>
+{
+    for transaction in transactions {
+        serde_json::to_writer(&mut writer, transaction)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Sink of accounts, e.g. for the final report
+pub trait AccountSink {
+    /// Writes a single account.
+    fn write_account(&mut self, account: &Account) -> Result<(), Error>;
+
+    /// Completes the output after the last account, e.g. flushes buffers.
+    fn finish(&mut self) -> Result<(), Error>;
+
+    /// Writes all accounts and completes the output.
+    fn write_all<I>(&mut self, accounts: I) -> Result<(), Error>
+        where I: IntoIterator<Item=Account>,
+              Self: Sized
+    {
+        for account in accounts {
+            self.write_account(&account)?;
+        }
+        self.finish()
+    }
+}
+
+/// Writes accounts as CSV with header, like [crate::csv::write_accounts]
+pub struct CsvAccounts<W: Write> {
+    writer: Writer<W>,
+}
+
+impl<W: Write> CsvAccounts<W> {
+    /// Writes accounts to the given writer.
+    pub fn new(writer: W) -> Self {
+        Self { writer: Writer::from_writer(writer) }
+    }
+}
+
+impl CsvAccounts<io::Stdout> {
+    /// Writes accounts to stdout.
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write> AccountSink for CsvAccounts<W> {
+    fn write_account(&mut self, account: &Account) -> Result<(), Error> {
+        self.writer.serialize(account)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Writes accounts as JSON array, the format read by the `diff` subcommand
+pub struct JsonAccounts<W> {
+    writer: W,
+    written: usize,
+}
+
+impl<W: Write> JsonAccounts<W> {
+    /// Writes accounts to the given writer.
+    pub fn new(writer: W) -> Self {
+        Self { writer, written: 0 }
+    }
+}
+
+impl<W: Write> AccountSink for JsonAccounts<W> {
+    fn write_account(&mut self, account: &Account) -> Result<(), Error> {
+        self.writer.write_all(if self.written == 0 { b"[" } else { b"," })?;
+        serde_json::to_writer(&mut self.writer, account).map_err(io::Error::from)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.writer.write_all(if self.written == 0 { b"[]\n" } else { b"]\n" })?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::pipeline::{process_source, Ignore};
+    use crate::PaymentsEngine;
+
+    fn accounts<S: TransactionSource>(source: S) -> Vec<Account> {
+        let mut engine = PaymentsEngine::new();
+        process_source(source, &mut engine, &mut Ignore, |_, _| {});
+        engine.accounts().collect()
+    }
+
+    #[test]
+    fn csv_and_json_sources_are_equivalent() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,1.5\n";
+        let json = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"5\"}\n\n\
+                    {\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \"amount\": \"1.5\"}\n";
+        let expected = accounts(read_transactions_from(csv.as_bytes()));
+        assert_eq!(Decimal::new(35, 1), expected[0].available);
+        assert_eq!(expected, accounts(JsonTransactions::new(json.as_bytes())));
+    }
+
+    #[test]
+    fn invalid_json_line_is_reported_with_line_number() {
+        let json = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"5\"}\n{\n";
+        let rows: Vec<_> = JsonTransactions::new(json.as_bytes()).rows().collect();
+        assert!(rows[0].is_ok());
+        assert!(rows[1].as_ref().unwrap_err().to_string().starts_with("line 2: "));
+    }
+
+    #[test]
+    fn written_json_transactions_are_read_back() {
+        let csv = "type,client,tx,amount,tags\ndeposit,1,1,5.5,a;b\nresolve,1,1,,\n";
+        let transactions: Vec<_> = read_transactions_from(csv.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        let mut output = Vec::new();
+        write_transactions_ndjson(&mut output, &transactions).unwrap();
+        let read: Vec<_> = JsonTransactions::new(&output[..]).rows().map(Result::unwrap).collect();
+        assert_eq!(transactions, read);
+    }
+
+    #[test]
+    fn json_accounts_round_trip() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(7, 0)).unwrap();
+        let mut output = Vec::new();
+        JsonAccounts::new(&mut output).write_all(engine.accounts()).unwrap();
+        let accounts: Vec<Account> = serde_json::from_slice(&output).unwrap();
+        assert_eq!(engine.accounts().collect::<Vec<_>>(), accounts);
+
+        let mut output = Vec::new();
+        CsvAccounts::new(&mut output).write_all(engine.accounts()).unwrap();
+        let accounts: Vec<_> = crate::csv::read_accounts(&output[..]).map(Result::unwrap).collect();
+        assert_eq!(engine.accounts().collect::<Vec<_>>(), accounts);
+    }
+}
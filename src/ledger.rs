@@ -0,0 +1,198 @@
+//! Double-entry ledger mirroring the operations of the engine
+//!
+//! Once enabled with [PaymentsEngine::enable_ledger], every successful operation posts balanced
+//! journal entries, each debiting one ledger account and crediting another by the same amount.
+//! Client funds are liabilities (credit balances) backed by the cash account. Chargebacks are
+//! booked through the chargeback loss account, which is recovered from the client's held funds.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::PaymentsEngine;
+
+/// Account of the ledger
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum LedgerAccount {
+    /// Funds held by the payment provider
+    Cash,
+    /// Fees earned
+    Fees,
+    /// Funds lost to chargebacks
+    ChargebackLoss,
+    /// Dust balances swept from client accounts, see [crate::dust]
+    Dust,
+    /// Counterpart of administrative adjustments, see [crate::adjust]
+    Adjustments,
+    /// Available funds of a client
+    ClientAvailable(u16),
+    /// Held funds of a client
+    ClientHeld(u16),
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAccount::Cash => f.write_str("cash"),
+            LedgerAccount::Fees => f.write_str("fees"),
+            LedgerAccount::ChargebackLoss => f.write_str("chargeback-loss"),
+            LedgerAccount::Dust => f.write_str("dust"),
+            LedgerAccount::Adjustments => f.write_str("adjustments"),
+            LedgerAccount::ClientAvailable(client) => write!(f, "client:{}:available", client),
+            LedgerAccount::ClientHeld(client) => write!(f, "client:{}:held", client),
+        }
+    }
+}
+
+impl FromStr for LedgerAccount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let account = match s {
+            "cash" => LedgerAccount::Cash,
+            "fees" => LedgerAccount::Fees,
+            "chargeback-loss" => LedgerAccount::ChargebackLoss,
+            "dust" => LedgerAccount::Dust,
+            "adjustments" => LedgerAccount::Adjustments,
+            _ => {
+                let client = |id: &str| id.parse().map_err(|_| format!("unknown account {:?}", s));
+                match s.strip_prefix("client:").and_then(|rest| rest.rsplit_once(':')) {
+                    Some((id, "available")) => LedgerAccount::ClientAvailable(client(id)?),
+                    Some((id, "held")) => LedgerAccount::ClientHeld(client(id)?),
+                    _ => return Err(format!("unknown account {:?}", s)),
+                }
+            }
+        };
+        Ok(account)
+    }
+}
+
+fn serialize_account<S: Serializer>(account: &LedgerAccount, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(account)
+}
+
+fn deserialize_account<'de, D: Deserializer<'de>>(d: D) -> Result<LedgerAccount, D::Error> {
+    String::deserialize(d)?.parse().map_err(D::Error::custom)
+}
+
+/// Journal entry moving an amount from the credited to the debited account
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct JournalEntry {
+    /// Sequence number of the engine operation that posted the entry
+    pub sequence: u64,
+    /// Transaction ID of the operation
+    pub tx: u32,
+    #[serde(serialize_with = "serialize_account", deserialize_with = "deserialize_account")]
+    pub debit: LedgerAccount,
+    #[serde(serialize_with = "serialize_account", deserialize_with = "deserialize_account")]
+    pub credit: LedgerAccount,
+    pub amount: Decimal,
+}
+
+/// Journal of all entries posted so far
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Ledger {
+    journal: Vec<JournalEntry>,
+}
+
+impl Ledger {
+    /// Posts an entry debiting and crediting the given accounts.
+    pub fn post(
+        &mut self,
+        sequence: u64,
+        tx: u32,
+        debit: LedgerAccount,
+        credit: LedgerAccount,
+        amount: Decimal,
+    ) {
+        self.journal.push(JournalEntry { sequence, tx, debit, credit, amount });
+    }
+
+    /// Returns all entries in the order they were posted.
+    pub fn journal(&self) -> &[JournalEntry] {
+        &self.journal
+    }
+
+    /// Returns the balance (debits minus credits) of every account that was posted to.
+    ///
+    /// As every entry is balanced, the balances always sum to zero.
+    pub fn trial_balance(&self) -> BTreeMap<LedgerAccount, Decimal> {
+        let mut balances = BTreeMap::new();
+        for entry in &self.journal {
+            *balances.entry(entry.debit).or_default() += entry.amount;
+            *balances.entry(entry.credit).or_default() -= entry.amount;
+        }
+        balances
+    }
+
+    /// Writes the journal as CSV.
+    pub fn write_journal<W: Write>(&self, writer: W) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for entry in &self.journal {
+            writer.serialize(entry)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl PaymentsEngine {
+    /// Starts posting journal entries for all subsequent operations.
+    pub fn enable_ledger(&mut self) {
+        self.ledger.get_or_insert_with(Ledger::default);
+    }
+
+    /// Returns the ledger if it is enabled.
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trial_balance_sums_to_zero_and_mirrors_accounts() {
+        let mut engine = PaymentsEngine::new();
+        engine.enable_ledger();
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(4, 0)).unwrap();
+        engine.withdraw(1, 3, Decimal::new(3, 0)).unwrap();
+        engine.dispute(2, 2).unwrap();
+        engine.chargeback(2, 2).unwrap();
+        engine.deposit(1, 4, Decimal::new(1, 0)).unwrap();
+        engine.dispute(1, 4).unwrap();
+        assert!(engine.withdraw(1, 5, Decimal::new(100, 0)).is_err());
+
+        let ledger = engine.ledger().unwrap();
+        let balances = ledger.trial_balance();
+        assert_eq!(Decimal::ZERO, balances.values().sum::<Decimal>());
+        assert_eq!(Decimal::new(8, 0), balances[&LedgerAccount::Cash]);
+        assert_eq!(Decimal::ZERO, balances[&LedgerAccount::ChargebackLoss]);
+        for account in engine.accounts() {
+            let client = account.client;
+            assert_eq!(-account.available, balances[&LedgerAccount::ClientAvailable(client)]);
+            let held = balances.get(&LedgerAccount::ClientHeld(client)).copied();
+            assert_eq!(-account.held, held.unwrap_or_default());
+        }
+
+        let mut csv = Vec::new();
+        ledger.write_journal(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let header = "sequence,tx,debit,credit,amount\n";
+        assert!(csv.starts_with(&format!("{}1,1,cash,client:1:available,10\n", header)));
+    }
+
+    #[test]
+    fn account_names_are_parsed() {
+        for account in [LedgerAccount::ChargebackLoss, LedgerAccount::ClientHeld(7)] {
+            assert_eq!(Ok(account), account.to_string().parse());
+        }
+        assert!("client:x:held".parse::<LedgerAccount>().is_err());
+    }
+}
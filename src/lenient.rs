@@ -0,0 +1,271 @@
+//! Lenient parsing of malformed CSV rows
+//!
+//! With [crate::csv::Transactions::lenient], rows are normalized before they are deserialized:
+//! - cells beyond the header are dropped and missing trailing cells are treated as blank,
+//! - values of the `type` column are parsed with [TransactionType::parse_lenient], i.e. regardless
+//!   of case and accepting aliases such as `withdraw` or `charge-back`.
+//!
+//! Rows that still cannot be deserialized fail with a [FieldError] naming the column of the
+//! offending cell, see [field_error].
+//!
+//! Independently, [crate::csv::Transactions::tolerant_amounts] parses the `amount` column with
+//! [parse_amount], which also accepts thousands separators and scientific notation.
+use std::error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use csv::{ByteRecord, Error, ErrorKind};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+
+use crate::models::{DisputeReason, TransactionType};
+
+/// Error of a single cell of a row that could not be parsed in lenient mode
+#[derive(Debug)]
+pub struct FieldError {
+    /// Line of the row in the input
+    pub line: Option<u64>,
+    /// Name of the column
+    pub column: String,
+    /// Value of the cell
+    pub value: String,
+    /// Description of the error
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}, ", line)?;
+        }
+        write!(f, "column {:?} with value {:?}: {}", self.column, self.value, self.message)
+    }
+}
+
+impl error::Error for FieldError {}
+
+/// Returns the [FieldError] of a row that could not be parsed in lenient mode, if any.
+pub fn field_error(error: &Error) -> Option<&FieldError> {
+    match error.kind() {
+        ErrorKind::Io(e) => e.get_ref()?.downcast_ref(),
+        _ => None,
+    }
+}
+
+/// Parses an amount that may contain `_` between digits (`1_000`), thousands separators
+/// (`1,234.56` or `1.234,56`), a decimal comma (`12,5`), or an exponent (`1e3`).
+///
+/// Amounts whose only separator is a single `,` or `.` that may group thousands, e.g. `1,234` or
+/// `1.234`, are rejected as ambiguous: it may separate thousands or decimals depending on the
+/// locale.
+pub fn parse_amount(amount: &str) -> Result<Decimal, String> {
+    let invalid = |reason: &str| format!("invalid amount {:?}: {}", amount, reason);
+    let digits = amount.replace('_', "");
+    if digits.contains(['e', 'E']) {
+        return Decimal::from_scientific(&digits).map_err(|e| invalid(&e.to_string()));
+    }
+    let (commas, dots) = (digits.matches(',').count(), digits.matches('.').count());
+    let decimal_mark = match (commas, dots) {
+        (1, 0) | (0, 1) if groups_thousands(&digits) => {
+            let separator = if commas == 1 { ',' } else { '.' };
+            return Err(invalid(&format!("`{}` may separate thousands or decimals", separator)));
+        }
+        (0, 0 | 1) => None,
+        (0, _) => Some(','),
+        (1, 0) => Some(','),
+        (_, 0) => Some('.'),
+        _ if digits.rfind(',') > digits.rfind('.') => Some(','),
+        _ => Some('.'),
+    };
+    let normalized = match decimal_mark {
+        None => digits,
+        Some(mark) => {
+            let group = if mark == ',' { '.' } else { ',' };
+            let (integer, fraction) = digits.split_once(mark).unwrap_or((&digits, ""));
+            if fraction.contains([',', '.']) {
+                return Err(invalid("separators after the decimal mark"));
+            }
+            let mut groups = integer.split(group);
+            let first = groups.next().unwrap_or_default().trim_start_matches(['-', '+']);
+            if integer.contains(group)
+                && (first.is_empty() || first.len() > 3 || !groups.all(|g| g.len() == 3)) {
+                return Err(invalid("thousands must be grouped by three digits"));
+            }
+            let integer = integer.replace(group, "");
+            if digits.contains(mark) { format!("{}.{}", integer, fraction) } else { integer }
+        }
+    };
+    Decimal::from_str(&normalized).map_err(|e| invalid(&e.to_string()))
+}
+
+/// Returns true iff the separator of the amount may group thousands, i.e. it is followed by three
+/// digits and preceded by one to three digits not starting with `0`.
+fn groups_thousands(digits: &str) -> bool {
+    let Some((integer, fraction)) = digits.split_once([',', '.']) else {
+        return false;
+    };
+    let integer = integer.trim_start_matches(['-', '+']);
+    fraction.len() == 3 && (1..=3).contains(&integer.len()) && !integer.starts_with('0')
+}
+
+/// Replaces the `amount` cell by its canonical form (see [parse_amount]) or returns a [FieldError].
+pub(crate) fn normalize_amount(record: &mut ByteRecord, headers: &ByteRecord) -> Result<(), Error> {
+    let Some(column) = headers.iter().position(|h| h == b"amount") else {
+        return Ok(());
+    };
+    let cell = String::from_utf8_lossy(record.get(column).unwrap_or_default()).into_owned();
+    if cell.is_empty() {
+        return Ok(());
+    }
+    let amount = parse_amount(&cell).map_err(|message| {
+        let field_error = FieldError {
+            line: record.position().map(|p| p.line()),
+            column: String::from("amount"),
+            value: cell,
+            message,
+        };
+        Error::from(io::Error::new(io::ErrorKind::InvalidData, field_error))
+    })?;
+    let mut normalized = ByteRecord::with_capacity(record.as_slice().len(), record.len());
+    for (i, field) in record.iter().enumerate() {
+        if i == column {
+            normalized.push_field(amount.to_string().as_bytes());
+        } else {
+            normalized.push_field(field);
+        }
+    }
+    normalized.set_position(record.position().cloned());
+    *record = normalized;
+    Ok(())
+}
+
+/// Returns the record with as many cells as there are headers and the canonical name in the `type`
+/// cell, if it can be parsed.
+pub(crate) fn normalize_record(record: &ByteRecord, headers: &ByteRecord) -> ByteRecord {
+    let mut normalized = ByteRecord::with_capacity(record.as_slice().len(), headers.len());
+    for (i, header) in headers.iter().enumerate() {
+        let cell = record.get(i).unwrap_or_default();
+        let kind = (header == b"type").then(|| std::str::from_utf8(cell).ok()).flatten();
+        if let Some(kind) = kind.and_then(TransactionType::parse_lenient) {
+            normalized.push_field(kind.to_string().as_bytes());
+        } else {
+            normalized.push_field(cell);
+        }
+    }
+    normalized.set_position(record.position().cloned());
+    normalized
+}
+
+/// Replaces a deserialization error by a [FieldError] naming the column of the first cell that
+/// cannot be parsed, if any.
+pub(crate) fn name_field(error: Error, record: &ByteRecord, headers: &ByteRecord) -> Error {
+    let ErrorKind::Deserialize { pos, .. } = error.kind() else {
+        return error;
+    };
+    let failed = headers.iter().zip(record.iter())
+        .find_map(|(header, cell)| Some((header, cell, cell_error(header, cell)?)));
+    let Some((header, cell, message)) = failed else {
+        return error;
+    };
+    let field_error = FieldError {
+        line: pos.as_ref().map(|p| p.line()),
+        column: String::from_utf8_lossy(header).into_owned(),
+        value: String::from_utf8_lossy(cell).into_owned(),
+        message,
+    };
+    Error::from(io::Error::new(io::ErrorKind::InvalidData, field_error))
+}
+
+/// Returns the error of parsing the cell of the given column, if any.
+fn cell_error(header: &[u8], cell: &[u8]) -> Option<String> {
+    fn parse<T: DeserializeOwned>(cell: &[u8]) -> Option<String> {
+        let error = ByteRecord::from(vec![cell]).deserialize::<Option<T>>(None).err()?;
+        Some(match error.kind() {
+            ErrorKind::Deserialize { err, .. } => err.kind().to_string(),
+            _ => error.to_string(),
+        })
+    }
+    match header {
+        b"type" => parse::<TransactionType>(cell),
+        b"client" => parse::<u16>(cell),
+        b"tx" => parse::<u32>(cell),
+        b"amount" => parse::<Decimal>(cell),
+        b"reason" => parse::<DisputeReason>(cell),
+        b"timestamp" => parse::<u64>(cell),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csv::read_transactions_from;
+
+    use super::*;
+
+    #[test]
+    fn malformed_rows_are_salvaged_or_fail_with_column() {
+        let csv = "type,client,tx,amount,memo\n\
+                   Deposit,1,1,5,a,extra\n\
+                   WITHDRAWAL,1,2\n\
+                   deposit,1,3,abc,b\n\
+                   charge-back,1,1\n\
+                   Withdraw,1,4,1\n";
+        let rows: Vec<_> = read_transactions_from(csv.as_bytes()).lenient().collect();
+
+        let first = rows[0].as_ref().unwrap();
+        assert_eq!((TransactionType::Deposit, Some("a")),
+                   (first.transaction_type, first.memo.as_deref()));
+        let second = rows[1].as_ref().unwrap();
+        assert_eq!((TransactionType::Withdrawal, None), (second.transaction_type, second.amount));
+        let error = field_error(rows[2].as_ref().unwrap_err()).unwrap();
+        assert_eq!((Some(4), "amount", "abc"),
+                   (error.line, error.column.as_str(), error.value.as_str()));
+        let kinds: Vec<_> = rows[3..].iter()
+            .map(|row| row.as_ref().unwrap().transaction_type)
+            .collect();
+        assert_eq!(vec![TransactionType::Chargeback, TransactionType::Withdrawal], kinds);
+    }
+
+    #[test]
+    fn tolerant_amounts_are_normalized() {
+        for (amount, expected) in [
+            ("1,234.56", Decimal::new(123456, 2)),
+            ("1.234.567,8", Decimal::new(12345678, 1)),
+            ("1_000", Decimal::new(1000, 0)),
+            ("1e3", Decimal::new(1000, 0)),
+            ("2.5E-2", Decimal::new(25, 3)),
+            ("12,5", Decimal::new(125, 1)),
+            ("1,234,567", Decimal::new(1234567, 0)),
+            ("-1.5", Decimal::new(-15, 1)),
+            ("0.123", Decimal::new(123, 3)),
+            ("0,123", Decimal::new(123, 3)),
+            ("1234.567", Decimal::new(1234567, 3)),
+            ("1.2345", Decimal::new(12345, 4)),
+        ] {
+            assert_eq!(Ok(expected), parse_amount(amount), "{}", amount);
+        }
+        for amount in ["1,234", "1.234", "-12.345", "1,23,4.5", "1.2,3.4", "12345,678.9", "abc"] {
+            assert!(parse_amount(amount).is_err(), "{}", amount);
+        }
+    }
+
+    #[test]
+    fn ambiguous_amount_fails_with_column() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,\"1,000.5\"\ndeposit,1,2,\"1,000\"\n";
+        let rows: Vec<_> = read_transactions_from(csv.as_bytes()).tolerant_amounts().collect();
+        assert_eq!(Some(Decimal::new(10005, 1)), rows[0].as_ref().unwrap().amount);
+        let error = field_error(rows[1].as_ref().unwrap_err()).unwrap();
+        assert_eq!((Some(3), "amount"), (error.line, error.column.as_str()));
+    }
+
+    #[test]
+    fn strict_reader_rejects_malformed_rows() {
+        let csv = "type,client,tx,amount\n\
+                   Deposit,1,1,5\n\
+                   deposit,1,2,5,extra\n\
+                   withdraw,1,3,1\n";
+        let rows: Vec<_> = read_transactions_from(csv.as_bytes()).collect();
+        assert!(rows.iter().all(Result::is_err));
+    }
+}
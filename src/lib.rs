@@ -3,7 +3,8 @@
 //! This crate contains a simple payments engine that handles deposits, withdrawals, disputes,
 //! resolves, and chargebacks.
 //!
-//! Be aware that the [PaymentsEngine] is not thread-safe!
+//! Be aware that the [PaymentsEngine] is not thread-safe! To feed it from other threads, move it
+//! to a dedicated thread with [engine::channel].
 //!
 //! ## Example Code
 //!
@@ -33,7 +34,7 @@
 //! let mut actual_accounts: Vec<Account> = engine
 //!     .accounts()
 //!     .collect();
-//! actual_accounts.sort_by(|a, b| a.client.partial_cmp(&b.client).unwrap());
+//! actual_accounts.sort_by_key(|a| a.client);
 //!
 //! let expected_accounts = vec![
 //!     Account {
@@ -54,11 +55,79 @@
 //!
 //! assert_eq!(&expected_accounts, &actual_accounts);
 //! ```
+pub use crate::config::EngineConfig;
 pub use crate::engine::PaymentsEngine;
 pub use crate::error::PaymentError;
-pub use crate::models::{Account, Transaction, TransactionType};
+pub use crate::models::{
+    Account, AdminOperation, DepositInfo, DisputeReason, DisputeState, DisputeStatus,
+    RejectionReason, Transaction, TransactionOutcome, TransactionType,
+};
 
 pub mod error;
 pub mod models;
+pub mod config;
 pub mod engine;
 pub mod csv;
+pub mod checkpoint;
+pub mod audit;
+pub mod digest;
+pub mod merkle;
+pub mod ledger;
+pub mod reconcile;
+pub mod statement;
+pub mod balance_history;
+pub mod subscription;
+pub mod report;
+pub mod anomaly;
+pub mod aml;
+pub mod velocity;
+pub mod screening;
+pub mod autolock;
+pub mod webhook;
+pub mod server;
+pub mod tenant;
+pub mod wallet;
+pub mod reorder;
+pub mod ordering;
+pub mod expiry;
+pub mod overdraft;
+pub mod dust;
+pub mod margin;
+pub mod standing;
+pub mod settlement;
+pub mod house;
+pub mod receivable;
+pub mod adjust;
+pub mod diff;
+pub mod export;
+pub mod wal;
+pub mod compact;
+pub mod bloom;
+pub mod summary;
+pub mod attributes;
+pub mod alias;
+pub mod validate;
+pub mod middleware;
+pub mod policy;
+pub mod pipeline;
+pub mod lenient;
+pub mod io;
+pub mod shared;
+pub mod ratelimit;
+pub mod golden;
+#[cfg(feature = "fast-csv")]
+mod fast_csv;
+#[cfg(feature = "fixed-point")]
+pub mod fixed;
+pub mod follow;
+pub mod testing;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "object-store")]
+pub mod object_store;
+#[cfg(feature = "schemars")]
+pub mod schema;
+#[cfg(feature = "proto")]
+pub mod proto;
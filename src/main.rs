@@ -1,48 +1,1565 @@
+use std::cell::{Cell, RefCell};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::TcpListener;
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{Arg, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+use rust_decimal::Decimal;
+use serde::Serialize;
 
-use toy_payments_engine::csv::{read_transactions, write_account_info};
-use toy_payments_engine::PaymentsEngine;
+use toy_payments_engine::alias::AliasTable;
+use toy_payments_engine::aml::ReportingThreshold;
+use toy_payments_engine::anomaly::{write_anomalies, DisputeRate, LargeDeposit, WithdrawalBurst};
+use toy_payments_engine::attributes::{read_account_attrs, write_accounts_with_attrs};
+use toy_payments_engine::audit::{self, AuditEntry, AuditLog};
+use toy_payments_engine::autolock::AutoLock;
+use toy_payments_engine::checkpoint;
+use toy_payments_engine::digest;
+use toy_payments_engine::expiry::{DisputeExpiry, ExpiryPolicy};
+use toy_payments_engine::golden;
+use toy_payments_engine::house::write_house_accounts;
+use toy_payments_engine::overdraft::{read_overdraft_limits, write_overdraft_accounts};
+use toy_payments_engine::lenient::field_error;
+use toy_payments_engine::ordering::{OutOfOrderPolicy, TimestampOrdered};
+use toy_payments_engine::pipeline::{
+    process_follow_ups, process_row, process_transactions, ErrorSink, Row,
+};
+use toy_payments_engine::policy::Policy;
+use toy_payments_engine::ratelimit::RateLimit;
+use toy_payments_engine::receivable::WithdrawnFundsPolicy;
+use toy_payments_engine::reconcile;
+use toy_payments_engine::reorder::ReorderWindow;
+use toy_payments_engine::screening::DenyList;
+use toy_payments_engine::server::{Operation, Progress, Request, Server, ServerOptions};
+use toy_payments_engine::standing::read_standing_orders;
+use toy_payments_engine::summary::write_extended_accounts;
+use toy_payments_engine::report::BatchStats;
+use toy_payments_engine::csv::{
+    read_accounts, read_transactions_from, write_account_info, write_accounts,
+};
+#[cfg(feature = "object-store")]
+use toy_payments_engine::object_store::{is_object_store_url, ObjectReader, ObjectWriter};
+use toy_payments_engine::follow::follow_transactions;
+use toy_payments_engine::testing::generator::{self, GeneratorConfig};
+use toy_payments_engine::testing::throughput::{self, Throughput};
+use toy_payments_engine::velocity::VelocityLimit;
+use toy_payments_engine::wal::{SyncPolicy, WalRecord, WriteAheadLog};
+use toy_payments_engine::wallet::write_wallet_accounts;
+use toy_payments_engine::webhook::{EventKind, WebhookConfig, WebhookDispatcher};
+use toy_payments_engine::{
+    Account, AdminOperation, PaymentError, PaymentsEngine, Transaction, TransactionOutcome,
+    TransactionType,
+};
 
 /// Command-line interface for the Toy Payments Engine.
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
-struct Args {
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(flatten)]
+    args: Args,
+}
+
+/// Subcommands other than processing transactions
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a synthetic transaction stream in CSV format
+    Generate(GenerateArgs),
+    /// Measure the throughput (rows per second) of processing the given file
+    Bench {
+        /// Path to CSV file with transactions
+        input_csv: PathBuf,
+    },
+    /// Export an audit log written with `--audit-log`
+    ExportAudit {
+        /// Path to the audit log
+        log: PathBuf,
+        /// Output format
+        #[clap(long, value_enum, default_value_t = AuditFormat::Csv)]
+        format: AuditFormat,
+        /// Write the export to this file instead of stdout
+        #[clap(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Process the given file and compare the resulting balances with an external statement
+    Reconcile {
+        /// Path to CSV file with transactions
+        input_csv: PathBuf,
+        /// Path to CSV file with the expected accounts, in the format of the account report
+        #[clap(long, value_name = "FILE")]
+        expected: PathBuf,
+    },
+    /// Process the given file and print the statement of one client
+    Statement(StatementArgs),
+    /// Process the given file and print aggregates for a quick health check of the batch
+    Report {
+        /// Path to CSV file with transactions
+        input_csv: PathBuf,
+        /// Number of accounts with the highest totals to list
+        #[clap(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Print per-client differences between two account reports (CSV, or JSON if the file name
+    /// ends with `.json`)
+    Diff {
+        /// Report before the change
+        before: PathBuf,
+        /// Report after the change
+        after: PathBuf,
+    },
+    /// Record the input, the resulting account report, and the state digest as golden fixture
+    Record {
+        /// Path to CSV file with transactions
+        input_csv: PathBuf,
+        /// Directory of the fixtures, replayed by `cargo test`
+        #[clap(long, value_name = "DIR", default_value = "tests/fixtures")]
+        fixtures: PathBuf,
+        /// Name of the fixture, defaults to the name of the input file without extension
+        #[clap(long)]
+        name: Option<String>,
+    },
+    /// Print the JSON Schema of transactions, accounts, errors, and the server's JSON lines
+    #[cfg(feature = "schemars")]
+    Schema {
+        /// Only print the schema of this payload
+        #[clap(value_parser = ["transaction", "account", "error", "batch", "batch_reply",
+                               "balance_event", "health"])]
+        payload: Option<String>,
+    },
+}
+
+/// Arguments of the `statement` subcommand
+#[derive(clap::Args, Debug)]
+struct StatementArgs {
     /// Path to CSV file with transactions
     input_csv: PathBuf,
+    /// Client identifier
+    #[clap(long)]
+    client: u16,
+    /// Only include transactions with a timestamp at or after this one
+    #[clap(long, value_name = "TIMESTAMP")]
+    from: Option<u64>,
+    /// Only include transactions with a timestamp before this one
+    #[clap(long, value_name = "TIMESTAMP")]
+    to: Option<u64>,
+    /// Only include transactions with this tag
+    #[clap(long)]
+    tag: Option<String>,
+    /// Output format
+    #[clap(long, value_enum, default_value_t = StatementFormat::Text)]
+    format: StatementFormat,
 }
 
-/// Process all transactions from file at given path.
-///
-/// Skips failed transactions and invalid rows with a log message to stderr.
-pub fn process_transactions<P>(path: P) -> Result<PaymentsEngine, csv::Error>
-    where P: AsRef<Path>
+/// Output formats of a statement
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum StatementFormat {
+    Text,
+    Csv,
+}
+
+/// Policies for rows that are not in timestamp order
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum TimestampOrder {
+    Reorder,
+    Reject,
+    Warn,
+}
+
+/// Export formats of the audit log
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum AuditFormat {
+    Csv,
+    Ndjson,
+}
+
+/// Arguments of the `generate` subcommand
+#[derive(clap::Args, Debug)]
+struct GenerateArgs {
+    /// Number of distinct clients
+    #[clap(long, default_value_t = 100, value_parser = clap::value_parser!(u16).range(1..))]
+    clients: u16,
+    /// Number of rows to generate
+    #[clap(long, default_value_t = 1000)]
+    rows: u64,
+    /// Share of withdrawals among deposits and withdrawals
+    #[clap(long, default_value_t = 0.3, value_parser = parse_probability)]
+    withdrawal_ratio: f64,
+    /// Probability of disputing an earlier deposit
+    #[clap(long, default_value_t = 0.02, value_parser = parse_probability)]
+    dispute_probability: f64,
+    /// Probability that a dispute ends with a chargeback rather than a resolve
+    #[clap(long, default_value_t = 0.2, value_parser = parse_probability)]
+    chargeback_probability: f64,
+    /// Probability of repeating the previous transaction
+    #[clap(long, default_value_t = 0.0, value_parser = parse_probability)]
+    duplicate_probability: f64,
+    /// Probability of emitting a malformed row
+    #[clap(long, default_value_t = 0.0, value_parser = parse_probability)]
+    invalid_probability: f64,
+    /// Seed of the random number generator
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+    /// Write the generated CSV to this file instead of stdout
+    #[clap(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+fn parse_probability(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(format!("{} is not a probability between 0 and 1", s)),
+    }
+}
+
+/// Arguments for processing transactions
+#[derive(clap::Args, Debug)]
+struct Args {
+    /// Path (or object store URL) of CSV file with transactions
+    #[clap(required_unless_present = "listen")]
+    input_csv: Option<PathBuf>,
+    /// Path to SQLite database to load the initial state from and persist the final state to
+    #[cfg(feature = "sqlite")]
+    #[clap(long, value_name = "DB")]
+    sqlite: Option<PathBuf>,
+    /// Write the account report to a CSV file, an object store URL, or a PostgreSQL URL instead
+    /// of stdout
+    #[clap(long, value_name = "TARGET")]
+    output: Option<String>,
+    /// Report balances per wallet (transactions may name a wallet in an optional `wallet` column)
+    /// instead of per client
+    #[clap(long)]
+    report_wallets: bool,
+    /// Append the internal house accounts (fee income, chargeback write-off, dust) to the report,
+    /// named in the `client` column
+    #[clap(long, conflicts_with_all = &["report-wallets", "overdraft-limits"])]
+    report_house_accounts: bool,
+    /// Add the number of open disputes, the lifetime number of chargebacks, and the latest
+    /// transaction timestamp of each client to the report
+    #[clap(long,
+    conflicts_with_all = &["report-wallets", "report-house-accounts", "overdraft-limits"])]
+    extended: bool,
+    /// Attach the attributes in this CSV file (columns `client`, `key`, and `value`) to the
+    /// accounts
+    #[clap(long, value_name = "FILE")]
+    account_attrs: Option<PathBuf>,
+    /// Add a column for each of these account attributes to the report
+    #[clap(long, value_name = "KEYS", value_delimiter = ',', conflicts_with_all = &[
+        "report-wallets", "report-house-accounts", "overdraft-limits", "extended"])]
+    report_attrs: Vec<String>,
+    /// Treat the `client` column as external identifiers, mapped to client IDs by this CSV file
+    /// (columns `alias` and `client`); unknown identifiers are assigned new IDs, which are written
+    /// back to the file
+    #[clap(long, value_name = "FILE", conflicts_with_all = &["follow", "listen"])]
+    aliases: Option<PathBuf>,
+    /// Salvage malformed rows: ignore extra cells, treat missing cells as blank, and accept the
+    /// transaction type in any case and under aliases such as `withdraw` or `charge-back`; rows
+    /// that still fail are reported with the offending column
+    #[clap(long, conflicts_with_all = &["follow", "listen"])]
+    lenient: bool,
+    /// Accept amounts with thousands separators (`1,234.56`, `1.234,56`), `_` between digits, or
+    /// exponents (`1e3`); ambiguous amounts such as `1,234` or `1.234` are rejected
+    #[clap(long, conflicts_with_all = &["follow", "listen"])]
+    tolerant_amounts: bool,
+    /// Append an entry for every accepted or rejected transaction to this audit log
+    #[clap(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+    /// Keep a double-entry ledger and write its journal to this CSV file
+    #[clap(long, value_name = "FILE")]
+    journal: Option<PathBuf>,
+    /// Write transactions flagged by anomaly rules as CSV to this file
+    #[clap(long, value_name = "FILE")]
+    anomaly_report: Option<PathBuf>,
+    /// Flag deposits above this amount
+    #[clap(long, value_name = "AMOUNT", requires = "anomaly-report")]
+    flag_deposits_over: Option<Decimal>,
+    /// Flag withdrawals beyond this number per client and minute (requires timestamps in seconds)
+    #[clap(long, value_name = "COUNT", requires = "anomaly-report")]
+    flag_withdrawals_per_minute: Option<usize>,
+    /// Flag disputes of clients with more than this number of disputes per deposit
+    #[clap(long, value_name = "RATE", requires = "anomaly-report")]
+    flag_dispute_rate: Option<f64>,
+    /// Flag deposits reaching this AML reporting threshold, alone or as a series within the AML
+    /// window
+    #[clap(long, value_name = "AMOUNT", requires = "anomaly-report")]
+    aml_threshold: Option<Decimal>,
+    /// Window for series of deposits reaching the AML threshold (requires timestamps)
+    #[clap(long, value_name = "SECONDS", default_value_t = 86_400)]
+    aml_window: u64,
+    /// Reject all transactions of the clients listed in this file (one client ID per line)
+    #[clap(long, value_name = "FILE")]
+    deny_list: Option<PathBuf>,
+    /// Configure limits, windows, accepted amounts and currencies, and fees from this TOML
+    /// policy file; other options take precedence
+    #[clap(long, value_name = "FILE")]
+    policy: Option<PathBuf>,
+    /// Allow withdrawals to overdraw accounts up to the limits in this CSV file (columns `client`
+    /// and `limit`) and report the overdrawn amounts
+    #[clap(long, value_name = "FILE")]
+    overdraft_limits: Option<PathBuf>,
+    /// Reject withdrawals that would leave less than this amount available
+    #[clap(long, value_name = "AMOUNT")]
+    min_balance: Option<Decimal>,
+    /// Handling of disputes of withdrawn funds: reject, negative (available funds), or receivable
+    /// (hold what is available, track the rest per client)
+    #[clap(long, value_name = "POLICY")]
+    withdrawn_funds: Option<WithdrawnFundsPolicy>,
+    /// Reject adjustments of locked accounts or beyond the available funds
+    #[clap(long)]
+    strict_adjustments: bool,
+    /// After processing, move available balances below this amount into the dust account
+    #[clap(long, value_name = "THRESHOLD")]
+    sweep_dust: Option<Decimal>,
+    /// Execute the standing orders in this CSV file (columns `client`, `type`, `amount`,
+    /// `interval`, and `start`) as soon as a row's timestamp reaches their due time
+    #[clap(long, value_name = "FILE")]
+    standing_orders: Option<PathBuf>,
+    /// Reject disputes of deposits more than this number of operations old
+    #[clap(long, value_name = "OPERATIONS")]
+    dispute_window: Option<u64>,
+    /// Drop deposits that can no longer be disputed every this number of operations
+    #[clap(long, value_name = "OPERATIONS", value_parser = clap::value_parser!(u64).range(1..))]
+    compact_every: Option<u64>,
+    /// After processing, close the settlement period under this date label, e.g. `2024-01-31`
+    #[clap(long, value_name = "DATE", requires = "settlement-report")]
+    close_day: Option<String>,
+    /// Write the settlement report of the closed day as CSV to this file
+    #[clap(long, value_name = "FILE", requires = "close-day")]
+    settlement_report: Option<PathBuf>,
+    /// Keep accounts locked for good once they reach this number of chargebacks
+    #[clap(long, value_name = "COUNT")]
+    auto_lock_chargebacks: Option<u32>,
+    /// Keep accounts locked for good once their ratio of chargebacks to deposits exceeds this
+    #[clap(long, value_name = "RATIO")]
+    auto_lock_ratio: Option<f64>,
+    /// POST notifications about key events as JSON to this URL (`http://` only)
+    #[clap(long, value_name = "URL")]
+    webhook: Option<String>,
+    /// Events to notify: chargeback, locked, large-withdrawal
+    #[clap(long, value_name = "EVENTS", value_delimiter = ',', requires = "webhook",
+    default_value = "chargeback,locked,large-withdrawal")]
+    webhook_events: Vec<EventKind>,
+    /// Minimum amount of a withdrawal to notify as large-withdrawal
+    #[clap(long, value_name = "AMOUNT", default_value_t = Decimal::new(10_000, 0))]
+    large_withdrawal: Decimal,
+    /// Number of retries of a failed webhook delivery, with exponential backoff
+    #[clap(long, value_name = "COUNT", default_value_t = 3)]
+    webhook_retries: u32,
+    /// Maximum number of webhook events waiting for delivery, further ones are dropped
+    #[clap(long, value_name = "COUNT", default_value_t = 10_000)]
+    webhook_queue: usize,
+    /// Length of the rolling window for withdrawal velocity limits (requires timestamps)
+    #[clap(long, value_name = "SECONDS", default_value_t = 3600)]
+    velocity_window: u64,
+    /// Reject withdrawals beyond this number per client within the velocity window
+    #[clap(long, value_name = "COUNT")]
+    max_withdrawals: Option<usize>,
+    /// Reject withdrawals beyond this amount per client within the velocity window
+    #[clap(long, value_name = "AMOUNT")]
+    max_withdrawal_volume: Option<Decimal>,
+    /// Park disputes, resolves, and chargebacks of deposits not seen yet for up to this number
+    /// of subsequent rows and retry them when the deposit arrives
+    #[clap(long, value_name = "ROWS")]
+    reorder_rows: Option<u64>,
+    /// Park disputes, resolves, and chargebacks of deposits not seen yet for up to this number
+    /// of seconds (requires timestamps) and retry them when the deposit arrives
+    #[clap(long, value_name = "SECONDS")]
+    reorder_seconds: Option<u64>,
+    /// Resolve (or charge back, see expiry policy) disputes that stay open for longer than this,
+    /// based on transaction timestamps
+    #[clap(long, value_name = "SECONDS")]
+    dispute_expiry: Option<u64>,
+    /// Action taken on expired disputes: resolve or chargeback
+    #[clap(long, value_name = "POLICY", default_value = "resolve", requires = "dispute-expiry")]
+    expiry_policy: ExpiryPolicy,
+    /// Execute rows in timestamp order: reorder them within the order window, or reject or warn
+    /// about out-of-order rows
+    #[clap(long, value_enum, value_name = "POLICY", conflicts_with_all = &["follow", "listen"])]
+    timestamp_order: Option<TimestampOrder>,
+    /// Window of timestamps within which rows are reordered
+    #[clap(long, value_name = "SECONDS", default_value_t = 60)]
+    order_window: u64,
+    /// Periodically write a checkpoint (engine snapshot and input offset) to this file
+    #[clap(long, value_name = "FILE")]
+    checkpoint: Option<PathBuf>,
+    /// Number of input rows between two checkpoints
+    #[clap(long, value_name = "ROWS", default_value_t = 100_000,
+    value_parser = clap::value_parser!(u64).range(1..))]
+    checkpoint_every: u64,
+    /// Print a digest of the final engine state to stderr, to verify that two runs are equivalent
+    #[clap(long)]
+    print_digest: bool,
+    /// Expected number of input rows, used to size the engine's state up front
+    #[clap(long, value_name = "ROWS")]
+    expect_rows: Option<usize>,
+    /// Log invalid rows, rejected transactions, and a final summary as one JSON object per line
+    /// (field `event`: `invalid_row`, `rejected`, `accepted`, or `summary`) on stderr
+    #[clap(long)]
+    log_json: bool,
+    /// Only log the final summary, not every invalid row and rejected transaction
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Raise the verbosity: log every accepted transaction (-vv), too
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Write the numbers of parsed and invalid rows, accepted transactions per type, and rejected
+    /// transactions per error code to this file, or to stderr without file
+    #[clap(long, value_name = "FILE", require_equals = true)]
+    summary: Option<Option<PathBuf>>,
+    /// Stop at the first invalid row or rejected transaction, without emitting the report
+    #[clap(long, conflicts_with_all = &["follow", "listen"])]
+    fail_fast: bool,
+    /// Display a progress bar (rows processed, rows per second, and remaining time) on stderr
+    #[clap(long, conflicts_with_all = &["follow", "listen"])]
+    progress: bool,
+    /// Resume an interrupted run from the given checkpoint instead of starting from scratch
+    #[clap(long, value_name = "CHECKPOINT")]
+    resume: Option<PathBuf>,
+    /// Append every parsed row to this write-ahead log before executing it, and replay the rows
+    /// past the checkpoint (all rows without `--resume`) on startup
+    #[clap(long, value_name = "FILE")]
+    wal: Option<PathBuf>,
+    /// Sync the write-ahead log to disk after every row (always), after the given number of rows,
+    /// or never
+    #[clap(long, value_name = "POLICY", default_value = "always", requires = "wal")]
+    wal_sync: SyncPolicy,
+    /// Keep waiting for rows appended to the input file (like `tail -f`) and periodically
+    /// re-emit the account report
+    #[clap(long)]
+    follow: bool,
+    /// Instead of reading a file, accept one transaction per line (CSV or JSON) on this TCP
+    /// address (or Unix domain socket `unix:PATH`), reply `OK` or `ERR <code>` per line, and
+    /// periodically re-emit the account report. Addresses other than loopback addresses require
+    /// `--listen-tokens`
+    #[clap(long, value_name = "ADDR", conflicts_with_all = &["input-csv", "follow"])]
+    listen: Option<String>,
+    /// Require connections to the server to authenticate with `AUTH <token>`, using the tokens in
+    /// this file (lines `submit <token>`, or `admin <token>` to also allow disputes, refunds, and
+    /// adjustments)
+    #[clap(long, value_name = "FILE", requires = "listen")]
+    listen_tokens: Option<PathBuf>,
+    /// Answer transactions beyond this number per client and second with `ERR rate_limited`
+    #[clap(long, value_name = "N", requires = "listen",
+    value_parser = clap::value_parser!(u32).range(1..))]
+    rate_limit_per_client: Option<u32>,
+    /// Answer transactions beyond this number per second of all clients with `ERR rate_limited`
+    #[clap(long, value_name = "N", requires = "listen",
+    value_parser = clap::value_parser!(u32).range(1..))]
+    rate_limit: Option<u32>,
+    /// Minimum number of seconds between two account reports in follow and server mode
+    #[clap(long, value_name = "SECONDS", default_value_t = 5,
+    value_parser = clap::value_parser!(u64).range(1..))]
+    report_interval: u64,
+}
+
+impl Args {
+    /// Returns the input path, which is required unless a subcommand is given.
+    fn input(&self) -> &Path {
+        self.input_csv.as_deref().expect("input is required without subcommand")
+    }
+
+    /// Returns the policy for rows that are not in timestamp order, if any.
+    fn out_of_order_policy(&self) -> Option<OutOfOrderPolicy> {
+        self.timestamp_order.map(|order| match order {
+            TimestampOrder::Reorder => OutOfOrderPolicy::Reorder { window: self.order_window },
+            TimestampOrder::Reject => OutOfOrderPolicy::Reject,
+            TimestampOrder::Warn => OutOfOrderPolicy::Warn,
+        })
+    }
+}
+
+/// Opens the transaction input, either a local file or an object store URL.
+fn open_input(input: &Path) -> io::Result<Box<dyn Read>> {
+    #[cfg(feature = "object-store")]
+    if let Some(url) = input.to_str().filter(|s| is_object_store_url(s)) {
+        return Ok(Box::new(ObjectReader::open(url)?));
+    }
+    Ok(Box::new(File::open(input)?))
+}
+
+/// Set on SIGINT or SIGTERM in follow and server mode, to stop accepting input and shut down
+/// gracefully
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Maximum time between two checks of [SHUTDOWN]
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Log events as JSON objects instead of text, see `--log-json`
+static LOG_JSON: AtomicBool = AtomicBool::new(false);
+
+/// Verbosity of the log: 0 with `--quiet`, 1 by default, and one more per `-v`
+static VERBOSITY: AtomicU8 = AtomicU8::new(1);
+
+/// Event logged to stderr
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum LogEvent<'a> {
+    /// Input row could not be parsed
+    InvalidRow { line: Option<u64>, error: String },
+    /// Transaction was executed successfully
+    Accepted {
+        client: u16,
+        tx: u32,
+        #[serde(rename = "type")]
+        kind: TransactionType,
+    },
+    /// Transaction was rejected by the engine
+    Rejected {
+        client: u16,
+        tx: u32,
+        #[serde(rename = "type")]
+        kind: TransactionType,
+        code: &'a str,
+        error: String,
+    },
+    /// Row or event that does not stop processing but needs attention
+    Warning { message: String },
+    /// Counts and exit code of a completed or aborted run
+    Summary { rows: u64, invalid: u64, rejected: u64, exit_code: u8 },
+}
+
+impl fmt::Display for LogEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogEvent::InvalidRow { error, .. } => write!(f, "Invalid input row: {}", error),
+            LogEvent::Accepted { client, tx, kind } => {
+                write!(f, "Accepted {:?} transaction {} of client {}", kind, tx, client)
+            }
+            LogEvent::Rejected { error, .. } => f.write_str(error),
+            LogEvent::Warning { message } => f.write_str(message),
+            LogEvent::Summary { rows, invalid, rejected, exit_code } => {
+                write!(f, "Summary: rows={} invalid={} rejected={} exit_code={}", rows, invalid,
+                       rejected, exit_code)
+            }
+        }
+    }
+}
+
+impl LogEvent<'_> {
+    /// Returns the verbosity at which the event is logged.
+    fn verbosity(&self) -> u8 {
+        match self {
+            LogEvent::Summary { .. } => 0,
+            LogEvent::InvalidRow { .. } | LogEvent::Rejected { .. } | LogEvent::Warning { .. } => 1,
+            LogEvent::Accepted { .. } => 3,
+        }
+    }
+}
+
+/// Returns true iff events of the given verbosity are logged.
+fn log_enabled(verbosity: u8) -> bool {
+    verbosity <= VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// Writes the event to stderr if enabled, as JSON object with `--log-json`.
+fn log(event: &LogEvent) {
+    if !log_enabled(event.verbosity()) {
+        return;
+    }
+    if LOG_JSON.load(Ordering::Relaxed) {
+        eprintln!("{}", serde_json::to_string(event).expect("log events are serializable"));
+    } else {
+        eprintln!("{}", event);
+    }
+}
+
+/// Logs the errors of skipped rows as [LogEvent]s.
+struct Log;
+
+impl ErrorSink for Log {
+    fn invalid_row(&mut self, error: &csv::Error) {
+        let line = error.position().map(|p| p.line())
+            .or_else(|| field_error(error).and_then(|e| e.line));
+        log(&LogEvent::InvalidRow { line, error: error.to_string() });
+    }
+
+    fn rejected(&mut self, transaction: &Transaction, error: &PaymentError) {
+        if log_enabled(1) {
+            let Transaction { client, tx, transaction_type: kind, .. } = *transaction;
+            let (code, error) = (error.code(), error.to_string());
+            log(&LogEvent::Rejected { client, tx, kind, code, error });
+        }
+    }
+
+    fn warning(&mut self, _: &Transaction, warning: &str) {
+        if log_enabled(1) {
+            log(&LogEvent::Warning { message: warning.to_owned() });
+        }
+    }
+}
+
+/// Optional persistence of engine state, transaction log, and audit log
+#[derive(Default)]
+struct Persistence {
+    #[cfg(feature = "sqlite")]
+    store: Option<toy_payments_engine::sqlite::SqliteStore>,
+    audit: Option<AuditLog>,
+}
+
+impl Persistence {
+    /// Opens the storage backends requested via command-line arguments.
+    fn open(args: &Args) -> Result<Self, String> {
+        let mut persistence = Self::default();
+        if let Some(path) = &args.audit_log {
+            let log = AuditLog::open(path)
+                .map_err(|e| format!("Could not open audit log {:?}: {}", path, e))?;
+            persistence.audit = Some(log);
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(path) = &args.sqlite {
+            let store = toy_payments_engine::sqlite::SqliteStore::open(path)
+                .map_err(|e| format!("Could not open database {:?}: {}", path, e))?;
+            persistence.store = Some(store);
+        }
+        Ok(persistence)
+    }
+
+    /// Loads the persisted engine or creates a new one if there is no persisted state, sized for
+    /// the expected number of rows if given.
+    fn load(&self, expected_rows: Option<usize>) -> Result<PaymentsEngine, String> {
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &self.store {
+            return store.load().map_err(|e| format!("Could not load state from database: {}", e));
+        }
+        Ok(expected_rows.map_or_else(PaymentsEngine::new, |rows| {
+            PaymentsEngine::with_capacity(rows, rows)
+        }))
+    }
+
+    /// Records an executed transaction and its outcome.
+    fn record(&mut self, transaction: &Transaction, outcome: &TransactionOutcome) {
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &mut self.store {
+            store.record(transaction, &outcome.status);
+        }
+        if let Some(log) = &mut self.audit {
+            if let Err(e) = log.append(&AuditEntry::new(transaction, outcome)) {
+                eprintln!("Could not write audit log: {}", e);
+            }
+        }
+    }
+
+    /// Records an applied administrative operation, its result, and the client's account before
+    /// and after.
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn record_operation(
+        &mut self,
+        operation: &AdminOperation,
+        status: &Result<(), PaymentError>,
+        before: Option<&Account>,
+        after: Option<&Account>,
+    ) {
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &mut self.store {
+            store.record_operation(operation, status);
+        }
+        if let Some(log) = &mut self.audit {
+            let entry = AuditEntry::for_operation(operation, status, before, after);
+            if let Err(e) = log.append(&entry) {
+                eprintln!("Could not write audit log: {}", e);
+            }
+        }
+    }
+
+    /// Persists the final state of the engine and flushes the audit log.
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn save(&mut self, payments_engine: &PaymentsEngine) -> Result<(), String> {
+        if let Some(log) = &mut self.audit {
+            log.flush().map_err(|e| format!("Could not write audit log: {}", e))?;
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &mut self.store {
+            store.save(payments_engine)
+                .map_err(|e| format!("Could not persist state to database: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the account report to stdout or the given output target.
+fn write_report(output: Option<&str>, payments_engine: &PaymentsEngine) -> Result<(), String> {
+    match output {
+        None => write_account_info(payments_engine.accounts()).map_err(|e| e.to_string()),
+        Some(url) if url.starts_with("postgres://") || url.starts_with("postgresql://") => {
+            #[cfg(feature = "postgres")]
+            return toy_payments_engine::postgres::upsert_accounts(url, payments_engine.accounts())
+                .map(|_| ())
+                .map_err(|e| e.to_string());
+            #[cfg(not(feature = "postgres"))]
+            Err(String::from("PostgreSQL output requires the `postgres` feature"))
+        }
+        #[cfg(feature = "object-store")]
+        Some(url) if is_object_store_url(url) => ObjectWriter::create(url)
+            .and_then(|mut writer| {
+                write_accounts(&mut writer, payments_engine.accounts())?;
+                writer.finish()
+            })
+            .map_err(|e| e.to_string()),
+        Some(path) => File::create(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| {
+                write_accounts(BufWriter::new(file), payments_engine.accounts())
+                    .map_err(|e| e.to_string())
+            }),
+    }
+}
+
+/// Writes the account report, the report per wallet if requested, or the report with overdrawn
+/// amounts if there are overdraft limits, as specified by the command-line arguments. House
+/// accounts are appended to the account report if requested, summary columns added to it with
+/// `--extended` and attribute columns with `--report-attrs`.
+fn emit_report(args: &Args, payments_engine: &PaymentsEngine) -> Result<(), String> {
+    let custom = args.report_wallets || args.report_house_accounts || args.extended
+        || !args.report_attrs.is_empty();
+    if !custom && !payments_engine.has_overdraft_limits() {
+        return write_report(args.output.as_deref(), payments_engine);
+    }
+    let write = |mut writer: Box<dyn Write>| if args.report_wallets {
+        write_wallet_accounts(writer, payments_engine.wallet_accounts())
+    } else if args.extended {
+        write_extended_accounts(writer, payments_engine.extended_accounts())
+    } else if !args.report_attrs.is_empty() {
+        write_accounts_with_attrs(writer, payments_engine, &args.report_attrs)
+    } else if args.report_house_accounts {
+        write_accounts(&mut writer, payments_engine.accounts())?;
+        write_house_accounts(writer, payments_engine.house_accounts())
+    } else {
+        write_overdraft_accounts(writer, payments_engine.accounts())
+    };
+    match args.output.as_deref() {
+        None => write(Box::new(io::stdout())),
+        Some(path) => File::create(path)
+            .map_err(csv::Error::from)
+            .and_then(|file| write(Box::new(BufWriter::new(file)))),
+    }.map_err(|e| e.to_string())
+}
+
+/// Exit code of a run that completed with invalid rows or rejected transactions
+const EXIT_REJECTED: u8 = 1;
+/// Exit code of a run that could not read its input or failed otherwise
+const EXIT_UNREADABLE: u8 = 2;
+/// Exit code of a run aborted by `--fail-fast`
+const EXIT_ABORTED: u8 = 3;
+
+/// Row counts of a run, logged with its exit code as final summary
+#[derive(Default)]
+struct RunCounts {
+    rows: u64,
+    invalid: u64,
+    rejected: u64,
+}
+
+/// Processes the input and emits the report, and logs the summary on every exit path, also if the
+/// run failed.
+fn run(args: Args) -> ExitCode {
+    let mut counts = RunCounts::default();
+    let exit_code = execute(args, &mut counts).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        EXIT_UNREADABLE
+    });
+    let RunCounts { rows, invalid, rejected } = counts;
+    log(&LogEvent::Summary { rows, invalid, rejected, exit_code });
+    ExitCode::from(exit_code)
+}
+
+/// Processes the input and emits the report, recording the row counts as soon as the rows are
+/// processed. Returns the exit code of a completed or aborted run.
+fn execute(args: Args, counts: &mut RunCounts) -> Result<u8, String> {
+    LOG_JSON.store(args.log_json, Ordering::Relaxed);
+    VERBOSITY.store(if args.quiet { 0 } else { args.verbose.saturating_add(1) }, Ordering::Relaxed);
+    let reordering = matches!(args.timestamp_order, Some(TimestampOrder::Reorder));
+    if reordering && (args.checkpoint.is_some() || args.resume.is_some() || args.wal.is_some()) {
+        return Err(String::from("Rows reordered by timestamp cannot be checkpointed or logged \
+                                 ahead, as the number of executed rows does not determine the \
+                                 input offset"));
+    }
+    let mut persistence = Persistence::open(&args)?;
+    let (mut payments_engine, mut rows) = match &args.resume {
+        Some(path) => {
+            let checkpoint = checkpoint::load(path)
+                .map_err(|e| format!("Could not load checkpoint {:?}: {}", path, e))?;
+            (checkpoint.engine, checkpoint.rows)
+        }
+        None => (persistence.load(args.expect_rows)?, 0),
+    };
+    if let Some(path) = &args.policy {
+        let policy = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|policy| policy.parse::<Policy>().map_err(|e| e.to_string()))
+            .map_err(|e| format!("Could not read policy {:?}: {}", path, e))?;
+        payments_engine.apply_policy(policy);
+    }
+    if args.journal.is_some() {
+        payments_engine.enable_ledger();
+    }
+    if args.auto_lock_chargebacks.is_some() || args.auto_lock_ratio.is_some() {
+        payments_engine.set_auto_lock(Some(AutoLock {
+            max_chargebacks: args.auto_lock_chargebacks,
+            max_chargeback_ratio: args.auto_lock_ratio,
+        }));
+    }
+    if let Some(path) = &args.deny_list {
+        let deny_list = File::open(path)
+            .and_then(|file| DenyList::read(io::BufReader::new(file)))
+            .map_err(|e| format!("Could not read deny-list {:?}: {}", path, e))?;
+        payments_engine.set_screening(deny_list);
+    }
+    if let Some(path) = &args.overdraft_limits {
+        let limits = File::open(path)
+            .map_err(csv::Error::from)
+            .and_then(read_overdraft_limits)
+            .map_err(|e| format!("Could not read overdraft limits {:?}: {}", path, e))?;
+        for (client, limit) in limits {
+            payments_engine.set_overdraft_limit(client, Some(limit));
+        }
+    }
+    if let Some(path) = &args.account_attrs {
+        let attrs = File::open(path)
+            .map_err(csv::Error::from)
+            .and_then(read_account_attrs)
+            .map_err(|e| format!("Could not read account attributes {:?}: {}", path, e))?;
+        for (client, key, value) in attrs {
+            payments_engine.set_account_attr(client, key, value);
+        }
+    }
+    if let Some(path) = &args.standing_orders {
+        let orders = File::open(path)
+            .map_err(csv::Error::from)
+            .and_then(read_standing_orders)
+            .map_err(|e| format!("Could not read standing orders {:?}: {}", path, e))?;
+        for order in orders {
+            payments_engine.add_standing_order(order)
+                .map_err(|e| format!("Invalid standing order in {:?}: {}", path, e))?;
+        }
+    }
+    if args.strict_adjustments {
+        payments_engine.set_strict_adjustments(true);
+    }
+    if let Some(policy) = args.withdrawn_funds {
+        payments_engine.set_withdrawn_funds_policy(policy);
+    }
+    if args.min_balance.is_some() {
+        payments_engine.set_min_balance(args.min_balance);
+    }
+    if args.dispute_window.is_some() {
+        payments_engine.set_dispute_window(args.dispute_window);
+    }
+    if args.compact_every.is_some() {
+        payments_engine.set_compaction_interval(args.compact_every);
+    }
+    if args.max_withdrawals.is_some() || args.max_withdrawal_volume.is_some() {
+        payments_engine.set_velocity_limit(Some(VelocityLimit {
+            window: args.velocity_window,
+            max_count: args.max_withdrawals,
+            max_volume: args.max_withdrawal_volume,
+        }));
+    }
+    if args.reorder_rows.is_some() || args.reorder_seconds.is_some() {
+        payments_engine.set_reorder_window(Some(ReorderWindow {
+            max_operations: args.reorder_rows,
+            max_age: args.reorder_seconds,
+        }));
+    }
+    if let Some(max_age) = args.dispute_expiry {
+        payments_engine.set_dispute_expiry(Some(DisputeExpiry {
+            max_age,
+            policy: args.expiry_policy,
+        }));
+    }
+    if let Some(threshold) = args.flag_deposits_over {
+        payments_engine.add_anomaly_rule(LargeDeposit { threshold });
+    }
+    if let Some(max) = args.flag_withdrawals_per_minute {
+        payments_engine.add_anomaly_rule(WithdrawalBurst::new(max, 60));
+    }
+    if let Some(max_rate) = args.flag_dispute_rate {
+        payments_engine.add_anomaly_rule(DisputeRate::new(max_rate));
+    }
+    if let Some(threshold) = args.aml_threshold {
+        payments_engine.add_anomaly_rule(ReportingThreshold::new(threshold, args.aml_window));
+    }
+    let webhook = args.webhook.as_ref()
+        .map(|url| WebhookDispatcher::start(WebhookConfig {
+            url: url.clone(),
+            events: args.webhook_events.clone(),
+            large_withdrawal: args.large_withdrawal,
+            retries: args.webhook_retries,
+            backoff: Duration::from_millis(500),
+            queue: args.webhook_queue,
+        }, |failure| log(&LogEvent::Warning { message: failure.to_string() })))
+        .transpose()?;
+    let mut wal_entries = None;
+    let mut wal = match &args.wal {
+        Some(path) => {
+            let (wal, entries) = WriteAheadLog::open(path, args.wal_sync)
+                .map_err(|e| format!("Could not open write-ahead log {:?}: {}", path, e))?;
+            wal_entries = Some(entries.len() as u64);
+            let checkpointed = rows;
+            for entry in entries.into_iter().filter(|entry| entry.row > checkpointed) {
+                rows = entry.row;
+                match entry.record {
+                    WalRecord::Transaction(transaction) => {
+                        process_row(Ok(transaction), &mut payments_engine, &mut Log,
+                                    &mut |_: &PaymentsEngine, _: Row| {});
+                    }
+                    WalRecord::Operation(operation) => {
+                        let _ = payments_engine.administer(operation);
+                    }
+                }
+            }
+            Some(wal)
+        }
+        None => None,
+    };
+    let progress = Arc::new(Progress::new(wal_entries));
+    let skip_rows = rows;
+    let mut stats = BatchStats::default();
+    let aborted = Cell::new(false);
+    let wal_error = RefCell::new(None);
+    let mut before_operation = None;
+    let on_row = |payments_engine: &PaymentsEngine, row: Row| {
+        let failed = match row {
+            Row::Invalid => {
+                stats.invalid_rows += 1;
+                true
+            }
+            Row::Accepted(_) | Row::Operation(_) | Row::Administered(..) => false,
+            Row::Executed(transaction, outcome)
+            | Row::Reordered(transaction, outcome)
+            | Row::Expired(transaction, outcome)
+            | Row::Scheduled(transaction, outcome) => {
+                stats.record(transaction, outcome);
+                if outcome.status.is_ok() {
+                    let Transaction { client, tx, transaction_type: kind, .. } = *transaction;
+                    log(&LogEvent::Accepted { client, tx, kind });
+                }
+                is_rejected(outcome)
+            }
+        };
+        if failed && args.fail_fast {
+            aborted.set(true);
+        }
+        if let Row::Accepted(_) | Row::Operation(_) = row {
+            if wal_error.borrow().is_some() {
+                return ControlFlow::Break(());
+            }
+            let appended = match (wal.as_mut(), &row) {
+                (Some(wal), Row::Accepted(transaction)) => wal.append(rows + 1, transaction),
+                (Some(wal), Row::Operation(operation)) => wal.append_operation(rows + 1, operation),
+                _ => Ok(()),
+            };
+            if let Err(e) = appended {
+                let message = format!("Could not write write-ahead log: {}", e);
+                if args.listen.is_some() {
+                    // The server rejects the row and goes on, as the next append may succeed
+                    log(&LogEvent::Warning { message });
+                } else {
+                    SHUTDOWN.store(true, Ordering::SeqCst);
+                    *wal_error.borrow_mut() = Some(message);
+                }
+                return ControlFlow::Break(());
+            }
+            if wal.is_some() {
+                progress.logged();
+            }
+            if let Row::Operation(operation) = row {
+                before_operation = payments_engine.account(operation.client());
+            }
+            return ControlFlow::Continue(());
+        }
+        if let Row::Executed(transaction, outcome)
+            | Row::Reordered(transaction, outcome)
+            | Row::Expired(transaction, outcome)
+            | Row::Scheduled(transaction, outcome) = row {
+            persistence.record(transaction, outcome);
+            if let Some(webhook) = &webhook {
+                webhook.notify(transaction, outcome);
+            }
+        }
+        if let Row::Administered(operation, status) = row {
+            let after = payments_engine.account(operation.client());
+            persistence.record_operation(operation, status, before_operation.take().as_ref(),
+                                         after.as_ref());
+        }
+        if !matches!(row, Row::Invalid | Row::Executed(..) | Row::Administered(..)) {
+            return ControlFlow::Continue(());
+        }
+        rows += 1;
+        if let Some(path) = args.checkpoint.as_ref().filter(|_| rows % args.checkpoint_every == 0) {
+            match save_checkpoint(path, rows, payments_engine, wal.as_mut()) {
+                Ok(()) => progress.checkpointed(),
+                Err(message) => log(&LogEvent::Warning { message }),
+            }
+        }
+        ControlFlow::Continue(())
+    };
+    let service = args.listen.is_some() || args.follow;
+    if service {
+        ctrlc::set_handler(|| SHUTDOWN.store(true, Ordering::SeqCst))
+            .map_err(|e| format!("Could not install signal handler: {}", e))?;
+    }
+    if let Some(addr) = &args.listen {
+        serve(&args, addr, &mut payments_engine, Arc::clone(&progress), on_row)?;
+    } else if args.follow {
+        follow(&args, skip_rows, &mut payments_engine, on_row)?;
+    } else {
+        let mut transactions = open_input(args.input())
+            .map_err(csv::Error::from)
+            .map(read_transactions_from)
+            .map(|transactions| if args.lenient { transactions.lenient() } else { transactions })
+            .map(|transactions| match args.tolerant_amounts {
+                true => transactions.tolerant_amounts(),
+                false => transactions,
+            })
+            .and_then(|transactions| transactions.skip_rows(skip_rows))
+            .map_err(|e| format!("Could not read file {:?}: {}", args.input(), e))?;
+        let progress = args.progress.then(|| progress_bar(args.input()));
+        if let Some(progress) = progress.clone() {
+            let start = Instant::now();
+            transactions = transactions.on_progress(move |rows, bytes| {
+                progress.set_position(bytes);
+                if rows % PROGRESS_ROWS == 0 {
+                    let rate = rows as f64 / start.elapsed().as_secs_f64();
+                    progress.set_message(format!("{} rows ({:.0} rows/s)", rows, rate));
+                }
+            });
+        }
+        if let Some(path) = &args.aliases {
+            let aliases = match File::open(path) {
+                Ok(file) => AliasTable::read(file),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(AliasTable::new()),
+                Err(e) => Err(csv::Error::from(e)),
+            }.map_err(|e| format!("Could not read aliases {:?}: {}", path, e))?;
+            transactions = transactions.with_aliases(aliases);
+        }
+        let rows = transactions.by_ref()
+            .take_while(|_| !aborted.get() && wal_error.borrow().is_none());
+        match args.out_of_order_policy() {
+            Some(policy) => {
+                let ordered = TimestampOrdered::with_sink(rows, policy, Log);
+                process_transactions(ordered, &mut payments_engine, &mut Log, on_row)
+            }
+            None => process_transactions(rows, &mut payments_engine, &mut Log, on_row),
+        }
+        if let Some(progress) = progress {
+            progress.finish();
+        }
+        if let (Some(path), Some(aliases)) = (&args.aliases, transactions.aliases()) {
+            File::create(path)
+                .map_err(csv::Error::from)
+                .and_then(|file| aliases.write(BufWriter::new(file)))
+                .map_err(|e| format!("Could not write aliases {:?}: {}", path, e))?;
+        }
+    }
+    let processed = rows - skip_rows;
+    let (invalid, rejected) = (stats.invalid_rows, stats.rejections.values().sum::<u64>());
+    *counts = RunCounts { rows: processed, invalid, rejected };
+    if let Some(dropped) = webhook.as_ref().map(WebhookDispatcher::dropped).filter(|&d| d > 0) {
+        log(&LogEvent::Warning { message: format!("Dropped webhook events: {}", dropped) });
+    }
+    if let Some(message) = wal_error.take() {
+        return Err(message);
+    }
+    if let Some(path) = &args.summary {
+        match path {
+            Some(path) => File::create(path)
+                .and_then(|file| stats.write_breakdown(BufWriter::new(file), processed)),
+            None => stats.write_breakdown(io::stderr().lock(), processed),
+        }.map_err(|e| format!("Could not write summary: {}", e))?;
+    }
+    if aborted.get() {
+        if let Some(wal) = &mut wal {
+            wal.sync().map_err(|e| format!("Could not write write-ahead log: {}", e))?;
+        }
+        eprintln!("Aborted after the first invalid row or rejected transaction (--fail-fast)");
+        return Ok(EXIT_ABORTED);
+    }
+    if let Some(threshold) = args.sweep_dust {
+        for (transaction, outcome) in payments_engine.sweep_dust(threshold) {
+            persistence.record(&transaction, &outcome);
+        }
+    }
+    if let (Some(date), Some(path)) = (&args.close_day, &args.settlement_report) {
+        File::create(path)
+            .map_err(csv::Error::from)
+            .and_then(|file| payments_engine.close_day(date).write_csv(BufWriter::new(file)))
+            .map_err(|e| format!("Could not write settlement report {:?}: {}", path, e))?;
+    }
+    if let Some(path) = args.checkpoint.as_ref().filter(|_| service) {
+        save_checkpoint(path, rows, &payments_engine, wal.as_mut())?;
+    }
+    if let Some(wal) = &mut wal {
+        wal.sync().map_err(|e| format!("Could not write write-ahead log: {}", e))?;
+    }
+    persistence.save(&payments_engine)?;
+    let exit_code = if service || invalid + rejected == 0 { 0 } else { EXIT_REJECTED };
+    if args.print_digest {
+        eprintln!("State digest: {}", digest::to_hex(&payments_engine.state_digest()));
+    }
+    if let (Some(path), Some(ledger)) = (&args.journal, payments_engine.ledger()) {
+        File::create(path)
+            .and_then(|file| ledger.write_journal(BufWriter::new(file)).map_err(io::Error::from))
+            .map_err(|e| format!("Could not write journal {:?}: {}", path, e))?;
+    }
+    if let Some(path) = &args.anomaly_report {
+        File::create(path)
+            .map_err(csv::Error::from)
+            .and_then(|file| write_anomalies(BufWriter::new(file), payments_engine.anomalies()))
+            .map_err(|e| format!("Could not write anomaly report {:?}: {}", path, e))?;
+    }
+    emit_report(&args, &payments_engine)
+        .map_err(|e| format!("Could not write account information: {}", e))?;
+    Ok(exit_code)
+}
+
+/// Writes a checkpoint and truncates the write-ahead log, whose entries it covers.
+fn save_checkpoint(
+    path: &Path,
+    rows: u64,
+    payments_engine: &PaymentsEngine,
+    wal: Option<&mut WriteAheadLog>,
+) -> Result<(), String> {
+    checkpoint::save(path, rows, payments_engine)
+        .map_err(|e| format!("Could not write checkpoint {:?}: {}", path, e))?;
+    wal.map_or(Ok(()), WriteAheadLog::truncate)
+        .map_err(|e| format!("Could not truncate write-ahead log: {}", e))
+}
+
+/// Returns true iff the transaction was rejected for good, i.e. not parked for a retry.
+fn is_rejected(outcome: &TransactionOutcome) -> bool {
+    matches!(outcome.status, Err(ref e) if !matches!(e, PaymentError::Deferred { .. }))
+}
+
+/// Number of rows between two updates of the row count and rate of the progress bar
+const PROGRESS_ROWS: u64 = 1000;
+
+/// Returns a progress bar over the bytes of the input file, or a spinner if its size is unknown.
+fn progress_bar(input: &Path) -> ProgressBar {
+    let (progress, template) = match std::fs::metadata(input) {
+        Ok(metadata) => (ProgressBar::new(metadata.len()),
+                         "{wide_bar} {bytes}/{total_bytes} {msg} ETA {eta}"),
+        Err(_) => (ProgressBar::new_spinner(), "{spinner} {bytes} {msg}"),
+    };
+    progress.set_style(ProgressStyle::with_template(template).expect("valid progress template"));
+    progress
+}
+
+/// Processes rows as they are appended to the input file, re-emitting the account report at most
+/// every report interval whenever the state changed, until [SHUTDOWN] is requested.
+fn follow<F>(
+    args: &Args,
+    skip_rows: u64,
+    payments_engine: &mut PaymentsEngine,
+    mut on_row: F,
+) -> Result<(), String>
+    where F: FnMut(&PaymentsEngine, Row) -> ControlFlow<()>
+{
+    let receiver = follow_transactions(args.input(), skip_rows)
+        .map_err(|e| format!("Could not read file {:?}: {}", args.input(), e))?;
+    let interval = Duration::from_secs(args.report_interval);
+    let mut last_report = Instant::now();
+    let mut changed = false;
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        match receiver.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+            Ok(row) => {
+                process_row(row, payments_engine, &mut Log, &mut on_row);
+                changed = true;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(format!("Stopped following file {:?}", args.input()));
+            }
+        }
+        if changed && last_report.elapsed() >= interval {
+            emit_report(args, payments_engine)
+                .map_err(|e| format!("Could not write account information: {}", e))?;
+            last_report = Instant::now();
+            changed = false;
+        }
+    }
+    for row in receiver.try_iter() {
+        process_row(row, payments_engine, &mut Log, &mut on_row);
+    }
+    Ok(())
+}
+
+/// Starts the line-protocol server on a TCP address or, with prefix `unix:`, a Unix domain socket.
+fn open_server(addr: &str, options: ServerOptions) -> Result<Server, String> {
+    if let Some(path) = addr.strip_prefix("unix:") {
+        #[cfg(all(unix, feature = "unix-socket"))]
+        return std::os::unix::net::UnixListener::bind(path)
+            .map(|listener| {
+                eprintln!("Listening on {}", addr);
+                Server::unix_with_options(listener, options)
+            })
+            .map_err(|e| format!("Could not listen on {}: {}", addr, e));
+        #[cfg(not(all(unix, feature = "unix-socket")))]
+        return Err(format!("Cannot listen on {:?}: Unix domain sockets require the `unix-socket` \
+                            feature", path));
+    }
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| format!("Could not listen on {}: {}", addr, e))?;
+    let local_addr = listener.local_addr()
+        .map_err(|e| format!("Could not listen on {}: {}", addr, e))?;
+    // Without tokens, every connection is admin, so only local processes may connect
+    if options.tokens.is_none() && !local_addr.ip().is_loopback() {
+        return Err(format!("Listening on {} requires --listen-tokens, as it is not a loopback \
+                            address", addr));
+    }
+    eprintln!("Listening on {}", local_addr);
+    Ok(Server::tcp_with_options(listener, options))
+}
+
+/// Executes transactions received by the line-protocol server, re-emitting the account report at
+/// most every report interval whenever the state changed, until [SHUTDOWN] is requested.
+fn serve<F>(
+    args: &Args,
+    addr: &str,
+    payments_engine: &mut PaymentsEngine,
+    progress: Arc<Progress>,
+    mut on_row: F,
+) -> Result<(), String>
+    where F: FnMut(&PaymentsEngine, Row) -> ControlFlow<()>
 {
-    let transaction_iter = read_transactions(path)?
-        .filter_map(|r| r.map_err(|e| {
-            eprintln!("Invalid input row: {}", e)
-        }).ok());
+    let tokens = args.listen_tokens.as_ref()
+        .map(|path| std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| content.parse())
+            .map_err(|e| format!("Could not read tokens {:?}: {}", path, e)))
+        .transpose()?;
+    let rate_limit = RateLimit { per_client: args.rate_limit_per_client, global: args.rate_limit };
+    let server = open_server(addr, ServerOptions { tokens, rate_limit, progress: Some(progress) })?;
+    let interval = Duration::from_secs(args.report_interval);
+    let mut last_report = Instant::now();
+    let mut changed = false;
+    let mut execute = |request: Request, payments_engine: &mut PaymentsEngine| {
+        let transaction = match request.operation.clone() {
+            Operation::Execute(transaction) => transaction,
+            Operation::Subscribe { .. } => return request.answer(payments_engine),
+            Operation::Admin(operation) => {
+                if on_row(payments_engine, Row::Operation(&operation)).is_break() {
+                    return request.reject("unavailable");
+                }
+                let status = payments_engine.administer(operation);
+                let _ = on_row(payments_engine, Row::Administered(&operation, &status));
+                return request.reply(&status);
+            }
+        };
+        let replayed = transaction.idempotency_key.as_deref()
+            .and_then(|key| payments_engine.idempotent_outcome(key))
+            .cloned();
+        if let Some(status) = replayed {
+            request.reply(&status);
+            return;
+        }
+        if on_row(payments_engine, Row::Accepted(&transaction)).is_break() {
+            return request.reject("unavailable");
+        }
+        let outcome = payments_engine.execute_with_outcome(transaction.clone());
+        let _ = on_row(payments_engine, Row::Executed(&transaction, &outcome));
+        let now = transaction.timestamp;
+        request.reply(&outcome.status);
+        process_follow_ups(payments_engine, now, &mut Log, &mut on_row);
+    };
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        match server.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+            Ok(request) => {
+                execute(request, payments_engine);
+                changed = true;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(format!("Stopped listening on {}", addr));
+            }
+        }
+        for error in server.errors() {
+            log(&LogEvent::Warning { message: error.to_string() });
+        }
+        if changed && last_report.elapsed() >= interval {
+            emit_report(args, payments_engine)
+                .map_err(|e| format!("Could not write account information: {}", e))?;
+            last_report = Instant::now();
+            changed = false;
+        }
+    }
+    while let Ok(request) = server.recv_timeout(Duration::ZERO) {
+        execute(request, payments_engine);
+    }
+    if server.rate_limited() > 0 {
+        eprintln!("Rate-limited transactions: {}", server.rate_limited());
+    }
+    #[cfg(all(unix, feature = "unix-socket"))]
+    if let Some(path) = addr.strip_prefix("unix:") {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
+}
+
+/// Writes a synthetic transaction stream to stdout or the specified file.
+fn generate(args: GenerateArgs) -> Result<(), String> {
+    let config = GeneratorConfig {
+        clients: args.clients,
+        rows: args.rows,
+        withdrawal_ratio: args.withdrawal_ratio,
+        dispute_probability: args.dispute_probability,
+        chargeback_probability: args.chargeback_probability,
+        duplicate_probability: args.duplicate_probability,
+        invalid_probability: args.invalid_probability,
+        seed: args.seed,
+    };
+    let result = match &args.output {
+        Some(path) => File::create(path)
+            .and_then(|file| generator::write_csv(BufWriter::new(file), config)),
+        None => generator::write_csv(io::stdout().lock(), config),
+    };
+    result.map_err(|e| format!("Could not write transactions: {}", e))
+}
+
+/// Measures the throughput of parsing, executing, and both combined for the given file.
+///
+/// The file is read into memory first, so that the measurement does not include disk IO.
+fn bench(input: &Path) -> Result<(), String> {
+    let csv = std::fs::read(input).map_err(|e| format!("Could not read file {:?}: {}", input, e))?;
+    let print = |name: &str, t: Throughput| {
+        println!("{:<15} {:>10} rows {:>10.3} s {:>12.0} rows/s", name, t.rows,
+                 t.elapsed.as_secs_f64(), t.per_second());
+    };
+    let (parsed, transactions) = throughput::parse(&csv);
+    print("parse", parsed);
+    print("execute", throughput::execute(transactions));
+    print("parse+execute", throughput::end_to_end(&csv));
+    Ok(())
+}
+
+/// Exports the audit log at the given path in the given format.
+fn export_audit(log: &Path, format: AuditFormat, output: Option<&Path>) -> Result<(), String> {
+    let entries = audit::read_entries(log)
+        .map_err(|e| format!("Could not read audit log {:?}: {}", log, e))?;
+    let writer: Box<dyn io::Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path).map_err(|e| e.to_string())?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    match format {
+        AuditFormat::Csv => audit::write_csv(writer, entries),
+        AuditFormat::Ndjson => audit::write_ndjson(writer, entries),
+    }.map_err(|e| format!("Could not export audit log: {}", e))
+}
+
+/// Processes the input and writes the mismatches with the expected accounts to stdout. Fails if
+/// there is any mismatch.
+fn reconcile(input: &Path, expected: &Path) -> Result<(), String> {
+    let statement = File::open(expected)
+        .map_err(csv::Error::from)
+        .and_then(|file| read_accounts(file).collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Could not read file {:?}: {}", expected, e))?;
+    let transactions = File::open(input)
+        .map(read_transactions_from)
+        .map_err(|e| format!("Could not read file {:?}: {}", input, e))?;
+    let mut payments_engine = PaymentsEngine::new();
+    process_transactions(transactions, &mut payments_engine, &mut Log, |_, _| {});
+
+    let report = payments_engine.reconcile(statement);
+    report.write_csv(io::stdout().lock())
+        .map_err(|e| format!("Could not write reconciliation report: {}", e))?;
+    if report.is_reconciled() {
+        Ok(())
+    } else {
+        Err(format!("{} of {} clients do not match the statement", report.mismatches.len(),
+                    report.matched + report.mismatches.len()))
+    }
+}
+
+/// Processes the input and writes the statement of the given client to stdout.
+fn statement(args: StatementArgs) -> Result<(), String> {
+    let transactions = File::open(&args.input_csv)
+        .map(read_transactions_from)
+        .map_err(|e| format!("Could not read file {:?}: {}", args.input_csv, e))?;
     let mut payments_engine = PaymentsEngine::new();
-    for transaction in transaction_iter {
-        if let Err(err) = payments_engine.execute(transaction) {
-            eprintln!("{}", err)
+    payments_engine.enable_history();
+    process_transactions(transactions, &mut payments_engine, &mut Log, |_, _| {});
+
+    let mut statement = payments_engine.statement(args.client, args.from, args.to);
+    if let Some(tag) = &args.tag {
+        statement = statement.tagged(tag);
+    }
+    match args.format {
+        StatementFormat::Text => {
+            print!("{}", statement);
+            Ok(())
         }
+        StatementFormat::Csv => statement.write_csv(io::stdout().lock())
+            .map_err(|e| format!("Could not write statement: {}", e)),
     }
-    Ok(payments_engine)
+}
+
+/// Processes the input and writes aggregates over the batch to stdout.
+fn report(input: &Path, top: usize) -> Result<(), String> {
+    let transactions = File::open(input)
+        .map(read_transactions_from)
+        .map_err(|e| format!("Could not read file {:?}: {}", input, e))?;
+    let mut payments_engine = PaymentsEngine::new();
+    let mut stats = BatchStats::default();
+    process_transactions(transactions, &mut payments_engine, &mut Log, |_, row| match row {
+        Row::Invalid => stats.invalid_rows += 1,
+        Row::Accepted(_) | Row::Operation(_) | Row::Administered(..) => {}
+        Row::Executed(transaction, outcome)
+        | Row::Reordered(transaction, outcome)
+        | Row::Expired(transaction, outcome)
+        | Row::Scheduled(transaction, outcome) => stats.record(transaction, outcome),
+    });
+    stats.write_summary(io::stdout().lock(), &payments_engine, top)
+        .map_err(|e| format!("Could not write report: {}", e))
+}
+
+/// Reads an account report in CSV or, if the file name ends with `.json`, JSON format.
+fn read_account_report(path: &Path) -> Result<Vec<Account>, String> {
+    let file = File::open(path).map_err(|e| format!("Could not read file {:?}: {}", path, e))?;
+    if path.extension().is_some_and(|extension| extension == "json") {
+        serde_json::from_reader(io::BufReader::new(file)).map_err(|e| e.to_string())
+    } else {
+        read_accounts(file).collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }.map_err(|e| format!("Could not read account report {:?}: {}", path, e))
+}
+
+/// Writes the per-client differences between two account reports to stdout.
+fn diff(before: &Path, after: &Path) -> Result<(), String> {
+    let report = reconcile::compare(read_account_report(before)?, read_account_report(after)?);
+    report.write_csv(io::stdout().lock())
+        .map_err(|e| format!("Could not write differences: {}", e))?;
+    eprintln!("{} of {} clients differ", report.mismatches.len(),
+              report.matched + report.mismatches.len());
+    Ok(())
+}
+
+/// Records the input as golden fixture.
+fn record(input: &Path, fixtures: &Path, name: Option<&str>) -> Result<(), String> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => input.file_stem()
+            .ok_or_else(|| format!("Could not derive fixture name from {:?}", input))?
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let fixture = golden::record(input, fixtures, &name)
+        .map_err(|e| format!("Could not record fixture {:?}: {}", name, e))?;
+    eprintln!("Recorded fixture {:?}", fixture);
+    Ok(())
+}
+
+/// Prints the JSON Schema of the given payload, or of all payloads by name.
+#[cfg(feature = "schemars")]
+fn schema(payload: Option<&str>) -> Result<(), String> {
+    let mut schemas = toy_payments_engine::schema::schemas();
+    let stdout = io::stdout().lock();
+    let result = match payload {
+        Some(payload) => serde_json::to_writer_pretty(stdout, &schemas.remove(payload)),
+        None => serde_json::to_writer_pretty(stdout, &schemas),
+    };
+    result.map_err(|e| format!("Could not write schema: {}", e))?;
+    println!();
+    Ok(())
+}
+
+/// Returns the command line interface including the `--config` option.
+fn command() -> clap::Command<'static> {
+    Cli::command().arg(Arg::new("config")
+        .long("config")
+        .value_name("FILE")
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("Read options from this TOML file (keys like `dispute_window = 100`, `true` for \
+               flags, arrays for repeated options); options on the command line take precedence"))
+}
+
+/// Returns the command line arguments with those from the `--config` file prepended, leaving out
+/// options that are given on the command line.
+fn args_with_config(args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    let command = command();
+    let Ok(matches) = command.clone().ignore_errors(true).try_get_matches_from(&args) else {
+        return Ok(args);
+    };
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return Ok(args);
+    };
+    let config: toml::Table = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|config| config.parse().map_err(|e: toml::de::Error| e.to_string()))
+        .map_err(|e| format!("Could not read config {:?}: {}", path, e))?;
+    let mut config_args = Vec::new();
+    for (key, value) in config {
+        let id = key.replace('_', "-");
+        let arg = command.get_arguments()
+            .find(|arg| arg.get_id() == id && id != "config")
+            .ok_or_else(|| format!("Unknown option {:?} in config {:?}", key, path))?;
+        if matches.value_source(&id) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::Boolean(true) if !arg.is_positional() => format!("--{}", id),
+                toml::Value::Boolean(false) => continue,
+                toml::Value::String(value) if arg.is_positional() => value,
+                toml::Value::String(value) => format!("--{}={}", id, value),
+                toml::Value::Integer(_) | toml::Value::Float(_) => format!("--{}={}", id, value),
+                _ => return Err(format!("Invalid value of {:?} in config {:?}", key, path)),
+            };
+            config_args.push(OsString::from(value));
+        }
+    }
+    let mut args = args.into_iter();
+    Ok(args.next().into_iter().chain(config_args).chain(args).collect())
 }
 
 pub fn main() -> ExitCode {
-    let args = Args::parse();
-    if let Ok(payments_engine) = process_transactions(&args.input_csv) {
-        if let Err(error) = write_account_info(payments_engine.accounts()) {
-            eprintln!("Could not write account information: {}", error);
-            return ExitCode::FAILURE;
+    let cli = args_with_config(std::env::args_os().collect())
+        .and_then(|args| Cli::from_arg_matches(&command().get_matches_from(args))
+            .map_err(|e| e.to_string()));
+    let cli = match cli {
+        Ok(cli) => cli,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::from(EXIT_UNREADABLE);
+        }
+    };
+    let result = match cli.command {
+        Some(Command::Generate(args)) => generate(args),
+        Some(Command::Bench { input_csv }) => bench(&input_csv),
+        Some(Command::ExportAudit { log, format, output }) => {
+            export_audit(&log, format, output.as_deref())
+        }
+        Some(Command::Reconcile { input_csv, expected }) => reconcile(&input_csv, &expected),
+        Some(Command::Statement(args)) => statement(args),
+        Some(Command::Report { input_csv, top }) => report(&input_csv, top),
+        Some(Command::Diff { before, after }) => diff(&before, &after),
+        Some(Command::Record { input_csv, fixtures, name }) => {
+            record(&input_csv, &fixtures, name.as_deref())
+        }
+        #[cfg(feature = "schemars")]
+        Some(Command::Schema { payload }) => schema(payload.as_deref()),
+        None => {
+            return run(cli.args);
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
         }
-    } else {
-        eprintln!("Could not read file {:?}", args.input_csv);
-        return ExitCode::FAILURE;
     }
-    ExitCode::SUCCESS
 }
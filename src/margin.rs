@@ -0,0 +1,104 @@
+//! Reservation of funds outside the dispute flow, e.g. as trading margin
+//!
+//! Reserved funds are moved from `available` to `held` until they are released. Reservations use
+//! transaction IDs of their own, independent of deposits.
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{PaymentError, Result};
+use crate::ledger::LedgerAccount;
+use crate::PaymentsEngine;
+
+/// Funds of a client held by a reservation
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct Reservation {
+    pub(crate) client: u16,
+    pub(crate) amount: Decimal,
+}
+
+impl PaymentsEngine {
+    /// Moves the amount from the client's available to held funds until it is released.
+    ///
+    /// Fails if the client account does not exist, is locked, or has insufficient available funds,
+    /// or a reservation with the same transaction ID exists.
+    pub fn reserve(&mut self, client: u16, tx: u32, amount: Decimal) -> Result<()> {
+        self.next_sequence();
+        let account = self.accounts.get_mut(&client).ok_or_else(|| {
+            PaymentError::UnknownClient { client, tx_type: String::from("Reserve") }
+        })?;
+        account.assert_not_locked(client, tx)?;
+        if self.reservations.contains_key(&tx) {
+            return Err(PaymentError::DuplicateTransaction { client, tx });
+        }
+        if account.available < amount {
+            return Err(PaymentError::InsufficientFunds {
+                client,
+                tx,
+                available: account.available,
+                amount,
+            });
+        }
+        account.available -= amount;
+        account.held += amount;
+        self.reservations.insert(tx, Reservation { client, amount });
+        self.post(tx, amount, &[
+            (LedgerAccount::ClientAvailable(client), LedgerAccount::ClientHeld(client)),
+        ]);
+        Ok(())
+    }
+
+    /// Moves the funds of a reservation back from the client's held to available funds.
+    ///
+    /// Fails if the reservation does not exist (for this client) or the account is locked.
+    pub fn release(&mut self, client: u16, tx: u32) -> Result<()> {
+        self.next_sequence();
+        let Reservation { amount, .. } = self.reservations.get(&tx)
+            .filter(|r| r.client == client)
+            .copied()
+            .ok_or_else(|| {
+                PaymentError::UnknownTransaction { client, tx, tx_type: String::from("Release") }
+            })?;
+        let account = self.accounts.get_mut(&client).expect("reserving client exists");
+        account.assert_not_locked(client, tx)?;
+        account.held -= amount;
+        account.available += amount;
+        self.reservations.remove(&tx);
+        self.post(tx, amount, &[
+            (LedgerAccount::ClientHeld(client), LedgerAccount::ClientAvailable(client)),
+        ]);
+        Ok(())
+    }
+
+    /// Returns the funds of the client held by reservations.
+    pub fn reserved(&self, client: u16) -> Decimal {
+        self.reservations.values().filter(|r| r.client == client).map(|r| r.amount).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_funds_are_held_until_released() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        engine.reserve(1, 7, Decimal::new(4, 0)).unwrap();
+        assert!(matches!(engine.reserve(1, 7, Decimal::ONE),
+            Err(PaymentError::DuplicateTransaction { tx: 7, .. })));
+        assert!(matches!(engine.reserve(1, 8, Decimal::new(7, 0)),
+            Err(PaymentError::InsufficientFunds { tx: 8, .. })));
+        assert!(engine.withdraw(1, 2, Decimal::new(7, 0)).is_err());
+
+        let account = engine.account(1).unwrap();
+        assert_eq!((Decimal::new(6, 0), Decimal::new(4, 0)), (account.available, account.held));
+        assert_eq!(Decimal::new(4, 0), engine.reserved(1));
+        assert!(matches!(engine.release(2, 7),
+            Err(PaymentError::UnknownTransaction { tx: 7, .. })));
+
+        engine.release(1, 7).unwrap();
+        assert_eq!(Decimal::new(10, 0), engine.account(1).unwrap().available);
+        assert_eq!(Decimal::ZERO, engine.reserved(1));
+        assert!(engine.release(1, 7).is_err());
+    }
+}
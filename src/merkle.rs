@@ -0,0 +1,171 @@
+//! Merkle tree over the log of executed transactions
+//!
+//! With [crate::EngineConfig::merkle_log] enabled, the engine records a leaf hash for every
+//! executed transaction (accepted or not). [PaymentsEngine::proof_for] then proves that a
+//! transaction is part of the batch with the root [PaymentsEngine::merkle_root], which a
+//! counterparty can check with [verify_proof] without access to the other transactions.
+//!
+//! Leaves and inner nodes are hashed with different prefixes, and a node without sibling is
+//! promoted to the next level unchanged instead of being paired with itself.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::engine::Map;
+use crate::models::Transaction;
+use crate::PaymentsEngine;
+
+/// SHA-256 hash of a tree node
+pub type Hash = [u8; 32];
+
+/// Hashes of the executed transactions in order of execution
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct MerkleLog {
+    leaves: Vec<Hash>,
+    /// Position of the first transaction with a given ID
+    positions: Map<u32, usize>,
+}
+
+impl MerkleLog {
+    pub(crate) fn record(&mut self, transaction: &Transaction) {
+        self.positions.entry(transaction.tx).or_insert(self.leaves.len());
+        self.leaves.push(leaf_hash(transaction));
+    }
+}
+
+/// Side on which a sibling hash is combined with the running hash
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Proof that a transaction is included in the tree with the given root
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MerkleProof {
+    /// Position of the transaction in the log
+    pub index: usize,
+    /// Sibling hashes from the leaf up to the root
+    pub path: Vec<(Side, Hash)>,
+    /// Root of the tree the proof was created for
+    pub root: Hash,
+}
+
+impl PaymentsEngine {
+    /// Returns the root of the Merkle tree over all executed transactions, `None` if no
+    /// transactions were recorded.
+    pub fn merkle_root(&self) -> Option<Hash> {
+        let mut level = self.merkle.leaves.clone();
+        if level.is_empty() {
+            return None;
+        }
+        while level.len() > 1 {
+            level = next_level(&level);
+        }
+        Some(level[0])
+    }
+
+    /// Returns a proof that the first executed transaction with the given ID is part of the
+    /// tree, `None` if no such transaction was recorded.
+    pub fn proof_for(&self, tx: u32) -> Option<MerkleProof> {
+        let index = *self.merkle.positions.get(&tx)?;
+        let mut level = self.merkle.leaves.clone();
+        let mut position = index;
+        let mut path = Vec::new();
+        while level.len() > 1 {
+            let sibling = position ^ 1;
+            if let Some(hash) = level.get(sibling) {
+                let side = if sibling < position { Side::Left } else { Side::Right };
+                path.push((side, *hash));
+            }
+            level = next_level(&level);
+            position /= 2;
+        }
+        Some(MerkleProof { index, path, root: level[0] })
+    }
+}
+
+/// Returns true iff the proof shows that the transaction is included in the tree with the given
+/// root.
+pub fn verify_proof(transaction: &Transaction, proof: &MerkleProof, root: &Hash) -> bool {
+    let computed = proof.path.iter().fold(leaf_hash(transaction), |hash, (side, sibling)| {
+        match side {
+            Side::Left => node_hash(sibling, &hash),
+            Side::Right => node_hash(&hash, sibling),
+        }
+    });
+    computed == *root && proof.root == *root
+}
+
+/// Hashes the canonical encoding of the transaction.
+pub fn leaf_hash(transaction: &Transaction) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0]);
+    hasher.update(transaction.transaction_type.to_string());
+    hasher.update([0]);
+    hasher.update(transaction.client.to_be_bytes());
+    hasher.update(transaction.tx.to_be_bytes());
+    if let Some(amount) = transaction.amount {
+        hasher.update(amount.normalize().to_string());
+    }
+    hasher.update([0]);
+    if let Some(reason) = transaction.reason {
+        hasher.update(reason.to_string());
+    }
+    hasher.finalize().into()
+}
+
+/// Combines pairs of nodes, promoting a node without sibling.
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level.chunks(2).map(|pair| match pair {
+        [left, right] => node_hash(left, right),
+        [single] => *single,
+        _ => unreachable!("chunks are never empty"),
+    }).collect()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::EngineConfig;
+
+    fn deposit(tx: u32) -> Transaction {
+        Transaction::deposit(1, tx, Decimal::new(tx as i64, 1))
+    }
+
+    #[test]
+    fn proofs_verify_for_all_transactions() {
+        let mut engine = PaymentsEngine::with_config(EngineConfig {
+            merkle_log: true,
+            ..Default::default()
+        });
+        assert_eq!(None, engine.merkle_root());
+        for tx in 1..=5 {
+            let _ = engine.execute(deposit(tx));
+        }
+        let root = engine.merkle_root().unwrap();
+
+        for tx in 1..=5 {
+            let proof = engine.proof_for(tx).unwrap();
+            assert!(verify_proof(&deposit(tx), &proof, &root));
+            assert!(!verify_proof(&deposit(tx + 1), &proof, &root));
+        }
+        assert_eq!(None, engine.proof_for(6));
+    }
+
+    #[test]
+    fn nothing_is_recorded_by_default() {
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit(1)).unwrap();
+        assert_eq!(None, engine.merkle_root());
+    }
+}
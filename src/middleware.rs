@@ -0,0 +1,119 @@
+//! Middleware around [PaymentsEngine::execute]
+//!
+//! Middleware added with [PaymentsEngine::add_middleware] wraps the execution of every transaction,
+//! like layers of a service: the first one added is the outermost. Each middleware receives the
+//! transaction and the remaining chain ([Next]), so it can transform the transaction, skip or
+//! repeat the rest of the chain, act on the engine afterwards (e.g. charge a fee), or map the
+//! outcome. Closures with the signature of [Middleware::call] work as middleware, too.
+//!
+//! While the chain runs, the middleware is detached from the engine: executing further
+//! transactions via [PaymentsEngine::execute] from within a middleware bypasses the chain.
+use rust_decimal::Decimal;
+
+use crate::error::Result;
+use crate::models::{Transaction, TransactionType};
+use crate::PaymentsEngine;
+
+/// Layer around the execution of transactions, see [crate::middleware]
+pub trait Middleware: Send {
+    /// Handles the transaction, usually by passing it on to `next`.
+    fn call(&mut self, transaction: Transaction, engine: &mut PaymentsEngine, next: Next<'_>)
+        -> Result<()>;
+}
+
+impl<F> Middleware for F
+    where F: FnMut(Transaction, &mut PaymentsEngine, Next<'_>) -> Result<()> + Send
+{
+    fn call(&mut self, transaction: Transaction, engine: &mut PaymentsEngine, next: Next<'_>)
+        -> Result<()>
+    {
+        self(transaction, engine, next)
+    }
+}
+
+/// Remaining middleware of the chain, followed by the execution itself
+pub struct Next<'a> {
+    middleware: &'a mut [Box<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middleware: &'a mut [Box<dyn Middleware>]) -> Self {
+        Self { middleware }
+    }
+
+    /// Passes the transaction to the next middleware, or executes it at the end of the chain.
+    pub fn run(self, transaction: Transaction, engine: &mut PaymentsEngine) -> Result<()> {
+        match self.middleware.split_first_mut() {
+            Some((middleware, rest)) => middleware.call(transaction, engine, Next::new(rest)),
+            None => engine.execute_idempotent(transaction),
+        }
+    }
+}
+
+/// Charges a fixed fee after every successful withdrawal. If the fee cannot be charged, e.g. due
+/// to insufficient funds, the withdrawal still succeeds and the fee is dropped.
+#[derive(Clone, Debug)]
+pub struct WithdrawalFee {
+    pub fee: Decimal,
+}
+
+impl Middleware for WithdrawalFee {
+    fn call(&mut self, transaction: Transaction, engine: &mut PaymentsEngine, next: Next<'_>)
+        -> Result<()>
+    {
+        let Transaction { transaction_type, client, tx, .. } = transaction;
+        next.run(transaction, engine)?;
+        if transaction_type == TransactionType::Withdrawal {
+            let _ = engine.charge_fee(client, tx, self.fee);
+        }
+        Ok(())
+    }
+}
+
+impl PaymentsEngine {
+    /// Adds a middleware inside all middleware added before.
+    pub fn add_middleware(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(Box::new(middleware));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::csv::read_transactions_from;
+    use crate::PaymentError;
+
+    #[test]
+    fn middleware_wraps_execution_in_order() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = PaymentsEngine::new();
+        let outer = events.clone();
+        engine.add_middleware(move |t: Transaction, e: &mut PaymentsEngine, next: Next<'_>| {
+            let tx = t.tx;
+            let result = next.run(t, e);
+            outer.lock().unwrap().push((tx, result.is_ok()));
+            result
+        });
+        engine.add_middleware(WithdrawalFee { fee: Decimal::ONE });
+        engine.add_middleware(|t: Transaction, e: &mut PaymentsEngine, next: Next<'_>| {
+            match next.run(t, e) {
+                Err(PaymentError::InsufficientFunds { .. }) => Ok(()),
+                result => result,
+            }
+        });
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,10\n\
+                   withdrawal,1,2,4\n\
+                   withdrawal,1,3,50\n\
+                   withdrawal,2,4,1\n";
+        for transaction in read_transactions_from(csv.as_bytes()) {
+            let _ = engine.execute(transaction.unwrap());
+        }
+
+        // the ignored insufficient funds count as success, so a fee is charged for tx 3, too
+        assert_eq!(Decimal::new(4, 0), engine.account(1).unwrap().available);
+        assert_eq!(vec![(1, true), (2, true), (3, true), (4, false)], *events.lock().unwrap());
+    }
+}
@@ -1,9 +1,14 @@
 //! Public structs of this crate
+use std::fmt;
+
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
+use crate::error;
+
 /// Enumeration of the transaction types
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -11,24 +16,305 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Refund,
+    Adjustment,
+}
+
+impl TransactionType {
+    /// Parses a type name regardless of case and of `-`, `_`, or spaces within it, also accepting
+    /// common aliases such as `withdraw` or `charge-back`, or returns `None` if unknown.
+    pub fn parse_lenient(name: &str) -> Option<Self> {
+        let name: String = name.chars()
+            .filter(|c| !matches!(c, '-' | '_' | ' '))
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        match name.as_str() {
+            "deposit" | "credit" => Some(TransactionType::Deposit),
+            "withdrawal" | "withdraw" | "debit" => Some(TransactionType::Withdrawal),
+            "dispute" => Some(TransactionType::Dispute),
+            "resolve" | "resolution" => Some(TransactionType::Resolve),
+            "chargeback" => Some(TransactionType::Chargeback),
+            "refund" => Some(TransactionType::Refund),
+            "adjustment" | "adjust" => Some(TransactionType::Adjustment),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Refund => "refund",
+            TransactionType::Adjustment => "adjustment",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Reason code given for a dispute
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum DisputeReason {
+    Fraud,
+    ProductNotReceived,
+    Duplicate,
+}
+
+impl fmt::Display for DisputeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DisputeReason::Fraud => "fraud",
+            DisputeReason::ProductNotReceived => "product-not-received",
+            DisputeReason::Duplicate => "duplicate",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Stage in the lifecycle of a dispute
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisputeState {
+    /// Disputed funds are held
+    Opened,
+    /// Disputed funds are held while the dispute is being reviewed
+    UnderReview,
+    /// Disputed funds were released to the client
+    Resolved,
+    /// Disputed funds were reversed and the account locked
+    ChargedBack,
+    /// Chargeback was reversed and the funds reinstated
+    Represented,
+}
+
+impl DisputeState {
+    /// Returns true iff the disputed funds are (still) held.
+    pub fn is_open(self) -> bool {
+        matches!(self, DisputeState::Opened | DisputeState::UnderReview)
+    }
+}
+
+impl fmt::Display for DisputeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DisputeState::Opened => "opened",
+            DisputeState::UnderReview => "under-review",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "charged-back",
+            DisputeState::Represented => "represented",
+        };
+        f.write_str(name)
+    }
+}
+
+/// State and reason code of the latest dispute of a deposit
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DisputeStatus {
+    pub state: DisputeState,
+    pub reason: Option<DisputeReason>,
 }
 
 /// Representation of a transaction
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct Transaction {
-    /// One of five transaction types
+    /// One of seven transaction types
     #[serde(rename = "type")]
     pub transaction_type: TransactionType,
     /// Client identifier
     pub client: u16,
     /// Transaction identifier
     pub tx: u32,
-    /// Amount: only required with deposits and withdrawals
+    /// Amount: only required with deposits, withdrawals, refunds, and adjustments (signed)
     pub amount: Option<Decimal>,
+    /// Reason code: only used with disputes, optional column
+    #[serde(default)]
+    pub reason: Option<DisputeReason>,
+    /// Timestamp, e.g. seconds since the Unix epoch: optional column
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Named wallet of the client, `None` for the default one: only used with deposits and
+    /// withdrawals, optional column
+    #[serde(default)]
+    pub wallet: Option<String>,
+    /// Free-text annotation, the reason of adjustments: optional column
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// Labels such as campaign IDs: optional column, separated by `;`
+    #[serde(default, with = "tags")]
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub tags: Vec<String>,
+    /// Key identifying redeliveries of the same transaction, e.g. a message ID: optional column
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// Currency code, e.g. `EUR`: only checked by validators (see [crate::validate]), optional
+    /// column
+    #[serde(default)]
+    pub currency: Option<String>,
+}
+
+impl Transaction {
+    /// Returns a transaction of the given type without any of the optional columns.
+    pub fn new(transaction_type: TransactionType, client: u16, tx: u32, amount: Option<Decimal>)
+        -> Self
+    {
+        Self {
+            transaction_type,
+            client,
+            tx,
+            amount,
+            reason: None,
+            timestamp: None,
+            wallet: None,
+            memo: None,
+            tags: Vec::new(),
+            idempotency_key: None,
+            currency: None,
+        }
+    }
+
+    /// Returns a deposit of the given amount.
+    pub fn deposit(client: u16, tx: u32, amount: Decimal) -> Self {
+        Self::new(TransactionType::Deposit, client, tx, Some(amount))
+    }
+
+    /// Returns a withdrawal of the given amount.
+    pub fn withdrawal(client: u16, tx: u32, amount: Decimal) -> Self {
+        Self::new(TransactionType::Withdrawal, client, tx, Some(amount))
+    }
+
+    /// Returns a dispute of deposit `tx`.
+    pub fn dispute(client: u16, tx: u32) -> Self {
+        Self::new(TransactionType::Dispute, client, tx, None)
+    }
+
+    /// Returns a resolve of the dispute of deposit `tx`.
+    pub fn resolve(client: u16, tx: u32) -> Self {
+        Self::new(TransactionType::Resolve, client, tx, None)
+    }
+
+    /// Returns a chargeback of the dispute of deposit `tx`.
+    pub fn chargeback(client: u16, tx: u32) -> Self {
+        Self::new(TransactionType::Chargeback, client, tx, None)
+    }
+
+    /// Returns a refund of the given amount of withdrawal `tx`.
+    pub fn refund(client: u16, tx: u32, amount: Decimal) -> Self {
+        Self::new(TransactionType::Refund, client, tx, Some(amount))
+    }
+
+    /// Returns an adjustment by the signed amount for the given reason.
+    pub fn adjustment(client: u16, tx: u32, amount: Decimal, reason: impl Into<String>) -> Self {
+        Self::new(TransactionType::Adjustment, client, tx, Some(amount)).with_memo(reason)
+    }
+
+    /// Sets the reason code of a dispute.
+    pub fn with_reason(mut self, reason: DisputeReason) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+
+    /// Sets the timestamp.
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the named wallet.
+    pub fn with_wallet(mut self, wallet: impl Into<String>) -> Self {
+        self.wallet = Some(wallet.into());
+        self
+    }
+
+    /// Sets the memo.
+    pub fn with_memo(mut self, memo: impl Into<String>) -> Self {
+        self.memo = Some(memo.into());
+        self
+    }
+
+    /// Adds a tag.
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Sets the idempotency key.
+    pub fn with_idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Sets the currency code.
+    pub fn with_currency(mut self, currency: impl Into<String>) -> Self {
+        self.currency = Some(currency.into());
+        self
+    }
+}
+
+#[cfg(test)]
+impl Transaction {
+    /// Returns a transaction with a whole amount, without amount if zero, for unit tests.
+    pub(crate) fn fixture(kind: TransactionType, client: u16, tx: u32, amount: i64) -> Self {
+        Self::new(kind, client, tx, (amount != 0).then(|| Decimal::new(amount, 0)))
+    }
+}
+
+/// Administrative operation on the engine that is not a transaction
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminOperation {
+    /// Mark an opened dispute as under review, see [crate::PaymentsEngine::review]
+    Review { client: u16, tx: u32 },
+    /// Reverse a chargeback, see [crate::PaymentsEngine::represent]
+    Represent { client: u16, tx: u32, unlock: bool },
+    /// Unlock an account, see [crate::PaymentsEngine::unlock]
+    Unlock { client: u16 },
+}
+
+impl AdminOperation {
+    /// Returns the client the operation applies to.
+    pub fn client(&self) -> u16 {
+        match *self {
+            AdminOperation::Review { client, .. }
+            | AdminOperation::Represent { client, .. }
+            | AdminOperation::Unlock { client } => client,
+        }
+    }
+
+    /// Returns the transaction the operation applies to, 0 for [AdminOperation::Unlock].
+    pub fn tx(&self) -> u32 {
+        match *self {
+            AdminOperation::Review { tx, .. } | AdminOperation::Represent { tx, .. } => tx,
+            AdminOperation::Unlock { .. } => 0,
+        }
+    }
+}
+
+impl fmt::Display for AdminOperation {
+    /// Formats the operation like the server protocol, e.g. `REPRESENT 1 2 unlock`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminOperation::Review { client, tx } => write!(f, "REVIEW {} {}", client, tx),
+            AdminOperation::Represent { client, tx, unlock: false } => {
+                write!(f, "REPRESENT {} {}", client, tx)
+            }
+            AdminOperation::Represent { client, tx, unlock: true } => {
+                write!(f, "REPRESENT {} {} unlock", client, tx)
+            }
+            AdminOperation::Unlock { client } => write!(f, "UNLOCK {}", client),
+        }
+    }
 }
 
 /// Information about client account
-#[derive(Debug, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct Account {
     /// Client identifier
     pub client: u16,
@@ -40,4 +326,224 @@ pub struct Account {
     pub total: Decimal,
     // True iff account is locked (if charge back occurred)
     pub locked: bool,
-}
\ No newline at end of file
+}
+
+impl Account {
+    /// Returns true iff the total funds are not negative, e.g. due to an overdraft.
+    pub fn is_solvent(&self) -> bool {
+        self.total >= Decimal::ZERO
+    }
+
+    /// Returns the share of the total funds held for disputes, `None` if the total is not positive.
+    pub fn disputed_ratio(&self) -> Option<Decimal> {
+        (self.total > Decimal::ZERO).then(|| self.held / self.total)
+    }
+}
+
+/// Deposit kept for potential disputes, see [crate::PaymentsEngine::deposits]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct DepositInfo {
+    /// Transaction identifier
+    pub tx: u32,
+    /// Client identifier
+    pub client: u16,
+    /// Deposited amount
+    pub amount: Decimal,
+    /// True iff the deposit is disputed and its amount held
+    pub disputed: bool,
+    /// True iff a dispute of the deposit would currently be accepted, apart from funds checks
+    pub disputable: bool,
+    /// Latest dispute, `None` if the deposit was never disputed
+    pub dispute: Option<DisputeStatus>,
+}
+
+/// Effect of an executed transaction on the client's account
+#[derive(Debug)]
+pub struct TransactionOutcome {
+    /// Client identifier
+    pub client: u16,
+    /// Transaction identifier
+    pub tx: u32,
+    /// Type of the executed transaction
+    pub kind: TransactionType,
+    /// Account before execution, `None` if it did not exist
+    pub balance_before: Option<Account>,
+    /// Account after execution, `None` if it does not exist
+    pub balance_after: Option<Account>,
+    /// Result of the execution; balances are unchanged if it failed
+    pub status: error::Result<()>,
+    /// Rule that automatically locked the account due to this transaction, see
+    /// [crate::autolock]
+    pub auto_lock: Option<String>,
+}
+
+/// Reason why a transaction would be rejected, see [crate::PaymentsEngine::explain]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RejectionReason {
+    /// Deposit or withdrawal without amount
+    MissingAmount,
+    /// Adjustment without reason (memo)
+    MissingReason,
+    /// Client account does not exist
+    UnknownClient,
+    /// Client account is locked
+    LockedAccount,
+    /// Available funds are less than the amount to be withdrawn or disputed
+    InsufficientFunds { available: Decimal, amount: Decimal },
+    /// Withdrawal would overdraw the available funds beyond the client's overdraft limit
+    OverdraftExceeded { available: Decimal, limit: Decimal, amount: Decimal },
+    /// Withdrawal would leave less than the minimum balance, see [crate::dust]
+    BelowMinimumBalance { min_balance: Decimal },
+    /// Refund exceeds the amount of the referenced withdrawal not refunded yet
+    RefundExceedsWithdrawal { refundable: Decimal, amount: Decimal },
+    /// Referenced deposit (or withdrawal for refunds) does not exist (for this client)
+    UnknownTransaction,
+    /// Deposit with this transaction ID already exists
+    DuplicateTransaction,
+    /// Referenced deposit is already disputed
+    AlreadyDisputed,
+    /// Referenced deposit is not disputed
+    NotDisputed,
+    /// Referenced deposit was charged back
+    ChargedBack,
+    /// Dispute window of the referenced deposit has passed, see [crate::compact]
+    DisputeWindowPassed,
+    /// Withdrawal exceeds the velocity limit, see [crate::velocity]
+    VelocityLimitExceeded,
+    /// Client is blocked by screening, see [crate::screening]
+    ClientBlocked,
+    /// Transaction violates the rule of a validator, see [crate::validate]
+    ValidationFailed(String),
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectionReason::MissingAmount => f.write_str("amount is missing"),
+            RejectionReason::MissingReason => f.write_str("reason is missing"),
+            RejectionReason::UnknownClient => f.write_str("client account does not exist"),
+            RejectionReason::LockedAccount => f.write_str("client account is locked"),
+            RejectionReason::InsufficientFunds { available, amount } => {
+                write!(f, "insufficient funds (available: {}, necessary: {})", available, amount)
+            }
+            RejectionReason::OverdraftExceeded { available, limit, amount } => {
+                write!(f, "overdraft limit exceeded (available: {}, limit: {}, necessary: {})",
+                       available, limit, amount)
+            }
+            RejectionReason::BelowMinimumBalance { min_balance } => {
+                write!(f, "balance would fall below the minimum of {}", min_balance)
+            }
+            RejectionReason::RefundExceedsWithdrawal { refundable, amount } => {
+                write!(f, "refund exceeds withdrawal (refundable: {}, refund: {})", refundable,
+                       amount)
+            }
+            RejectionReason::UnknownTransaction => {
+                f.write_str("referenced deposit does not exist for this client")
+            }
+            RejectionReason::DuplicateTransaction => {
+                f.write_str("deposit with this transaction ID already exists")
+            }
+            RejectionReason::AlreadyDisputed => f.write_str("deposit is already disputed"),
+            RejectionReason::NotDisputed => f.write_str("deposit is not disputed"),
+            RejectionReason::ChargedBack => f.write_str("deposit was charged back"),
+            RejectionReason::DisputeWindowPassed => f.write_str("dispute window has passed"),
+            RejectionReason::VelocityLimitExceeded => {
+                f.write_str("withdrawal exceeds the velocity limit")
+            }
+            RejectionReason::ClientBlocked => f.write_str("client is blocked by screening"),
+            RejectionReason::ValidationFailed(reason) => write!(f, "validation failed: {}", reason),
+        }
+    }
+}
+
+/// (De)serialization of tags as a single field separated by `;`, e.g. `campaign-7;promo`
+///
+/// Sequences of strings are accepted as well, e.g. in JSON.
+pub(crate) mod tags {
+    use std::fmt;
+
+    use serde::de::{SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    /// Separator of tags within a single field
+    pub const SEPARATOR: char = ';';
+
+    /// Splits a field into its non-empty tags.
+    pub fn split(field: &str) -> Vec<String> {
+        field.split(SEPARATOR).map(str::trim).filter(|t| !t.is_empty()).map(String::from).collect()
+    }
+
+    pub fn serialize<S: Serializer>(tags: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&tags.join(&SEPARATOR.to_string()))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct TagsVisitor;
+
+        impl<'de> Visitor<'de> for TagsVisitor {
+            type Value = Vec<String>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("tags separated by ';' or a sequence of tags")
+            }
+
+            fn visit_str<E>(self, field: &str) -> Result<Self::Value, E> {
+                Ok(split(field))
+            }
+
+            fn visit_unit<E>(self) -> Result<Self::Value, E> {
+                Ok(Vec::new())
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut tags = Vec::new();
+                while let Some(tag) = seq.next_element()? {
+                    tags.push(tag);
+                }
+                Ok(tags)
+            }
+        }
+
+        deserializer.deserialize_any(TagsVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constructed_transactions_match_parsed_rows() {
+        let csv = "type,client,tx,amount,reason,timestamp,memo\n\
+                   deposit,1,1,2.5,,,\n\
+                   dispute,1,1,,fraud,7,\n\
+                   adjustment,2,3,-1,,,correction\n";
+        let parsed: Vec<_> = crate::csv::read_transactions_from(csv.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(vec![
+            Transaction::deposit(1, 1, Decimal::new(25, 1)),
+            Transaction::dispute(1, 1).with_reason(DisputeReason::Fraud).with_timestamp(7),
+            Transaction::adjustment(2, 3, Decimal::new(-1, 0), "correction"),
+        ], parsed);
+    }
+
+    #[test]
+    fn accounts_can_be_collected_in_sets() {
+        let account = |client, available: i64, held: i64| Account {
+            client,
+            available: Decimal::new(available, 0),
+            held: Decimal::new(held, 0),
+            total: Decimal::new(available + held, 0),
+            locked: false,
+        };
+        let accounts = [account(1, 5, 5), account(2, -4, 2), account(1, 5, 5)];
+        assert_eq!(2, accounts.iter().collect::<std::collections::HashSet<_>>().len());
+
+        assert_eq!((true, Some(Decimal::new(5, 1))),
+                   (accounts[0].is_solvent(), accounts[0].disputed_ratio()));
+        assert_eq!((false, None), (accounts[1].is_solvent(), accounts[1].disputed_ratio()));
+    }
+}
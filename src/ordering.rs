@@ -0,0 +1,231 @@
+//! Timestamp-ordered execution of inputs that are not strictly ordered, e.g. merged files
+//!
+//! [TimestampOrdered] wraps an iterator over parsed rows and checks or restores the order of their
+//! timestamps according to an [OutOfOrderPolicy]. Rows that are rejected by the policy are
+//! returned as errors, like rows that could not be parsed. Warnings about rows that are kept go to
+//! an [ErrorSink].
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::Transaction;
+use crate::pipeline::{ErrorSink, Stderr};
+
+/// Treatment of rows with a timestamp earlier than that of a preceding row
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutOfOrderPolicy {
+    /// Buffer rows and release them sorted by timestamp once the input has advanced by the
+    /// window (e.g. 60 for a minute with timestamps in seconds); rows later than that are rejected
+    Reorder {
+        window: u64,
+    },
+    /// Reject out-of-order rows
+    Reject,
+    /// Keep out-of-order rows in input order, passing a warning to the [ErrorSink]
+    Warn,
+}
+
+/// Buffered row, ordered by timestamp and then by input position
+struct Pending {
+    timestamp: u64,
+    position: u64,
+    transaction: Transaction,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        (self.timestamp, self.position) == (other.timestamp, other.position)
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.timestamp, self.position).cmp(&(other.timestamp, other.position))
+    }
+}
+
+/// Iterator over rows in timestamp order, see [crate::ordering]
+///
+/// Rows without timestamp are treated as if they had the latest timestamp seen so far, so they
+/// keep their position relative to the surrounding rows.
+pub struct TimestampOrdered<I, W = Stderr> {
+    rows: I,
+    policy: OutOfOrderPolicy,
+    warnings: W,
+    /// Latest timestamp seen so far
+    latest: Option<u64>,
+    /// Timestamp of the last row released from the buffer
+    released: Option<u64>,
+    buffer: BinaryHeap<Reverse<Pending>>,
+    position: u64,
+}
+
+impl<I> TimestampOrdered<I>
+    where I: Iterator<Item=Result<Transaction, csv::Error>>
+{
+    /// Wraps the rows, applying the policy to rows that are out of order and writing warnings to
+    /// stderr.
+    pub fn new(rows: I, policy: OutOfOrderPolicy) -> Self {
+        Self::with_sink(rows, policy, Stderr)
+    }
+}
+
+impl<I, W> TimestampOrdered<I, W>
+    where I: Iterator<Item=Result<Transaction, csv::Error>>,
+          W: ErrorSink
+{
+    /// Wraps the rows, applying the policy to rows that are out of order and passing warnings to
+    /// the sink.
+    pub fn with_sink(rows: I, policy: OutOfOrderPolicy, warnings: W) -> Self {
+        Self {
+            rows,
+            policy,
+            warnings,
+            latest: None,
+            released: None,
+            buffer: BinaryHeap::new(),
+            position: 0,
+        }
+    }
+
+    /// Returns the oldest buffered row if the input has advanced far enough or is exhausted.
+    fn release(&mut self, window: u64, exhausted: bool) -> Option<Transaction> {
+        let Reverse(oldest) = self.buffer.peek()?;
+        let ready = exhausted || self.latest
+            .is_some_and(|latest| oldest.timestamp.saturating_add(window) <= latest);
+        if !ready {
+            return None;
+        }
+        let Reverse(Pending { timestamp, transaction, .. }) = self.buffer.pop()?;
+        self.released = Some(timestamp);
+        Some(transaction)
+    }
+}
+
+impl<I, W> Iterator for TimestampOrdered<I, W>
+    where I: Iterator<Item=Result<Transaction, csv::Error>>,
+          W: ErrorSink
+{
+    type Item = Result<Transaction, csv::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let OutOfOrderPolicy::Reorder { window } = self.policy {
+                if let Some(transaction) = self.release(window, false) {
+                    return Some(Ok(transaction));
+                }
+            }
+            let transaction = match self.rows.next() {
+                Some(Ok(transaction)) => transaction,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    return match self.policy {
+                        OutOfOrderPolicy::Reorder { window } => self.release(window, true).map(Ok),
+                        _ => None,
+                    };
+                }
+            };
+            let timestamp = transaction.timestamp.or(self.latest).unwrap_or_default();
+            let threshold = match self.policy {
+                OutOfOrderPolicy::Reorder { .. } => self.released,
+                _ => self.latest,
+            };
+            let late = threshold.filter(|&threshold| timestamp < threshold);
+            self.latest = self.latest.max(Some(timestamp));
+            match (self.policy, late) {
+                (OutOfOrderPolicy::Warn, Some(threshold)) => {
+                    let warning = out_of_order(&transaction, timestamp, threshold);
+                    self.warnings.warning(&transaction, &warning);
+                }
+                (_, Some(threshold)) => {
+                    let error = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        out_of_order(&transaction, timestamp, threshold),
+                    );
+                    return Some(Err(csv::Error::from(error)));
+                }
+                (OutOfOrderPolicy::Reorder { .. }, None) => {
+                    self.position += 1;
+                    let position = self.position;
+                    self.buffer.push(Reverse(Pending { timestamp, position, transaction }));
+                    continue;
+                }
+                (_, None) => {}
+            }
+            return Some(Ok(transaction));
+        }
+    }
+}
+
+fn out_of_order(transaction: &Transaction, timestamp: u64, threshold: u64) -> String {
+    format!("Transaction {} with timestamp {} is out of order (after timestamp {})",
+            transaction.tx, timestamp, threshold)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Warnings(Vec<String>);
+
+    impl ErrorSink for Warnings {
+        fn invalid_row(&mut self, _: &csv::Error) {}
+
+        fn rejected(&mut self, _: &Transaction, _: &crate::PaymentError) {}
+
+        fn warning(&mut self, _: &Transaction, warning: &str) {
+            self.0.push(warning.to_owned());
+        }
+    }
+
+    fn ordered(policy: OutOfOrderPolicy) -> Vec<Result<u32, String>> {
+        let csv = "type,client,tx,amount,timestamp\n\
+                   deposit,1,1,1,100\n\
+                   deposit,1,2,1,130\n\
+                   deposit,1,3,1,110\n\
+                   deposit,1,4,1,\n\
+                   deposit,1,5,1,200\n\
+                   deposit,1,6,1,120\n";
+        let rows = crate::csv::read_transactions_from(csv.as_bytes());
+        TimestampOrdered::new(rows, policy)
+            .map(|row| row.map(|t| t.tx).map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn rows_are_reordered_within_window() {
+        let rows = ordered(OutOfOrderPolicy::Reorder { window: 50 });
+        assert_eq!(vec![Ok(1), Ok(3), Ok(2), Ok(4)], rows[..4]);
+        assert!(rows[4].as_ref().unwrap_err().contains("Transaction 6 with timestamp 120"));
+        assert_eq!(Ok(5), rows[5]);
+    }
+
+    #[test]
+    fn out_of_order_rows_are_rejected_or_kept() {
+        let rejected = ordered(OutOfOrderPolicy::Reject);
+        assert_eq!(vec![true, true, false, true, true, false],
+                   rejected.iter().map(Result::is_ok).collect::<Vec<_>>());
+        let kept = ordered(OutOfOrderPolicy::Warn);
+        assert_eq!(vec![Ok(1), Ok(2), Ok(3), Ok(4), Ok(5), Ok(6)], kept);
+
+        let csv = "type,client,tx,amount,timestamp\ndeposit,1,1,1,100\ndeposit,1,2,1,90\n";
+        let rows = crate::csv::read_transactions_from(csv.as_bytes());
+        let mut warnings = Warnings::default();
+        let kept = TimestampOrdered::with_sink(rows, OutOfOrderPolicy::Warn, &mut warnings).count();
+        assert_eq!(2, kept);
+        assert_eq!(vec![String::from("Transaction 2 with timestamp 90 is out of order (after \
+                                      timestamp 100)")], warnings.0);
+    }
+}
@@ -0,0 +1,118 @@
+//! Overdraft limits (credit lines) per client
+//!
+//! Withdrawals of a client with an overdraft limit may take the available funds negative, down to
+//! minus the limit, and fail with [crate::PaymentError::OverdraftExceeded] beyond. With named
+//! wallets, only the default wallet can be overdrawn. Disputes still require sufficient available
+//! funds.
+use std::io::{Read, Write};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::models::Account;
+use crate::PaymentsEngine;
+
+/// Row of the account report of engines with overdraft limits
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct OverdraftAccount {
+    /// Client identifier
+    pub client: u16,
+    /// Funds available for trading, negative if overdrawn
+    pub available: Decimal,
+    /// Funds held for dispute
+    pub held: Decimal,
+    /// Total funds available or held
+    pub total: Decimal,
+    /// True iff the account is locked
+    pub locked: bool,
+    /// Amount by which the available funds are overdrawn
+    pub overdrawn: Decimal,
+}
+
+impl From<Account> for OverdraftAccount {
+    fn from(account: Account) -> Self {
+        let Account { client, available, held, total, locked } = account;
+        let overdrawn = (-available).max(Decimal::ZERO);
+        Self { client, available, held, total, locked, overdrawn }
+    }
+}
+
+/// Overdraft limit of a client, as read from CSV
+#[derive(Deserialize)]
+struct Limit {
+    client: u16,
+    limit: Decimal,
+}
+
+/// Reads overdraft limits from CSV with the columns `client` and `limit`.
+pub fn read_overdraft_limits<R: Read>(reader: R) -> Result<Vec<(u16, Decimal)>, csv::Error> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .into_deserialize()
+        .map(|row| row.map(|Limit { client, limit }| (client, limit)))
+        .collect()
+}
+
+/// Writes the accounts with an additional `overdrawn` column as CSV to the given writer.
+pub fn write_overdraft_accounts<W, I>(writer: W, accounts: I) -> Result<(), csv::Error>
+    where W: Write,
+          I: IntoIterator<Item=Account>
+{
+    let mut writer = csv::Writer::from_writer(writer);
+    for account in accounts {
+        writer.serialize(OverdraftAccount::from(account))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+impl PaymentsEngine {
+    /// Sets the client's overdraft limit, removing it for `None` or zero.
+    pub fn set_overdraft_limit(&mut self, client: u16, limit: Option<Decimal>) {
+        match limit.filter(|limit| *limit > Decimal::ZERO) {
+            Some(limit) => self.overdraft_limits.insert(client, limit),
+            None => self.overdraft_limits.remove(&client),
+        };
+    }
+
+    /// Returns the client's overdraft limit, zero if there is none.
+    pub fn overdraft_limit(&self, client: u16) -> Decimal {
+        self.overdraft_limits.get(&client).copied().unwrap_or_default()
+    }
+
+    /// Returns true iff any client has an overdraft limit.
+    pub fn has_overdraft_limits(&self) -> bool {
+        !self.overdraft_limits.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PaymentError, RejectionReason, Transaction};
+
+    #[test]
+    fn withdrawals_may_overdraw_up_to_the_limit() {
+        let mut engine = PaymentsEngine::new();
+        let limits = read_overdraft_limits("client,limit\n1,10\n".as_bytes()).unwrap();
+        for (client, limit) in limits {
+            engine.set_overdraft_limit(client, Some(limit));
+        }
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        engine.withdraw(1, 2, Decimal::new(12, 0)).unwrap();
+        let withdrawal = Transaction::withdrawal(1, 3, Decimal::new(4, 0));
+        assert_eq!(vec![RejectionReason::OverdraftExceeded {
+            available: Decimal::new(-7, 0),
+            limit: Decimal::new(10, 0),
+            amount: Decimal::new(4, 0),
+        }], engine.explain(&withdrawal));
+        assert!(matches!(engine.execute(withdrawal),
+            Err(PaymentError::OverdraftExceeded { tx: 3, .. })));
+
+        let mut report = Vec::new();
+        write_overdraft_accounts(&mut report, engine.accounts()).unwrap();
+        assert_eq!("client,available,held,total,locked,overdrawn\n1,-7,0,-7,false,7\n",
+                   String::from_utf8(report).unwrap());
+    }
+}
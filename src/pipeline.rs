@@ -0,0 +1,264 @@
+//! Skip-and-continue processing of transaction streams
+//!
+//! Invalid rows and rejected transactions do not stop the processing: their errors are passed to
+//! an [ErrorSink], e.g. [Stderr], and the next row is processed.
+use std::ops::ControlFlow;
+
+use csv::Error;
+
+use crate::error::{self, PaymentError};
+use crate::io::TransactionSource;
+use crate::models::{AdminOperation, Transaction, TransactionOutcome};
+use crate::PaymentsEngine;
+
+/// Outcome of processing a single input row
+pub enum Row<'a> {
+    /// Row could not be parsed
+    Invalid,
+    /// Row was parsed and is about to be executed
+    Accepted(&'a Transaction),
+    /// Row was parsed and executed with the given outcome
+    Executed(&'a Transaction, &'a TransactionOutcome),
+    /// Row parked earlier (see [crate::reorder]) was retried or expired with the given outcome
+    Reordered(&'a Transaction, &'a TransactionOutcome),
+    /// Dispute expired (see [crate::expiry]) with the given transaction and outcome
+    Expired(&'a Transaction, &'a TransactionOutcome),
+    /// Standing order (see [crate::standing]) was executed with the given transaction and outcome
+    Scheduled(&'a Transaction, &'a TransactionOutcome),
+    /// Administrative operation, e.g. received by [crate::server], is about to be applied
+    Operation(&'a AdminOperation),
+    /// Administrative operation was applied with the given result
+    Administered(&'a AdminOperation, &'a error::Result<()>),
+}
+
+/// Return value of `on_row`: [ControlFlow::Break] in reply to [Row::Accepted] (or
+/// [Row::Operation]) skips the row without executing it, e.g. because it could not be logged. Any
+/// other value, including `()`, goes on.
+pub trait RowFlow {
+    /// Returns true iff the row is to be skipped.
+    fn is_break(&self) -> bool;
+}
+
+impl RowFlow for () {
+    fn is_break(&self) -> bool {
+        false
+    }
+}
+
+impl RowFlow for ControlFlow<()> {
+    fn is_break(&self) -> bool {
+        ControlFlow::is_break(self)
+    }
+}
+
+/// Receives the errors of skipped rows
+pub trait ErrorSink {
+    /// Handles a row that could not be parsed.
+    fn invalid_row(&mut self, error: &Error);
+
+    /// Handles a transaction rejected by the engine, including transactions parked for a retry
+    /// ([PaymentError::Deferred]).
+    fn rejected(&mut self, transaction: &Transaction, error: &PaymentError);
+
+    /// Handles a transaction that is processed despite a warning, e.g. because it is out of order
+    /// (see [crate::ordering::OutOfOrderPolicy::Warn]). Ignores the warning by default.
+    fn warning(&mut self, _transaction: &Transaction, _warning: &str) {}
+}
+
+/// Writes one line per error to stderr
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stderr;
+
+impl ErrorSink for Stderr {
+    fn invalid_row(&mut self, error: &Error) {
+        eprintln!("Invalid input row: {}", error);
+    }
+
+    fn rejected(&mut self, _: &Transaction, error: &PaymentError) {
+        eprintln!("{}", error);
+    }
+
+    fn warning(&mut self, _: &Transaction, warning: &str) {
+        eprintln!("{}", warning);
+    }
+}
+
+/// Discards all errors
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ignore;
+
+impl ErrorSink for Ignore {
+    fn invalid_row(&mut self, _: &Error) {}
+
+    fn rejected(&mut self, _: &Transaction, _: &PaymentError) {}
+}
+
+impl<S: ErrorSink + ?Sized> ErrorSink for &mut S {
+    fn invalid_row(&mut self, error: &Error) {
+        (**self).invalid_row(error);
+    }
+
+    fn rejected(&mut self, transaction: &Transaction, error: &PaymentError) {
+        (**self).rejected(transaction, error);
+    }
+
+    fn warning(&mut self, transaction: &Transaction, warning: &str) {
+        (**self).warning(transaction, warning);
+    }
+}
+
+/// Processes all transactions from the given iterator with the given engine.
+///
+/// Skips failed transactions and invalid rows, passing their errors to `errors`. After each row,
+/// `on_row` is called with the engine and the outcome of the row; in reply to [Row::Accepted], it
+/// may skip the row (see [RowFlow]). Parked rows are failed at the end of the input.
+pub fn process_transactions<I, E, F, R>(
+    transactions: I,
+    payments_engine: &mut PaymentsEngine,
+    errors: &mut E,
+    mut on_row: F,
+)
+    where I: Iterator<Item=Result<Transaction, Error>>,
+          E: ErrorSink + ?Sized,
+          F: FnMut(&PaymentsEngine, Row) -> R,
+          R: RowFlow
+{
+    for transaction in transactions {
+        process_row(transaction, payments_engine, errors, &mut on_row);
+    }
+    payments_engine.flush_parked();
+    process_follow_ups(payments_engine, None, errors, &mut on_row);
+}
+
+/// Processes all transactions from the given source like [process_transactions].
+pub fn process_source<S, E, F, R>(
+    source: S,
+    payments_engine: &mut PaymentsEngine,
+    errors: &mut E,
+    on_row: F,
+)
+    where S: TransactionSource,
+          E: ErrorSink + ?Sized,
+          F: FnMut(&PaymentsEngine, Row) -> R,
+          R: RowFlow
+{
+    process_transactions(source.rows(), payments_engine, errors, on_row);
+}
+
+/// Processes a single input row with the given engine (see [process_transactions]).
+pub fn process_row<E, F, R>(
+    row: Result<Transaction, Error>,
+    payments_engine: &mut PaymentsEngine,
+    errors: &mut E,
+    on_row: &mut F,
+)
+    where E: ErrorSink + ?Sized,
+          F: FnMut(&PaymentsEngine, Row) -> R,
+          R: RowFlow
+{
+    let transaction = match row {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            errors.invalid_row(&e);
+            on_row(payments_engine, Row::Invalid);
+            return;
+        }
+    };
+    if on_row(payments_engine, Row::Accepted(&transaction)).is_break() {
+        return;
+    }
+    let outcome = payments_engine.execute_with_outcome(transaction.clone());
+    report_rejection(errors, &transaction, &outcome);
+    on_row(payments_engine, Row::Executed(&transaction, &outcome));
+    process_follow_ups(payments_engine, transaction.timestamp, errors, on_row);
+}
+
+/// Passes the outcomes of parked rows that were retried or expired to `on_row`, then expires the
+/// disputes that are too old and executes the standing orders due at the given timestamp.
+pub fn process_follow_ups<E, F, R>(
+    payments_engine: &mut PaymentsEngine,
+    now: Option<u64>,
+    errors: &mut E,
+    on_row: &mut F,
+)
+    where E: ErrorSink + ?Sized,
+          F: FnMut(&PaymentsEngine, Row) -> R,
+          R: RowFlow
+{
+    for (transaction, outcome) in payments_engine.take_reordered() {
+        report_rejection(errors, &transaction, &outcome);
+        on_row(payments_engine, Row::Reordered(&transaction, &outcome));
+    }
+    let expired = now.map_or_else(Vec::new, |now| payments_engine.expire_disputes(now));
+    for (transaction, outcome) in expired {
+        report_rejection(errors, &transaction, &outcome);
+        on_row(payments_engine, Row::Expired(&transaction, &outcome));
+    }
+    let due = now.map_or_else(Vec::new, |now| payments_engine.run_due_orders(now));
+    for (transaction, outcome) in due {
+        report_rejection(errors, &transaction, &outcome);
+        on_row(payments_engine, Row::Scheduled(&transaction, &outcome));
+    }
+}
+
+fn report_rejection<E>(errors: &mut E, transaction: &Transaction, outcome: &TransactionOutcome)
+    where E: ErrorSink + ?Sized
+{
+    if let Err(error) = &outcome.status {
+        errors.rejected(transaction, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv::read_transactions_from;
+
+    #[derive(Default)]
+    struct Collect(Vec<String>);
+
+    impl ErrorSink for Collect {
+        fn invalid_row(&mut self, error: &Error) {
+            self.0.push(format!("line {}", error.position().map_or(0, |p| p.line())));
+        }
+
+        fn rejected(&mut self, transaction: &Transaction, error: &PaymentError) {
+            self.0.push(format!("tx {}: {}", transaction.tx, error.code()));
+        }
+    }
+
+    #[test]
+    fn errors_are_passed_to_sink_and_processing_continues() {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,5\n\
+                   withdrawal,1,2,9\n\
+                   foo,1,3,1\n\
+                   deposit,1,4,1\n";
+        let mut engine = PaymentsEngine::new();
+        let mut errors = Collect::default();
+        let mut executed = 0;
+        process_transactions(read_transactions_from(csv.as_bytes()), &mut engine, &mut errors,
+                             |_, row| if let Row::Executed(..) = row { executed += 1 });
+
+        assert_eq!(vec!["tx 2: insufficient_funds", "line 4"], errors.0);
+        assert_eq!(3, executed);
+        assert_eq!(rust_decimal::Decimal::new(6, 0), engine.account(1).unwrap().available);
+    }
+
+    #[test]
+    fn rows_broken_off_when_accepted_are_not_executed() {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,5\n\
+                   deposit,1,2,3\n";
+        let mut engine = PaymentsEngine::new();
+        process_transactions(read_transactions_from(csv.as_bytes()), &mut engine, &mut Ignore,
+                             |_, row| match row {
+                                 Row::Accepted(transaction) if transaction.tx == 2 => {
+                                     ControlFlow::Break(())
+                                 }
+                                 _ => ControlFlow::Continue(()),
+                             });
+
+        assert_eq!(rust_decimal::Decimal::new(5, 0), engine.account(1).unwrap().available);
+    }
+}
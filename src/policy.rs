@@ -0,0 +1,191 @@
+//! Declarative policy files configuring limits, windows, validators, and fees
+//!
+//! A policy is written in TOML, e.g.:
+//!
+//! ```toml
+//! currencies = ["EUR", "USD"]
+//!
+//! [engine]
+//! dispute_window = 100000
+//! min_balance = "1.00"
+//! velocity_limit = { window = 3600, max_count = 10, max_volume = "5000" }
+//!
+//! [amounts]
+//! max = "10000"
+//!
+//! [fees]
+//! withdrawal = "0.25"
+//! ```
+//!
+//! The `[engine]` section has the fields of [EngineConfig]; only the ones it sets replace those
+//! of the engine's configuration. Unknown keys are rejected, so that typos do not silently
+//! disable a rule.
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::autolock::AutoLock;
+use crate::config::EngineConfig;
+use crate::expiry::DisputeExpiry;
+use crate::middleware::WithdrawalFee;
+use crate::receivable::WithdrawnFundsPolicy;
+use crate::reorder::ReorderWindow;
+use crate::validate::{AmountRange, CurrencyWhitelist};
+use crate::velocity::VelocityLimit;
+use crate::PaymentsEngine;
+
+/// Rules of the engine loaded from a policy file, see [crate::policy]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Accepted currencies, `None` to accept all, see [CurrencyWhitelist]
+    pub currencies: Option<Vec<String>>,
+    /// Settings of the engine's configuration
+    pub engine: EngineSettings,
+    /// Accepted range of transaction amounts, `None` for no limit
+    pub amounts: Option<AmountRange>,
+    /// Fees charged on top of transactions
+    pub fees: Fees,
+}
+
+/// Fields of [EngineConfig] set by a [Policy], `None` to keep the configured value
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineSettings {
+    pub representment_window: Option<u64>,
+    pub dispute_window: Option<u64>,
+    pub compaction_interval: Option<u64>,
+    pub merkle_log: Option<bool>,
+    pub velocity_limit: Option<VelocityLimit>,
+    pub auto_lock: Option<AutoLock>,
+    pub reorder_window: Option<ReorderWindow>,
+    pub dispute_expiry: Option<DisputeExpiry>,
+    pub min_balance: Option<Decimal>,
+    pub withdrawn_funds: Option<WithdrawnFundsPolicy>,
+    pub strict_adjustments: Option<bool>,
+}
+
+impl EngineSettings {
+    /// Replaces the fields of the configuration that are set.
+    pub fn apply_to(self, config: &mut EngineConfig) {
+        let EngineSettings {
+            representment_window,
+            dispute_window,
+            compaction_interval,
+            merkle_log,
+            velocity_limit,
+            auto_lock,
+            reorder_window,
+            dispute_expiry,
+            min_balance,
+            withdrawn_funds,
+            strict_adjustments,
+        } = self;
+        config.representment_window = representment_window.or(config.representment_window);
+        config.dispute_window = dispute_window.or(config.dispute_window);
+        config.compaction_interval = compaction_interval.or(config.compaction_interval);
+        config.merkle_log = merkle_log.unwrap_or(config.merkle_log);
+        config.velocity_limit = velocity_limit.or(config.velocity_limit.take());
+        config.auto_lock = auto_lock.or(config.auto_lock.take());
+        config.reorder_window = reorder_window.or(config.reorder_window.take());
+        config.dispute_expiry = dispute_expiry.or(config.dispute_expiry.take());
+        config.min_balance = min_balance.or(config.min_balance);
+        config.withdrawn_funds = withdrawn_funds.unwrap_or(config.withdrawn_funds);
+        config.strict_adjustments = strict_adjustments.unwrap_or(config.strict_adjustments);
+    }
+}
+
+/// Fees of a [Policy]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fees {
+    /// Fee charged after every withdrawal, see [WithdrawalFee]
+    pub withdrawal: Option<Decimal>,
+}
+
+impl FromStr for Policy {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+impl PaymentsEngine {
+    /// Creates new [PaymentsEngine] following the policy.
+    pub fn with_policy(policy: Policy) -> Self {
+        let mut engine = Self::new();
+        engine.apply_policy(policy);
+        engine
+    }
+
+    /// Replaces the settings of the configuration that the policy sets and adds its validators and
+    /// fees.
+    pub fn apply_policy(&mut self, policy: Policy) {
+        policy.engine.apply_to(&mut self.config);
+        if let Some(range) = policy.amounts {
+            self.add_validator(range);
+        }
+        if let Some(currencies) = policy.currencies {
+            self.add_validator(CurrencyWhitelist::from_iter(currencies));
+        }
+        if let Some(fee) = policy.fees.withdrawal {
+            self.add_middleware(WithdrawalFee { fee });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv::read_transactions_from;
+    use crate::velocity::VelocityLimit;
+
+    #[test]
+    fn policy_configures_engine_validators_and_fees() {
+        let policy: Policy = "currencies = [\"EUR\"]\n\
+                              [engine]\n\
+                              dispute_window = 10\n\
+                              velocity_limit = { window = 60, max_count = 2 }\n\
+                              [amounts]\n\
+                              max = \"100\"\n\
+                              [fees]\n\
+                              withdrawal = \"0.5\"\n".parse().unwrap();
+        let velocity_limit = VelocityLimit { window: 60, max_count: Some(2), max_volume: None };
+        assert_eq!(Some(velocity_limit), policy.engine.velocity_limit);
+
+        let mut engine = PaymentsEngine::with_policy(policy);
+        assert_eq!(Some(10), engine.config().dispute_window);
+        let csv = "type,client,tx,amount,currency\n\
+                   deposit,1,1,50,EUR\n\
+                   deposit,1,2,500,EUR\n\
+                   deposit,1,3,5,USD\n\
+                   withdrawal,1,4,10,EUR\n";
+        let codes: Vec<_> = read_transactions_from(csv.as_bytes())
+            .map(|t| engine.execute(t.unwrap()).err().map(|e| e.code()))
+            .collect();
+        assert_eq!(vec![None, Some("validation_failed"), Some("validation_failed"), None], codes);
+        assert_eq!(Decimal::new(395, 1), engine.account(1).unwrap().available);
+
+        assert!("[engine]\ndispute_windw = 10\n".parse::<Policy>().is_err());
+    }
+
+    #[test]
+    fn policy_keeps_settings_it_does_not_set() {
+        let mut engine = PaymentsEngine::with_config(EngineConfig {
+            dispute_window: Some(5),
+            merkle_log: true,
+            min_balance: Some(Decimal::ONE),
+            ..EngineConfig::default()
+        });
+        engine.apply_policy("[engine]\nmin_balance = \"2\"\n".parse().unwrap());
+
+        assert_eq!(&EngineConfig {
+            dispute_window: Some(5),
+            merkle_log: true,
+            min_balance: Some(Decimal::TWO),
+            ..EngineConfig::default()
+        }, engine.config());
+    }
+}
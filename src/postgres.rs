@@ -0,0 +1,52 @@
+//! PostgreSQL sink for account reports
+//!
+//! Only available with the `postgres` feature.
+use postgres::{Client, Error, NoTls};
+
+use crate::Account;
+
+/// Table that the account report is written to
+pub const ACCOUNTS_TABLE: &str = "accounts";
+
+/// Upserts [Account]s from iterator into the accounts table of the database at the given URL.
+///
+/// Creates the table if it does not exist and returns the number of written rows. All accounts
+/// are written in a single database transaction.
+pub fn upsert_accounts<I>(url: &str, accounts: I) -> Result<u64, Error>
+    where I: IntoIterator<Item=Account>
+{
+    let mut client = Client::connect(url, NoTls)?;
+    let mut db_tx = client.transaction()?;
+    db_tx.batch_execute(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            client INTEGER PRIMARY KEY,
+            available NUMERIC NOT NULL,
+            held NUMERIC NOT NULL,
+            total NUMERIC NOT NULL,
+            locked BOOLEAN NOT NULL
+        )",
+        ACCOUNTS_TABLE
+    ))?;
+    let stmt = db_tx.prepare(&format!(
+        "INSERT INTO {} (client, available, held, total, locked)
+        VALUES ($1, $2::TEXT::NUMERIC, $3::TEXT::NUMERIC, $4::TEXT::NUMERIC, $5)
+        ON CONFLICT (client) DO UPDATE SET
+            available = EXCLUDED.available,
+            held = EXCLUDED.held,
+            total = EXCLUDED.total,
+            locked = EXCLUDED.locked",
+        ACCOUNTS_TABLE
+    ))?;
+    let mut rows = 0;
+    for account in accounts {
+        rows += db_tx.execute(&stmt, &[
+            &i32::from(account.client),
+            &account.available.to_string(),
+            &account.held.to_string(),
+            &account.total.to_string(),
+            &account.locked,
+        ])?;
+    }
+    db_tx.commit()?;
+    Ok(rows)
+}
@@ -0,0 +1,211 @@
+//! Protobuf types of transactions and accounts
+//!
+//! The messages are generated from `proto/payments.proto` by the build script, with a vendored
+//! `protoc`, and are encoded and decoded with [prost::Message]. Convert to and from the native
+//! models with [From] and [TryFrom]:
+//! ```
+//! use prost::Message;
+//! use rust_decimal::Decimal;
+//! use toy_payments_engine::{proto, Transaction};
+//!
+//! let deposit = Transaction::deposit(1, 7, Decimal::new(25, 1));
+//! let bytes = proto::Transaction::from(deposit.clone()).encode_to_vec();
+//! let decoded = proto::Transaction::decode(&bytes[..]).unwrap();
+//! assert_eq!(deposit, Transaction::try_from(decoded).unwrap());
+//! ```
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::models;
+
+include!(concat!(env!("OUT_DIR"), "/payments.rs"));
+
+/// Message that cannot be converted into a native model
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum ProtoError {
+    #[error("Unknown or unspecified transaction type {0}")]
+    InvalidType(i32),
+    #[error("Unknown or unspecified dispute reason {0}")]
+    InvalidReason(i32),
+    #[error("Client {0} is out of range")]
+    ClientOutOfRange(u32),
+    #[error("Invalid amount {0:?}")]
+    InvalidAmount(String),
+}
+
+impl From<models::TransactionType> for TransactionType {
+    fn from(kind: models::TransactionType) -> Self {
+        match kind {
+            models::TransactionType::Deposit => Self::Deposit,
+            models::TransactionType::Withdrawal => Self::Withdrawal,
+            models::TransactionType::Dispute => Self::Dispute,
+            models::TransactionType::Resolve => Self::Resolve,
+            models::TransactionType::Chargeback => Self::Chargeback,
+            models::TransactionType::Refund => Self::Refund,
+            models::TransactionType::Adjustment => Self::Adjustment,
+        }
+    }
+}
+
+impl TryFrom<TransactionType> for models::TransactionType {
+    type Error = ProtoError;
+
+    fn try_from(kind: TransactionType) -> Result<Self, Self::Error> {
+        Ok(match kind {
+            TransactionType::Unspecified => return Err(ProtoError::InvalidType(kind as i32)),
+            TransactionType::Deposit => Self::Deposit,
+            TransactionType::Withdrawal => Self::Withdrawal,
+            TransactionType::Dispute => Self::Dispute,
+            TransactionType::Resolve => Self::Resolve,
+            TransactionType::Chargeback => Self::Chargeback,
+            TransactionType::Refund => Self::Refund,
+            TransactionType::Adjustment => Self::Adjustment,
+        })
+    }
+}
+
+impl From<models::DisputeReason> for DisputeReason {
+    fn from(reason: models::DisputeReason) -> Self {
+        match reason {
+            models::DisputeReason::Fraud => Self::Fraud,
+            models::DisputeReason::ProductNotReceived => Self::ProductNotReceived,
+            models::DisputeReason::Duplicate => Self::Duplicate,
+        }
+    }
+}
+
+impl TryFrom<DisputeReason> for models::DisputeReason {
+    type Error = ProtoError;
+
+    fn try_from(reason: DisputeReason) -> Result<Self, Self::Error> {
+        Ok(match reason {
+            DisputeReason::Unspecified => return Err(ProtoError::InvalidReason(reason as i32)),
+            DisputeReason::Fraud => Self::Fraud,
+            DisputeReason::ProductNotReceived => Self::ProductNotReceived,
+            DisputeReason::Duplicate => Self::Duplicate,
+        })
+    }
+}
+
+impl From<models::Transaction> for Transaction {
+    fn from(transaction: models::Transaction) -> Self {
+        Self {
+            r#type: TransactionType::from(transaction.transaction_type) as i32,
+            client: u32::from(transaction.client),
+            tx: transaction.tx,
+            amount: transaction.amount.map(|amount| amount.to_string()),
+            reason: transaction.reason.map(|reason| DisputeReason::from(reason) as i32),
+            timestamp: transaction.timestamp,
+            wallet: transaction.wallet,
+            memo: transaction.memo,
+            tags: transaction.tags,
+            idempotency_key: transaction.idempotency_key,
+            currency: transaction.currency,
+        }
+    }
+}
+
+impl TryFrom<Transaction> for models::Transaction {
+    type Error = ProtoError;
+
+    fn try_from(transaction: Transaction) -> Result<Self, Self::Error> {
+        let kind = TransactionType::try_from(transaction.r#type)
+            .map_err(|_| ProtoError::InvalidType(transaction.r#type))?;
+        let reason = transaction.reason
+            .map(|reason| DisputeReason::try_from(reason)
+                .map_err(|_| ProtoError::InvalidReason(reason))
+                .and_then(models::DisputeReason::try_from))
+            .transpose()?;
+        Ok(Self {
+            transaction_type: kind.try_into()?,
+            client: client(transaction.client)?,
+            tx: transaction.tx,
+            amount: transaction.amount.as_deref().map(amount).transpose()?,
+            reason,
+            timestamp: transaction.timestamp,
+            wallet: transaction.wallet,
+            memo: transaction.memo,
+            tags: transaction.tags,
+            idempotency_key: transaction.idempotency_key,
+            currency: transaction.currency,
+        })
+    }
+}
+
+impl From<models::Account> for Account {
+    fn from(account: models::Account) -> Self {
+        Self {
+            client: u32::from(account.client),
+            available: account.available.to_string(),
+            held: account.held.to_string(),
+            total: account.total.to_string(),
+            locked: account.locked,
+        }
+    }
+}
+
+impl TryFrom<Account> for models::Account {
+    type Error = ProtoError;
+
+    fn try_from(account: Account) -> Result<Self, Self::Error> {
+        Ok(Self {
+            client: client(account.client)?,
+            available: amount(&account.available)?,
+            held: amount(&account.held)?,
+            total: amount(&account.total)?,
+            locked: account.locked,
+        })
+    }
+}
+
+fn client(client: u32) -> Result<u16, ProtoError> {
+    u16::try_from(client).map_err(|_| ProtoError::ClientOutOfRange(client))
+}
+
+fn amount(amount: &str) -> Result<Decimal, ProtoError> {
+    Decimal::from_str(amount).map_err(|_| ProtoError::InvalidAmount(amount.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    #[test]
+    fn models_round_trip_through_protobuf() {
+        let dispute = models::Transaction::dispute(3, 9)
+            .with_reason(models::DisputeReason::ProductNotReceived)
+            .with_timestamp(42)
+            .with_tag("a")
+            .with_tag("b");
+        let decoded = Transaction::decode(&*Transaction::from(dispute.clone()).encode_to_vec());
+        assert_eq!(dispute, decoded.unwrap().try_into().unwrap());
+
+        let account = models::Account {
+            client: 3,
+            available: Decimal::new(-15, 1),
+            held: Decimal::new(2, 0),
+            total: Decimal::new(5, 1),
+            locked: true,
+        };
+        let decoded = Account::decode(&*Account::from(account.clone()).encode_to_vec());
+        assert_eq!(account, decoded.unwrap().try_into().unwrap());
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected() {
+        let deposit = Transaction::from(models::Transaction::deposit(1, 1, Decimal::ONE));
+        let unspecified = Transaction { r#type: 0, ..deposit.clone() };
+        assert_eq!(Err(ProtoError::InvalidType(0)), models::Transaction::try_from(unspecified));
+        let unknown = Transaction { reason: Some(9), ..deposit.clone() };
+        assert_eq!(Err(ProtoError::InvalidReason(9)), models::Transaction::try_from(unknown));
+        let client = Transaction { client: 70_000, ..deposit.clone() };
+        assert_eq!(Err(ProtoError::ClientOutOfRange(70_000)),
+                   models::Transaction::try_from(client));
+        let amount = Transaction { amount: Some(String::from("1,5")), ..deposit };
+        assert!(matches!(models::Transaction::try_from(amount), Err(ProtoError::InvalidAmount(_))));
+    }
+}
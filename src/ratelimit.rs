@@ -0,0 +1,98 @@
+//! Token-bucket rate limiting of submitted transactions, see [crate::server]
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Limits in transactions per second, each allowing bursts of one second's worth
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RateLimit {
+    /// Limit per client
+    pub per_client: Option<u32>,
+    /// Limit of all clients together
+    pub global: Option<u32>,
+}
+
+/// Bucket refilled at a constant rate up to one second's worth of tokens
+#[derive(Clone, Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, now: Instant) -> Self {
+        Self { rate: f64::from(rate), tokens: f64::from(rate), updated: now }
+    }
+
+    /// Refills the bucket for the time elapsed since the last refill and returns true iff a token
+    /// is available.
+    fn refill(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated = self.updated.max(now);
+        self.tokens >= 1.0
+    }
+}
+
+/// Per-client and global token buckets enforcing a [RateLimit]
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    per_client: Option<u32>,
+    global: Option<TokenBucket>,
+    clients: HashMap<u16, TokenBucket>,
+    limited: u64,
+}
+
+impl RateLimiter {
+    /// Creates a limiter with full buckets.
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            per_client: limit.per_client,
+            global: limit.global.map(|rate| TokenBucket::new(rate, Instant::now())),
+            clients: HashMap::new(),
+            limited: 0,
+        }
+    }
+
+    /// Returns true and takes a token from the buckets of the client and the global one if both
+    /// have one left at the given time.
+    pub fn allow(&mut self, client: u16, now: Instant) -> bool {
+        let client = self.per_client
+            .map(|rate| self.clients.entry(client).or_insert_with(|| TokenBucket::new(rate, now)));
+        let mut buckets = [client, self.global.as_mut()];
+        if buckets.iter_mut().flatten().all(|bucket| bucket.refill(now)) {
+            buckets.iter_mut().flatten().for_each(|bucket| bucket.tokens -= 1.0);
+            true
+        } else {
+            self.limited += 1;
+            false
+        }
+    }
+
+    /// Returns the number of transactions that were not allowed so far.
+    pub fn limited(&self) -> u64 {
+        self.limited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn buckets_limit_bursts_and_refill_over_time() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(RateLimit { per_client: Some(2), global: Some(3) });
+        let allowed: Vec<_> = [1, 1, 1, 2, 2].iter().map(|&c| limiter.allow(c, start)).collect();
+        assert_eq!(vec![true, true, false, true, false], allowed);
+        assert_eq!(2, limiter.limited());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.allow(2, later));
+        assert!(!limiter.allow(1, later));
+        assert!(limiter.allow(1, start + Duration::from_secs(2)));
+        assert!(RateLimiter::new(RateLimit::default()).allow(1, start));
+    }
+}
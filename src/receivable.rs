@@ -0,0 +1,132 @@
+//! Disputes of deposits whose funds were already (partly) withdrawn
+//!
+//! By default such disputes fail with insufficient funds. [WithdrawnFundsPolicy::Negative] holds
+//! the full amount anyway, taking the available funds negative. [WithdrawnFundsPolicy::Receivable]
+//! only holds the available funds and tracks the shortfall as a [Receivable] of the client, which
+//! stays open after a chargeback so collections can follow up.
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::PaymentsEngine;
+
+/// Handling of disputes whose deposit amount exceeds the available funds
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WithdrawnFundsPolicy {
+    /// Reject the dispute with insufficient funds
+    #[default]
+    Reject,
+    /// Hold the full amount, taking the available funds negative
+    Negative,
+    /// Hold the available funds and track the rest as a receivable of the client
+    Receivable,
+}
+
+impl FromStr for WithdrawnFundsPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "negative" => Ok(Self::Negative),
+            "receivable" => Ok(Self::Receivable),
+            _ => Err(format!("unknown policy {:?}, expected reject, negative, or receivable", s)),
+        }
+    }
+}
+
+/// Amount of a disputed deposit the client could not cover with available funds
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Receivable {
+    /// Client identifier
+    pub client: u16,
+    /// Transaction ID of the disputed deposit
+    pub tx: u32,
+    /// Amount not held for the dispute
+    pub amount: Decimal,
+}
+
+impl PaymentsEngine {
+    /// Replaces the policy for disputes of withdrawn funds of the engine's configuration.
+    pub fn set_withdrawn_funds_policy(&mut self, policy: WithdrawnFundsPolicy) {
+        self.config.withdrawn_funds = policy;
+    }
+
+    /// Returns the receivables of open and charged back disputes, ordered by client and
+    /// transaction ID.
+    ///
+    /// Receivables are removed when their dispute is resolved or the chargeback represented.
+    pub fn receivables(&self) -> Vec<Receivable> {
+        let mut receivables: Vec<_> = self.receivables.values().copied().collect();
+        receivables.sort_unstable_by_key(|r| (r.client, r.tx));
+        receivables
+    }
+
+    /// Returns the sum of the client's receivables.
+    pub fn receivable_total(&self, client: u16) -> Decimal {
+        self.receivables.values().filter(|r| r.client == client).map(|r| r.amount).sum()
+    }
+
+    /// Returns the amount of the disputed deposit that is not held, zero without receivable.
+    pub(crate) fn shortfall(&self, tx: u32) -> Decimal {
+        self.receivables.get(&tx).map_or(Decimal::ZERO, |r| r.amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PaymentError;
+
+    fn engine(policy: WithdrawnFundsPolicy) -> PaymentsEngine {
+        let mut engine = PaymentsEngine::new();
+        engine.set_withdrawn_funds_policy(policy);
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        engine.withdraw(1, 2, Decimal::new(7, 0)).unwrap();
+        engine
+    }
+
+    #[test]
+    fn disputes_of_withdrawn_funds_follow_policy() {
+        let mut rejecting = engine(WithdrawnFundsPolicy::Reject);
+        assert!(matches!(rejecting.dispute(1, 1), Err(PaymentError::InsufficientFunds { .. })));
+
+        let mut negative = engine(WithdrawnFundsPolicy::Negative);
+        negative.dispute(1, 1).unwrap();
+        let account = negative.account(1).unwrap();
+        assert_eq!((Decimal::new(-7, 0), Decimal::new(10, 0)), (account.available, account.held));
+        assert!(negative.receivables().is_empty());
+    }
+
+    #[test]
+    fn shortfall_stays_receivable_after_chargeback() {
+        let mut engine = engine(WithdrawnFundsPolicy::Receivable);
+        engine.dispute(1, 1).unwrap();
+        let account = engine.account(1).unwrap();
+        assert_eq!((Decimal::ZERO, Decimal::new(3, 0)), (account.available, account.held));
+        assert_eq!(vec![Receivable { client: 1, tx: 1, amount: Decimal::new(7, 0) }],
+                   engine.receivables());
+
+        engine.chargeback(1, 1).unwrap();
+        assert_eq!(Decimal::ZERO, engine.account(1).unwrap().total);
+        assert_eq!(Decimal::new(7, 0), engine.receivable_total(1));
+
+        engine.represent(1, 1, true).unwrap();
+        assert_eq!(Decimal::new(3, 0), engine.account(1).unwrap().available);
+        assert!(engine.receivables().is_empty());
+    }
+
+    #[test]
+    fn resolved_dispute_clears_receivable() {
+        let mut engine = engine(WithdrawnFundsPolicy::Receivable);
+        engine.enable_ledger();
+        engine.dispute(1, 1).unwrap();
+        engine.resolve(1, 1).unwrap();
+        let account = engine.account(1).unwrap();
+        assert_eq!((Decimal::new(3, 0), Decimal::ZERO), (account.available, account.held));
+        assert!(engine.receivables().is_empty());
+        assert!(engine.ledger().unwrap().trial_balance().values().sum::<Decimal>().is_zero());
+    }
+}
@@ -0,0 +1,179 @@
+//! Reconciliation of computed balances against an external statement or another report
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{Account, PaymentsEngine};
+
+/// Client whose computed account differs from the statement
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mismatch {
+    /// Client identifier
+    pub client: u16,
+    /// Account according to the statement, `None` if the statement does not list the client
+    pub expected: Option<Account>,
+    /// Account computed by the engine, `None` if the engine does not know the client
+    pub actual: Option<Account>,
+}
+
+impl Mismatch {
+    /// Computed minus expected available funds, missing accounts count as zero
+    pub fn available_delta(&self) -> Decimal {
+        self.delta(|account| account.available)
+    }
+
+    /// Computed minus expected held funds, missing accounts count as zero
+    pub fn held_delta(&self) -> Decimal {
+        self.delta(|account| account.held)
+    }
+
+    /// Computed minus expected total funds, missing accounts count as zero
+    pub fn total_delta(&self) -> Decimal {
+        self.delta(|account| account.total)
+    }
+
+    fn delta(&self, field: impl Fn(&Account) -> Decimal) -> Decimal {
+        let actual = self.actual.as_ref().map(&field).unwrap_or_default();
+        let expected = self.expected.as_ref().map(&field).unwrap_or_default();
+        (actual - expected).normalize()
+    }
+}
+
+/// Row of the CSV reconciliation report
+#[derive(Serialize)]
+struct MismatchRow {
+    client: u16,
+    available_delta: Decimal,
+    held_delta: Decimal,
+    total_delta: Decimal,
+    expected_locked: Option<bool>,
+    actual_locked: Option<bool>,
+}
+
+/// Result of [PaymentsEngine::reconcile] and [compare]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReconciliationReport {
+    /// Number of clients whose accounts match the statement
+    pub matched: usize,
+    /// Clients whose accounts differ, in order of client ID
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl ReconciliationReport {
+    /// Returns true iff all accounts match the statement.
+    pub fn is_reconciled(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    /// Writes the mismatches with their deltas as CSV to the given writer.
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for mismatch in &self.mismatches {
+            writer.serialize(MismatchRow {
+                client: mismatch.client,
+                available_delta: mismatch.available_delta(),
+                held_delta: mismatch.held_delta(),
+                total_delta: mismatch.total_delta(),
+                expected_locked: mismatch.expected.as_ref().map(|account| account.locked),
+                actual_locked: mismatch.actual.as_ref().map(|account| account.locked),
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl PaymentsEngine {
+    /// Compares the computed accounts with the expected ones, e.g. from a bank statement.
+    ///
+    /// Clients missing on either side are reported as mismatches.
+    pub fn reconcile(&self, expected: impl IntoIterator<Item=Account>) -> ReconciliationReport {
+        compare(expected, self.accounts())
+    }
+}
+
+/// Compares two sets of accounts, e.g. two account reports before and after a change.
+///
+/// Deltas are `actual` minus `expected`. If a client occurs more than once on one side, the last
+/// entry wins.
+pub fn compare<E, A>(expected: E, actual: A) -> ReconciliationReport
+    where E: IntoIterator<Item=Account>,
+          A: IntoIterator<Item=Account>
+{
+    let mut expected: BTreeMap<u16, Account> = expected.into_iter()
+        .map(|account| (account.client, account))
+        .collect();
+    let actual: BTreeMap<u16, Account> = actual.into_iter()
+        .map(|account| (account.client, account))
+        .collect();
+
+    let mut report = ReconciliationReport::default();
+    for (client, account) in actual {
+        match expected.remove(&client) {
+            Some(statement) if statement == account => report.matched += 1,
+            statement => report.mismatches.push(Mismatch {
+                client,
+                expected: statement,
+                actual: Some(account),
+            }),
+        }
+    }
+    report.mismatches.extend(expected.into_values().map(|statement| Mismatch {
+        client: statement.client,
+        expected: Some(statement),
+        actual: None,
+    }));
+    report.mismatches.sort_by_key(|mismatch| mismatch.client);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(client: u16, available: i64, held: i64, locked: bool) -> Account {
+        let (available, held) = (Decimal::new(available, 0), Decimal::new(held, 0));
+        Account { client, available, held, total: available + held, locked }
+    }
+
+    #[test]
+    fn reports_deltas_and_missing_clients() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(5, 0)).unwrap();
+        engine.dispute(2, 2).unwrap();
+        engine.deposit(3, 3, Decimal::new(1, 0)).unwrap();
+
+        let report = engine.reconcile(vec![
+            account(1, 10, 0, false),
+            account(2, 5, 0, false),
+            account(4, 2, 0, true),
+        ]);
+        assert_eq!(1, report.matched);
+        assert!(!report.is_reconciled());
+        let clients: Vec<u16> = report.mismatches.iter().map(|m| m.client).collect();
+        assert_eq!(vec![2, 3, 4], clients);
+        assert_eq!(Decimal::new(-5, 0), report.mismatches[0].available_delta());
+        assert_eq!(Decimal::new(5, 0), report.mismatches[0].held_delta());
+        assert_eq!(Decimal::ZERO, report.mismatches[0].total_delta());
+        assert_eq!(Decimal::new(1, 0), report.mismatches[1].total_delta());
+        assert_eq!(Decimal::new(-2, 0), report.mismatches[2].total_delta());
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        assert_eq!("client,available_delta,held_delta,total_delta,expected_locked,actual_locked\n\
+                    2,-5,5,0,false,false\n3,1,0,1,,false\n4,-2,0,-2,true,\n",
+                   String::from_utf8(csv).unwrap());
+    }
+
+    #[test]
+    fn equal_amounts_with_different_scale_match() {
+        let before = Account { available: Decimal::new(150, 2), ..account(1, 0, 0, false) };
+        let after = Account { available: Decimal::new(15, 1), ..account(1, 0, 0, false) };
+        let report = compare(vec![before], vec![after]);
+        assert!(report.is_reconciled());
+        assert_eq!(1, report.matched);
+    }
+}
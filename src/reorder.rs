@@ -0,0 +1,195 @@
+//! Reordering buffer for disputes that arrive before the deposit they refer to
+//!
+//! Streaming sources do not always preserve the order of transactions. With
+//! [crate::EngineConfig::reorder_window], disputes, resolves, and chargebacks that fail because
+//! the referenced deposit (or the client account) does not exist yet are parked and fail with
+//! [PaymentError::Deferred] instead. They are retried as soon as the deposit is accepted, or fail
+//! with their original error once they exceed the window. The outcomes of both are collected for
+//! [PaymentsEngine::take_reordered].
+use std::collections::VecDeque;
+use std::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::PaymentError;
+use crate::models::{Transaction, TransactionOutcome, TransactionType};
+use crate::PaymentsEngine;
+
+/// Limits on how long transactions are parked, both optional
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ReorderWindow {
+    /// Maximum number of engine operations after the parked transaction, `None` for no limit
+    pub max_operations: Option<u64>,
+    /// Maximum difference of transaction timestamps, e.g. 60 for a minute with timestamps in
+    /// seconds, `None` for no limit. Transactions without timestamp only expire by operations.
+    pub max_age: Option<u64>,
+}
+
+/// Transaction waiting for the deposit it refers to
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Parked {
+    transaction: Transaction,
+    /// Sequence number of the operation that parked the transaction
+    sequence: u64,
+    /// Error of the failed attempt, reported if the transaction expires
+    error: PaymentError,
+}
+
+impl PaymentsEngine {
+    /// Replaces the reorder window of the engine's configuration, e.g. after loading a snapshot.
+    pub fn set_reorder_window(&mut self, window: Option<ReorderWindow>) {
+        self.config.reorder_window = window;
+    }
+
+    /// Returns the parked transactions in the order they were executed.
+    pub fn parked(&self) -> impl Iterator<Item=&Transaction> {
+        self.parked.iter().map(|parked| &parked.transaction)
+    }
+
+    /// Returns the parked transactions that were retried or expired since the last call, along
+    /// with their outcomes, in execution order.
+    pub fn take_reordered(&mut self) -> Vec<(Transaction, TransactionOutcome)> {
+        mem::take(&mut self.reordered)
+    }
+
+    /// Fails all parked transactions with their original errors, e.g. at the end of the input.
+    pub fn flush_parked(&mut self) {
+        for parked in mem::take(&mut self.parked) {
+            self.fail_parked(parked);
+        }
+    }
+
+    /// Parks the transaction if it failed because it refers to a deposit not seen yet and returns
+    /// the error to report.
+    pub(crate) fn park(&mut self, transaction: &Transaction, error: PaymentError) -> PaymentError {
+        let deferrable = matches!(transaction.transaction_type,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback)
+            && matches!(error,
+                PaymentError::UnknownClient { .. } | PaymentError::UnknownTransaction { .. });
+        if !deferrable || self.config.reorder_window.is_none() {
+            return error;
+        }
+        let sequence = self.sequence;
+        self.parked.push_back(Parked { transaction: transaction.clone(), sequence, error });
+        PaymentError::Deferred { client: transaction.client, tx: transaction.tx }
+    }
+
+    /// Retries the transactions parked for a deposit after its successful execution.
+    pub(crate) fn retry_parked(&mut self, transaction: &Transaction) {
+        if transaction.transaction_type != TransactionType::Deposit
+            || !self.parked.iter().any(|parked| parked.transaction.tx == transaction.tx) {
+            return;
+        }
+        let (ready, waiting): (VecDeque<_>, _) = mem::take(&mut self.parked)
+            .into_iter()
+            .partition(|parked| parked.transaction.tx == transaction.tx);
+        self.parked = waiting;
+        for Parked { transaction, .. } in ready {
+            if let Some(key) = &transaction.idempotency_key {
+                self.idempotency.remove(key);
+            }
+            let outcome = self.execute_with_outcome(transaction.clone());
+            self.reordered.push((transaction, outcome));
+        }
+    }
+
+    /// Fails the parked transactions that exceed the reorder window.
+    pub(crate) fn expire_parked(&mut self, timestamp: Option<u64>) {
+        let Some(window) = self.config.reorder_window.clone() else {
+            return;
+        };
+        let sequence = self.sequence;
+        let expired = |parked: &Parked| {
+            let too_old = match (window.max_age, timestamp, parked.transaction.timestamp) {
+                (Some(max_age), Some(now), Some(then)) => now.saturating_sub(then) > max_age,
+                _ => false,
+            };
+            too_old || window.max_operations.is_some_and(|max| sequence - parked.sequence > max)
+        };
+        if !self.parked.iter().any(expired) {
+            return;
+        }
+        let (expired, waiting): (VecDeque<_>, _) = mem::take(&mut self.parked)
+            .into_iter()
+            .partition(expired);
+        self.parked = waiting;
+        for parked in expired {
+            self.fail_parked(parked);
+        }
+    }
+
+    fn fail_parked(&mut self, parked: Parked) {
+        let Parked { transaction, error, .. } = parked;
+        if let Some(key) = &transaction.idempotency_key {
+            self.idempotency.insert(key.clone(), Err(error.clone()));
+        }
+        let account = self.account(transaction.client);
+        let outcome = TransactionOutcome {
+            client: transaction.client,
+            tx: transaction.tx,
+            kind: transaction.transaction_type,
+            balance_before: account.clone(),
+            balance_after: account,
+            status: Err(error),
+            auto_lock: None,
+        };
+        self.reordered.push((transaction, outcome));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    fn execute(engine: &mut PaymentsEngine, row: &str) -> Result<(), &'static str> {
+        let transaction = crate::server::parse_line(row).unwrap();
+        engine.execute(transaction).map_err(|e| e.code())
+    }
+
+    #[test]
+    fn disputes_are_retried_when_the_deposit_arrives() {
+        let mut engine = PaymentsEngine::with_config(crate::EngineConfig {
+            reorder_window: Some(ReorderWindow { max_operations: Some(3), max_age: None }),
+            ..Default::default()
+        });
+        assert_eq!(Err("deferred"), execute(&mut engine, "dispute, 1, 1,"));
+        assert_eq!(Err("deferred"), execute(&mut engine, "dispute, 1, 2,"));
+        assert_eq!(Ok(()), execute(&mut engine, "deposit, 1, 1, 5"));
+
+        let reordered = engine.take_reordered();
+        assert_eq!(vec![(1, Ok(()))], reordered.iter()
+            .map(|(transaction, outcome)| (transaction.tx, outcome.status.clone()))
+            .collect::<Vec<_>>());
+        assert_eq!(Decimal::new(5, 0), engine.account(1).unwrap().held);
+        assert_eq!(vec![2], engine.parked().map(|t| t.tx).collect::<Vec<_>>());
+
+        execute(&mut engine, "deposit, 1, 3, 1").unwrap();
+        execute(&mut engine, "deposit, 1, 4, 1").unwrap();
+        let expired = engine.take_reordered();
+        assert_eq!(2, expired[0].0.tx);
+        assert_eq!("unknown_client", expired[0].1.status.as_ref().unwrap_err().code());
+        assert_eq!(0, engine.parked().count());
+    }
+
+    #[test]
+    fn parked_transactions_expire_by_timestamp_or_flush() {
+        let mut engine = PaymentsEngine::new();
+        engine.set_reorder_window(Some(ReorderWindow { max_operations: None, max_age: Some(60) }));
+        let csv = "type,client,tx,amount,timestamp\n\
+                   deposit,1,1,5,100\n\
+                   dispute,1,2,,100\n\
+                   resolve,1,3,,150\n\
+                   deposit,1,4,5,161\n";
+        for transaction in crate::csv::read_transactions_from(csv.as_bytes()) {
+            let _ = engine.execute(transaction.unwrap());
+        }
+        assert_eq!(vec![2], engine.take_reordered().iter().map(|r| r.0.tx).collect::<Vec<_>>());
+
+        engine.flush_parked();
+        let flushed = engine.take_reordered();
+        assert_eq!(3, flushed[0].0.tx);
+        assert_eq!("unknown_transaction", flushed[0].1.status.as_ref().unwrap_err().code());
+    }
+}
@@ -0,0 +1,210 @@
+//! Aggregate reporting over a batch of transactions
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use rust_decimal::Decimal;
+
+use crate::error::PaymentError;
+use crate::models::{Account, Transaction, TransactionOutcome, TransactionType};
+use crate::PaymentsEngine;
+
+/// Upper bounds (exclusive) of the deposit amount buckets; the last bucket is unbounded
+pub const DEPOSIT_BUCKETS: [u32; 6] = [1, 10, 100, 1_000, 10_000, 100_000];
+
+/// Count and volume of the transactions of one type
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TypeStats {
+    /// Number of accepted transactions
+    pub accepted: u64,
+    /// Number of rejected transactions
+    pub rejected: u64,
+    /// Sum of the amounts of accepted transactions; for disputes, resolves, and chargebacks the
+    /// amount of the referenced deposit
+    pub volume: Decimal,
+}
+
+/// Aggregates over the transactions of a batch
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BatchStats {
+    /// Statistics per transaction type
+    pub by_type: BTreeMap<TransactionType, TypeStats>,
+    /// Number of accepted deposits per amount bucket, see [DEPOSIT_BUCKETS]
+    pub deposit_buckets: [u64; DEPOSIT_BUCKETS.len() + 1],
+    /// Number of rows that could not be parsed
+    pub invalid_rows: u64,
+    /// Number of rejected transactions per error code, see [crate::PaymentError::code]
+    pub rejections: BTreeMap<&'static str, u64>,
+}
+
+impl BatchStats {
+    /// Adds an executed transaction to the aggregates. Transactions parked for a retry (see
+    /// [crate::reorder]) are only added with their final outcome.
+    pub fn record(&mut self, transaction: &Transaction, outcome: &TransactionOutcome) {
+        let stats = self.by_type.entry(transaction.transaction_type).or_default();
+        match &outcome.status {
+            Err(PaymentError::Deferred { .. }) => return,
+            Err(e) => {
+                stats.rejected += 1;
+                *self.rejections.entry(e.code()).or_default() += 1;
+                return;
+            }
+            Ok(()) => {}
+        }
+        stats.accepted += 1;
+        let held = |account: &Option<Account>| account.as_ref().map_or(Decimal::ZERO, |a| a.held);
+        let amount = transaction.amount.unwrap_or_else(|| {
+            (held(&outcome.balance_after) - held(&outcome.balance_before)).abs()
+        });
+        stats.volume += amount;
+        if transaction.transaction_type == TransactionType::Deposit {
+            let bucket = DEPOSIT_BUCKETS.iter()
+                .position(|&bound| amount < Decimal::from(bound))
+                .unwrap_or(DEPOSIT_BUCKETS.len());
+            self.deposit_buckets[bucket] += 1;
+        }
+    }
+
+    /// Returns the share of accepted deposits that were charged back, `None` without deposits.
+    pub fn chargeback_rate(&self) -> Option<f64> {
+        let accepted = |kind| self.by_type.get(&kind).map_or(0, |stats| stats.accepted);
+        let deposits = accepted(TransactionType::Deposit);
+        (deposits > 0).then(|| accepted(TransactionType::Chargeback) as f64 / deposits as f64)
+    }
+
+    /// Writes the numbers of parsed and invalid rows (out of the given number of input rows),
+    /// accepted transactions per type, and rejected transactions per error code to the writer, one
+    /// `name count` pair per line.
+    pub fn write_breakdown<W: Write>(&self, mut writer: W, rows: u64) -> io::Result<()> {
+        let parsed = rows.saturating_sub(self.invalid_rows);
+        writeln!(writer, "{:<40} {:>10}", "rows_parsed", parsed)?;
+        writeln!(writer, "{:<40} {:>10}", "parse_failures", self.invalid_rows)?;
+        for (kind, stats) in &self.by_type {
+            writeln!(writer, "{:<40} {:>10}", format!("accepted.{}", kind), stats.accepted)?;
+        }
+        for (code, count) in &self.rejections {
+            writeln!(writer, "{:<40} {:>10}", format!("rejected.{}", code), count)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a human-readable summary of the aggregates and the engine state to the writer.
+    pub fn write_summary<W: Write>(
+        &self,
+        mut writer: W,
+        engine: &PaymentsEngine,
+        top: usize,
+    ) -> io::Result<()> {
+        writeln!(writer, "{:<12} {:>10} {:>10} {:>16}", "type", "accepted", "rejected", "volume")?;
+        for (kind, stats) in &self.by_type {
+            writeln!(writer, "{:<12} {:>10} {:>10} {:>16}", kind.to_string(), stats.accepted,
+                     stats.rejected, stats.volume)?;
+        }
+        if self.invalid_rows > 0 {
+            writeln!(writer, "{:<12} {:>10} {:>10}", "invalid", 0, self.invalid_rows)?;
+        }
+
+        writeln!(writer, "\nDeposit amounts")?;
+        let mut lower = 0;
+        for (bound, count) in DEPOSIT_BUCKETS.iter().zip(&self.deposit_buckets) {
+            writeln!(writer, "{:>12} {:>10}", format!("{}-{}", lower, bound), count)?;
+            lower = *bound;
+        }
+        writeln!(writer, "{:>12} {:>10}", format!(">={}", lower),
+                 self.deposit_buckets[DEPOSIT_BUCKETS.len()])?;
+
+        writeln!(writer, "\nTotal held funds: {}", engine.total_held())?;
+        match self.chargeback_rate() {
+            Some(rate) => writeln!(writer, "Chargeback rate: {:.2}%", rate * 100.0)?,
+            None => writeln!(writer, "Chargeback rate: n/a")?,
+        }
+
+        writeln!(writer, "\nTop {} accounts by total", top)?;
+        writeln!(writer, "{:>8} {:>16} {:>16} {:>16}", "client", "available", "held", "total")?;
+        for account in engine.top_accounts(top) {
+            writeln!(writer, "{:>8} {:>16} {:>16} {:>16}{}", account.client, account.available,
+                     account.held, account.total, if account.locked { " locked" } else { "" })?;
+        }
+        Ok(())
+    }
+}
+
+impl PaymentsEngine {
+    /// Returns the `n` accounts with the highest total funds, ties broken by client ID.
+    pub fn top_accounts(&self, n: usize) -> Vec<Account> {
+        let mut accounts: Vec<Account> = self.accounts().collect();
+        accounts.sort_unstable_by(|a, b| b.total.cmp(&a.total).then(a.client.cmp(&b.client)));
+        accounts.truncate(n);
+        accounts
+    }
+
+    /// Returns the sum of the held funds of all accounts.
+    pub fn total_held(&self) -> Decimal {
+        self.accounts.values().map(|account| account.held).sum()
+    }
+
+    /// Returns the sum of the available funds of all accounts.
+    pub fn total_available(&self) -> Decimal {
+        self.accounts.values().map(|account| account.available).sum()
+    }
+
+    /// Returns the number of client accounts.
+    pub fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Returns the number of operations attempted so far, including rejected ones.
+    pub fn transaction_count(&self) -> u64 {
+        self.sequence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(kind: TransactionType, client: u16, tx: u32, amount: Option<i64>) -> Transaction {
+        Transaction::fixture(kind, client, tx, amount.unwrap_or_default())
+    }
+
+    #[test]
+    fn aggregates_accepted_and_rejected_transactions() {
+        let mut engine = PaymentsEngine::new();
+        let mut stats = BatchStats::default();
+        for transaction in [
+            row(TransactionType::Deposit, 1, 1, Some(5)),
+            row(TransactionType::Deposit, 2, 2, Some(250)),
+            row(TransactionType::Withdrawal, 1, 3, Some(10)),
+            row(TransactionType::Dispute, 2, 2, None),
+            row(TransactionType::Chargeback, 2, 2, None),
+        ] {
+            let outcome = engine.execute_with_outcome(transaction.clone());
+            stats.record(&transaction, &outcome);
+        }
+
+        let deposits = stats.by_type[&TransactionType::Deposit];
+        assert_eq!((2, 0, Decimal::new(255, 0)), (deposits.accepted, deposits.rejected,
+                                                   deposits.volume));
+        assert_eq!(1, stats.by_type[&TransactionType::Withdrawal].rejected);
+        assert_eq!(Decimal::new(250, 0), stats.by_type[&TransactionType::Chargeback].volume);
+        assert_eq!([0, 1, 0, 1, 0, 0, 0], stats.deposit_buckets);
+        assert_eq!(Some(0.5), stats.chargeback_rate());
+        assert_eq!(BTreeMap::from([("insufficient_funds", 1)]), stats.rejections);
+
+        let top: Vec<u16> = engine.top_accounts(1).iter().map(|a| a.client).collect();
+        assert_eq!(vec![1], top);
+        assert_eq!(Decimal::ZERO, engine.total_held());
+    }
+
+    #[test]
+    fn counts_and_totals_cover_all_accounts() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(5, 0)).unwrap();
+        engine.deposit(2, 2, Decimal::new(7, 0)).unwrap();
+        engine.dispute(2, 2).unwrap();
+        assert!(engine.withdraw(1, 3, Decimal::new(9, 0)).is_err());
+
+        assert_eq!((2, 4), (engine.account_count(), engine.transaction_count()));
+        assert_eq!((Decimal::new(5, 0), Decimal::new(7, 0)),
+                   (engine.total_available(), engine.total_held()));
+    }
+}
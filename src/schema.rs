@@ -0,0 +1,64 @@
+//! JSON Schema of the payloads exchanged with the engine
+//!
+//! Integrations can validate transactions sent to the server (see [crate::server]) and parse
+//! accounts and errors against these schemas, also printed by the `schema` subcommand. The server
+//! has no HTTP API to describe with OpenAPI; instead, the schemas cover the JSON payloads of its
+//! line protocol, so that client code can be generated from them.
+use std::collections::BTreeMap;
+
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+
+use crate::server::{BatchItemReply, Health, MAX_BATCH};
+use crate::subscription::BalanceEvent;
+use crate::{Account, PaymentError, Transaction};
+
+/// Returns the schemas of [Transaction], [Account], and [PaymentError] by the names
+/// `transaction`, `account`, and `error`, and those of the server's JSON lines by the names
+/// `batch` (up to [MAX_BATCH] transactions), `batch_reply` (the reply to a batch),
+/// `balance_event` (streamed after `SUBSCRIBE`), and `health` (the reply to `HEALTH` after `OK`).
+pub fn schemas() -> BTreeMap<&'static str, RootSchema> {
+    let mut batch = schema_for!(Vec<Transaction>);
+    batch.schema.array().max_items = Some(MAX_BATCH as u32);
+    BTreeMap::from([
+        ("transaction", schema_for!(Transaction)),
+        ("account", schema_for!(Account)),
+        ("error", schema_for!(PaymentError)),
+        ("batch", batch),
+        ("batch_reply", schema_for!(Vec<BatchItemReply>)),
+        ("balance_event", schema_for!(BalanceEvent)),
+        ("health", schema_for!(Health)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transaction_schema_lists_required_columns_and_types() {
+        let schemas = serde_json::to_value(schemas()).unwrap();
+        let transaction = &schemas["transaction"];
+        for column in ["type", "client", "tx"] {
+            assert!(transaction["required"].as_array().unwrap().contains(&column.into()), "{}",
+                    column);
+        }
+        assert_eq!("string", transaction["properties"]["tags"]["type"]);
+        let types = &transaction["definitions"]["TransactionType"]["enum"];
+        assert!(types.as_array().unwrap().contains(&"chargeback".into()));
+        assert_eq!("string", schemas["account"]["properties"]["available"]["type"]);
+        assert!(schemas["error"]["oneOf"].as_array().unwrap().len() > 10);
+    }
+
+    #[test]
+    fn server_payloads_are_described() {
+        let schemas = serde_json::to_value(schemas()).unwrap();
+        assert_eq!(1000, schemas["batch"]["maxItems"]);
+        assert_eq!("#/definitions/Transaction", schemas["batch"]["items"]["$ref"]);
+        let status = &schemas["batch_reply"]["definitions"]["BatchItemStatus"]["oneOf"];
+        assert_eq!(2, status.as_array().unwrap().len());
+        let change = &schemas["balance_event"]["definitions"]["BalanceChange"]["oneOf"];
+        assert_eq!(4, change.as_array().unwrap().len());
+        assert_eq!("integer", schemas["health"]["properties"]["pending"]["type"]);
+    }
+}
@@ -0,0 +1,124 @@
+//! Inline screening of clients against deny-lists
+use std::collections::HashSet;
+use std::io::{self, BufRead};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{PaymentError, Result};
+use crate::models::TransactionType;
+use crate::PaymentsEngine;
+
+/// Decides whether the transactions of a client must be rejected, e.g. due to sanctions
+pub trait ScreeningProvider: Send {
+    /// Returns true iff the client is blocked.
+    fn is_blocked(&self, client: u16) -> bool;
+}
+
+/// Fixed set of blocked clients
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DenyList {
+    clients: HashSet<u16>,
+}
+
+impl DenyList {
+    /// Reads one client ID per line; empty lines and lines starting with `#` are ignored.
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut clients = HashSet::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let client = line.parse().map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", line, e))
+            })?;
+            clients.insert(client);
+        }
+        Ok(Self { clients })
+    }
+}
+
+impl FromIterator<u16> for DenyList {
+    fn from_iter<I: IntoIterator<Item=u16>>(iter: I) -> Self {
+        Self { clients: iter.into_iter().collect() }
+    }
+}
+
+impl ScreeningProvider for DenyList {
+    fn is_blocked(&self, client: u16) -> bool {
+        self.clients.contains(&client)
+    }
+}
+
+/// Transaction rejected by screening
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BlockedTransaction {
+    /// Client identifier
+    pub client: u16,
+    /// Transaction identifier
+    pub tx: u32,
+    /// Type of the transaction
+    #[serde(rename = "type")]
+    pub kind: TransactionType,
+}
+
+impl PaymentsEngine {
+    /// Screens the clients of all transactions subsequently executed, via
+    /// [PaymentsEngine::execute] or operations such as [PaymentsEngine::deposit], with the given
+    /// provider, replacing any previous one.
+    pub fn set_screening(&mut self, provider: impl ScreeningProvider + 'static) {
+        *self.screening = Some(Box::new(provider));
+    }
+
+    /// Returns the transactions rejected by screening in order of execution.
+    pub fn blocked_transactions(&self) -> &[BlockedTransaction] {
+        &self.blocked
+    }
+
+    /// Returns true iff the client is blocked by the screening provider.
+    pub(crate) fn is_blocked(&self, client: u16) -> bool {
+        self.screening.as_ref().is_some_and(|provider| provider.is_blocked(client))
+    }
+
+    /// Fails with [PaymentError::ClientBlocked] and records the transaction if the client is
+    /// blocked.
+    pub(crate) fn screen(&mut self, client: u16, tx: u32, kind: TransactionType) -> Result<()> {
+        if !self.is_blocked(client) {
+            return Ok(());
+        }
+        self.blocked.push(BlockedTransaction { client, tx, kind });
+        Err(PaymentError::ClientBlocked { client, tx })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::{RejectionReason, Transaction};
+
+    #[test]
+    fn transactions_of_denied_clients_are_rejected_and_recorded() {
+        let deny_list = DenyList::read("# sanctioned\n2\n\n 3 \n".as_bytes()).unwrap();
+        assert_eq!(DenyList::from_iter([2, 3]), deny_list);
+
+        let mut engine = PaymentsEngine::new();
+        engine.set_screening(deny_list);
+        let deposit = |client| Transaction::deposit(client, u32::from(client), Decimal::ONE);
+        engine.execute(deposit(1)).unwrap();
+        assert_eq!(vec![RejectionReason::ClientBlocked], engine.explain(&deposit(2)));
+        assert!(matches!(engine.execute(deposit(2)),
+            Err(PaymentError::ClientBlocked { client: 2, tx: 2 })));
+
+        assert!(engine.account(2).is_none());
+        assert!(matches!(engine.deposit(3, 3, Decimal::ONE),
+            Err(PaymentError::ClientBlocked { client: 3, tx: 3 })));
+        assert!(engine.account(3).is_none());
+        let kind = TransactionType::Deposit;
+        let blocked = |client, tx| BlockedTransaction { client, tx, kind };
+        assert_eq!([blocked(2, 2), blocked(3, 3)], engine.blocked_transactions());
+        assert!(DenyList::read("abc\n".as_bytes()).is_err());
+    }
+}
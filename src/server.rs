@@ -0,0 +1,955 @@
+//! Line-protocol ingestion server for systems that can open a socket but cannot speak HTTP
+//!
+//! Each line of a connection holds one transaction, either as CSV row without header (`deposit, 1,
+//! 1, 2.5`) or as JSON object (`{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}`). The
+//! server replies to each line with `OK` or `ERR <code>`, where the code is
+//! [crate::PaymentError::code] or `invalid_row` if the line could not be parsed. Empty lines are
+//! ignored.
+//!
+//! A server started with [Tokens] requires every connection to authenticate with `AUTH <token>`
+//! first, answered with `OK` or `ERR unauthorized` (closing the connection). The [Role] of the
+//! token determines which transactions it may submit, others are answered with `ERR forbidden`.
+//! Transactions beyond a [RateLimit] are answered with `ERR rate_limited` without reaching the
+//! engine.
+//!
+//! A line may also hold a JSON array of up to [MAX_BATCH] transactions, answered with a JSON array
+//! of a [BatchItemReply] per item, e.g.
+//! `[{"status":"applied"},{"status":"rejected","error":"insufficient_funds"}]`. Each item is
+//! submitted like a single line, so some may fail while others succeed.
+//!
+//! Admin tokens may additionally drive the operations that are not transactions:
+//! `REVIEW <client> <tx>` marks an opened dispute as under review, `REPRESENT <client> <tx>`
+//! reverses a chargeback, `REPRESENT <client> <tx> unlock` also unlocking the account, and
+//! `UNLOCK <client>` unlocks an account (see [AdminOperation]). Callers that record transactions
+//! for recovery should record these operations as well, e.g. with
+//! [crate::wal::WriteAheadLog::append_operation].
+//!
+//! `SUBSCRIBE <client>` turns a connection into a stream of the client's balances: after `OK`,
+//! every [BalanceEvent] is sent as a JSON line until the connection is closed or falls behind
+//! (see [PaymentsEngine::subscribe]). `SUBSCRIBE *` streams the balances of all clients, e.g. for
+//! live dashboards (admin only, see [PaymentsEngine::subscribe_all]).
+//!
+//! `HEALTH` is answered by the server itself, also before `AUTH`, with `OK` followed by the
+//! [Health] of the server as JSON, e.g. `OK {"pending":0}`, so that orchestrators can probe it.
+//! As the connection threads answer it without the engine, it serves as liveness probe. `READY`
+//! is the readiness probe: it is answered like `HEALTH` once the caller receives requests (see
+//! [Server::recv_timeout]), and with `ERR not_ready` before or while the caller has not asked for
+//! requests for [MAX_POLL_DELAY], e.g. because it is stuck.
+//!
+//! Failed connections and accepts do not stop the server; they are queued as [ServerError]s for
+//! the caller to report (see [Server::errors]).
+use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+#[cfg(all(unix, feature = "unix-socket"))]
+use std::os::unix::net::UnixListener;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use csv::Trim;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::error;
+use crate::models::{AdminOperation, Transaction, TransactionType};
+use crate::ratelimit::{RateLimit, RateLimiter};
+use crate::subscription::BalanceEvent;
+use crate::PaymentsEngine;
+
+/// Operation requested by a connection
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    /// Execute a transaction
+    Execute(Transaction),
+    /// Apply an administrative operation, see [PaymentsEngine::administer] (admin only)
+    Admin(AdminOperation),
+    /// Stream the client's balances, see [PaymentsEngine::subscribe], or those of all clients
+    /// without client, see [PaymentsEngine::subscribe_all] (admin only)
+    Subscribe { client: Option<u16> },
+}
+
+impl Operation {
+    /// Returns the client the operation applies to, 0 for subscriptions to all clients.
+    pub fn client(&self) -> u16 {
+        match self {
+            Operation::Execute(transaction) => transaction.client,
+            Operation::Admin(operation) => operation.client(),
+            Operation::Subscribe { client } => client.unwrap_or_default(),
+        }
+    }
+
+    /// Applies the operation to the engine. Subscriptions change nothing, see [Request::answer].
+    pub fn apply(self, engine: &mut PaymentsEngine) -> error::Result<()> {
+        match self {
+            Operation::Execute(transaction) => engine.execute(transaction),
+            Operation::Admin(operation) => engine.administer(operation),
+            Operation::Subscribe { .. } => Ok(()),
+        }
+    }
+}
+
+/// Operation received from a connection, awaiting its reply
+pub struct Request {
+    /// Parsed operation
+    pub operation: Operation,
+    reply: Sender<Reply>,
+}
+
+impl Request {
+    /// Applies the operation to the engine and replies, or subscribes the connection to the
+    /// balances if the operation is [Operation::Subscribe].
+    pub fn answer(self, engine: &mut PaymentsEngine) {
+        match self.operation {
+            Operation::Subscribe { client } => {
+                let events = match client {
+                    Some(client) => engine.subscribe(client),
+                    None => engine.subscribe_all(),
+                };
+                let _ = self.reply.send(Reply::Events(events));
+            }
+            _ => {
+                let status = self.operation.clone().apply(engine);
+                self.reply(&status);
+            }
+        }
+    }
+
+    /// Sends the result of applying the operation back to the connection.
+    pub fn reply(self, status: &error::Result<()>) {
+        let _ = self.reply.send(Reply::Line(match status {
+            Ok(()) => String::from("OK"),
+            Err(e) => format!("ERR {}", e.code()),
+        }));
+    }
+
+    /// Answers with `ERR <code>` without applying the operation.
+    pub fn reject(self, code: &str) {
+        let _ = self.reply.send(Reply::Line(format!("ERR {}", code)));
+    }
+}
+
+/// Reply to a line of a connection
+enum Reply {
+    /// Line to send
+    Line(String),
+    /// Events to stream after `OK`
+    Events(Receiver<BalanceEvent>),
+}
+
+impl Reply {
+    /// Returns the line to send first.
+    fn line(&self) -> &str {
+        match self {
+            Reply::Line(line) => line,
+            Reply::Events(_) => "OK",
+        }
+    }
+}
+
+/// Maximum number of transactions in a batch, larger ones are answered with
+/// `ERR batch_too_large`
+pub const MAX_BATCH: usize = 1000;
+
+/// Outcome of an item of a batch
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum BatchItemStatus {
+    /// Executed by the engine
+    Applied,
+    /// Not executed, see [BatchItemReply::error]
+    Rejected,
+}
+
+/// Reply to an item of a batch
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct BatchItemReply {
+    /// Whether the item was executed
+    pub status: BatchItemStatus,
+    /// Error code of a rejected item, as after `ERR` in the reply to a single line, e.g.
+    /// `insufficient_funds`, `invalid_row`, or `forbidden`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BatchItemReply {
+    /// Returns the reply to an item answered with the given line.
+    fn from_line(line: &str) -> Self {
+        match line.strip_prefix("ERR ") {
+            Some(code) => {
+                BatchItemReply { status: BatchItemStatus::Rejected, error: Some(code.to_owned()) }
+            }
+            None => BatchItemReply { status: BatchItemStatus::Applied, error: None },
+        }
+    }
+}
+
+/// Maximum number of [ServerError]s queued until the caller collects them, further ones are
+/// dropped
+pub const MAX_PENDING_ERRORS: usize = 100;
+
+/// Error that ended a connection or occurred while waiting for one
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("Could not accept connection: {0}")]
+    Accept(io::Error),
+    #[error("Connection failed: {0}")]
+    Connection(io::Error),
+}
+
+/// Permissions granted by a token
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    /// May submit deposits and withdrawals, and subscribe to the balances of a client
+    Submit,
+    /// May submit all transactions, including disputes, resolves, chargebacks, refunds, and
+    /// adjustments, and request the operations that are not transactions
+    Admin,
+}
+
+impl Role {
+    /// Returns true iff the role may submit transactions of the given type.
+    pub fn permits(self, kind: TransactionType) -> bool {
+        self == Role::Admin
+            || matches!(kind, TransactionType::Deposit | TransactionType::Withdrawal)
+    }
+
+    /// Returns true iff the role may request the operation.
+    pub fn permits_operation(self, operation: &Operation) -> bool {
+        match operation {
+            Operation::Execute(transaction) => self.permits(transaction.transaction_type),
+            Operation::Subscribe { client } => client.is_some() || self == Role::Admin,
+            Operation::Admin(_) => self == Role::Admin,
+        }
+    }
+}
+
+/// Bearer tokens and their roles
+#[derive(Clone, Debug, Default)]
+pub struct Tokens(Vec<(String, Role)>);
+
+impl Tokens {
+    /// Grants the role to the token, replacing its previous role.
+    pub fn insert(&mut self, token: impl Into<String>, role: Role) {
+        let token = token.into();
+        self.0.retain(|(known, _)| *known != token);
+        self.0.push((token, role));
+    }
+
+    /// Returns the role of the token, `None` if unknown.
+    ///
+    /// The token is compared with all known tokens in constant time, so the time taken does not
+    /// reveal how much of a known token it matches.
+    pub fn role(&self, token: &str) -> Option<Role> {
+        self.0.iter().fold(None, |role, (known, known_role)| {
+            match constant_time_eq(known.as_bytes(), token.as_bytes()) {
+                true => Some(*known_role),
+                false => role,
+            }
+        })
+    }
+}
+
+/// Returns true iff both byte strings are equal, comparing all bytes of equally long ones.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl FromStr for Tokens {
+    type Err = String;
+
+    /// Parses lines `submit <token>` or `admin <token>`, ignoring blank lines and lines starting
+    /// with `#`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = Tokens::default();
+        for (i, line) in s.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (role, token) = match line.split_once(char::is_whitespace) {
+                Some(("submit", token)) => (Role::Submit, token),
+                Some(("admin", token)) => (Role::Admin, token),
+                _ => {
+                    return Err(format!("line {}: expected `submit <token>` or `admin <token>`", i));
+                }
+            };
+            tokens.insert(token.trim(), role);
+        }
+        Ok(tokens)
+    }
+}
+
+/// Access control of a [Server]
+#[derive(Clone, Debug, Default)]
+pub struct ServerOptions {
+    /// Tokens that connections must authenticate with, `None` to accept any connection as admin,
+    /// which is only safe on loopback addresses and Unix domain sockets
+    pub tokens: Option<Tokens>,
+    /// Limits of the transactions submitted by all connections together
+    pub rate_limit: RateLimit,
+    /// Durability of the caller's state, reported by `HEALTH`
+    pub progress: Option<Arc<Progress>>,
+}
+
+/// Durability of the state of a server's caller, updated by the caller as it logs requests to a
+/// write-ahead log (see [crate::wal]) and writes checkpoints (see [crate::checkpoint])
+#[derive(Debug, Default)]
+pub struct Progress {
+    /// Number of entries in the write-ahead log, `None` without log
+    wal_entries: Option<AtomicU64>,
+    /// Milliseconds since the Unix epoch when the last checkpoint was written, 0 if none yet
+    checkpoint: AtomicU64,
+}
+
+impl Progress {
+    /// Creates the progress of a caller with a write-ahead log of the given number of entries,
+    /// `None` without log.
+    pub fn new(wal_entries: Option<u64>) -> Self {
+        Self { wal_entries: wal_entries.map(AtomicU64::new), checkpoint: AtomicU64::new(0) }
+    }
+
+    /// Records that an entry was appended to the write-ahead log.
+    pub fn logged(&self) {
+        if let Some(entries) = &self.wal_entries {
+            entries.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records that a checkpoint was written just now, truncating the write-ahead log.
+    pub fn checkpointed(&self) {
+        if let Some(entries) = &self.wal_entries {
+            entries.store(0, Ordering::Relaxed);
+        }
+        self.checkpoint.store(unix_millis(), Ordering::Relaxed);
+    }
+}
+
+/// Returns the milliseconds since the Unix epoch.
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// State of a [Server] reported by `HEALTH`
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Health {
+    /// Number of requests waiting for the caller, growing if the engine falls behind
+    pub pending: usize,
+    /// Number of write-ahead log entries not covered by a checkpoint, i.e. replayed on recovery,
+    /// if there is a log (see [Progress])
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wal_lag: Option<u64>,
+    /// Seconds since the last checkpoint was written, if any (see [Progress])
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkpoint_age: Option<u64>,
+}
+
+/// Maximum time between two calls of [Server::recv_timeout] for the server to answer `READY`
+/// with `OK`
+pub const MAX_POLL_DELAY: Duration = Duration::from_secs(5);
+
+/// State shared by the connections of a server
+struct Access {
+    tokens: Option<Tokens>,
+    limiter: Mutex<RateLimiter>,
+    /// Number of requests sent to the caller but not yet received
+    pending: AtomicUsize,
+    progress: Option<Arc<Progress>>,
+    started: Instant,
+    /// Milliseconds after `started` when the caller last asked for a request, `u64::MAX` if never
+    polled: AtomicU64,
+}
+
+impl Access {
+    fn health(&self) -> Health {
+        let progress = self.progress.as_deref();
+        let checkpoint = progress.map(|p| p.checkpoint.load(Ordering::Relaxed)).filter(|&t| t > 0);
+        Health {
+            pending: self.pending.load(Ordering::Relaxed),
+            wal_lag: progress.and_then(|p| p.wal_entries.as_ref())
+                .map(|entries| entries.load(Ordering::Relaxed)),
+            checkpoint_age: checkpoint.map(|t| unix_millis().saturating_sub(t) / 1000),
+        }
+    }
+
+    /// Returns true iff the caller asked for a request within the last [MAX_POLL_DELAY].
+    fn ready(&self) -> bool {
+        let polled = self.polled.load(Ordering::Relaxed);
+        let now = self.started.elapsed().as_millis() as u64;
+        polled != u64::MAX && now.saturating_sub(polled) <= MAX_POLL_DELAY.as_millis() as u64
+    }
+
+    /// Records that the caller asks for a request.
+    fn poll(&self) {
+        self.polled.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Server accepting connections on a background thread
+///
+/// The engine is not touched by the server: the caller receives [Request]s, executes them in
+/// order, and replies.
+pub struct Server {
+    receiver: Receiver<Request>,
+    errors: Receiver<ServerError>,
+    access: Arc<Access>,
+}
+
+impl Server {
+    /// Starts accepting connections from the TCP listener.
+    pub fn tcp(listener: TcpListener) -> Self {
+        Self::tcp_with_options(listener, ServerOptions::default())
+    }
+
+    /// Starts accepting connections from the TCP listener with the given access control.
+    pub fn tcp_with_options(listener: TcpListener, options: ServerOptions) -> Self {
+        Self::start(move || listener.accept().map(|(stream, _)| stream), options)
+    }
+
+    /// Starts accepting connections from the Unix domain socket listener, for co-located
+    /// processes.
+    #[cfg(all(unix, feature = "unix-socket"))]
+    pub fn unix(listener: UnixListener) -> Self {
+        Self::unix_with_options(listener, ServerOptions::default())
+    }
+
+    /// Starts accepting connections from the Unix domain socket listener with the given access
+    /// control.
+    #[cfg(all(unix, feature = "unix-socket"))]
+    pub fn unix_with_options(listener: UnixListener, options: ServerOptions) -> Self {
+        Self::start(move || listener.accept().map(|(stream, _)| stream), options)
+    }
+
+    /// Calls `accept` on a background thread and handles each connection on its own thread.
+    fn start<S, A>(mut accept: A, options: ServerOptions) -> Self
+        where S: Send + 'static,
+              for<'a> &'a S: Read + Write,
+              A: FnMut() -> io::Result<S> + Send + 'static
+    {
+        let (sender, receiver) = mpsc::channel();
+        let (error_sender, errors) = mpsc::sync_channel(MAX_PENDING_ERRORS);
+        let access = Arc::new(Access {
+            tokens: options.tokens,
+            limiter: Mutex::new(RateLimiter::new(options.rate_limit)),
+            pending: AtomicUsize::new(0),
+            progress: options.progress,
+            started: Instant::now(),
+            polled: AtomicU64::new(u64::MAX),
+        });
+        let shared = Arc::clone(&access);
+        thread::spawn(move || loop {
+            match accept() {
+                Ok(stream) => {
+                    let sender = sender.clone();
+                    let error_sender = error_sender.clone();
+                    let access = Arc::clone(&shared);
+                    thread::spawn(move || {
+                        if let Err(e) = handle(BufReader::new(&stream), &stream, sender, &access) {
+                            report(&error_sender, ServerError::Connection(e));
+                        }
+                    });
+                }
+                Err(e) => report(&error_sender, ServerError::Accept(e)),
+            }
+        });
+        Self { receiver, errors, access }
+    }
+
+    /// Returns the errors queued since the last call, without waiting.
+    pub fn errors(&self) -> impl Iterator<Item=ServerError> + '_ {
+        self.errors.try_iter()
+    }
+
+    /// Waits for the next request at most for the given time.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Request, RecvTimeoutError> {
+        self.access.poll();
+        let request = self.receiver.recv_timeout(timeout)?;
+        self.access.pending.fetch_sub(1, Ordering::Relaxed);
+        Ok(request)
+    }
+
+    /// Returns the current state of the server, as reported by `HEALTH`.
+    pub fn health(&self) -> Health {
+        self.access.health()
+    }
+
+    /// Returns the number of transactions answered with `ERR rate_limited` so far.
+    pub fn rate_limited(&self) -> u64 {
+        self.access.limiter.lock().unwrap_or_else(PoisonError::into_inner).limited()
+    }
+}
+
+/// Queues the error for [Server::errors], dropping it if the queue is full.
+fn report(errors: &SyncSender<ServerError>, error: ServerError) {
+    let _ = errors.try_send(error);
+}
+
+/// Parses a line of the protocol into a transaction.
+pub fn parse_line(line: &str) -> Result<Transaction, String> {
+    if line.starts_with('{') {
+        return serde_json::from_str(line).map_err(|e| e.to_string());
+    }
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(line.as_bytes())
+        .into_deserialize()
+        .next()
+        .unwrap_or_else(|| Err(csv::Error::from(io::Error::from(io::ErrorKind::UnexpectedEof))))
+        .map_err(|e| e.to_string())
+}
+
+/// Parses a line of the protocol into an operation, i.e. an admin command or a transaction.
+pub fn parse_operation(line: &str) -> Result<Operation, String> {
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    let args: Vec<_> = args.split_whitespace().collect();
+    let admin = match (command, args.as_slice()) {
+        ("REVIEW", [client, tx]) => AdminOperation::Review { client: id(client)?, tx: id(tx)? },
+        ("REPRESENT", [client, tx]) => {
+            AdminOperation::Represent { client: id(client)?, tx: id(tx)?, unlock: false }
+        }
+        ("REPRESENT", [client, tx, "unlock"]) => {
+            AdminOperation::Represent { client: id(client)?, tx: id(tx)?, unlock: true }
+        }
+        ("UNLOCK", [client]) => AdminOperation::Unlock { client: id(client)? },
+        ("SUBSCRIBE", ["*"]) => return Ok(Operation::Subscribe { client: None }),
+        ("SUBSCRIBE", [client]) => {
+            return Ok(Operation::Subscribe { client: Some(id(client)?) });
+        }
+        ("SUBSCRIBE", _) => return Err(String::from("expected `SUBSCRIBE <client>|*`")),
+        ("UNLOCK", _) => return Err(String::from("expected `UNLOCK <client>`")),
+        ("REVIEW" | "REPRESENT", _) => {
+            return Err(format!("expected `{} <client> <tx>`", command));
+        }
+        _ => return parse_line(line).map(Operation::Execute),
+    };
+    Ok(Operation::Admin(admin))
+}
+
+/// Parses a client or transaction ID of an admin command.
+fn id<T>(arg: &str) -> Result<T, String>
+    where T: FromStr,
+          T::Err: Display
+{
+    arg.parse().map_err(|e: T::Err| format!("invalid ID {:?}: {}", arg, e))
+}
+
+/// Answers the lines of a connection until it is closed, or until authentication fails if tokens
+/// are given.
+fn handle<R, W>(reader: R, mut writer: W, sender: Sender<Request>, access: &Access)
+    -> io::Result<()>
+    where R: BufRead,
+          W: Write
+{
+    let tokens = access.tokens.as_ref();
+    let mut role = if tokens.is_some() { None } else { Some(Role::Admin) };
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "HEALTH" || line == "READY" && access.ready() {
+            writeln!(writer, "OK {}", serde_json::to_string(&access.health())?)?;
+            continue;
+        }
+        if line == "READY" {
+            writeln!(writer, "ERR not_ready")?;
+            continue;
+        }
+        if let Some(tokens) = tokens.filter(|_| role.is_none()) {
+            role = line.strip_prefix("AUTH ").and_then(|token| tokens.role(token.trim()));
+            if role.is_none() {
+                writeln!(writer, "ERR unauthorized")?;
+                return Ok(());
+            }
+            writeln!(writer, "OK")?;
+            continue;
+        }
+        let reply = if line.starts_with('[') {
+            match serde_json::from_str::<Vec<serde_json::Value>>(line) {
+                Ok(items) if items.len() > MAX_BATCH => {
+                    Reply::Line(String::from("ERR batch_too_large"))
+                }
+                Ok(items) => {
+                    let pending: Vec<_> = items.into_iter()
+                        .map(|item| serde_json::from_value(item).map_err(invalid)
+                            .and_then(|transaction| {
+                                submit(Operation::Execute(transaction), role, access, &sender)
+                            }))
+                        .collect();
+                    let replies: Vec<_> = pending.into_iter()
+                        .map(|submitted| BatchItemReply::from_line(wait(submitted).line()))
+                        .collect();
+                    Reply::Line(serde_json::to_string(&replies)?)
+                }
+                Err(e) => Reply::Line(invalid(e)),
+            }
+        } else {
+            wait(parse_operation(line).map_err(invalid)
+                .and_then(|operation| submit(operation, role, access, &sender)))
+        };
+        writeln!(writer, "{}", reply.line())?;
+        if let Reply::Events(events) = reply {
+            for event in events {
+                writeln!(writer, "{}", serde_json::to_string(&event)?)?;
+            }
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Queues the operation if the role permits it and the rate limit is not exceeded, and returns the
+/// receiver of its reply or the reply to send right away.
+fn submit(operation: Operation, role: Option<Role>, access: &Access, sender: &Sender<Request>)
+    -> Result<Receiver<Reply>, String>
+{
+    if role.is_some_and(|role| !role.permits_operation(&operation)) {
+        return Err(String::from("ERR forbidden"));
+    }
+    let mut limiter = access.limiter.lock().unwrap_or_else(PoisonError::into_inner);
+    if !limiter.allow(operation.client(), Instant::now()) {
+        return Err(String::from("ERR rate_limited"));
+    }
+    drop(limiter);
+    let (reply, response) = mpsc::channel();
+    access.pending.fetch_add(1, Ordering::Relaxed);
+    if sender.send(Request { operation, reply }).is_err() {
+        access.pending.fetch_sub(1, Ordering::Relaxed);
+        return Err(String::from("ERR unavailable"));
+    }
+    Ok(response)
+}
+
+/// Returns the reply to a line or item that could not be parsed.
+fn invalid<E>(_: E) -> String {
+    String::from("ERR invalid_row")
+}
+
+/// Waits for the reply to a submitted transaction.
+fn wait(submitted: Result<Receiver<Reply>, String>) -> Reply {
+    submitted.and_then(|response| response.recv().map_err(|_| String::from("ERR unavailable")))
+        .unwrap_or_else(Reply::Line)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::{PaymentsEngine, TransactionType};
+
+    #[test]
+    fn csv_and_json_lines_are_parsed() {
+        let csv = parse_line("withdrawal, 2, 3, 1.5").unwrap();
+        let json = parse_line(r#"{"type": "withdrawal", "client": 2, "tx": 3, "amount": "1.5"}"#)
+            .unwrap();
+        for transaction in [csv, json] {
+            assert_eq!(TransactionType::Withdrawal, transaction.transaction_type);
+            assert_eq!((2, 3), (transaction.client, transaction.tx));
+            assert_eq!(Some(Decimal::new(15, 1)), transaction.amount);
+        }
+        assert_eq!(None, parse_line("dispute, 2, 3,").unwrap().amount);
+        assert!(parse_line("type, client, tx, amount").is_err());
+    }
+
+    #[test]
+    fn admin_commands_are_parsed() {
+        let admin = |operation| Ok(Operation::Admin(operation));
+        assert_eq!(admin(AdminOperation::Review { client: 2, tx: 3 }),
+                   parse_operation("REVIEW 2 3"));
+        assert_eq!(admin(AdminOperation::Represent { client: 2, tx: 3, unlock: true }),
+                   parse_operation("REPRESENT  2 3 unlock"));
+        assert_eq!(admin(AdminOperation::Represent { client: 2, tx: 3, unlock: false }),
+                   parse_operation("REPRESENT 2 3"));
+        assert!(parse_operation("REVIEW 2").is_err());
+        assert!(parse_operation("REPRESENT 2 3 lock").is_err());
+        assert!(parse_operation("REVIEW 70000 3").is_err());
+        assert_eq!(admin(AdminOperation::Unlock { client: 2 }), parse_operation("UNLOCK 2"));
+        assert!(parse_operation("UNLOCK 2 3").is_err());
+        assert_eq!(Ok(Operation::Subscribe { client: Some(2) }), parse_operation("SUBSCRIBE 2"));
+        assert_eq!(Ok(Operation::Subscribe { client: None }), parse_operation("SUBSCRIBE *"));
+        assert!(matches!(parse_operation("dispute, 2, 3,"), Ok(Operation::Execute(_))));
+    }
+
+    #[test]
+    fn admin_commands_require_an_admin_token() {
+        let tokens: Tokens = "submit s3cret\nadmin t0ken\n".parse().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = ServerOptions { tokens: Some(tokens), ..Default::default() };
+        let server = Server::tcp_with_options(listener, options);
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"AUTH s3cret\nUNLOCK 1\n").unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut replies = String::new();
+            stream.read_to_string(&mut replies).unwrap();
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"AUTH t0ken\nREPRESENT 1 1 unlock\nREVIEW 1 1\n").unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            stream.read_to_string(&mut replies).unwrap();
+            replies
+        });
+
+        let mut engine = PaymentsEngine::new();
+        for transaction in [Transaction::deposit(1, 1, Decimal::TWO), Transaction::dispute(1, 1),
+                            Transaction::chargeback(1, 1)] {
+            engine.execute(transaction).unwrap();
+        }
+        for _ in 0..2 {
+            let request = server.recv_timeout(Duration::from_secs(5)).unwrap();
+            let status = request.operation.clone().apply(&mut engine);
+            request.reply(&status);
+        }
+        assert_eq!("OK\nERR forbidden\nOK\nOK\nERR invalid_transaction\n",
+                   client.join().unwrap());
+        assert!(!engine.accounts().next().unwrap().locked);
+    }
+
+    #[test]
+    fn subscribed_connections_receive_balance_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::tcp(listener);
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"SUBSCRIBE 1\n").unwrap();
+            BufReader::new(stream).lines().take(2).collect::<Result<Vec<_>, _>>().unwrap()
+        });
+
+        let mut engine = PaymentsEngine::new();
+        server.recv_timeout(Duration::from_secs(5)).unwrap().answer(&mut engine);
+        engine.execute(Transaction::deposit(1, 1, Decimal::TWO)).unwrap();
+        let event = concat!(r#"{"change":{"type":"transaction","tx":1,"kind":"deposit"},"#,
+                            r#""timestamp":null,"account":{"client":1,"available":"2","held":"0","#,
+                            r#""total":"2","locked":false}}"#);
+        assert_eq!(vec!["OK", event], client.join().unwrap());
+    }
+
+    #[test]
+    fn health_is_reported_without_authentication() {
+        let tokens: Tokens = "submit s3cret\n".parse().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = ServerOptions { tokens: Some(tokens), ..Default::default() };
+        let server = Server::tcp_with_options(listener, options);
+        let mut submitter = TcpStream::connect(addr).unwrap();
+        submitter.write_all(b"AUTH s3cret\ndeposit, 1, 1, 2.0\n").unwrap();
+        let mut replies = BufReader::new(&submitter).lines();
+        assert_eq!("OK", replies.next().unwrap().unwrap());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.health().pending == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut probe = TcpStream::connect(addr).unwrap();
+        probe.write_all(b"HEALTH\n").unwrap();
+        let mut health = String::new();
+        BufReader::new(&probe).read_line(&mut health).unwrap();
+
+        assert_eq!("OK {\"pending\":1}\n", health);
+        let request = server.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(0, server.health().pending);
+        request.answer(&mut PaymentsEngine::new());
+        assert_eq!("OK", replies.next().unwrap().unwrap());
+    }
+
+    #[test]
+    fn health_reports_durability_and_readiness() {
+        let progress = Arc::new(Progress::new(Some(2)));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = ServerOptions { progress: Some(Arc::clone(&progress)), ..Default::default() };
+        let server = Server::tcp_with_options(listener, options);
+        let probe = |line: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(format!("{}\n", line).as_bytes()).unwrap();
+            let mut reply = String::new();
+            BufReader::new(&stream).read_line(&mut reply).unwrap();
+            reply
+        };
+
+        progress.logged();
+        assert_eq!("OK {\"pending\":0,\"wal_lag\":3}\n", probe("HEALTH"));
+        assert_eq!("ERR not_ready\n", probe("READY"));
+        progress.checkpointed();
+        progress.logged();
+        let _ = server.recv_timeout(Duration::ZERO);
+        assert_eq!("OK {\"pending\":0,\"wal_lag\":1,\"checkpoint_age\":0}\n", probe("READY"));
+    }
+
+    #[test]
+    fn lines_are_answered_in_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::tcp(listener);
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"deposit, 1, 1, 2.0\n\nwithdrawal, 1, 2, 3.0\nfoo\n").unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut replies = String::new();
+            stream.read_to_string(&mut replies).unwrap();
+            replies
+        });
+
+        let mut engine = PaymentsEngine::new();
+        for _ in 0..2 {
+            let request = server.recv_timeout(Duration::from_secs(5)).unwrap();
+            let status = request.operation.clone().apply(&mut engine);
+            request.reply(&status);
+        }
+        assert_eq!("OK\nERR insufficient_funds\nERR invalid_row\n", client.join().unwrap());
+    }
+
+    #[test]
+    fn failed_connections_are_reported() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::tcp(listener);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"deposit, 1, 1, \xff\n").unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        stream.read_to_end(&mut Vec::new()).unwrap();
+
+        let errors: Vec<_> = server.errors().map(|e| e.to_string()).collect();
+        assert_eq!(1, errors.len());
+        assert!(errors[0].starts_with("Connection failed: "), "{}", errors[0]);
+    }
+
+    #[test]
+    fn connections_authenticate_and_are_limited_to_their_role() {
+        let tokens: Tokens = "# partners\nsubmit s3cret\n\nadmin  t0ken\n".parse().unwrap();
+        assert_eq!((Some(Role::Submit), Some(Role::Admin), None),
+                   (tokens.role("s3cret"), tokens.role("t0ken"), tokens.role("other")));
+        assert!("owner t0ken".parse::<Tokens>().is_err());
+        assert_eq!((None, None), (tokens.role("s3cre"), tokens.role("s3cret!")));
+        let mut replaced = tokens.clone();
+        replaced.insert("s3cret", Role::Admin);
+        assert_eq!(Some(Role::Admin), replaced.role("s3cret"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = ServerOptions { tokens: Some(tokens), ..Default::default() };
+        let server = Server::tcp_with_options(listener, options);
+        let session = move |lines: &'static [u8]| thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(lines).unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut replies = String::new();
+            stream.read_to_string(&mut replies).unwrap();
+            replies
+        });
+        let anonymous = session(b"deposit, 1, 1, 2.0\n");
+        let submitter = session(b"AUTH s3cret\nadjustment, 1, 2, 1.0\ndeposit, 1, 3, 2.0\n");
+
+        let request = server.recv_timeout(Duration::from_secs(5)).unwrap();
+        let deposit = Transaction::deposit(1, 3, Decimal::TWO);
+        assert_eq!(Operation::Execute(deposit), request.operation);
+        request.reply(&Ok(()));
+        assert_eq!("ERR unauthorized\n", anonymous.join().unwrap());
+        assert_eq!("OK\nERR forbidden\nOK\n", submitter.join().unwrap());
+    }
+
+    #[test]
+    fn submit_tokens_cannot_dispute_refund_or_subscribe_to_all_clients() {
+        let tokens: Tokens = "submit s3cret\n".parse().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = ServerOptions { tokens: Some(tokens), ..Default::default() };
+        let _server = Server::tcp_with_options(listener, options);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"AUTH s3cret\ndispute, 2, 1,\nresolve, 2, 1,\nchargeback, 2, 1,\n\
+                           refund, 2, 3, 1.0\nSUBSCRIBE *\n").unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut replies = String::new();
+        stream.read_to_string(&mut replies).unwrap();
+
+        assert_eq!(format!("OK\n{}", "ERR forbidden\n".repeat(5)), replies);
+    }
+
+    #[test]
+    fn batches_are_answered_per_item() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::tcp(listener);
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let batch = br#"[{"type": "deposit", "client": 1, "tx": 1, "amount": "2"}, {"x": 1},
+                            {"type": "withdrawal", "client": 1, "tx": 2, "amount": "3"}]"#;
+            stream.write_all(&batch.map(|b| if b == b'\n' { b' ' } else { b })).unwrap();
+            let large = format!("[{}]\n", vec!["{}"; MAX_BATCH + 1].join(","));
+            stream.write_all(format!("\n[\n{}", large).as_bytes()).unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut replies = String::new();
+            stream.read_to_string(&mut replies).unwrap();
+            replies
+        });
+
+        let mut engine = PaymentsEngine::new();
+        for _ in 0..2 {
+            let request = server.recv_timeout(Duration::from_secs(5)).unwrap();
+            let status = request.operation.clone().apply(&mut engine);
+            request.reply(&status);
+        }
+        let replies = concat!(r#"[{"status":"applied"},"#,
+                              r#"{"status":"rejected","error":"invalid_row"},"#,
+                              r#"{"status":"rejected","error":"insufficient_funds"}]"#,
+                              "\nERR invalid_row\nERR batch_too_large\n");
+        assert_eq!(replies, client.join().unwrap());
+    }
+
+    #[test]
+    fn transactions_beyond_the_rate_limit_are_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let rate_limit = RateLimit { per_client: Some(1), global: None };
+        let options = ServerOptions { rate_limit, ..Default::default() };
+        let server = Server::tcp_with_options(listener, options);
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"deposit, 1, 1, 2.0\ndeposit, 1, 2, 2.0\ndeposit, 2, 3, 2.0\n")
+                .unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut replies = String::new();
+            stream.read_to_string(&mut replies).unwrap();
+            replies
+        });
+
+        for _ in 0..2 {
+            server.recv_timeout(Duration::from_secs(5)).unwrap().reply(&Ok(()));
+        }
+        assert_eq!("OK\nERR rate_limited\nOK\n", client.join().unwrap());
+        assert_eq!(1, server.rate_limited());
+    }
+
+    #[cfg(all(unix, feature = "unix-socket"))]
+    #[test]
+    fn unix_socket_connections_are_answered() {
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir()
+            .join(format!("toy-payments-engine-server-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = Server::unix(UnixListener::bind(&path).unwrap());
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"deposit, 1, 1, 2.0\n").unwrap();
+
+        let request = server.recv_timeout(Duration::from_secs(5)).unwrap();
+        let status = request.operation.clone().apply(&mut PaymentsEngine::new());
+        request.reply(&status);
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply).unwrap();
+        assert_eq!("OK\n", reply);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+//! Settlement periods and the end-of-day close
+//!
+//! The engine accumulates deposits, withdrawals, fees, chargebacks, and adjustments per client
+//! during the current period. [PaymentsEngine::close_day] freezes these figures as a [Settlement]
+//! and starts a new period; balances carry over unchanged.
+use std::io::Write;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Map;
+use crate::PaymentsEngine;
+
+/// Movement of funds counted in the figures of the current period
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Movement {
+    Deposit(Decimal),
+    Withdrawal(Decimal),
+    Fee(Decimal),
+    /// Charged back amount, negative for representments
+    Chargeback(Decimal),
+    /// Signed amount of an administrative adjustment
+    Adjustment(Decimal),
+}
+
+/// Figures of a client accumulated during the current period
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+struct Figures {
+    deposits: Decimal,
+    withdrawals: Decimal,
+    fees: Decimal,
+    chargebacks: Decimal,
+    adjustments: Decimal,
+}
+
+/// Current settlement period
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Period {
+    /// Total funds at the start of the period, by client
+    opening: Map<u16, Decimal>,
+    figures: Map<u16, Figures>,
+}
+
+/// Row of a settlement report: the figures of one client for one period
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ClientSettlement {
+    /// Label of the closed day, e.g. `2024-01-31`
+    pub date: String,
+    /// Client identifier
+    pub client: u16,
+    /// Total funds at the start of the period
+    pub opening: Decimal,
+    /// Sum of accepted deposits
+    pub deposits: Decimal,
+    /// Sum of accepted withdrawals, less refunds
+    pub withdrawals: Decimal,
+    /// Sum of charged fees
+    pub fees: Decimal,
+    /// Sum of charged back deposits, less representments
+    pub chargebacks: Decimal,
+    /// Sum of the signed amounts of administrative adjustments
+    pub adjustments: Decimal,
+    /// Net movement of the total funds during the period
+    pub net: Decimal,
+    /// Total funds at the end of the period
+    pub closing: Decimal,
+}
+
+/// Frozen figures of a closed period
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Settlement {
+    /// Label of the closed day
+    pub date: String,
+    /// Figures per client, ordered by client
+    pub clients: Vec<ClientSettlement>,
+}
+
+impl Settlement {
+    /// Writes the settlement report as CSV.
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for client in &self.clients {
+            writer.serialize(client)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl PaymentsEngine {
+    /// Closes the current period under the given date label and starts a new one.
+    ///
+    /// Returns the frozen figures of every client, which are also kept (see
+    /// [PaymentsEngine::settlements]).
+    pub fn close_day(&mut self, date: &str) -> &Settlement {
+        let mut clients: Vec<ClientSettlement> = self.accounts().map(|account| {
+            let opening = self.period.opening.get(&account.client).copied().unwrap_or_default();
+            let Figures { deposits, withdrawals, fees, chargebacks, adjustments } =
+                self.period.figures.get(&account.client).copied().unwrap_or_default();
+            ClientSettlement {
+                date: date.to_owned(),
+                client: account.client,
+                opening,
+                deposits,
+                withdrawals,
+                fees,
+                chargebacks,
+                adjustments,
+                net: account.total - opening,
+                closing: account.total,
+            }
+        }).collect();
+        clients.sort_unstable_by_key(|settlement| settlement.client);
+        self.period = Period {
+            opening: clients.iter().map(|s| (s.client, s.closing)).collect(),
+            figures: Map::default(),
+        };
+        self.settlements.push(Settlement { date: date.to_owned(), clients });
+        self.settlements.last().expect("settlement was just added")
+    }
+
+    /// Returns the settlements of all closed periods, oldest first.
+    pub fn settlements(&self) -> &[Settlement] {
+        &self.settlements
+    }
+
+    /// Adds a movement to the client's figures of the current period.
+    pub(crate) fn record_movement(&mut self, client: u16, movement: Movement) {
+        let figures = self.period.figures.entry(client).or_default();
+        match movement {
+            Movement::Deposit(amount) => figures.deposits += amount,
+            Movement::Withdrawal(amount) => figures.withdrawals += amount,
+            Movement::Fee(amount) => figures.fees += amount,
+            Movement::Chargeback(amount) => figures.chargebacks += amount,
+            Movement::Adjustment(amount) => figures.adjustments += amount,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closed_days_freeze_figures_and_keep_balances() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        engine.deposit(1, 2, Decimal::new(4, 0)).unwrap();
+        engine.withdraw(1, 3, Decimal::new(3, 0)).unwrap();
+        engine.charge_fee(1, 4, Decimal::ONE).unwrap();
+        engine.dispute(1, 2).unwrap();
+        engine.chargeback(1, 2).unwrap();
+
+        let day1 = engine.close_day("2024-01-01").clients[0].clone();
+        assert_eq!((Decimal::ZERO, Decimal::new(14, 0), Decimal::new(3, 0)),
+                   (day1.opening, day1.deposits, day1.withdrawals));
+        assert_eq!((Decimal::ONE, Decimal::new(4, 0)), (day1.fees, day1.chargebacks));
+        assert_eq!((Decimal::new(6, 0), Decimal::new(6, 0)), (day1.net, day1.closing));
+
+        engine.represent(1, 2, true).unwrap();
+        let day2 = engine.close_day("2024-01-02").clients[0].clone();
+        assert_eq!((Decimal::new(6, 0), Decimal::ZERO), (day2.opening, day2.deposits));
+        assert_eq!((Decimal::new(-4, 0), Decimal::new(4, 0), Decimal::new(10, 0)),
+                   (day2.chargebacks, day2.net, day2.closing));
+        assert_eq!(2, engine.settlements().len());
+
+        let mut report = Vec::new();
+        engine.settlements()[0].write_csv(&mut report).unwrap();
+        assert_eq!("date,client,opening,deposits,withdrawals,fees,chargebacks,adjustments,net,\
+                    closing\n2024-01-01,1,0,14,3,1,4,0,6,6\n", String::from_utf8(report).unwrap());
+    }
+}
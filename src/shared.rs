@@ -0,0 +1,143 @@
+//! Thread-safe handle to one engine shared by several threads, e.g. web handlers
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use rust_decimal::Decimal;
+
+use crate::error::Result;
+use crate::models::{Account, DisputeStatus, Transaction, TransactionOutcome};
+use crate::PaymentsEngine;
+
+/// Cloneable, thread-safe handle to a [PaymentsEngine]
+///
+/// Every method locks the engine for its whole duration, so each operation is atomic. Several
+/// operations that must not interleave with others, e.g. checking the dispute status of a deposit
+/// before resolving it, run atomically with [SharedPaymentsEngine::with]. The engine is behind a
+/// mutex rather than a read-write lock because validators, middleware, and other hooks are only
+/// required to be [Send].
+#[derive(Clone, Default)]
+pub struct SharedPaymentsEngine {
+    engine: Arc<Mutex<PaymentsEngine>>,
+}
+
+impl SharedPaymentsEngine {
+    /// Shares the given engine.
+    pub fn new(engine: PaymentsEngine) -> Self {
+        Self { engine: Arc::new(Mutex::new(engine)) }
+    }
+
+    /// Runs `f` with exclusive access to the engine and returns its result.
+    pub fn with<R>(&self, f: impl FnOnce(&mut PaymentsEngine) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    /// Executes the transaction, see [PaymentsEngine::execute].
+    pub fn execute(&self, transaction: Transaction) -> Result<()> {
+        self.lock().execute(transaction)
+    }
+
+    /// Executes the transaction, see [PaymentsEngine::execute_with_outcome].
+    pub fn execute_with_outcome(&self, transaction: Transaction) -> TransactionOutcome {
+        self.lock().execute_with_outcome(transaction)
+    }
+
+    /// Deposits funds, see [PaymentsEngine::deposit].
+    pub fn deposit(&self, client: u16, tx: u32, amount: Decimal) -> Result<()> {
+        self.lock().deposit(client, tx, amount)
+    }
+
+    /// Withdraws funds, see [PaymentsEngine::withdraw].
+    pub fn withdraw(&self, client: u16, tx: u32, amount: Decimal) -> Result<()> {
+        self.lock().withdraw(client, tx, amount)
+    }
+
+    /// Disputes a deposit, see [PaymentsEngine::dispute].
+    pub fn dispute(&self, client: u16, tx: u32) -> Result<()> {
+        self.lock().dispute(client, tx)
+    }
+
+    /// Resolves a dispute, see [PaymentsEngine::resolve].
+    pub fn resolve(&self, client: u16, tx: u32) -> Result<()> {
+        self.lock().resolve(client, tx)
+    }
+
+    /// Charges back a disputed deposit, see [PaymentsEngine::chargeback].
+    pub fn chargeback(&self, client: u16, tx: u32) -> Result<()> {
+        self.lock().chargeback(client, tx)
+    }
+
+    /// Returns the status of the latest dispute of the deposit, see
+    /// [PaymentsEngine::dispute_status].
+    pub fn dispute_status(&self, tx: u32) -> Option<DisputeStatus> {
+        self.lock().dispute_status(tx)
+    }
+
+    /// Returns the [Account] of the given client if it exists.
+    pub fn account(&self, client: u16) -> Option<Account> {
+        self.lock().account(client)
+    }
+
+    /// Returns all [Account]s at one point in time.
+    pub fn accounts(&self) -> Vec<Account> {
+        self.lock().accounts().collect()
+    }
+
+    /// Returns a copy of the engine at one point in time, see [PaymentsEngine::fork].
+    pub fn snapshot(&self) -> PaymentsEngine {
+        self.lock().fork()
+    }
+
+    /// Returns the engine if this is the last handle, otherwise the handle itself.
+    pub fn try_unwrap(self) -> std::result::Result<PaymentsEngine, Self> {
+        Arc::try_unwrap(self.engine)
+            .map(|engine| engine.into_inner().unwrap_or_else(PoisonError::into_inner))
+            .map_err(|engine| Self { engine })
+    }
+
+    /// Locks the engine. A panic in another thread leaves at most one operation incomplete, so the
+    /// engine remains usable after poisoning.
+    fn lock(&self) -> MutexGuard<'_, PaymentsEngine> {
+        self.engine.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl From<PaymentsEngine> for SharedPaymentsEngine {
+    fn from(engine: PaymentsEngine) -> Self {
+        Self::new(engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn handles_are_shared_between_threads() {
+        let shared = SharedPaymentsEngine::default();
+        let threads: Vec<_> = (0..4u16).map(|client| {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for i in 0..100 {
+                    let tx = u32::from(client) * 100 + i;
+                    shared.deposit(client, tx, Decimal::new(1, 0)).unwrap();
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let disputed = shared.with(|engine| {
+            let open = engine.dispute_status(7).is_some_and(|d| d.state.is_open());
+            if !open {
+                engine.dispute(0, 7).unwrap();
+            }
+            !open
+        });
+        assert!(disputed);
+        assert_eq!(4, shared.accounts().len());
+        let engine = shared.try_unwrap().ok().unwrap();
+        assert_eq!(Decimal::new(99, 0), engine.account(0).unwrap().available);
+    }
+}
@@ -0,0 +1,362 @@
+//! SQLite persistence of accounts, deposits, and the logs of transactions and administrative
+//! operations
+//!
+//! Only available with the `sqlite` feature. The database can be inspected with ordinary SQL
+//! tools, e.g. `sqlite3 payments.db 'SELECT * FROM accounts'`.
+use std::path::Path;
+use std::str::FromStr;
+
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, Error, Result};
+use serde::de::{DeserializeOwned, IntoDeserializer};
+
+use crate::engine::{Deposit, SparseAccount};
+use crate::error;
+use crate::models::{tags, AdminOperation, DisputeStatus, Transaction};
+use crate::PaymentsEngine;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client INTEGER PRIMARY KEY,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        locked INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS deposits (
+        tx INTEGER PRIMARY KEY,
+        client INTEGER NOT NULL,
+        amount TEXT NOT NULL,
+        dispute TEXT,
+        reason TEXT
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        type TEXT NOT NULL,
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        amount TEXT,
+        error TEXT,
+        memo TEXT,
+        tags TEXT
+    );
+    CREATE TABLE IF NOT EXISTS operations (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        operation TEXT NOT NULL,
+        client INTEGER NOT NULL,
+        error TEXT
+    );
+";
+
+/// Columns added to the transaction log after its initial version
+const LOG_MIGRATIONS: [(&str, &str); 2] = [("memo", "TEXT"), ("tags", "TEXT")];
+
+/// Copies deposits with the `disputed` flag of the initial version, renamed to
+/// `flagged_deposits`, into the deposits table with dispute states, as opened disputes if flagged
+const DEPOSIT_MIGRATION: &str = "
+    INSERT INTO deposits (tx, client, amount, dispute)
+        SELECT tx, client, amount, CASE WHEN disputed THEN 'opened' END FROM flagged_deposits;
+    DROP TABLE flagged_deposits;
+";
+
+/// Row of the transaction log that has not been written yet
+struct LogRow {
+    transaction: Transaction,
+    error: Option<String>,
+}
+
+/// SQLite database holding the state of a [PaymentsEngine] and its transaction log
+pub struct SqliteStore {
+    conn: Connection,
+    pending: Vec<LogRow>,
+    /// Administrative operations and their errors that have not been written yet
+    pending_operations: Vec<(AdminOperation, Option<String>)>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at the specified path and makes sure the schema exists.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        migrate(&mut conn)?;
+        Ok(Self { conn, pending: Vec::new(), pending_operations: Vec::new() })
+    }
+
+    /// Creates a new [PaymentsEngine] from the accounts and deposits stored in the database.
+    pub fn load(&self) -> Result<PaymentsEngine> {
+        let mut engine = PaymentsEngine::new();
+
+        let mut stmt = self.conn.prepare("SELECT client, available, held, locked FROM accounts")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, u16>(0)?, SparseAccount {
+                available: parse_amount(row.get(1)?, 1)?,
+                held: parse_amount(row.get(2)?, 2)?,
+                locked: row.get(3)?,
+            }))
+        })?;
+        for row in rows {
+            let (client, account) = row?;
+            engine.accounts.insert(client, account);
+        }
+
+        let mut stmt = self.conn
+            .prepare("SELECT tx, client, amount, dispute, reason FROM deposits")?;
+        let rows = stmt.query_map([], |row| {
+            let dispute = match parse_name(row.get(3)?, 3)? {
+                Some(state) => Some(DisputeStatus { state, reason: parse_name(row.get(4)?, 4)? }),
+                None => None,
+            };
+            Ok((row.get::<_, u32>(0)?, Deposit {
+                client: row.get(1)?,
+                amount: parse_amount(row.get(2)?, 2)?,
+                dispute,
+            }))
+        })?;
+        for row in rows {
+            let (tx, deposit) = row?;
+            engine.deposits.insert(tx, deposit);
+        }
+        Ok(engine)
+    }
+
+    /// Queues a processed transaction and its result for the transaction log.
+    ///
+    /// Queued rows are written by the next call to [SqliteStore::save].
+    pub fn record(&mut self, transaction: &Transaction, result: &error::Result<()>) {
+        self.pending.push(LogRow {
+            transaction: transaction.clone(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+    }
+
+    /// Queues an applied administrative operation and its result for the operation log, see
+    /// [SqliteStore::record].
+    pub fn record_operation(&mut self, operation: &AdminOperation, result: &error::Result<()>) {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        self.pending_operations.push((*operation, error));
+    }
+
+    /// Replaces the stored accounts and deposits with the state of the engine and appends all
+    /// recorded transactions and operations to the logs, atomically.
+    pub fn save(&mut self, engine: &PaymentsEngine) -> Result<()> {
+        let db_tx = self.conn.transaction()?;
+        db_tx.execute("DELETE FROM accounts", [])?;
+        db_tx.execute("DELETE FROM deposits", [])?;
+        {
+            let mut stmt = db_tx.prepare(
+                "INSERT INTO accounts (client, available, held, locked) VALUES (?1, ?2, ?3, ?4)"
+            )?;
+            for (client, account) in &engine.accounts {
+                stmt.execute(params![
+                    client,
+                    account.available.to_string(),
+                    account.held.to_string(),
+                    account.locked,
+                ])?;
+            }
+            let mut stmt = db_tx.prepare(
+                "INSERT INTO deposits (tx, client, amount, dispute, reason) \
+                VALUES (?1, ?2, ?3, ?4, ?5)"
+            )?;
+            for (tx, deposit) in &engine.deposits {
+                stmt.execute(params![
+                    tx,
+                    deposit.client,
+                    deposit.amount().to_string(),
+                    deposit.dispute.map(|d| d.state.to_string()),
+                    deposit.dispute.and_then(|d| d.reason).map(|r| r.to_string()),
+                ])?;
+            }
+            let mut stmt = db_tx.prepare(
+                "INSERT INTO transactions (type, client, tx, amount, error, memo, tags) \
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+            )?;
+            for LogRow { transaction, error } in &self.pending {
+                stmt.execute(params![
+                    transaction.transaction_type.to_string(),
+                    transaction.client,
+                    transaction.tx,
+                    transaction.amount.map(|a| a.to_string()),
+                    error,
+                    transaction.memo,
+                    (!transaction.tags.is_empty())
+                        .then(|| transaction.tags.join(&tags::SEPARATOR.to_string())),
+                ])?;
+            }
+            let mut stmt = db_tx.prepare(
+                "INSERT INTO operations (operation, client, error) VALUES (?1, ?2, ?3)"
+            )?;
+            for (operation, error) in &self.pending_operations {
+                stmt.execute(params![operation.to_string(), operation.client(), error])?;
+            }
+        }
+        db_tx.commit()?;
+        self.pending.clear();
+        self.pending_operations.clear();
+        Ok(())
+    }
+}
+
+impl PaymentsEngine {
+    /// Creates a new [PaymentsEngine] from the state persisted in the SQLite database at the
+    /// specified path (see [SqliteStore]).
+    pub fn open_sqlite<P: AsRef<Path>>(path: P) -> Result<Self> {
+        SqliteStore::open(path)?.load()
+    }
+}
+
+/// Adds the columns missing in transaction logs and replaces the `disputed` flag of deposits with
+/// dispute states in databases created by earlier versions, atomically.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let db_tx = conn.transaction()?;
+    let log_columns = columns(&db_tx, "transactions")?;
+    for (column, sql_type) in LOG_MIGRATIONS {
+        if !log_columns.iter().any(|c| c == column) {
+            let sql = format!("ALTER TABLE transactions ADD COLUMN {column} {sql_type}");
+            db_tx.execute_batch(&sql)?;
+        }
+    }
+    if columns(&db_tx, "deposits")?.iter().any(|c| c == "disputed") {
+        db_tx.execute_batch("ALTER TABLE deposits RENAME TO flagged_deposits")?;
+        db_tx.execute_batch(SCHEMA)?;
+        db_tx.execute_batch(DEPOSIT_MIGRATION)?;
+    }
+    db_tx.commit()
+}
+
+/// Returns the column names of the table.
+fn columns(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)")?;
+    let columns = stmt.query_map([table], |row| row.get(0))?.collect();
+    columns
+}
+
+fn parse_amount<T>(value: String, column: usize) -> Result<T>
+    where T: FromStr,
+          T::Err: std::error::Error + Send + Sync + 'static
+{
+    T::from_str(&value)
+        .map_err(|e| Error::FromSqlConversionFailure(column, Type::Text, Box::new(e)))
+}
+
+/// Parses an optional enum variant stored by its serialized name.
+fn parse_name<T: DeserializeOwned>(value: Option<String>, column: usize) -> Result<Option<T>> {
+    value
+        .map(|name| T::deserialize(name.into_deserializer()))
+        .transpose()
+        .map_err(|e: serde::de::value::Error| {
+            Error::FromSqlConversionFailure(column, Type::Text, Box::new(e))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::models::DisputeState;
+
+    #[test]
+    fn state_survives_save_and_load() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(155, 1)).unwrap();
+        engine.deposit(2, 2, Decimal::new(3, 0)).unwrap();
+        engine.dispute_with_reason(2, 2, Some(crate::DisputeReason::Fraud)).unwrap();
+
+        let mut store = SqliteStore::open(":memory:").unwrap();
+        store.save(&engine).unwrap();
+        let loaded = store.load().unwrap();
+
+        assert_eq!(engine.accounts, loaded.accounts);
+        assert_eq!(engine.deposits, loaded.deposits);
+    }
+
+    #[test]
+    fn recorded_transactions_are_logged() {
+        let engine = PaymentsEngine::new();
+        let mut store = SqliteStore::open(":memory:").unwrap();
+        let transaction = Transaction::dispute(1, 2)
+            .with_memo("chargeback claim")
+            .with_tag("campaign-7")
+            .with_tag("promo");
+        store.record(&transaction, &Err(crate::PaymentError::UnknownClient {
+            client: 1,
+            tx_type: "Dispute".to_string(),
+        }));
+        store.save(&engine).unwrap();
+
+        let (tx_type, error, tags): (String, Option<String>, String) = store.conn
+            .query_row("SELECT type, error, tags FROM transactions", [], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+            })
+            .unwrap();
+        assert_eq!("dispute", tx_type);
+        assert!(error.unwrap().contains("unknown client account"));
+        assert_eq!("campaign-7;promo", tags);
+
+        let represent = AdminOperation::Represent { client: 1, tx: 2, unlock: true };
+        store.record_operation(&represent, &Ok(()));
+        store.save(&engine).unwrap();
+        let operation: String = store.conn
+            .query_row("SELECT operation FROM operations WHERE error IS NULL", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!("REPRESENT 1 2 unlock", operation);
+    }
+
+    #[test]
+    fn transaction_log_of_earlier_versions_is_migrated() {
+        let path = std::env::temp_dir()
+            .join(format!("toy-payments-engine-migrate-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Connection::open(&path).unwrap().execute_batch("
+            CREATE TABLE transactions (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                type TEXT NOT NULL,
+                client INTEGER NOT NULL,
+                tx INTEGER NOT NULL,
+                amount TEXT,
+                error TEXT
+            );
+        ").unwrap();
+
+        let store = SqliteStore::open(&path).unwrap();
+        SqliteStore::open(&path).unwrap();
+        store.conn.execute("INSERT INTO transactions (type, client, tx, memo) \
+            VALUES ('deposit', 1, 1, 'migrated')", []).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn deposits_with_disputed_flag_are_migrated() {
+        let path = std::env::temp_dir()
+            .join(format!("toy-payments-engine-migrate-deposits-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Connection::open(&path).unwrap().execute_batch("
+            CREATE TABLE accounts (
+                client INTEGER PRIMARY KEY,
+                available TEXT NOT NULL,
+                held TEXT NOT NULL,
+                locked INTEGER NOT NULL
+            );
+            CREATE TABLE deposits (
+                tx INTEGER PRIMARY KEY,
+                client INTEGER NOT NULL,
+                amount TEXT NOT NULL,
+                disputed INTEGER NOT NULL
+            );
+            INSERT INTO accounts VALUES (1, '2', '3', 0);
+            INSERT INTO deposits VALUES (1, 1, '2', 0), (2, 1, '3', 1);
+        ").unwrap();
+
+        let mut store = SqliteStore::open(&path).unwrap();
+        let mut engine = store.load().unwrap();
+
+        assert_eq!(None, engine.dispute_status(1));
+        assert_eq!(Some(DisputeState::Opened), engine.dispute_status(2).map(|d| d.state));
+        engine.resolve(1, 2).unwrap();
+        store.save(&engine).unwrap();
+        let reopened = PaymentsEngine::open_sqlite(&path).unwrap();
+        assert_eq!(Some(DisputeState::Resolved), reopened.dispute_status(2).map(|d| d.state));
+        assert_eq!(Decimal::new(5, 0), reopened.account(1).unwrap().available);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! Standing orders: deposits or withdrawals repeated at a fixed interval
+//!
+//! [PaymentsEngine::run_due_orders] executes the occurrences of standing orders that are due at
+//! the given time, in order of due time and at most [MAX_DUE_OCCURRENCES] per call. Executed
+//! occurrences get transaction IDs counting down from [u32::MAX], skipping IDs of existing deposits
+//! and withdrawals, so they do not collide with the (usually small) IDs of the input, and the memo
+//! [STANDING_ORDER_MEMO]. A standing order ends when its next due time would exceed [u64::MAX];
+//! all standing orders end when the transaction IDs are used up.
+use std::io::Read;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{PaymentError, Result};
+use crate::models::{Transaction, TransactionOutcome, TransactionType};
+use crate::PaymentsEngine;
+
+/// Memo of the transactions executed for standing orders
+pub const STANDING_ORDER_MEMO: &str = "standing order";
+
+/// Maximum number of occurrences executed by one call of [PaymentsEngine::run_due_orders], further
+/// ones stay due for the next call
+pub const MAX_DUE_OCCURRENCES: usize = 1000;
+
+/// Definition of a deposit or withdrawal repeated at a fixed interval
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct StandingOrder {
+    /// Client identifier
+    pub client: u16,
+    /// Either deposit or withdrawal
+    #[serde(rename = "type")]
+    pub kind: TransactionType,
+    /// Amount of every occurrence
+    pub amount: Decimal,
+    /// Time between two occurrences, in the unit of transaction timestamps
+    pub interval: u64,
+    /// Time of the first occurrence
+    pub start: u64,
+}
+
+/// Standing order and the time its next occurrence is due, `None` once it ended
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct ScheduledOrder {
+    order: StandingOrder,
+    next_due: Option<u64>,
+}
+
+/// Reads standing orders from CSV with the columns `client`, `type`, `amount`, `interval`, and
+/// `start`.
+pub fn read_standing_orders<R>(reader: R) -> std::result::Result<Vec<StandingOrder>, csv::Error>
+    where R: Read
+{
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .into_deserialize()
+        .collect()
+}
+
+impl PaymentsEngine {
+    /// Adds a standing order.
+    ///
+    /// Fails if it is neither a deposit nor a withdrawal or its interval is zero.
+    pub fn add_standing_order(&mut self, order: StandingOrder) -> Result<()> {
+        if !matches!(order.kind, TransactionType::Deposit | TransactionType::Withdrawal) {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Standing order of client {} must be a deposit or withdrawal, not a {}",
+                order.client,
+                order.kind
+            )));
+        }
+        if order.interval == 0 {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Standing order of client {} has an interval of zero",
+                order.client
+            )));
+        }
+        let next_due = Some(order.start);
+        self.standing_orders.push(ScheduledOrder { order, next_due });
+        Ok(())
+    }
+
+    /// Returns the standing orders in the order they were added.
+    pub fn standing_orders(&self) -> impl Iterator<Item=&StandingOrder> {
+        self.standing_orders.iter().map(|scheduled| &scheduled.order)
+    }
+
+    /// Executes the occurrences of standing orders due at time `now` (see [crate::standing]) and
+    /// returns the executed transactions and their outcomes.
+    pub fn run_due_orders(&mut self, now: u64) -> Vec<(Transaction, TransactionOutcome)> {
+        let mut executed = Vec::new();
+        while executed.len() < MAX_DUE_OCCURRENCES {
+            let due = self.standing_orders.iter()
+                .enumerate()
+                .filter_map(|(i, scheduled)| Some((i, scheduled.next_due?)))
+                .filter(|&(_, next_due)| next_due <= now)
+                .min_by_key(|&(_, next_due)| next_due);
+            let Some((i, timestamp)) = due else {
+                break;
+            };
+            let Some(tx) = self.next_standing_order_tx() else {
+                break;
+            };
+            let scheduled = &mut self.standing_orders[i];
+            let StandingOrder { client, kind, amount, interval, .. } = scheduled.order;
+            scheduled.next_due = timestamp.checked_add(interval);
+            let transaction = Transaction::new(kind, client, tx, Some(amount))
+                .with_timestamp(timestamp)
+                .with_memo(STANDING_ORDER_MEMO);
+            let outcome = self.execute_with_outcome(transaction.clone());
+            executed.push((transaction, outcome));
+        }
+        executed
+    }
+
+    /// Returns the next transaction ID for a standing order that is not used by a deposit or
+    /// withdrawal, `None` if all IDs are used up.
+    fn next_standing_order_tx(&mut self) -> Option<u32> {
+        loop {
+            let tx = u32::MAX.checked_sub(self.standing_order_txs)?;
+            self.standing_order_txs = self.standing_order_txs.checked_add(1)?;
+            if !self.deposit_id_used(tx) && !self.withdrawals.contains_key(&tx) {
+                return Some(tx);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn due_occurrences_are_executed_in_order() {
+        let csv = "client,type,amount,interval,start\n1,deposit,10,100,0\n1,withdrawal,3,50,20\n";
+        let mut engine = PaymentsEngine::new();
+        for order in read_standing_orders(csv.as_bytes()).unwrap() {
+            engine.add_standing_order(order).unwrap();
+        }
+        assert!(engine.add_standing_order(StandingOrder {
+            client: 1,
+            kind: TransactionType::Dispute,
+            amount: Decimal::ONE,
+            interval: 10,
+            start: 0,
+        }).is_err());
+
+        let executed = engine.run_due_orders(120);
+        let summary: Vec<_> = executed.iter()
+            .map(|(t, o)| (t.transaction_type, t.tx, t.timestamp, o.status.is_ok()))
+            .collect();
+        assert_eq!(vec![
+            (TransactionType::Deposit, u32::MAX, Some(0), true),
+            (TransactionType::Withdrawal, u32::MAX - 1, Some(20), true),
+            (TransactionType::Withdrawal, u32::MAX - 2, Some(70), true),
+            (TransactionType::Deposit, u32::MAX - 3, Some(100), true),
+            (TransactionType::Withdrawal, u32::MAX - 4, Some(120), true),
+        ], summary);
+        assert_eq!(Decimal::new(11, 0), engine.account(1).unwrap().available);
+        assert!(engine.run_due_orders(120).is_empty());
+    }
+
+    #[test]
+    fn occurrences_are_capped_per_call_and_end_at_the_last_timestamp() {
+        let mut engine = PaymentsEngine::new();
+        let order = |interval, start| StandingOrder {
+            client: 1,
+            kind: TransactionType::Deposit,
+            amount: Decimal::ONE,
+            interval,
+            start,
+        };
+        engine.add_standing_order(order(1, 0)).unwrap();
+        engine.add_standing_order(order(10, u64::MAX - 5)).unwrap();
+
+        assert_eq!(MAX_DUE_OCCURRENCES, engine.run_due_orders(1500).len());
+        assert_eq!(501, engine.run_due_orders(1500).len());
+        engine.standing_orders.remove(0);
+        let executed = engine.run_due_orders(u64::MAX);
+        assert_eq!(vec![Some(u64::MAX - 5)],
+                   executed.iter().map(|(t, _)| t.timestamp).collect::<Vec<_>>());
+        assert!(engine.run_due_orders(u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn transaction_ids_of_the_input_are_skipped() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, u32::MAX, Decimal::TEN).unwrap();
+        engine.withdraw(1, u32::MAX - 1, Decimal::ONE).unwrap();
+        engine.add_standing_order(StandingOrder {
+            client: 1,
+            kind: TransactionType::Withdrawal,
+            amount: Decimal::ONE,
+            interval: 10,
+            start: 0,
+        }).unwrap();
+
+        let executed = engine.run_due_orders(10);
+
+        assert_eq!(vec![u32::MAX - 2, u32::MAX - 3],
+                   executed.iter().map(|(t, _)| t.tx).collect::<Vec<_>>());
+        engine.standing_order_txs = u32::MAX;
+        assert!(engine.run_due_orders(20).is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+//! Per-client account statements
+use std::fmt;
+use std::io::Write;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Map;
+use crate::models::{Transaction, TransactionType};
+use crate::PaymentsEngine;
+
+/// Statement lines of all clients, in order of execution
+pub(crate) type History = Map<u16, Vec<StatementLine>>;
+
+/// Executed transaction with the resulting balances of the client's account
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct StatementLine {
+    /// Sequence number of the operation
+    pub sequence: u64,
+    /// Timestamp of the transaction, if given
+    pub timestamp: Option<u64>,
+    /// Type of the transaction
+    #[serde(rename = "type")]
+    pub kind: TransactionType,
+    /// Transaction identifier
+    pub tx: u32,
+    /// Amount of the transaction or, for disputes, resolves, and chargebacks, of the deposit
+    pub amount: Decimal,
+    /// Available funds after the transaction
+    pub available: Decimal,
+    /// Held funds after the transaction
+    pub held: Decimal,
+    /// Total funds after the transaction
+    pub total: Decimal,
+    /// True iff the account is locked after the transaction
+    pub locked: bool,
+    /// Memo of the transaction, if given
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// Tags of the transaction
+    #[serde(default, with = "crate::models::tags")]
+    pub tags: Vec<String>,
+}
+
+/// Chronological list of a client's transactions with running balances
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Statement {
+    /// Client identifier
+    pub client: u16,
+    /// Accepted transactions in order of execution
+    pub lines: Vec<StatementLine>,
+}
+
+impl Statement {
+    /// Keeps only the lines of transactions with the given tag, e.g. a campaign ID.
+    pub fn tagged(mut self, tag: &str) -> Self {
+        self.lines.retain(|line| line.tags.iter().any(|t| t == tag));
+        self
+    }
+
+    /// Writes the statement lines as CSV to the given writer.
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for line in &self.lines {
+            writer.serialize(line)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Statement of client {}", self.client)?;
+        writeln!(f, "{:>8} {:>12} {:<10} {:>10} {:>14} {:>14} {:>14}",
+                 "seq", "timestamp", "type", "tx", "amount", "available", "held")?;
+        for line in &self.lines {
+            let timestamp = line.timestamp.map_or_else(|| String::from("-"), |t| t.to_string());
+            let memo = line.memo.as_ref().map_or_else(String::new, |memo| format!("  {}", memo));
+            writeln!(f, "{:>8} {:>12} {:<10} {:>10} {:>14} {:>14} {:>14}{}{}",
+                     line.sequence, timestamp, line.kind.to_string(), line.tx, line.amount,
+                     line.available, line.held, if line.locked { " locked" } else { "" }, memo)?;
+        }
+        match self.lines.last() {
+            Some(line) => writeln!(f, "Closing balance: {}", line.total),
+            None => writeln!(f, "No transactions"),
+        }
+    }
+}
+
+impl PaymentsEngine {
+    /// Starts recording the accepted transactions of all clients for [PaymentsEngine::statement].
+    pub fn enable_history(&mut self) {
+        self.history.get_or_insert_with(History::default);
+    }
+
+    /// Returns the client's accepted transactions with timestamps in `from..to`, along with the
+    /// balances after each of them.
+    ///
+    /// A missing bound is unbounded. Transactions without timestamp are only included if both
+    /// bounds are missing. Only transactions executed via [PaymentsEngine::execute] after
+    /// [PaymentsEngine::enable_history] are recorded.
+    pub fn statement(&self, client: u16, from: Option<u64>, to: Option<u64>) -> Statement {
+        let in_range = |line: &&StatementLine| match (line.timestamp, from, to) {
+            (_, None, None) => true,
+            (None, _, _) => false,
+            (Some(t), from, to) => from.is_none_or(|from| t >= from) && to.is_none_or(|to| t < to),
+        };
+        let lines = self.history.as_ref()
+            .and_then(|history| history.get(&client))
+            .map(|lines| lines.iter().filter(in_range).cloned().collect())
+            .unwrap_or_default();
+        Statement { client, lines }
+    }
+
+    /// Appends a line for an accepted transaction to the client's history, if it is enabled.
+    ///
+    /// Without amount, the amount of the referenced deposit is used.
+    pub(crate) fn record_history(&mut self, transaction: &Transaction) {
+        let Transaction { transaction_type: kind, client, tx, amount, timestamp, .. } =
+            *transaction;
+        let (Some(history), Some(account)) = (&mut self.history, self.accounts.get(&client)) else {
+            return;
+        };
+        let amount = amount.or_else(|| self.deposits.get(&tx).map(|deposit| deposit.amount()));
+        history.entry(client).or_default().push(StatementLine {
+            sequence: self.sequence,
+            timestamp,
+            kind,
+            tx,
+            amount: amount.unwrap_or_default(),
+            available: account.available,
+            held: account.held,
+            total: account.available + account.held,
+            locked: account.locked,
+            memo: transaction.memo.clone(),
+            tags: transaction.tags.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(kind: TransactionType, tx: u32, amount: i64, timestamp: u64) -> Transaction {
+        Transaction::fixture(kind, 1, tx, amount).with_timestamp(timestamp)
+    }
+
+    #[test]
+    fn statement_lists_accepted_transactions_with_running_balances() {
+        let mut engine = PaymentsEngine::new();
+        engine.enable_history();
+        engine.execute(transaction(TransactionType::Deposit, 1, 10, 100)).unwrap();
+        engine.execute(transaction(TransactionType::Withdrawal, 2, 3, 200)).unwrap();
+        assert!(engine.execute(transaction(TransactionType::Withdrawal, 3, 30, 250)).is_err());
+        engine.execute(transaction(TransactionType::Deposit, 4, 2, 280)).unwrap();
+        engine.execute(transaction(TransactionType::Dispute, 4, 0, 300)).unwrap();
+
+        let statement = engine.statement(1, None, None);
+        let balances: Vec<_> = statement.lines.iter()
+            .map(|line| (line.tx, line.amount, line.available, line.held))
+            .collect();
+        assert_eq!(vec![
+            (1, Decimal::new(10, 0), Decimal::new(10, 0), Decimal::ZERO),
+            (2, Decimal::new(3, 0), Decimal::new(7, 0), Decimal::ZERO),
+            (4, Decimal::new(2, 0), Decimal::new(9, 0), Decimal::ZERO),
+            (4, Decimal::new(2, 0), Decimal::new(7, 0), Decimal::new(2, 0)),
+        ], balances);
+
+        let range = engine.statement(1, Some(200), Some(300));
+        assert_eq!(vec![2, 4], range.lines.iter().map(|line| line.tx).collect::<Vec<_>>());
+        assert!(engine.statement(2, None, None).lines.is_empty());
+        assert!(statement.to_string().ends_with("Closing balance: 9\n"));
+    }
+
+    #[test]
+    fn statement_can_be_filtered_by_tag() {
+        let mut engine = PaymentsEngine::new();
+        engine.enable_history();
+        let csv = "type,client,tx,amount,memo,tags\n\
+                   deposit,1,1,5,welcome bonus,campaign-7;promo\n\
+                   deposit,1,2,3,,\n";
+        for transaction in crate::csv::read_transactions_from(csv.as_bytes()) {
+            engine.execute(transaction.unwrap()).unwrap();
+        }
+
+        let statement = engine.statement(1, None, None).tagged("promo");
+        assert_eq!(vec![1], statement.lines.iter().map(|line| line.tx).collect::<Vec<_>>());
+        assert_eq!(Some("welcome bonus"), statement.lines[0].memo.as_deref());
+        let mut csv = Vec::new();
+        statement.write_csv(&mut csv).unwrap();
+        assert!(String::from_utf8(csv).unwrap().ends_with(",welcome bonus,campaign-7;promo\n"));
+    }
+}
@@ -0,0 +1,168 @@
+//! Subscriptions to the balance changes of one or all clients
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+use serde::Serialize;
+
+use crate::engine::Map;
+use crate::models::{Account, TransactionType};
+use crate::PaymentsEngine;
+
+/// Maximum number of events a subscriber may fall behind, see [PaymentsEngine::subscribe]
+pub const MAX_PENDING_EVENTS: usize = 1000;
+
+/// Change of a client's account or its disputes that caused a [BalanceEvent]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BalanceChange {
+    /// Accepted transaction
+    Transaction { tx: u32, kind: TransactionType },
+    /// Dispute marked as under review, see [PaymentsEngine::review]
+    Review { tx: u32 },
+    /// Chargeback reversed, see [PaymentsEngine::represent]
+    Representment { tx: u32 },
+    /// Account unlocked, see [PaymentsEngine::unlock]
+    Unlock,
+}
+
+/// Balances of a client's account after a change
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct BalanceEvent {
+    /// Cause of the event
+    pub change: BalanceChange,
+    /// Timestamp of the transaction, if it had one
+    pub timestamp: Option<u64>,
+    /// Account after the change
+    pub account: Account,
+}
+
+/// Senders of the subscribed clients, see [PaymentsEngine::subscribe]
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    senders: Map<u16, Vec<SyncSender<BalanceEvent>>>,
+    /// Senders subscribed to all clients, see [PaymentsEngine::subscribe_all]
+    all: Vec<SyncSender<BalanceEvent>>,
+}
+
+impl PaymentsEngine {
+    /// Returns a receiver of the client's balances after every accepted transaction and every
+    /// review, representment, and unlock.
+    ///
+    /// Events are sent while executing, so a receiver on another thread sees them right away.
+    /// Only [PaymentsEngine::execute] sends events for transactions; calling operations such as
+    /// [PaymentsEngine::deposit] directly does not. A subscriber that falls more than
+    /// [MAX_PENDING_EVENTS] behind is disconnected: its receiver yields the pending events and
+    /// then ends. Subscriptions also end when their receiver is dropped; they are neither
+    /// serialized nor copied by [PaymentsEngine::fork].
+    pub fn subscribe(&mut self, client: u16) -> Receiver<BalanceEvent> {
+        let (sender, receiver) = mpsc::sync_channel(MAX_PENDING_EVENTS);
+        self.subscriptions.senders.entry(client).or_default().push(sender);
+        receiver
+    }
+
+    /// Returns a receiver of the balances of every client after each of its changes, like
+    /// [PaymentsEngine::subscribe] for all clients, e.g. for live dashboards.
+    pub fn subscribe_all(&mut self) -> Receiver<BalanceEvent> {
+        let (sender, receiver) = mpsc::sync_channel(MAX_PENDING_EVENTS);
+        self.subscriptions.all.push(sender);
+        receiver
+    }
+
+    /// Sends the client's balances after a change to its subscribers and those of all clients, if
+    /// any, disconnecting those that lag behind.
+    pub(crate) fn notify_subscribers(
+        &mut self,
+        client: u16,
+        change: BalanceChange,
+        timestamp: Option<u64>,
+    ) {
+        let subscribed = self.subscriptions.senders.contains_key(&client);
+        if !subscribed && self.subscriptions.all.is_empty() {
+            return;
+        }
+        let Some(account) = self.account(client) else {
+            return;
+        };
+        let event = BalanceEvent { change, timestamp, account };
+        let subscriptions = &mut *self.subscriptions;
+        subscriptions.all.retain(|sender| sender.try_send(event.clone()).is_ok());
+        if let Some(senders) = subscriptions.senders.get_mut(&client) {
+            senders.retain(|sender| sender.try_send(event.clone()).is_ok());
+            if senders.is_empty() {
+                subscriptions.senders.remove(&client);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::Transaction;
+
+    #[test]
+    fn subscribers_receive_the_balances_of_their_client() {
+        let mut engine = PaymentsEngine::new();
+        let events = engine.subscribe(1);
+        let dropped = engine.subscribe(1);
+        drop(dropped);
+        let listener = thread::spawn(move || {
+            let balances = |e: BalanceEvent| (e.change, e.account.available, e.account.held);
+            events.iter().map(balances).collect::<Vec<_>>()
+        });
+
+        engine.execute(Transaction::deposit(1, 1, Decimal::TWO)).unwrap();
+        engine.execute(Transaction::deposit(2, 2, Decimal::TWO)).unwrap();
+        assert!(engine.execute(Transaction::withdrawal(1, 3, Decimal::TEN)).is_err());
+        engine.execute(Transaction::dispute(1, 1)).unwrap();
+        engine.review(1, 1).unwrap();
+        engine.execute(Transaction::chargeback(1, 1)).unwrap();
+        engine.represent(1, 1, false).unwrap();
+        engine.unlock(1).unwrap();
+        drop(engine);
+
+        let transaction = |tx, kind| BalanceChange::Transaction { tx, kind };
+        let expected = vec![
+            (transaction(1, TransactionType::Deposit), Decimal::TWO, Decimal::ZERO),
+            (transaction(1, TransactionType::Dispute), Decimal::ZERO, Decimal::TWO),
+            (BalanceChange::Review { tx: 1 }, Decimal::ZERO, Decimal::TWO),
+            (transaction(1, TransactionType::Chargeback), Decimal::ZERO, Decimal::ZERO),
+            (BalanceChange::Representment { tx: 1 }, Decimal::TWO, Decimal::ZERO),
+            (BalanceChange::Unlock, Decimal::TWO, Decimal::ZERO),
+        ];
+        assert_eq!(expected, listener.join().unwrap());
+    }
+
+    #[test]
+    fn subscribers_of_all_clients_receive_every_change() {
+        let mut engine = PaymentsEngine::new();
+        let events = engine.subscribe_all();
+
+        engine.execute(Transaction::deposit(1, 1, Decimal::TWO)).unwrap();
+        engine.execute(Transaction::deposit(2, 2, Decimal::ONE)).unwrap();
+        assert!(engine.execute(Transaction::withdrawal(2, 3, Decimal::TEN)).is_err());
+        engine.unlock(1).unwrap_err();
+        drop(engine);
+
+        let balances = events.iter().map(|e| (e.account.client, e.account.available));
+        assert_eq!(vec![(1, Decimal::TWO), (2, Decimal::ONE)], balances.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn lagging_subscribers_are_disconnected() {
+        let mut engine = PaymentsEngine::new();
+        let events = engine.subscribe(1);
+
+        for tx in 0..=MAX_PENDING_EVENTS as u32 {
+            engine.execute(Transaction::deposit(1, tx, Decimal::ONE)).unwrap();
+        }
+
+        assert_eq!(MAX_PENDING_EVENTS, events.iter().count());
+        assert!(engine.subscriptions.senders.is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+//! Per-client summary of disputes, chargebacks, and activity for the extended account report
+use std::io::Write;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Map;
+use crate::models::{Account, Transaction};
+use crate::PaymentsEngine;
+
+/// Activity of a client tracked for the extended account report
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct Activity {
+    chargebacks: u32,
+    last_timestamp: Option<u64>,
+}
+
+/// Row of the extended account report
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExtendedAccount {
+    /// Client identifier
+    pub client: u16,
+    /// Funds available for trading
+    pub available: Decimal,
+    /// Funds held for dispute
+    pub held: Decimal,
+    /// Total funds available or held
+    pub total: Decimal,
+    /// True iff the account is locked
+    pub locked: bool,
+    /// Number of deposits currently disputed
+    pub open_disputes: u32,
+    /// Number of chargebacks over the lifetime of the account
+    pub chargebacks: u32,
+    /// Latest timestamp of an executed transaction, `None` without timestamps
+    pub last_timestamp: Option<u64>,
+}
+
+/// Writes the accounts with the summary columns as CSV to the given writer.
+pub fn write_extended_accounts<W, I>(writer: W, accounts: I) -> Result<(), csv::Error>
+    where W: Write,
+          I: IntoIterator<Item=ExtendedAccount>
+{
+    let mut writer = csv::Writer::from_writer(writer);
+    for account in accounts {
+        writer.serialize(account)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+impl PaymentsEngine {
+    /// Returns the accounts with the number of open disputes, the lifetime number of chargebacks,
+    /// and the latest transaction timestamp of each client.
+    pub fn extended_accounts(&self) -> Vec<ExtendedAccount> {
+        let mut open_disputes: Map<u16, u32> = Map::default();
+        for deposit in self.deposits.values().filter(|deposit| deposit.is_disputed()) {
+            *open_disputes.entry(deposit.client).or_default() += 1;
+        }
+        self.accounts()
+            .map(|account| {
+                let Account { client, available, held, total, locked } = account;
+                let activity = self.activity.get(&client).copied().unwrap_or_default();
+                ExtendedAccount {
+                    client,
+                    available,
+                    held,
+                    total,
+                    locked,
+                    open_disputes: open_disputes.get(&client).copied().unwrap_or_default(),
+                    chargebacks: activity.chargebacks,
+                    last_timestamp: activity.last_timestamp,
+                }
+            })
+            .collect()
+    }
+
+    /// Records the timestamp of an executed transaction.
+    pub(crate) fn record_activity(&mut self, transaction: &Transaction) {
+        if let Some(timestamp) = transaction.timestamp {
+            let last = &mut self.activity.entry(transaction.client).or_default().last_timestamp;
+            *last = Some(last.map_or(timestamp, |last| last.max(timestamp)));
+        }
+    }
+
+    /// Counts an accepted chargeback for the extended account report.
+    pub(crate) fn record_chargeback(&mut self, client: u16) {
+        self.activity.entry(client).or_default().chargebacks += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransactionType;
+
+    #[test]
+    fn summarizes_disputes_chargebacks_and_timestamps() {
+        let mut engine = PaymentsEngine::new();
+        for (transaction_type, tx, amount, timestamp) in [
+            (TransactionType::Deposit, 1, Some(Decimal::new(5, 0)), Some(20)),
+            (TransactionType::Deposit, 2, Some(Decimal::new(3, 0)), Some(10)),
+            (TransactionType::Deposit, 3, Some(Decimal::new(1, 0)), None),
+            (TransactionType::Dispute, 1, None, Some(30)),
+            (TransactionType::Dispute, 3, None, Some(40)),
+            (TransactionType::Chargeback, 3, None, Some(50)),
+            (TransactionType::Withdrawal, 4, Some(Decimal::new(1, 0)), Some(60)),
+        ] {
+            let _ = engine.execute(Transaction {
+                timestamp,
+                ..Transaction::new(transaction_type, 1, tx, amount)
+            });
+        }
+
+        let mut csv = Vec::new();
+        write_extended_accounts(&mut csv, engine.extended_accounts()).unwrap();
+        assert_eq!("client,available,held,total,locked,open_disputes,chargebacks,last_timestamp\n\
+                    1,3,5,8,true,1,1,50\n", String::from_utf8(csv).unwrap());
+    }
+}
@@ -0,0 +1,104 @@
+//! Several isolated engines in one process, keyed by tenant ID
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::models::{Account, Transaction};
+use crate::{EngineConfig, PaymentsEngine};
+
+/// Engine holding an isolated [PaymentsEngine] per tenant, e.g. per business unit
+///
+/// Client and transaction IDs of different tenants never collide. Tenants are created with the
+/// shared configuration on their first transaction.
+#[derive(Default, Deserialize, Serialize)]
+pub struct MultiTenantEngine {
+    config: EngineConfig,
+    tenants: BTreeMap<String, PaymentsEngine>,
+}
+
+/// Row of the account report of all tenants
+#[derive(Serialize)]
+struct TenantAccount<'a> {
+    tenant: &'a str,
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+impl MultiTenantEngine {
+    /// Creates an engine without tenants.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an engine whose tenants use the given configuration.
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self { config, tenants: BTreeMap::new() }
+    }
+
+    /// Executes a [Transaction] within the tenant's engine, creating it if necessary.
+    pub fn execute(&mut self, tenant: &str, transaction: Transaction) -> Result<()> {
+        self.tenant_mut(tenant).execute(transaction)
+    }
+
+    /// Returns the engine of the tenant if it exists.
+    pub fn tenant(&self, tenant: &str) -> Option<&PaymentsEngine> {
+        self.tenants.get(tenant)
+    }
+
+    /// Returns the engine of the tenant, creating it if necessary, e.g. to enable features.
+    pub fn tenant_mut(&mut self, tenant: &str) -> &mut PaymentsEngine {
+        if !self.tenants.contains_key(tenant) {
+            let engine = PaymentsEngine::with_config(self.config.clone());
+            self.tenants.insert(tenant.to_owned(), engine);
+        }
+        self.tenants.get_mut(tenant).expect("tenant exists")
+    }
+
+    /// Returns iterator over the tenant IDs and their engines, ordered by tenant ID.
+    pub fn tenants(&self) -> impl Iterator<Item=(&str, &PaymentsEngine)> {
+        self.tenants.iter().map(|(tenant, engine)| (tenant.as_str(), engine))
+    }
+
+    /// Writes the accounts of all tenants as CSV with an additional leading `tenant` column.
+    pub fn write_accounts<W: Write>(&self, writer: W) -> std::result::Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for (tenant, engine) in self.tenants() {
+            for Account { client, available, held, total, locked } in engine.accounts() {
+                writer.serialize(TenantAccount { tenant, client, available, held, total, locked })?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deposit(client: u16, tx: u32, amount: i64) -> Transaction {
+        Transaction::deposit(client, tx, Decimal::new(amount, 0))
+    }
+
+    #[test]
+    fn tenants_are_isolated() {
+        let mut engine = MultiTenantEngine::new();
+        engine.execute("retail", deposit(1, 1, 5)).unwrap();
+        engine.execute("wholesale", deposit(1, 1, 7)).unwrap();
+        assert!(engine.execute("retail", deposit(2, 1, 1)).is_err());
+
+        assert_eq!(Decimal::new(5, 0), engine.tenant("retail").unwrap().account(1).unwrap().total);
+        assert!(engine.tenant("other").is_none());
+        let mut report = Vec::new();
+        engine.write_accounts(&mut report).unwrap();
+        assert_eq!("tenant,client,available,held,total,locked\n\
+                    retail,1,5,0,5,false\n\
+                    wholesale,1,7,0,7,false\n", String::from_utf8(report).unwrap());
+    }
+}
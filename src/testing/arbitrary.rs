@@ -0,0 +1,59 @@
+//! [proptest] strategies for transactions
+//!
+//! Only available with the `proptest` feature.
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+
+use crate::models::{Transaction, TransactionType};
+
+impl Arbitrary for TransactionType {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        prop_oneof![
+            Just(TransactionType::Deposit),
+            Just(TransactionType::Withdrawal),
+            Just(TransactionType::Dispute),
+            Just(TransactionType::Resolve),
+            Just(TransactionType::Chargeback),
+            Just(TransactionType::Refund),
+        ].boxed()
+    }
+}
+
+impl Arbitrary for Transaction {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// Generates transactions over few clients and transaction IDs, so that disputes, resolves,
+    /// and chargebacks frequently refer to existing deposits.
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        transaction(1u16..=5, 1u32..=20)
+    }
+}
+
+/// Strategy for positive amounts with up to four decimal places
+pub fn amount() -> impl Strategy<Value=Decimal> {
+    (1i64..10_000_000).prop_map(|minor_units| Decimal::new(minor_units, 4))
+}
+
+/// Strategy for transactions with clients and transaction IDs from the given ranges
+///
+/// Deposits, withdrawals, and refunds always carry an amount, all other transactions never do.
+pub fn transaction<C, T>(clients: C, txs: T) -> BoxedStrategy<Transaction>
+    where C: Strategy<Value=u16> + 'static,
+          T: Strategy<Value=u32> + 'static
+{
+    (any::<TransactionType>(), clients, txs, amount())
+        .prop_map(|(transaction_type, client, tx, amount)| {
+            let amount = match transaction_type {
+                TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Refund => Some(amount),
+                _ => None,
+            };
+            Transaction::new(transaction_type, client, tx, amount)
+        })
+        .boxed()
+}
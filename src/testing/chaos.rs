@@ -0,0 +1,255 @@
+//! Fault injection for robustness testing
+//!
+//! [StorageFaults] make writes fail, optionally after writing part of the data, e.g. the appends to
+//! a write-ahead log (see [WriteAheadLog::inject_faults]). [Chaotic] wraps a [TransactionSource]
+//! and duplicates or reorders some of its rows. All faults are drawn from a random number
+//! generator seeded by the [ChaosConfig], so a failing run is reproduced with the same config.
+//!
+//! [WriteAheadLog::inject_faults]: crate::wal::WriteAheadLog::inject_faults
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use csv::Error;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::io::TransactionSource;
+use crate::models::Transaction;
+
+/// Mixed into the seed of the storage faults, so they do not draw the same numbers as the rows
+const STORAGE_SEED: u64 = 0x5eed_d15c;
+
+/// Configuration of the injected faults; probabilities must be within 0 and 1
+#[derive(Clone, Debug)]
+pub struct ChaosConfig {
+    /// Seed of the random number generator
+    pub seed: u64,
+    /// Probability of a write to fail
+    pub storage_failure_probability: f64,
+    /// Probability of a failed write to leave part of the data behind
+    pub torn_write_probability: f64,
+    /// Probability of a row to be emitted twice
+    pub duplicate_probability: f64,
+    /// Probability of a row to be overtaken by one of the rows after it
+    pub reorder_probability: f64,
+    /// Number of upcoming rows among which an overtaking row is picked
+    pub reorder_window: usize,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            storage_failure_probability: 0.1,
+            torn_write_probability: 0.5,
+            duplicate_probability: 0.05,
+            reorder_probability: 0.05,
+            reorder_window: 4,
+        }
+    }
+}
+
+/// Randomly failing writes
+#[derive(Clone, Debug)]
+pub struct StorageFaults {
+    rng: StdRng,
+    failure_probability: f64,
+    torn_probability: f64,
+    injected: u64,
+}
+
+impl StorageFaults {
+    /// Creates storage faults with the probabilities of the given config.
+    pub fn new(config: &ChaosConfig) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(config.seed ^ STORAGE_SEED),
+            failure_probability: config.storage_failure_probability,
+            torn_probability: config.torn_write_probability,
+            injected: 0,
+        }
+    }
+
+    /// Returns the number of failed writes so far.
+    pub fn injected(&self) -> u64 {
+        self.injected
+    }
+
+    /// Writes the whole buffer or fails, possibly after writing a prefix of it.
+    pub fn write_all<W: Write + ?Sized>(&mut self, writer: &mut W, buf: &[u8]) -> io::Result<()> {
+        if !self.rng.random_bool(self.failure_probability) {
+            return writer.write_all(buf);
+        }
+        self.injected += 1;
+        if self.rng.random_bool(self.torn_probability) {
+            writer.write_all(&buf[..self.rng.random_range(0..buf.len().max(1))])?;
+        }
+        Err(io::Error::other("injected storage fault"))
+    }
+}
+
+/// Source that randomly duplicates or reorders the rows of another source
+///
+/// Only rows that could be read are duplicated. A duplicate immediately follows the original unless
+/// it is overtaken itself.
+pub struct Chaotic<S> {
+    source: S,
+    rng: StdRng,
+    duplicate_probability: f64,
+    reorder_probability: f64,
+    window: usize,
+    buffer: VecDeque<Result<Transaction, Error>>,
+    duplicated: u64,
+    reordered: u64,
+}
+
+impl<S: TransactionSource> Chaotic<S> {
+    /// Wraps the source with the probabilities of the given config.
+    pub fn new(source: S, config: &ChaosConfig) -> Self {
+        Self {
+            source,
+            rng: StdRng::seed_from_u64(config.seed),
+            duplicate_probability: config.duplicate_probability,
+            reorder_probability: config.reorder_probability,
+            window: config.reorder_window.max(1),
+            buffer: VecDeque::new(),
+            duplicated: 0,
+            reordered: 0,
+        }
+    }
+
+    /// Returns the number of duplicated rows so far.
+    pub fn duplicated(&self) -> u64 {
+        self.duplicated
+    }
+
+    /// Returns the number of rows that overtook others so far.
+    pub fn reordered(&self) -> u64 {
+        self.reordered
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for Chaotic<S> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, Error>> {
+        while self.buffer.len() < self.window {
+            match self.source.next_transaction() {
+                Some(row) => self.buffer.push_back(row),
+                None => break,
+            }
+        }
+        let mut index = 0;
+        if self.buffer.len() > 1 && self.rng.random_bool(self.reorder_probability) {
+            index = self.rng.random_range(1..self.buffer.len());
+            self.reordered += 1;
+        }
+        let row = self.buffer.remove(index)?;
+        if let Ok(transaction) = &row {
+            if self.rng.random_bool(self.duplicate_probability) {
+                self.buffer.push_front(Ok(transaction.clone()));
+                self.duplicated += 1;
+            }
+        }
+        Some(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::csv::{read_transactions_from, write_transactions, Transactions};
+    use crate::wal::{SyncPolicy, WalRecord, WriteAheadLog};
+    use crate::{Account, PaymentsEngine};
+
+    fn transactions() -> Vec<Transaction> {
+        (1..=200)
+            .map(|tx| {
+                let client = (tx % 5) as u16;
+                let transaction = if tx % 3 == 0 {
+                    Transaction::withdrawal(client, tx, Decimal::new(2, 0))
+                } else {
+                    Transaction::deposit(client, tx, Decimal::new(tx as i64, 1))
+                };
+                transaction.with_idempotency_key(format!("key-{}", tx))
+            })
+            .collect()
+    }
+
+    fn source(transactions: &[Transaction]) -> Transactions<io::Cursor<Vec<u8>>> {
+        let mut csv = Vec::new();
+        write_transactions(&mut csv, transactions).unwrap();
+        read_transactions_from(io::Cursor::new(csv))
+    }
+
+    fn accounts(engine: &PaymentsEngine) -> Vec<Account> {
+        let mut accounts: Vec<_> = engine.accounts().collect();
+        accounts.sort_by_key(|a| a.client);
+        accounts
+    }
+
+    #[test]
+    fn duplicates_with_idempotency_keys_do_not_change_balances() {
+        let transactions = transactions();
+        let mut expected = PaymentsEngine::new();
+        for transaction in transactions.iter().cloned() {
+            let _ = expected.execute(transaction);
+        }
+
+        let config = ChaosConfig { duplicate_probability: 0.3, reorder_probability: 0.0,
+                                   ..Default::default() };
+        let mut rows = Chaotic::new(source(&transactions), &config);
+        let mut engine = PaymentsEngine::new();
+        while let Some(row) = rows.next_transaction() {
+            let _ = engine.execute(row.unwrap());
+        }
+        assert!(rows.duplicated() > 0);
+        assert_eq!(accounts(&expected), accounts(&engine));
+    }
+
+    #[test]
+    fn reordering_is_a_reproducible_permutation() {
+        let transactions = transactions();
+        let config = ChaosConfig { seed: 7, duplicate_probability: 0.0, reorder_probability: 0.5,
+                                   ..Default::default() };
+        let order = || -> Vec<u32> {
+            Chaotic::new(source(&transactions), &config).rows().map(|row| row.unwrap().tx).collect()
+        };
+        let mut reordered = order();
+        assert_eq!(reordered, order());
+        assert_ne!((1..=200).collect::<Vec<_>>(), reordered);
+        reordered.sort();
+        assert_eq!((1..=200).collect::<Vec<_>>(), reordered);
+    }
+
+    #[test]
+    fn retried_wal_appends_recover_the_state() {
+        let path = std::env::temp_dir()
+            .join(format!("toy-payments-engine-chaos-wal-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = ChaosConfig { storage_failure_probability: 0.3, torn_write_probability: 0.5,
+                                   ..Default::default() };
+        let (mut wal, _) = WriteAheadLog::open(&path, SyncPolicy::Never).unwrap();
+        wal.inject_faults(StorageFaults::new(&config));
+        let mut engine = PaymentsEngine::new();
+        let mut failures = 0;
+        for (row, transaction) in (1..).zip(transactions()) {
+            while wal.append(row, &transaction).is_err() {
+                failures += 1;
+            }
+            let _ = engine.execute(transaction);
+        }
+        drop(wal);
+
+        let (_, entries) = WriteAheadLog::open(&path, SyncPolicy::Never).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut recovered = PaymentsEngine::new();
+        for entry in entries.iter().cloned() {
+            if let WalRecord::Transaction(transaction) = entry.record {
+                let _ = recovered.execute(transaction);
+            }
+        }
+        assert!(failures > 0);
+        assert_eq!(200, entries.len());
+        assert_eq!(accounts(&engine), accounts(&recovered));
+    }
+}
@@ -0,0 +1,231 @@
+//! Generator of synthetic transaction streams
+//!
+//! The generated streams resemble real input: deposits and withdrawals of random amounts, disputes
+//! of earlier deposits that are later resolved or charged back, and optionally duplicated or
+//! invalid rows. Generating with the same seed yields the same stream.
+use std::io::{self, Write};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::Decimal;
+
+use crate::models::{Transaction, TransactionType};
+
+/// Header of generated CSV files
+pub const HEADER: &str = "type,client,tx,amount";
+
+/// Malformed rows used for invalid row injection
+const INVALID_ROWS: [&str; 4] = [
+    "deposit,not-a-client,1,1.0",
+    "withdrawal,1,2,not-an-amount",
+    "transfer,1,3,1.0",
+    "deposit,1",
+];
+
+/// Configuration of the [Generator]
+#[derive(Clone, Debug)]
+pub struct GeneratorConfig {
+    /// Number of distinct clients
+    pub clients: u16,
+    /// Number of rows to generate
+    pub rows: u64,
+    /// Share of withdrawals among deposits and withdrawals
+    pub withdrawal_ratio: f64,
+    /// Probability of disputing an earlier deposit instead of moving funds
+    pub dispute_probability: f64,
+    /// Probability that a dispute ends with a chargeback rather than a resolve
+    pub chargeback_probability: f64,
+    /// Probability of repeating the previous transaction
+    pub duplicate_probability: f64,
+    /// Probability of emitting a malformed row
+    pub invalid_probability: f64,
+    /// Seed of the random number generator
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            clients: 100,
+            rows: 1000,
+            withdrawal_ratio: 0.3,
+            dispute_probability: 0.02,
+            chargeback_probability: 0.2,
+            duplicate_probability: 0.0,
+            invalid_probability: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Row of a generated transaction stream
+#[derive(Clone, Debug)]
+pub enum GeneratedRow {
+    /// Well-formed transaction (which may still fail, e.g. due to insufficient funds)
+    Valid(Transaction),
+    /// Malformed CSV row
+    Invalid(&'static str),
+}
+
+impl GeneratedRow {
+    /// Writes the row in CSV format (without trailing newline).
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            GeneratedRow::Valid(t) => {
+                write!(writer, "{},{},{},", t.transaction_type, t.client, t.tx)?;
+                match t.amount {
+                    Some(amount) => write!(writer, "{}", amount),
+                    None => Ok(()),
+                }
+            }
+            GeneratedRow::Invalid(row) => writer.write_all(row.as_bytes()),
+        }
+    }
+}
+
+/// Iterator over randomly generated rows
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: StdRng,
+    generated: u64,
+    next_tx: u32,
+    previous: Option<Transaction>,
+    /// Deposits that may be disputed: (client, tx)
+    deposits: Vec<(u16, u32)>,
+    /// Open disputes: (client, tx)
+    disputes: Vec<(u16, u32)>,
+}
+
+impl Generator {
+    /// Creates new [Generator] with the given configuration.
+    ///
+    /// Panics if the configuration specifies no clients or probabilities outside of `[0, 1]`.
+    pub fn new(config: GeneratorConfig) -> Self {
+        assert!(config.clients > 0, "at least one client is required");
+        for p in [
+            config.withdrawal_ratio,
+            config.dispute_probability,
+            config.chargeback_probability,
+            config.duplicate_probability,
+            config.invalid_probability,
+        ] {
+            assert!((0.0..=1.0).contains(&p), "probability {} is not within [0, 1]", p);
+        }
+        Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            generated: 0,
+            next_tx: 1,
+            previous: None,
+            deposits: Vec::new(),
+            disputes: Vec::new(),
+        }
+    }
+
+    fn amount(&mut self) -> Decimal {
+        // Up to 10,000 with four decimal places
+        Decimal::new(self.rng.random_range(1..100_000_000), 4)
+    }
+
+    fn take_random(&mut self, from_disputes: bool) -> (u16, u32) {
+        let entries = if from_disputes { &mut self.disputes } else { &mut self.deposits };
+        let i = self.rng.random_range(0..entries.len());
+        entries.swap_remove(i)
+    }
+
+    fn next_transaction(&mut self) -> Transaction {
+        if !self.disputes.is_empty() && self.rng.random_bool(self.config.dispute_probability) {
+            let (client, tx) = self.take_random(true);
+            let transaction_type = if self.rng.random_bool(self.config.chargeback_probability) {
+                TransactionType::Chargeback
+            } else {
+                self.deposits.push((client, tx));
+                TransactionType::Resolve
+            };
+            return Transaction::new(transaction_type, client, tx, None);
+        }
+        if !self.deposits.is_empty() && self.rng.random_bool(self.config.dispute_probability) {
+            let (client, tx) = self.take_random(false);
+            self.disputes.push((client, tx));
+            return Transaction::dispute(client, tx);
+        }
+        let client = self.rng.random_range(1..=self.config.clients);
+        let tx = self.next_tx;
+        self.next_tx = self.next_tx.wrapping_add(1);
+        let transaction_type = if self.rng.random_bool(self.config.withdrawal_ratio) {
+            TransactionType::Withdrawal
+        } else {
+            self.deposits.push((client, tx));
+            TransactionType::Deposit
+        };
+        Transaction::new(transaction_type, client, tx, Some(self.amount()))
+    }
+}
+
+impl Iterator for Generator {
+    type Item = GeneratedRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.generated >= self.config.rows {
+            return None;
+        }
+        self.generated += 1;
+        if self.rng.random_bool(self.config.invalid_probability) {
+            let i = self.rng.random_range(0..INVALID_ROWS.len());
+            return Some(GeneratedRow::Invalid(INVALID_ROWS[i]));
+        }
+        if let Some(previous) = self.previous.clone() {
+            if self.rng.random_bool(self.config.duplicate_probability) {
+                return Some(GeneratedRow::Valid(previous));
+            }
+        }
+        let transaction = self.next_transaction();
+        self.previous = Some(transaction.clone());
+        Some(GeneratedRow::Valid(transaction))
+    }
+}
+
+/// Writes a CSV file with header and rows generated according to the given configuration.
+pub fn write_csv<W: Write>(mut writer: W, config: GeneratorConfig) -> io::Result<()> {
+    writeln!(writer, "{}", HEADER)?;
+    for row in Generator::new(config) {
+        row.write_csv(&mut writer)?;
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csv::read_transactions_from;
+
+    use super::*;
+
+    #[test]
+    fn same_seed_generates_same_stream() {
+        let config = GeneratorConfig { seed: 7, ..Default::default() };
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        write_csv(&mut first, config.clone()).unwrap();
+        write_csv(&mut second, config).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn generated_csv_can_be_read() {
+        let config = GeneratorConfig {
+            rows: 500,
+            dispute_probability: 0.2,
+            duplicate_probability: 0.1,
+            invalid_probability: 0.1,
+            ..Default::default()
+        };
+        let mut csv = Vec::new();
+        write_csv(&mut csv, config).unwrap();
+
+        let rows: Vec<_> = read_transactions_from(csv.as_slice()).collect();
+        assert_eq!(500, rows.len());
+        assert!(rows.iter().any(|r| r.is_err()));
+        assert!(rows.iter().flatten().any(|t| t.transaction_type == TransactionType::Dispute));
+    }
+}
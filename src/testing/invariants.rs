@@ -0,0 +1,136 @@
+//! Checker for invariants that must hold after every transaction
+use std::collections::HashMap;
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::{Account, PaymentsEngine};
+
+/// Violation of an engine invariant
+#[derive(Debug, PartialEq)]
+pub enum InvariantViolation {
+    /// Total funds differ from the sum of available and held funds
+    TotalMismatch(Account),
+    /// Held funds are negative
+    NegativeHeld(Account),
+    /// Account changed after it had been locked
+    LockedAccountChanged { before: Account, after: Account },
+    /// Locked account disappeared
+    LockedAccountRemoved(Account),
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::TotalMismatch(a) => write!(
+                f, "Total of client {} is not the sum of available and held: {:?}", a.client, a
+            ),
+            InvariantViolation::NegativeHeld(a) => {
+                write!(f, "Held funds of client {} are negative: {:?}", a.client, a)
+            }
+            InvariantViolation::LockedAccountChanged { before, after } => write!(
+                f,
+                "Locked account of client {} changed from {:?} to {:?}",
+                before.client,
+                before,
+                after
+            ),
+            InvariantViolation::LockedAccountRemoved(a) => {
+                write!(f, "Locked account of client {} was removed: {:?}", a.client, a)
+            }
+        }
+    }
+}
+
+/// Checks engine invariants and remembers locked accounts to verify that they are never modified
+///
+/// Call [InvariantChecker::check] after every transaction:
+/// ```
+/// use rust_decimal::Decimal;
+/// use toy_payments_engine::PaymentsEngine;
+/// use toy_payments_engine::testing::invariants::InvariantChecker;
+///
+/// let mut engine = PaymentsEngine::new();
+/// let mut checker = InvariantChecker::new();
+/// engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+/// checker.check(&engine).unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct InvariantChecker {
+    locked: HashMap<u16, Account>,
+}
+
+impl InvariantChecker {
+    /// Creates new [InvariantChecker] that does not know any locked accounts yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks all invariants for the current state of the engine and returns the first violation.
+    pub fn check(&mut self, engine: &PaymentsEngine) -> Result<(), InvariantViolation> {
+        let mut accounts = HashMap::new();
+        for account in engine.accounts() {
+            if account.total != account.available + account.held {
+                return Err(InvariantViolation::TotalMismatch(account));
+            }
+            if account.held < Decimal::ZERO {
+                return Err(InvariantViolation::NegativeHeld(account));
+            }
+            accounts.insert(account.client, account);
+        }
+        for (client, before) in &self.locked {
+            match accounts.get(client) {
+                Some(after) if after != before => {
+                    return Err(InvariantViolation::LockedAccountChanged {
+                        before: before.clone(),
+                        after: after.clone(),
+                    });
+                }
+                Some(_) => {}
+                None => return Err(InvariantViolation::LockedAccountRemoved(before.clone())),
+            }
+        }
+        for (client, account) in accounts {
+            if account.locked {
+                self.locked.entry(client).or_insert(account);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_transactions_preserve_invariants() {
+        let mut engine = PaymentsEngine::new();
+        let mut checker = InvariantChecker::new();
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        checker.check(&engine).unwrap();
+        engine.dispute(1, 1).unwrap();
+        checker.check(&engine).unwrap();
+        engine.chargeback(1, 1).unwrap();
+        checker.check(&engine).unwrap();
+        assert!(engine.deposit(1, 2, Decimal::new(10, 0)).is_err());
+        checker.check(&engine).unwrap();
+    }
+
+    #[test]
+    fn modified_locked_account_is_detected() {
+        let mut engine = PaymentsEngine::new();
+        let mut checker = InvariantChecker::new();
+        engine.deposit(1, 1, Decimal::new(10, 0)).unwrap();
+        engine.dispute(1, 1).unwrap();
+        engine.chargeback(1, 1).unwrap();
+        checker.check(&engine).unwrap();
+
+        engine.accounts.get_mut(&1).unwrap().available = Decimal::new(1, 0);
+
+        assert!(matches!(
+            checker.check(&engine),
+            Err(InvariantViolation::LockedAccountChanged { .. })
+        ));
+    }
+}
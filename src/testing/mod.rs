@@ -0,0 +1,10 @@
+//! Utilities for testing and benchmarking the payments engine
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "proptest")]
+pub mod arbitrary;
+pub mod generator;
+pub mod invariants;
+pub mod reference;
+pub mod simulation;
+pub mod throughput;
@@ -0,0 +1,208 @@
+//! Slow but obviously correct reference implementation for differential testing
+//!
+//! The [ReferenceEngine] only stores the log of accepted transactions and recomputes all balances
+//! from the full log whenever it needs them. [compare] runs the same transactions against both
+//! the [PaymentsEngine] and the [ReferenceEngine] and reports the first divergence.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::models::{Account, Transaction, TransactionType};
+use crate::testing::generator::{GeneratedRow, Generator, GeneratorConfig};
+use crate::PaymentsEngine;
+
+/// State recomputed from the transaction log
+#[derive(Default)]
+struct State {
+    accounts: BTreeMap<u16, Account>,
+    /// Deposits by transaction ID: (client, amount, disputed)
+    deposits: HashMap<u32, (u16, Decimal, bool)>,
+    /// Withdrawals by transaction ID: (client, amount not refunded yet)
+    withdrawals: HashMap<u32, (u16, Decimal)>,
+}
+
+impl State {
+    /// Replays the given accepted transactions from scratch.
+    fn replay(log: &[Transaction]) -> Self {
+        let mut state = State::default();
+        for t in log {
+            let amount = t.amount.unwrap_or_default();
+            let deposit = state.deposits.get(&t.tx).map(|d| d.1).unwrap_or_default();
+            let account = state.accounts.entry(t.client).or_insert_with(|| Account {
+                client: t.client,
+                available: Decimal::ZERO,
+                held: Decimal::ZERO,
+                total: Decimal::ZERO,
+                locked: false,
+            });
+            match t.transaction_type {
+                TransactionType::Deposit => {
+                    account.available += amount;
+                    state.deposits.insert(t.tx, (t.client, amount, false));
+                }
+                TransactionType::Withdrawal => {
+                    account.available -= amount;
+                    state.withdrawals.insert(t.tx, (t.client, amount));
+                }
+                TransactionType::Adjustment => account.available += amount,
+                TransactionType::Refund => {
+                    account.available += amount;
+                    state.withdrawals.insert(t.tx, (t.client, state.withdrawals[&t.tx].1 - amount));
+                }
+                TransactionType::Dispute => {
+                    account.available -= deposit;
+                    account.held += deposit;
+                    state.deposits.insert(t.tx, (t.client, deposit, true));
+                }
+                TransactionType::Resolve => {
+                    account.available += deposit;
+                    account.held -= deposit;
+                    state.deposits.insert(t.tx, (t.client, deposit, false));
+                }
+                TransactionType::Chargeback => {
+                    account.held -= deposit;
+                    account.locked = true;
+                    state.deposits.insert(t.tx, (t.client, deposit, false));
+                }
+            }
+            account.total = account.available + account.held;
+        }
+        state
+    }
+
+    /// Returns true iff the transaction is valid in this state.
+    fn accepts(&self, t: &Transaction) -> bool {
+        let account = self.accounts.get(&t.client);
+        if account.is_some_and(|a| a.locked) {
+            return false;
+        }
+        let deposit = self.deposits.get(&t.tx).filter(|d| d.0 == t.client);
+        match (t.transaction_type, t.amount, account, deposit) {
+            (TransactionType::Deposit, Some(_), _, _) => !self.deposits.contains_key(&t.tx),
+            (TransactionType::Withdrawal, Some(amount), Some(a), _) => a.available >= amount,
+            (TransactionType::Dispute, _, Some(a), Some(&(_, amount, disputed))) => {
+                !disputed && a.available >= amount
+            }
+            (TransactionType::Resolve | TransactionType::Chargeback, _, Some(_), Some(d)) => d.2,
+            (TransactionType::Refund, Some(amount), Some(_), _) => self.withdrawals.get(&t.tx)
+                .is_some_and(|&(client, refundable)| client == t.client && amount <= refundable),
+            _ => false,
+        }
+    }
+}
+
+/// Reference implementation that recomputes all state from the log of accepted transactions
+#[derive(Default)]
+pub struct ReferenceEngine {
+    log: Vec<Transaction>,
+}
+
+impl ReferenceEngine {
+    /// Creates new [ReferenceEngine] with empty log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the transaction to the log and returns true iff it is valid.
+    pub fn execute(&mut self, transaction: Transaction) -> bool {
+        let accepted = State::replay(&self.log).accepts(&transaction);
+        if accepted {
+            self.log.push(transaction);
+        }
+        accepted
+    }
+
+    /// Returns all accounts ordered by client.
+    pub fn accounts(&self) -> Vec<Account> {
+        State::replay(&self.log).accounts.into_values().collect()
+    }
+}
+
+/// First difference observed between [PaymentsEngine] and [ReferenceEngine]
+#[derive(Debug)]
+pub struct Divergence {
+    /// Zero-based index of the transaction after which the engines diverged
+    pub step: usize,
+    /// Transaction after which the engines diverged
+    pub transaction: Transaction,
+    /// Whether the [PaymentsEngine] accepted the transaction
+    pub engine_accepted: bool,
+    /// Whether the [ReferenceEngine] accepted the transaction
+    pub reference_accepted: bool,
+    /// Accounts of the [PaymentsEngine] ordered by client
+    pub engine_accounts: Vec<Account>,
+    /// Accounts of the [ReferenceEngine] ordered by client
+    pub reference_accounts: Vec<Account>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Engines diverged at step {} ({:?})", self.step, self.transaction)?;
+        writeln!(f, "accepted: engine {}, reference {}", self.engine_accepted,
+                 self.reference_accepted)?;
+        writeln!(f, "engine accounts: {:?}", self.engine_accounts)?;
+        write!(f, "reference accounts: {:?}", self.reference_accounts)
+    }
+}
+
+/// Executes the transactions against a new [PaymentsEngine] and a new [ReferenceEngine] and
+/// compares acceptance and resulting accounts after every step.
+#[allow(clippy::result_large_err)] // Divergence is only returned once, by test code
+pub fn compare<I>(transactions: I) -> Result<(), Divergence>
+    where I: IntoIterator<Item=Transaction>
+{
+    let mut engine = PaymentsEngine::new();
+    let mut reference = ReferenceEngine::new();
+    for (step, transaction) in transactions.into_iter().enumerate() {
+        let engine_accepted = engine.execute(transaction.clone()).is_ok();
+        let reference_accepted = reference.execute(transaction.clone());
+        let mut engine_accounts: Vec<Account> = engine.accounts().collect();
+        engine_accounts.sort_by_key(|a| a.client);
+        let reference_accounts = reference.accounts();
+        if engine_accepted != reference_accepted || engine_accounts != reference_accounts {
+            return Err(Divergence {
+                step,
+                transaction,
+                engine_accepted,
+                reference_accepted,
+                engine_accounts,
+                reference_accounts,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Runs [compare] on the valid rows of a random transaction stream.
+#[allow(clippy::result_large_err)]
+pub fn compare_generated(config: GeneratorConfig) -> Result<(), Divergence> {
+    compare(Generator::new(config).filter_map(|row| match row {
+        GeneratedRow::Valid(transaction) => Some(transaction),
+        GeneratedRow::Invalid(_) => None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engines_agree_on_random_streams() {
+        for seed in 0..20 {
+            let config = GeneratorConfig {
+                clients: 5,
+                rows: 300,
+                withdrawal_ratio: 0.4,
+                dispute_probability: 0.2,
+                chargeback_probability: 0.3,
+                duplicate_probability: 0.05,
+                seed,
+                ..Default::default()
+            };
+            if let Err(divergence) = compare_generated(config) {
+                panic!("{}", divergence);
+            }
+        }
+    }
+}
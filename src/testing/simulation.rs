@@ -0,0 +1,234 @@
+//! Deterministic simulation of the engine with a seeded workload and a virtual clock
+//!
+//! A [Simulation] feeds the transactions of a [Generator] to the engine, stamped with the time of
+//! a [VirtualClock] that advances by a random number of ticks per step. After every step, disputes
+//! are expired and standing orders executed at the virtual time, and the invariants are checked
+//! (see [InvariantChecker]). Everything random derives from the seed of the [GeneratorConfig], so
+//! a failure is reproduced by running the simulation with the seed it reports:
+//! ```
+//! use toy_payments_engine::testing::generator::GeneratorConfig;
+//! use toy_payments_engine::testing::simulation::{Simulation, SimulationConfig};
+//!
+//! let config = SimulationConfig {
+//!     workload: GeneratorConfig { seed: 42, ..Default::default() },
+//!     ..Default::default()
+//! };
+//! let report = Simulation::new(config.clone()).run().unwrap();
+//! assert_eq!(report, Simulation::new(config).run().unwrap());
+//! ```
+use std::fmt;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::expiry::TimeProvider;
+use crate::models::Transaction;
+use crate::testing::generator::{GeneratedRow, Generator, GeneratorConfig};
+use crate::testing::invariants::{InvariantChecker, InvariantViolation};
+use crate::{EngineConfig, PaymentsEngine};
+
+/// Mixed into the seed of the clock, so it does not draw the same numbers as the workload
+const CLOCK_SEED: u64 = 0x5eed_c10c;
+
+/// Clock that only advances when told to
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct VirtualClock {
+    now: u64,
+}
+
+impl VirtualClock {
+    /// Creates a clock at the given time.
+    pub fn new(now: u64) -> Self {
+        Self { now }
+    }
+
+    /// Advances the clock by the given number of ticks.
+    pub fn advance(&mut self, ticks: u64) {
+        self.now = self.now.saturating_add(ticks);
+    }
+}
+
+impl TimeProvider for VirtualClock {
+    fn now(&self) -> u64 {
+        self.now
+    }
+}
+
+/// Configuration of a [Simulation]
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    /// Workload, including the seed of the simulation
+    pub workload: GeneratorConfig,
+    /// Configuration of the simulated engine, e.g. with a dispute expiry
+    pub engine: EngineConfig,
+    /// Maximum number of ticks the clock advances per step
+    pub max_ticks: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            workload: GeneratorConfig::default(),
+            engine: EngineConfig::default(),
+            max_ticks: 60,
+        }
+    }
+}
+
+/// Summary of a simulation without invariant violations
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SimulationReport {
+    /// Number of executed steps, i.e. generated rows
+    pub steps: u64,
+    /// Number of accepted transactions, including expiries and standing orders
+    pub accepted: u64,
+    /// Number of rejected transactions and invalid rows
+    pub rejected: u64,
+    /// Virtual time at the end
+    pub time: u64,
+    /// Digest of the final state, see [PaymentsEngine::state_digest]
+    pub digest: [u8; 32],
+}
+
+/// Invariant violation found by a simulation
+#[derive(Debug)]
+pub struct SimulationFailure {
+    /// Seed that reproduces the failure
+    pub seed: u64,
+    /// Step at which the violation was detected, starting at 1
+    pub step: u64,
+    /// Virtual time of the step
+    pub time: u64,
+    /// Transaction executed in the step, `None` for an invalid row
+    pub transaction: Option<Transaction>,
+    /// Violated invariant
+    pub violation: InvariantViolation,
+}
+
+impl fmt::Display for SimulationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Seed {} failed at step {} (time {}): {}",
+               self.seed, self.step, self.time, self.violation)
+    }
+}
+
+/// Deterministic simulation run, see [crate::testing::simulation]
+pub struct Simulation {
+    seed: u64,
+    max_ticks: u64,
+    workload: Generator,
+    engine: PaymentsEngine,
+    clock: VirtualClock,
+    rng: StdRng,
+    checker: InvariantChecker,
+}
+
+impl Simulation {
+    /// Creates a simulation with a fresh engine and the clock at zero.
+    ///
+    /// Panics if the workload configuration is invalid (see [Generator::new]).
+    pub fn new(config: SimulationConfig) -> Self {
+        let seed = config.workload.seed;
+        Self {
+            seed,
+            max_ticks: config.max_ticks,
+            workload: Generator::new(config.workload),
+            engine: PaymentsEngine::with_config(config.engine),
+            clock: VirtualClock::default(),
+            rng: StdRng::seed_from_u64(seed ^ CLOCK_SEED),
+            checker: InvariantChecker::new(),
+        }
+    }
+
+    /// Returns the simulated engine, e.g. to inspect it after a failure.
+    pub fn engine(&self) -> &PaymentsEngine {
+        &self.engine
+    }
+
+    /// Runs the whole workload and returns a report or the first invariant violation.
+    pub fn run(&mut self) -> Result<SimulationReport, Box<SimulationFailure>> {
+        let (mut steps, mut accepted, mut rejected) = (0, 0, 0);
+        for row in self.workload.by_ref() {
+            steps += 1;
+            self.clock.advance(self.rng.random_range(0..=self.max_ticks));
+            let now = self.clock.now();
+            let transaction = match row {
+                GeneratedRow::Valid(transaction) => Some(transaction.with_timestamp(now)),
+                GeneratedRow::Invalid(_) => None,
+            };
+            let mut outcomes = Vec::new();
+            if let Some(transaction) = transaction.clone() {
+                outcomes.push(self.engine.execute_with_outcome(transaction));
+            } else {
+                rejected += 1;
+            }
+            let expired = self.engine.expire_disputes_at(&self.clock);
+            let due = self.engine.run_due_orders(now);
+            outcomes.extend(expired.into_iter().chain(due).map(|(_, outcome)| outcome));
+            for outcome in outcomes {
+                match outcome.status {
+                    Ok(()) => accepted += 1,
+                    Err(_) => rejected += 1,
+                }
+            }
+            if let Err(violation) = self.checker.check(&self.engine) {
+                return Err(Box::new(SimulationFailure {
+                    seed: self.seed,
+                    step: steps,
+                    time: now,
+                    transaction,
+                    violation,
+                }));
+            }
+        }
+        Ok(SimulationReport {
+            steps,
+            accepted,
+            rejected,
+            time: self.clock.now(),
+            digest: self.engine.state_digest(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expiry::{DisputeExpiry, ExpiryPolicy};
+
+    use super::*;
+
+    fn config(seed: u64) -> SimulationConfig {
+        SimulationConfig {
+            workload: GeneratorConfig {
+                clients: 5,
+                rows: 500,
+                dispute_probability: 0.2,
+                invalid_probability: 0.05,
+                seed,
+                ..Default::default()
+            },
+            engine: EngineConfig {
+                dispute_expiry: Some(DisputeExpiry { max_age: 300, policy: ExpiryPolicy::Resolve }),
+                ..Default::default()
+            },
+            max_ticks: 10,
+        }
+    }
+
+    #[test]
+    fn same_seed_reproduces_simulation() {
+        let first = Simulation::new(config(3)).run().unwrap();
+        assert_eq!(first, Simulation::new(config(3)).run().unwrap());
+        assert_eq!(500, first.steps);
+        assert!(first.accepted > 0 && first.rejected > 0);
+        assert_ne!(first.digest, Simulation::new(config(4)).run().unwrap().digest);
+    }
+
+    #[test]
+    fn virtual_clock_only_advances_when_told() {
+        let mut clock = VirtualClock::new(5);
+        assert_eq!(5, clock.now());
+        clock.advance(7);
+        assert_eq!(12, clock.now());
+    }
+}
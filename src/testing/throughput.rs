@@ -0,0 +1,63 @@
+//! Simple throughput measurements, e.g. for the `bench` subcommand
+use std::time::{Duration, Instant};
+
+use crate::csv::read_transactions_from;
+use crate::models::Transaction;
+use crate::PaymentsEngine;
+
+/// Number of rows processed in the measured time
+#[derive(Clone, Copy, Debug)]
+pub struct Throughput {
+    /// Number of processed rows (including invalid ones)
+    pub rows: u64,
+    /// Elapsed wall-clock time
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// Returns the number of rows processed per second.
+    pub fn per_second(&self) -> f64 {
+        self.rows as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Measures parsing the CSV data without executing the transactions.
+///
+/// Returns the throughput and the successfully parsed transactions.
+pub fn parse(csv: &[u8]) -> (Throughput, Vec<Transaction>) {
+    let start = Instant::now();
+    let mut rows = 0;
+    let mut transactions = Vec::new();
+    for row in read_transactions_from(csv) {
+        rows += 1;
+        if let Ok(transaction) = row {
+            transactions.push(transaction);
+        }
+    }
+    (Throughput { rows, elapsed: start.elapsed() }, transactions)
+}
+
+/// Measures executing already parsed transactions with a new [PaymentsEngine].
+pub fn execute(transactions: Vec<Transaction>) -> Throughput {
+    let rows = transactions.len() as u64;
+    let mut engine = PaymentsEngine::new();
+    let start = Instant::now();
+    for transaction in transactions {
+        let _ = engine.execute(transaction);
+    }
+    Throughput { rows, elapsed: start.elapsed() }
+}
+
+/// Measures parsing the CSV data and executing the transactions with a new [PaymentsEngine].
+pub fn end_to_end(csv: &[u8]) -> Throughput {
+    let start = Instant::now();
+    let mut rows = 0;
+    let mut engine = PaymentsEngine::new();
+    for row in read_transactions_from(csv) {
+        rows += 1;
+        if let Ok(transaction) = row {
+            let _ = engine.execute(transaction);
+        }
+    }
+    Throughput { rows, elapsed: start.elapsed() }
+}
@@ -0,0 +1,175 @@
+//! Pluggable validation of transactions before their execution
+//!
+//! Validators added with [PaymentsEngine::add_validator] run in the order they were added before
+//! every transaction executed via [PaymentsEngine::execute]. The first failing validator rejects
+//! the transaction with [PaymentError::ValidationFailed]. Besides the validators below, closures
+//! taking the transaction and the engine can be added.
+use std::collections::HashSet;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::error::{PaymentError, Result};
+use crate::models::Transaction;
+use crate::PaymentsEngine;
+
+/// Check of a transaction before its execution, see [crate::validate]
+pub trait Validator: Send {
+    /// Returns a description of the violated rule if the transaction must be rejected.
+    fn validate(&self, transaction: &Transaction, engine: &PaymentsEngine)
+        -> std::result::Result<(), String>;
+}
+
+impl<F> Validator for F
+    where F: Fn(&Transaction, &PaymentsEngine) -> std::result::Result<(), String> + Send
+{
+    fn validate(&self, transaction: &Transaction, engine: &PaymentsEngine)
+        -> std::result::Result<(), String>
+    {
+        self(transaction, engine)
+    }
+}
+
+/// Rejects amounts outside the inclusive range; transactions without amount pass.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AmountRange {
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl Validator for AmountRange {
+    fn validate(&self, transaction: &Transaction, _: &PaymentsEngine)
+        -> std::result::Result<(), String>
+    {
+        let Some(amount) = transaction.amount else {
+            return Ok(());
+        };
+        match (self.min, self.max) {
+            (Some(min), _) if amount < min => Err(format!("amount {} is below {}", amount, min)),
+            (_, Some(max)) if amount > max => Err(format!("amount {} is above {}", amount, max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects currencies not in the list; transactions without currency pass.
+#[derive(Clone, Debug, Default)]
+pub struct CurrencyWhitelist {
+    currencies: HashSet<String>,
+}
+
+impl<S: Into<String>> FromIterator<S> for CurrencyWhitelist {
+    fn from_iter<I: IntoIterator<Item=S>>(iter: I) -> Self {
+        Self { currencies: iter.into_iter().map(Into::into).collect() }
+    }
+}
+
+impl Validator for CurrencyWhitelist {
+    fn validate(&self, transaction: &Transaction, _: &PaymentsEngine)
+        -> std::result::Result<(), String>
+    {
+        match &transaction.currency {
+            Some(currency) if !self.currencies.contains(currency) => {
+                Err(format!("currency {} is not accepted", currency))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects transactions of clients not in the list.
+#[derive(Clone, Debug, Default)]
+pub struct ClientAllowList {
+    clients: HashSet<u16>,
+}
+
+impl FromIterator<u16> for ClientAllowList {
+    fn from_iter<I: IntoIterator<Item=u16>>(iter: I) -> Self {
+        Self { clients: iter.into_iter().collect() }
+    }
+}
+
+impl Validator for ClientAllowList {
+    fn validate(&self, transaction: &Transaction, _: &PaymentsEngine)
+        -> std::result::Result<(), String>
+    {
+        if self.clients.contains(&transaction.client) {
+            Ok(())
+        } else {
+            Err(format!("client {} is not allowed", transaction.client))
+        }
+    }
+}
+
+impl PaymentsEngine {
+    /// Appends a validator to the chain run before every transaction.
+    pub fn add_validator(&mut self, validator: impl Validator + 'static) {
+        self.validators.push(Box::new(validator));
+    }
+
+    /// Runs the validator chain, failing with the first violated rule.
+    pub(crate) fn validate(&self, transaction: &Transaction) -> Result<()> {
+        self.validation_failure(transaction).map_or(Ok(()), |reason| {
+            Err(PaymentError::ValidationFailed {
+                client: transaction.client,
+                tx: transaction.tx,
+                reason,
+            })
+        })
+    }
+
+    /// Returns the first violated rule of the validator chain, if any.
+    pub(crate) fn validation_failure(&self, transaction: &Transaction) -> Option<String> {
+        self.validators.iter().find_map(|v| v.validate(transaction, self).err())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv::read_transactions_from;
+    use crate::RejectionReason;
+
+    #[test]
+    fn validators_run_in_order_and_reject_with_first_failure() {
+        let mut engine = PaymentsEngine::new();
+        engine.add_validator(ClientAllowList::from_iter([1, 2]));
+        engine.add_validator(AmountRange { min: Some(Decimal::ONE), max: Some(Decimal::TEN) });
+        engine.add_validator(CurrencyWhitelist::from_iter(["EUR", "USD"]));
+        engine.add_validator(|transaction: &Transaction, _: &PaymentsEngine| {
+            match transaction.memo.as_deref() {
+                Some("test") => Err(String::from("test transactions are not accepted")),
+                _ => Ok(()),
+            }
+        });
+        let csv = "type,client,tx,amount,currency,memo\n\
+                   deposit,1,1,5,EUR,\n\
+                   deposit,3,2,50,EUR,\n\
+                   deposit,1,3,50,CHF,\n\
+                   deposit,2,4,5,CHF,\n\
+                   deposit,2,5,5,,test\n\
+                   deposit,2,6,5,,\n";
+        let failures: Vec<_> = read_transactions_from(csv.as_bytes())
+            .map(|t| match engine.execute(t.unwrap()) {
+                Err(PaymentError::ValidationFailed { reason, .. }) => Some(reason),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![
+            None,
+            Some(String::from("client 3 is not allowed")),
+            Some(String::from("amount 50 is above 10")),
+            Some(String::from("currency CHF is not accepted")),
+            Some(String::from("test transactions are not accepted")),
+            None,
+        ], failures);
+        assert_eq!(Decimal::new(5, 0), engine.account(1).unwrap().available);
+        assert_eq!(Decimal::new(5, 0), engine.account(2).unwrap().available);
+
+        let withdrawal = read_transactions_from("type,client,tx,amount\nwithdrawal,1,7,0.5\n"
+            .as_bytes()).next().unwrap().unwrap();
+        assert_eq!(vec![RejectionReason::ValidationFailed(String::from("amount 0.5 is below 1"))],
+                   engine.explain(&withdrawal));
+    }
+}
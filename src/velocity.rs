@@ -0,0 +1,130 @@
+//! Velocity limits on withdrawals
+use std::collections::VecDeque;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Map;
+use crate::error::Result;
+use crate::PaymentsEngine;
+
+/// Limits on the withdrawals of each client within a rolling window of transaction timestamps
+///
+/// Withdrawals without timestamp are neither checked nor counted.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct VelocityLimit {
+    /// Length of the window, e.g. 3600 for an hour with timestamps in seconds
+    pub window: u64,
+    /// Maximum number of withdrawals within the window, `None` for no limit
+    pub max_count: Option<usize>,
+    /// Maximum withdrawn amount within the window, `None` for no limit
+    pub max_volume: Option<Decimal>,
+}
+
+/// Timestamps and amounts of the accepted withdrawals within the window, by client
+pub(crate) type RecentWithdrawals = Map<u16, VecDeque<(u64, Decimal)>>;
+
+impl PaymentsEngine {
+    /// Replaces the velocity limit of the engine's configuration, e.g. after loading a snapshot.
+    pub fn set_velocity_limit(&mut self, limit: Option<VelocityLimit>) {
+        self.config.velocity_limit = limit;
+    }
+
+    /// Withdraws like [PaymentsEngine::withdraw] at the given timestamp.
+    ///
+    /// Also fails if the withdrawal exceeds the velocity limit.
+    pub fn withdraw_at(
+        &mut self,
+        client: u16,
+        tx: u32,
+        amount: Decimal,
+        timestamp: Option<u64>,
+    ) -> Result<()> {
+        self.withdraw_from(client, tx, amount, timestamp, None)
+    }
+
+    /// Counts an accepted withdrawal for the velocity limit.
+    pub(crate) fn record_recent_withdrawal(
+        &mut self,
+        client: u16,
+        amount: Decimal,
+        timestamp: Option<u64>,
+    ) {
+        if let (Some(limit), Some(timestamp)) = (&self.config.velocity_limit, timestamp) {
+            let recent = self.recent_withdrawals.entry(client).or_default();
+            let expired = |&(t, _): &(u64, Decimal)| t.saturating_add(limit.window) <= timestamp;
+            while recent.front().is_some_and(expired) {
+                recent.pop_front();
+            }
+            recent.push_back((timestamp, amount));
+        }
+    }
+
+    /// Returns true iff the withdrawal would exceed the configured velocity limit.
+    pub(crate) fn exceeds_velocity_limit(
+        &self,
+        client: u16,
+        amount: Decimal,
+        timestamp: Option<u64>,
+    ) -> bool {
+        let (Some(limit), Some(timestamp)) = (&self.config.velocity_limit, timestamp) else {
+            return false;
+        };
+        let (count, volume) = self.recent_withdrawals.get(&client)
+            .into_iter()
+            .flatten()
+            .filter(|&&(t, _)| t.saturating_add(limit.window) > timestamp)
+            .fold((1, amount), |(count, volume), &(_, amount)| (count + 1, volume + amount));
+        limit.max_count.is_some_and(|max| count > max)
+            || limit.max_volume.is_some_and(|max| volume > max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EngineConfig, PaymentError, RejectionReason, Transaction};
+
+    fn withdrawal(tx: u32, amount: i64, timestamp: Option<u64>) -> Transaction {
+        Transaction { timestamp, ..Transaction::withdrawal(1, tx, Decimal::new(amount, 0)) }
+    }
+
+    fn engine(max_count: Option<usize>, max_volume: Option<i64>) -> PaymentsEngine {
+        let max_volume = max_volume.map(|max| Decimal::new(max, 0));
+        let velocity_limit = Some(VelocityLimit { window: 60, max_count, max_volume });
+        let mut engine = PaymentsEngine::with_config(EngineConfig {
+            velocity_limit,
+            ..Default::default()
+        });
+        engine.deposit(1, 1, Decimal::new(100, 0)).unwrap();
+        engine
+    }
+
+    #[test]
+    fn withdrawals_beyond_max_count_in_window_fail() {
+        let mut engine = engine(Some(2), None);
+        engine.execute(withdrawal(2, 1, Some(0))).unwrap();
+        engine.execute(withdrawal(3, 1, Some(30))).unwrap();
+        let third = withdrawal(4, 1, Some(59));
+        assert_eq!(vec![RejectionReason::VelocityLimitExceeded], engine.explain(&third));
+        assert!(matches!(engine.execute(third),
+            Err(PaymentError::VelocityLimitExceeded { client: 1, tx: 4 })));
+        engine.execute(withdrawal(5, 1, Some(60))).unwrap();
+        engine.execute(withdrawal(6, 1, None)).unwrap();
+        engine.withdraw_at(1, 7, Decimal::ONE, Some(200)).unwrap();
+        engine.withdraw_at(1, 8, Decimal::ONE, Some(200)).unwrap();
+        assert!(matches!(engine.withdraw_at(1, 9, Decimal::ONE, Some(200)),
+            Err(PaymentError::VelocityLimitExceeded { client: 1, tx: 9 })));
+    }
+
+    #[test]
+    fn withdrawals_beyond_max_volume_in_window_fail() {
+        let mut engine = engine(None, Some(10));
+        engine.execute(withdrawal(2, 6, Some(0))).unwrap();
+        assert!(matches!(engine.execute(withdrawal(3, 5, Some(10))),
+            Err(PaymentError::VelocityLimitExceeded { .. })));
+        engine.execute(withdrawal(4, 4, Some(10))).unwrap();
+        engine.execute(withdrawal(5, 6, Some(70))).unwrap();
+        assert_eq!(Decimal::new(84, 0), engine.account(1).unwrap().available);
+    }
+}
@@ -0,0 +1,271 @@
+//! Write-ahead log of input rows for crash-safe streaming
+//!
+//! Every accepted row is appended to the log as one JSON line with its row number before the
+//! engine executes it, and so is every administrative operation of the server (see
+//! [WalRecord]). On startup, the entries past the last checkpoint (see [crate::checkpoint])
+//! are replayed, so no acknowledged transaction is lost if the process dies in between. Follow-ups
+//! such as retried parked rows or expired disputes are not logged, replaying the rows repeats them.
+//! A failed append is rolled back, so the log stays readable and the append can be retried. Once a
+//! checkpoint covers all entries, the log is truncated (see [WriteAheadLog::truncate]).
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "chaos")]
+use crate::testing::chaos::StorageFaults;
+use crate::models::AdminOperation;
+use crate::Transaction;
+
+/// When appended entries are synced to disk
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SyncPolicy {
+    /// After every entry
+    #[default]
+    Always,
+    /// After the given number of entries
+    Every(u64),
+    /// Never, leaving it to the operating system
+    Never,
+}
+
+impl FromStr for SyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => match s.parse() {
+                Ok(entries) if entries > 0 => Ok(Self::Every(entries)),
+                _ => Err(format!("invalid sync policy {:?}, expected always, never, or a positive \
+                                  number of entries", s)),
+            },
+        }
+    }
+}
+
+/// Entry of the write-ahead log
+#[derive(Clone, Debug, Deserialize)]
+pub struct WalEntry {
+    /// Number of input rows (including invalid ones) consumed after this one
+    pub row: u64,
+    #[serde(flatten)]
+    pub record: WalRecord,
+}
+
+/// Row logged in an entry, tagged by its kind, e.g. `{"row":1,"operation":{"type":"unlock",
+/// "client":1}}`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WalRecord {
+    Transaction(Transaction),
+    Operation(AdminOperation),
+}
+
+#[derive(Serialize)]
+struct WalEntryRef<'a> {
+    row: u64,
+    #[serde(flatten)]
+    record: WalRecordRef<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum WalRecordRef<'a> {
+    Transaction(&'a Transaction),
+    Operation(&'a AdminOperation),
+}
+
+/// Writer appending entries to a write-ahead log file
+pub struct WriteAheadLog {
+    file: File,
+    sync: SyncPolicy,
+    /// Number of entries appended since the last sync
+    unsynced: u64,
+    /// Length of the complete entries
+    len: u64,
+    #[cfg(feature = "chaos")]
+    faults: Option<StorageFaults>,
+}
+
+impl WriteAheadLog {
+    /// Opens the log at the given path, creating it if necessary, and returns it together with
+    /// the entries it already contains.
+    ///
+    /// An incomplete last line left behind by an interrupted write is discarded.
+    pub fn open<P: AsRef<Path>>(path: P, sync: SyncPolicy) -> io::Result<(Self, Vec<WalEntry>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        let mut entries = Vec::new();
+        let mut complete = 0;
+        while let Some(end) = content[complete..].iter().position(|&b| b == b'\n') {
+            let line = &content[complete..complete + end];
+            if !line.is_empty() {
+                entries.push(serde_json::from_slice(line)?);
+            }
+            complete += end + 1;
+        }
+        file.set_len(complete as u64)?;
+        file.seek(SeekFrom::End(0))?;
+        let wal = Self {
+            file,
+            sync,
+            unsynced: 0,
+            len: complete as u64,
+            #[cfg(feature = "chaos")]
+            faults: None,
+        };
+        Ok((wal, entries))
+    }
+
+    /// Makes appends fail randomly, see [crate::testing::chaos].
+    #[cfg(feature = "chaos")]
+    pub fn inject_faults(&mut self, faults: StorageFaults) {
+        self.faults = Some(faults);
+    }
+
+    /// Appends the given row and syncs it to disk according to the sync policy.
+    ///
+    /// If the entry cannot be written, whatever part of it was written is truncated again.
+    pub fn append(&mut self, row: u64, transaction: &Transaction) -> io::Result<()> {
+        self.append_record(row, WalRecordRef::Transaction(transaction))
+    }
+
+    /// Appends the given administrative operation like a row, see [WriteAheadLog::append].
+    pub fn append_operation(&mut self, row: u64, operation: &AdminOperation) -> io::Result<()> {
+        self.append_record(row, WalRecordRef::Operation(operation))
+    }
+
+    fn append_record(&mut self, row: u64, record: WalRecordRef) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&WalEntryRef { row, record })?;
+        line.push(b'\n');
+        if let Err(e) = self.write_line(&line) {
+            self.file.set_len(self.len)?;
+            self.file.seek(SeekFrom::End(0))?;
+            return Err(e);
+        }
+        self.len += line.len() as u64;
+        self.unsynced += 1;
+        match self.sync {
+            SyncPolicy::Always => self.sync(),
+            SyncPolicy::Every(entries) if self.unsynced >= entries => self.sync(),
+            _ => Ok(()),
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        #[cfg(feature = "chaos")]
+        if let Some(faults) = &mut self.faults {
+            return faults.write_all(&mut self.file, line);
+        }
+        self.file.write_all(line)
+    }
+
+    /// Discards all entries, e.g. once a checkpoint covers them, so the log does not grow without
+    /// bound and recovery does not replay the whole history.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.len = 0;
+        self.sync()
+    }
+
+    /// Syncs all appended entries to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.unsynced = 0;
+        self.file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn entries_survive_reopening_and_torn_writes_are_discarded() {
+        let path = std::env::temp_dir()
+            .join(format!("toy-payments-engine-wal-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let deposit = Transaction::deposit(1, 1, Decimal::new(15, 1)).with_tag("promo");
+
+        let (mut wal, entries) = WriteAheadLog::open(&path, SyncPolicy::Every(2)).unwrap();
+        assert!(entries.is_empty());
+        wal.append(1, &deposit).unwrap();
+        wal.append(3, &deposit).unwrap();
+        wal.file.write_all(br#"{"row":4,"transa"#).unwrap();
+        drop(wal);
+
+        let (mut wal, entries) = WriteAheadLog::open(&path, SyncPolicy::Never).unwrap();
+        wal.append(4, &deposit).unwrap();
+        drop(wal);
+        let (_, entries_after) = WriteAheadLog::open(&path, SyncPolicy::Always).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let rows: Vec<u64> = entries.iter().map(|entry| entry.row).collect();
+        assert_eq!(vec![1, 3], rows);
+        let WalRecord::Transaction(logged) = &entries[1].record else { panic!("{:?}", entries) };
+        assert_eq!(deposit.amount, logged.amount);
+        assert_eq!(deposit.tags, logged.tags);
+        assert_eq!(3, entries_after.len());
+    }
+
+    #[test]
+    fn truncated_entries_are_not_replayed() {
+        let path = std::env::temp_dir()
+            .join(format!("toy-payments-engine-wal-truncate-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let deposit = Transaction::deposit(1, 1, Decimal::new(15, 1));
+
+        let (mut wal, _) = WriteAheadLog::open(&path, SyncPolicy::Always).unwrap();
+        wal.append(1, &deposit).unwrap();
+        wal.append(2, &deposit).unwrap();
+        wal.truncate().unwrap();
+        wal.append(3, &deposit).unwrap();
+        drop(wal);
+        let (_, entries) = WriteAheadLog::open(&path, SyncPolicy::Always).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let rows: Vec<u64> = entries.iter().map(|entry| entry.row).collect();
+        assert_eq!(vec![3], rows);
+    }
+
+    #[test]
+    fn operations_are_logged_as_tagged_records() {
+        let path = std::env::temp_dir()
+            .join(format!("toy-payments-engine-wal-operation-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let unlock = AdminOperation::Unlock { client: 1 };
+
+        let (mut wal, _) = WriteAheadLog::open(&path, SyncPolicy::Always).unwrap();
+        wal.append(1, &Transaction::deposit(1, 1, Decimal::ONE)).unwrap();
+        wal.append_operation(2, &unlock).unwrap();
+        drop(wal);
+        let content = std::fs::read_to_string(&path).unwrap();
+        let (_, entries) = WriteAheadLog::open(&path, SyncPolicy::Always).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let expected = r#"{"row":2,"operation":{"type":"unlock","client":1}}"#;
+        assert_eq!(Some(expected), content.lines().last());
+        assert!(matches!(entries[0].record, WalRecord::Transaction(_)));
+        assert!(matches!(entries[1].record, WalRecord::Operation(op) if op == unlock));
+    }
+
+    #[test]
+    fn sync_policies_are_parsed() {
+        assert_eq!(Ok(SyncPolicy::Always), "always".parse());
+        assert_eq!(Ok(SyncPolicy::Every(100)), "100".parse());
+        assert_eq!(Ok(SyncPolicy::Never), "never".parse());
+        assert!("0".parse::<SyncPolicy>().is_err());
+    }
+}
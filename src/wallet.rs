@@ -0,0 +1,291 @@
+//! Named wallets (sub-accounts) of clients
+//!
+//! Deposits and withdrawals may name a wallet of the client, e.g. "trading" or "savings"; all other
+//! funds belong to the client's [DEFAULT_WALLET]. Disputes, resolves, and chargebacks affect the
+//! wallet of the disputed deposit, reinstated funds of representments go to the default wallet.
+//! The client's [crate::Account] always aggregates all wallets.
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{PaymentError, Result};
+use crate::models::{Transaction, TransactionType};
+use crate::receivable::WithdrawnFundsPolicy;
+use crate::PaymentsEngine;
+
+/// Name of the wallet holding all funds not assigned to a named wallet
+pub const DEFAULT_WALLET: &str = "default";
+
+/// Balance of a named wallet
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Wallet {
+    /// Funds available for trading
+    pub available: Decimal,
+    /// Funds held for dispute
+    pub held: Decimal,
+}
+
+/// Row of the account report per wallet
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct WalletAccount {
+    /// Client identifier
+    pub client: u16,
+    /// Wallet name
+    pub wallet: String,
+    /// Funds available for trading
+    pub available: Decimal,
+    /// Funds held for dispute
+    pub held: Decimal,
+    /// Total funds available or held
+    pub total: Decimal,
+    /// True iff the client account is locked
+    pub locked: bool,
+}
+
+/// Writes the wallet accounts as CSV to the given writer.
+pub fn write_wallet_accounts<W, I>(writer: W, accounts: I) -> std::result::Result<(), csv::Error>
+    where W: Write,
+          I: IntoIterator<Item=WalletAccount>
+{
+    let mut writer = csv::Writer::from_writer(writer);
+    for account in accounts {
+        writer.serialize(account)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Returns the name of a named wallet, `None` for the default wallet.
+fn named(wallet: Option<&str>) -> Option<&str> {
+    wallet.filter(|&wallet| wallet != DEFAULT_WALLET)
+}
+
+impl PaymentsEngine {
+    /// Returns the balance of the client's wallet (`None` for the default wallet) if the client
+    /// exists.
+    pub fn wallet(&self, client: u16, wallet: Option<&str>) -> Option<Wallet> {
+        let account = self.accounts.get(&client)?;
+        let wallets = self.wallets.get(&client);
+        match named(wallet) {
+            Some(name) => Some(wallets.and_then(|w| w.get(name)).copied().unwrap_or_default()),
+            None => Some(wallets.into_iter().flat_map(BTreeMap::values).fold(
+                Wallet { available: account.available, held: account.held },
+                |default, wallet| Wallet {
+                    available: default.available - wallet.available,
+                    held: default.held - wallet.held,
+                },
+            )),
+        }
+    }
+
+    /// Returns the accounts per wallet, ordered by client and wallet name.
+    pub fn wallet_accounts(&self) -> Vec<WalletAccount> {
+        let mut accounts = Vec::new();
+        let mut clients: Vec<u16> = self.accounts.keys().copied().collect();
+        clients.sort_unstable();
+        for client in clients {
+            let locked = self.accounts[&client].locked;
+            let names = self.wallets.get(&client).into_iter().flat_map(BTreeMap::keys);
+            for name in std::iter::once(DEFAULT_WALLET).chain(names.map(String::as_str)) {
+                let wallet = self.wallet(client, Some(name)).expect("client exists");
+                accounts.push(WalletAccount {
+                    client,
+                    wallet: name.to_owned(),
+                    available: wallet.available,
+                    held: wallet.held,
+                    total: wallet.available + wallet.held,
+                    locked,
+                });
+            }
+        }
+        accounts
+    }
+
+    /// Moves available funds between two wallets of the client (`None` for the default wallet).
+    ///
+    /// Fails if the client account does not exist or is locked, or the source wallet has
+    /// insufficient available funds.
+    pub fn move_funds(
+        &mut self,
+        client: u16,
+        tx: u32,
+        from: Option<&str>,
+        to: Option<&str>,
+        amount: Decimal,
+    ) -> Result<()> {
+        let account = self.accounts.get(&client).ok_or_else(|| {
+            PaymentError::UnknownClient { client, tx_type: "Move".to_string() }
+        })?;
+        if account.locked {
+            return Err(PaymentError::LockedAccount { client, tx });
+        }
+        let available = self.wallet(client, from).expect("client exists").available;
+        if available < amount {
+            return Err(PaymentError::InsufficientFunds { client, tx, available, amount });
+        }
+        self.add_to_wallet(client, from, -amount, Decimal::ZERO);
+        self.add_to_wallet(client, to, amount, Decimal::ZERO);
+        Ok(())
+    }
+
+    /// Returns the funds available in the wallet a transaction draws from if they do not suffice,
+    /// see [PaymentsEngine::withdrawal_wallet_shortfall] and
+    /// [PaymentsEngine::dispute_wallet_shortfall].
+    pub(crate) fn insufficient_wallet_funds(&self, transaction: &Transaction)
+        -> Option<(Decimal, Decimal)>
+    {
+        let Transaction { transaction_type, client, tx, amount, .. } = *transaction;
+        match transaction_type {
+            TransactionType::Withdrawal => {
+                self.withdrawal_wallet_shortfall(client, transaction.wallet.as_deref(), amount?)
+            }
+            TransactionType::Dispute => self.dispute_wallet_shortfall(client, tx),
+            _ => None,
+        }
+    }
+
+    /// Returns the funds available in the wallet (`None` for the default wallet) and the amount
+    /// if the client has named wallets and they do not suffice for the withdrawal.
+    pub(crate) fn withdrawal_wallet_shortfall(
+        &self,
+        client: u16,
+        wallet: Option<&str>,
+        amount: Decimal,
+    ) -> Option<(Decimal, Decimal)> {
+        if !self.wallets.contains_key(&client) {
+            return None;
+        }
+        let available = self.wallet(client, wallet)?.available;
+        let overdraft = match named(wallet) {
+            None => self.overdraft_limit(client),
+            Some(_) => Decimal::ZERO,
+        };
+        (available + overdraft < amount).then_some((available, amount))
+    }
+
+    /// Returns the funds available in the wallet of the deposit and its amount if the client has
+    /// named wallets and they do not suffice for the dispute, unless withdrawn funds may be
+    /// disputed.
+    pub(crate) fn dispute_wallet_shortfall(&self, client: u16, tx: u32)
+        -> Option<(Decimal, Decimal)>
+    {
+        if !self.wallets.contains_key(&client)
+            || self.config.withdrawn_funds != WithdrawnFundsPolicy::Reject {
+            return None;
+        }
+        let deposit = self.deposits.get(&tx).filter(|d| d.client == client)?;
+        let wallet = self.deposit_wallets.get(&tx).map(String::as_str);
+        let available = self.wallet(client, wallet)?.available;
+        (available < deposit.amount()).then_some((available, deposit.amount()))
+    }
+
+    /// Updates the named wallets after the successful execution of a transaction naming one;
+    /// disputes, resolves, and chargebacks update the wallet of the deposit themselves.
+    pub(crate) fn record_wallet(&mut self, transaction: &Transaction) {
+        let Transaction { transaction_type, client, tx, amount, .. } = *transaction;
+        let Some(wallet) = named(transaction.wallet.as_deref()) else {
+            return;
+        };
+        let amount = amount.unwrap_or_default();
+        let available = match transaction_type {
+            TransactionType::Deposit => {
+                self.deposit_wallets.insert(tx, wallet.to_owned());
+                amount
+            }
+            TransactionType::Withdrawal => -amount,
+            TransactionType::Refund | TransactionType::Adjustment => amount,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                return;
+            }
+        };
+        self.add_to_wallet(client, Some(wallet), available, Decimal::ZERO);
+    }
+
+    /// Adds to the balance of the named wallet of the deposit, if any.
+    pub(crate) fn add_to_deposit_wallet(
+        &mut self,
+        client: u16,
+        tx: u32,
+        available: Decimal,
+        held: Decimal,
+    ) {
+        if let Some(wallet) = self.deposit_wallets.get(&tx).cloned() {
+            self.add_to_wallet(client, Some(&wallet), available, held);
+        }
+    }
+
+    /// Adds to the balance of a named wallet; the default wallet is implied by the account.
+    fn add_to_wallet(&mut self, client: u16, wallet: Option<&str>, available: Decimal,
+                     held: Decimal) {
+        if let Some(name) = named(wallet) {
+            let wallets = self.wallets.entry(client).or_default();
+            let wallet = wallets.entry(name.to_owned()).or_default();
+            wallet.available += available;
+            wallet.held += held;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RejectionReason;
+
+    fn transaction(kind: TransactionType, tx: u32, amount: i64, wallet: &str) -> Transaction {
+        let wallet = (!wallet.is_empty()).then(|| wallet.to_owned());
+        Transaction { wallet, ..Transaction::fixture(kind, 1, tx, amount) }
+    }
+
+    #[test]
+    fn wallets_are_funded_separately() {
+        let mut engine = PaymentsEngine::new();
+        engine.execute(transaction(TransactionType::Deposit, 1, 10, "")).unwrap();
+        engine.execute(transaction(TransactionType::Deposit, 2, 5, "savings")).unwrap();
+        let withdrawal = transaction(TransactionType::Withdrawal, 3, 6, "savings");
+        let reasons = engine.explain(&withdrawal);
+        assert!(matches!(engine.execute(withdrawal),
+            Err(PaymentError::InsufficientFunds { tx: 3, .. })));
+        assert_eq!(vec![RejectionReason::InsufficientFunds {
+            available: Decimal::new(5, 0),
+            amount: Decimal::new(6, 0),
+        }], reasons);
+
+        engine.move_funds(1, 4, None, Some("savings"), Decimal::new(3, 0)).unwrap();
+        engine.execute(transaction(TransactionType::Withdrawal, 5, 6, "savings")).unwrap();
+        let dispute = transaction(TransactionType::Dispute, 2, 0, "");
+        assert!(engine.execute(dispute.clone()).is_err());
+        engine.move_funds(1, 6, None, Some("savings"), Decimal::new(3, 0)).unwrap();
+        engine.execute(dispute).unwrap();
+        assert!(engine.move_funds(1, 7, Some("savings"), None, Decimal::ONE).is_err());
+
+        let wallets: Vec<_> = engine.wallet_accounts().into_iter()
+            .map(|w| (w.wallet, w.available, w.held))
+            .collect();
+        assert_eq!(vec![
+            (String::from("default"), Decimal::new(4, 0), Decimal::ZERO),
+            (String::from("savings"), Decimal::ZERO, Decimal::new(5, 0)),
+        ], wallets);
+    }
+
+    #[test]
+    fn operations_check_and_update_wallets() {
+        let mut engine = PaymentsEngine::new();
+        engine.deposit(1, 1, Decimal::new(2, 0)).unwrap();
+        engine.execute(transaction(TransactionType::Deposit, 2, 5, "savings")).unwrap();
+
+        assert!(matches!(engine.withdraw(1, 3, Decimal::new(3, 0)),
+            Err(PaymentError::InsufficientFunds { tx: 3, .. })));
+        engine.withdraw(1, 4, Decimal::new(2, 0)).unwrap();
+        engine.dispute(1, 2).unwrap();
+        assert_eq!(Some(Wallet { available: Decimal::ZERO, held: Decimal::new(5, 0) }),
+                   engine.wallet(1, Some("savings")));
+        engine.resolve(1, 2).unwrap();
+        assert_eq!(Some(Wallet { available: Decimal::new(5, 0), held: Decimal::ZERO }),
+                   engine.wallet(1, Some("savings")));
+        engine.move_funds(1, 5, Some("savings"), None, Decimal::new(5, 0)).unwrap();
+        assert!(matches!(engine.dispute(1, 2),
+            Err(PaymentError::InsufficientFunds { tx: 2, .. })));
+    }
+}
@@ -0,0 +1,431 @@
+//! Webhook notifications about key events, delivered as JSON via HTTP POST
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::models::{Transaction, TransactionOutcome, TransactionType};
+
+/// Time limit of connecting to the endpoint, and of each read and write
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Kinds of events that can be subscribed to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EventKind {
+    /// Chargeback executed
+    Chargeback,
+    /// Account locked by a transaction
+    Locked,
+    /// Withdrawal of at least the configured amount
+    LargeWithdrawal,
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chargeback" => Ok(Self::Chargeback),
+            "locked" => Ok(Self::Locked),
+            "large-withdrawal" => Ok(Self::LargeWithdrawal),
+            _ => Err(format!("unknown event {:?}, expected chargeback, locked, or \
+                              large-withdrawal", s)),
+        }
+    }
+}
+
+/// Payload of a webhook notification
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Chargeback of a disputed deposit was executed
+    ChargebackExecuted { client: u16, tx: u32 },
+    /// Account was locked by the transaction, with the automatic locking rule if any
+    AccountLocked { client: u16, tx: u32, auto_lock: Option<String> },
+    /// Withdrawal of at least the configured amount was executed
+    LargeWithdrawal { client: u16, tx: u32, amount: Decimal },
+}
+
+/// Events to notify and where to deliver them
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    /// Endpoint as `http://host[:port][/path]`
+    pub url: String,
+    /// Subscribed events
+    pub events: Vec<EventKind>,
+    /// Minimum amount of a [Event::LargeWithdrawal]
+    pub large_withdrawal: Decimal,
+    /// Number of retries of a failed delivery
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further one
+    pub backoff: Duration,
+    /// Maximum number of events waiting for delivery, further ones are dropped
+    pub queue: usize,
+}
+
+impl WebhookConfig {
+    /// Returns the subscribed events caused by an executed transaction.
+    pub fn events(&self, transaction: &Transaction, outcome: &TransactionOutcome) -> Vec<Event> {
+        if outcome.status.is_err() {
+            return Vec::new();
+        }
+        let TransactionOutcome { client, tx, .. } = *outcome;
+        let mut events = Vec::new();
+        for kind in &self.events {
+            match kind {
+                EventKind::Chargeback if outcome.kind == TransactionType::Chargeback => {
+                    events.push(Event::ChargebackExecuted { client, tx });
+                }
+                EventKind::Locked if outcome.balance_after.as_ref().is_some_and(|a| a.locked)
+                    && !outcome.balance_before.as_ref().is_some_and(|a| a.locked) => {
+                    let auto_lock = outcome.auto_lock.clone();
+                    events.push(Event::AccountLocked { client, tx, auto_lock });
+                }
+                EventKind::LargeWithdrawal if outcome.kind == TransactionType::Withdrawal => {
+                    let amount = transaction.amount.filter(|&a| a >= self.large_withdrawal);
+                    if let Some(amount) = amount {
+                        events.push(Event::LargeWithdrawal { client, tx, amount });
+                    }
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+/// Error of a single delivery attempt
+#[derive(Debug)]
+pub enum DeliveryError {
+    /// Connection or transfer failed
+    Io(io::Error),
+    /// Endpoint responded with a status other than 2xx
+    Status(u16),
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => e.fmt(f),
+            Self::Status(status) => write!(f, "endpoint responded with status {}", status),
+        }
+    }
+}
+
+impl From<io::Error> for DeliveryError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Event that could not be delivered, not even with retries
+#[derive(Debug)]
+pub struct DeliveryFailure {
+    /// Endpoint the event was posted to
+    pub endpoint: String,
+    /// Undelivered event
+    pub event: Event,
+    /// Error of the last attempt
+    pub error: DeliveryError,
+}
+
+impl fmt::Display for DeliveryFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not deliver webhook to {}: {}", self.endpoint, self.error)
+    }
+}
+
+/// Delivers events on a background thread, so that slow endpoints do not stall processing
+///
+/// Failed deliveries are retried with exponential backoff and finally passed to the failure
+/// callback. Events beyond [WebhookConfig::queue] are dropped and counted, see
+/// [WebhookDispatcher::dropped].
+pub struct WebhookDispatcher {
+    config: WebhookConfig,
+    sender: Option<SyncSender<Event>>,
+    worker: Option<JoinHandle<()>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl WebhookDispatcher {
+    /// Starts a dispatcher, failing if the URL is not a plain HTTP URL. `on_failure` is called on
+    /// the background thread for every event that could not be delivered.
+    pub fn start<F>(config: WebhookConfig, mut on_failure: F) -> Result<Self, String>
+        where F: FnMut(DeliveryFailure) + Send + 'static
+    {
+        let endpoint = Endpoint::parse(&config.url)?;
+        let (sender, receiver) = mpsc::sync_channel::<Event>(config.queue);
+        let (retries, backoff) = (config.retries, config.backoff);
+        let worker = thread::spawn(move || {
+            for event in receiver {
+                if let Err(error) = endpoint.deliver(&event, retries, backoff) {
+                    on_failure(DeliveryFailure { endpoint: endpoint.to_string(), event, error });
+                }
+            }
+        });
+        let dropped = Arc::new(AtomicU64::new(0));
+        Ok(Self { config, sender: Some(sender), worker: Some(worker), dropped })
+    }
+
+    /// Queues the subscribed events caused by an executed transaction for delivery.
+    pub fn notify(&self, transaction: &Transaction, outcome: &TransactionOutcome) {
+        for event in self.config.events(transaction, outcome) {
+            self.queue(event);
+        }
+    }
+
+    /// Returns the number of events dropped so far because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn queue(&self, event: Event) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        if let Err(TrySendError::Full(_)) = sender.try_send(event) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Waits until all queued events are delivered or given up on.
+    pub fn finish(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for WebhookDispatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Host, port, and path of a plain HTTP URL
+struct Endpoint {
+    /// Host name or IP address, without the brackets of an IPv6 literal
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self, String> {
+        let rest = url.strip_prefix("http://")
+            .ok_or_else(|| format!("Unsupported webhook URL {:?}, expected http://", url))?;
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(literal) => literal.split_once(']')
+                .ok_or_else(|| format!("Unclosed IPv6 address in webhook URL {:?}", url))?,
+            None => authority.find(':').map_or((authority, ""), |i| authority.split_at(i)),
+        };
+        let port = match port {
+            "" => 80,
+            port => port.strip_prefix(':')
+                .and_then(|port| port.parse().ok())
+                .ok_or_else(|| format!("Invalid port in webhook URL {:?}", url))?,
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in webhook URL {:?}", url));
+        }
+        Ok(Self { host: host.to_owned(), port, path: path.to_owned() })
+    }
+
+    /// Posts the event, retrying failed attempts with exponential backoff.
+    fn deliver(&self, event: &Event, retries: u32, backoff: Duration)
+        -> Result<(), DeliveryError>
+    {
+        let body = serde_json::to_string(event).expect("events are serializable");
+        let mut delay = backoff;
+        for _ in 0..retries {
+            if self.post(&body).is_ok() {
+                return Ok(());
+            }
+            thread::sleep(delay);
+            delay *= 2;
+        }
+        self.post(&body)
+    }
+
+    fn post(&self, body: &str) -> Result<(), DeliveryError> {
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+                               Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                              self.path, self.host(), body.len(), body);
+        stream.write_all(request.as_bytes())?;
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        let status = status_line.split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response"))?;
+        match status {
+            200..=299 => Ok(()),
+            _ => Err(DeliveryError::Status(status)),
+        }
+    }
+
+    /// Connects to the first address of the host that accepts within the time limit.
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for addr in (self.host.as_str(), self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "host has no addresses")
+        }))
+    }
+
+    /// Returns the host as written in a URL, with brackets around IPv6 literals.
+    fn host(&self) -> String {
+        match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host(), self.port, self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::PaymentsEngine;
+
+    fn transaction(transaction_type: TransactionType, tx: u32, amount: i64) -> Transaction {
+        Transaction::fixture(transaction_type, 1, tx, amount)
+    }
+
+    #[test]
+    fn events_are_delivered_with_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for (i, stream) in listener.incoming().take(4).enumerate() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some(("Content-Length", value)) => length = value.parse().unwrap(),
+                        None if line.trim_end().is_empty() => break,
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let status = if i == 0 { "503 Service Unavailable" } else { "204 No Content" };
+                write!(reader.get_mut(), "HTTP/1.1 {}\r\n\r\n", status).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+            }
+            bodies
+        });
+
+        let dispatcher = WebhookDispatcher::start(WebhookConfig {
+            url: format!("http://127.0.0.1:{}/events", port),
+            events: vec![EventKind::Chargeback, EventKind::Locked, EventKind::LargeWithdrawal],
+            large_withdrawal: Decimal::new(50, 0),
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            queue: 100,
+        }, |failure| panic!("{}", failure)).unwrap();
+        let mut engine = PaymentsEngine::new();
+        for transaction in [
+            transaction(TransactionType::Deposit, 1, 100),
+            transaction(TransactionType::Deposit, 2, 10),
+            transaction(TransactionType::Withdrawal, 3, 50),
+            transaction(TransactionType::Withdrawal, 4, 10),
+            transaction(TransactionType::Dispute, 2, 0),
+            transaction(TransactionType::Chargeback, 2, 0),
+        ] {
+            let outcome = engine.execute_with_outcome(transaction.clone());
+            dispatcher.notify(&transaction, &outcome);
+        }
+        dispatcher.finish();
+
+        assert_eq!(vec![
+            r#"{"event":"large_withdrawal","client":1,"tx":3,"amount":"50"}"#,
+            r#"{"event":"large_withdrawal","client":1,"tx":3,"amount":"50"}"#,
+            r#"{"event":"chargeback_executed","client":1,"tx":2}"#,
+            r#"{"event":"account_locked","client":1,"tx":2,"auto_lock":null}"#,
+        ], server.join().unwrap());
+    }
+
+    #[test]
+    fn only_plain_http_urls_are_supported() {
+        let endpoint = Endpoint::parse("http://example.com").unwrap();
+        assert_eq!("http://example.com:80/", endpoint.to_string());
+        assert!(Endpoint::parse("https://example.com/hook").is_err());
+        assert!(Endpoint::parse("http://:8080/hook").is_err());
+        assert!(Endpoint::parse("http://example.com:/hook").is_err());
+    }
+
+    #[test]
+    fn ipv6_literals_are_parsed() {
+        let endpoint = Endpoint::parse("http://[::1]:8080/hook").unwrap();
+        assert_eq!(("::1", 8080), (endpoint.host.as_str(), endpoint.port));
+        assert_eq!("http://[::1]:8080/hook", endpoint.to_string());
+        assert_eq!("http://[::1]:80/", Endpoint::parse("http://[::1]").unwrap().to_string());
+        assert!(Endpoint::parse("http://[::1:8080/hook").is_err());
+        assert!(Endpoint::parse("http://[::1]8080/hook").is_err());
+    }
+
+    #[test]
+    fn failures_are_reported_and_events_beyond_the_queue_dropped() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let (failed, failures) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let dispatcher = WebhookDispatcher::start(WebhookConfig {
+            url: format!("http://127.0.0.1:{}/events", port),
+            events: vec![EventKind::Chargeback],
+            large_withdrawal: Decimal::ONE,
+            retries: 0,
+            backoff: Duration::ZERO,
+            queue: 1,
+        }, move |failure: DeliveryFailure| {
+            failed.send(failure.to_string()).unwrap();
+            let _ = released.recv();
+        }).unwrap();
+
+        let event = |tx| Event::ChargebackExecuted { client: 1, tx };
+        dispatcher.queue(event(1));
+        let failure = failures.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(failure.starts_with(&format!("Could not deliver webhook to http://127.0.0.1:{}/\
+                                              events: ", port)), "{}", failure);
+        for tx in 2..5 {
+            dispatcher.queue(event(tx));
+        }
+        assert_eq!(2, dispatcher.dropped());
+        drop(release);
+        dispatcher.finish();
+        assert_eq!(1, failures.iter().count());
+    }
+}
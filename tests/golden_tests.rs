@@ -0,0 +1,17 @@
+// Replays the golden fixtures in tests/fixtures, recorded with the `record` subcommand
+use std::path::Path;
+
+use toy_payments_engine::golden;
+
+#[test]
+fn recorded_fixtures_are_unchanged() {
+    let fixtures = golden::fixtures(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures"))
+        .unwrap();
+    assert!(!fixtures.is_empty());
+    let failures: Vec<String> = fixtures.iter()
+        .filter_map(|fixture| golden::verify(fixture).err())
+        .map(|e| e.to_string())
+        .collect();
+    assert!(failures.is_empty(), "{}\nRecord intended changes again with `cargo run -- record`",
+            failures.join("\n"));
+}
@@ -23,12 +23,29 @@ fn last_transaction_fails() -> Result<(), Box<dyn Error>> {
 
     cmd.arg("tests/resources/example_transactions.csv");
     cmd.assert()
-        .success()
+        .code(1)
         .stderr(predicates::str::contains("insufficient funds for transaction"));
 
     Ok(())
 }
 
+#[test]
+fn chargeback_keeps_other_disputed_deposits_held() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-chargeback-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,2\ndeposit,1,2,3\ndispute,1,1,\n\
+                            dispute,1,2,\nchargeback,1,1,\n")?;
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+
+    // Only the 2 charged back leave the account, the 3 of the other dispute stay held
+    cmd.arg(&input);
+    cmd.assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,0,3,3,true\n");
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
 #[test]
 fn valid_transactions_processed_as_expected() -> Result<(), Box<dyn Error>> {
     let mut cmd = Command::cargo_bin("toy-payments-engine")?;
@@ -44,4 +61,1089 @@ fn valid_transactions_processed_as_expected() -> Result<(), Box<dyn Error>> {
 
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_state_is_updated_incrementally() -> Result<(), Box<dyn Error>> {
+    let db = std::env::temp_dir().join(format!("toy-payments-engine-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&db);
+
+    // Repeated deposits are rejected as duplicates, repeated withdrawals are applied
+    for _ in 0..2 {
+        Command::cargo_bin("toy-payments-engine")?
+            .arg("tests/resources/example_transactions.csv")
+            .arg("--sqlite")
+            .arg(&db)
+            .assert()
+            .code(1);
+    }
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("tests/resources/example_transactions.csv").arg("--sqlite").arg(&db);
+    cmd.assert()
+        .code(1)
+        .stdout(predicates::str::contains("1,0.0,0,0,false\n")
+            .and(predicates::str::contains("2,2,0,2,false\n")));
+
+    std::fs::remove_file(&db)?;
+    Ok(())
+}
+
+#[test]
+fn report_is_written_to_output_file() -> Result<(), Box<dyn Error>> {
+    let output = std::env::temp_dir()
+        .join(format!("toy-payments-engine-{}.csv", std::process::id()));
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("tests/resources/valid_transactions.csv").arg("--output").arg(&output);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::is_empty());
+
+    let report = std::fs::read_to_string(&output)?;
+    assert!(report.starts_with("client,available,held,total,locked\n"));
+    assert!(report.contains("3,1.2,4,5.2,false\n"));
+
+    std::fs::remove_file(&output)?;
+    Ok(())
+}
+
+#[test]
+fn resumed_run_matches_uninterrupted_run() -> Result<(), Box<dyn Error>> {
+    let checkpoint = std::env::temp_dir()
+        .join(format!("toy-payments-engine-{}.checkpoint", std::process::id()));
+
+    // Checkpoint after 10 of 13 rows, then resume as if the first run had crashed afterwards
+    Command::cargo_bin("toy-payments-engine")?
+        .arg("tests/resources/valid_transactions.csv")
+        .arg("--checkpoint").arg(&checkpoint)
+        .arg("--checkpoint-every").arg("5")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("tests/resources/valid_transactions.csv").arg("--resume").arg(&checkpoint);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains("1,3.5,0,3.5,true\n")
+            .and(predicates::str::contains("2,5.3,0,5.3,false\n"))
+            .and(predicates::str::contains("3,1.2,4,5.2,false\n")));
+
+    std::fs::remove_file(&checkpoint)?;
+    Ok(())
+}
+
+#[test]
+fn follow_mode_emits_report_and_keeps_running() -> Result<(), Box<dyn Error>> {
+    let mut cmd = assert_cmd::Command::cargo_bin("toy-payments-engine")?;
+
+    cmd.arg("tests/resources/valid_transactions.csv")
+        .arg("--follow")
+        .arg("--report-interval").arg("1")
+        .timeout(std::time::Duration::from_secs(3));
+    // The last row is not terminated by a newline, so it is still considered incomplete
+    cmd.assert()
+        .interrupted()
+        .stdout(predicates::str::starts_with("client,available,held,total,locked\n")
+            .and(predicates::str::contains("1,3.5,0,3.5,true\n"))
+            .and(predicates::str::contains("3,5.2,0,5.2,false\n")));
+
+    Ok(())
+}
+
+#[test]
+fn generated_transactions_can_be_processed() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-generated-{}.csv", std::process::id()));
+
+    Command::cargo_bin("toy-payments-engine")?
+        .args(["generate", "--clients", "5", "--rows", "200", "--seed", "3", "--output"])
+        .arg(&input)
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input);
+    cmd.assert()
+        .code(1)
+        .stdout(predicates::function::function(|s: &str| s.lines().count() == 6));
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn bench_reports_throughput() -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+
+    cmd.args(["bench", "tests/resources/valid_transactions.csv"]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains("parse+execute")
+            .and(predicates::str::contains("13 rows"))
+            .and(predicates::str::contains("rows/s")));
+
+    Ok(())
+}
+
+#[test]
+fn audit_log_can_be_exported() -> Result<(), Box<dyn Error>> {
+    let log = std::env::temp_dir()
+        .join(format!("toy-payments-engine-audit-{}.ndjson", std::process::id()));
+    let _ = std::fs::remove_file(&log);
+
+    Command::cargo_bin("toy-payments-engine")?
+        .arg("tests/resources/example_transactions.csv")
+        .arg("--audit-log").arg(&log)
+        .assert()
+        .code(1);
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("export-audit").arg(&log);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("timestamp,type,client,tx,amount,accepted,error,")
+            .and(predicates::str::contains(",withdrawal,2,5,3,false,"))
+            .and(predicates::function::function(|s: &str| s.lines().count() == 6)));
+
+    std::fs::remove_file(&log)?;
+    Ok(())
+}
+
+#[test]
+fn digest_of_resumed_run_matches_uninterrupted_run() -> Result<(), Box<dyn Error>> {
+    let checkpoint = std::env::temp_dir()
+        .join(format!("toy-payments-engine-digest-{}.checkpoint", std::process::id()));
+    let digest = |cmd: &mut Command| -> Result<String, Box<dyn Error>> {
+        let output = cmd
+            .arg("tests/resources/valid_transactions.csv")
+            .arg("--print-digest")
+            .output()?;
+        let stderr = String::from_utf8(output.stderr)?;
+        let line = stderr.lines().find(|l| l.starts_with("State digest: "));
+        Ok(line.unwrap_or_default().to_owned())
+    };
+
+    let uninterrupted = digest(Command::cargo_bin("toy-payments-engine")?
+        .arg("--checkpoint").arg(&checkpoint)
+        .arg("--checkpoint-every").arg("5"))?;
+    let resumed = digest(Command::cargo_bin("toy-payments-engine")?
+        .arg("--resume").arg(&checkpoint))?;
+
+    std::fs::remove_file(&checkpoint)?;
+    assert_eq!("State digest: ".len() + 64, uninterrupted.len());
+    assert_eq!(uninterrupted, resumed);
+    Ok(())
+}
+
+#[test]
+fn journal_is_written() -> Result<(), Box<dyn Error>> {
+    let journal = std::env::temp_dir()
+        .join(format!("toy-payments-engine-journal-{}.csv", std::process::id()));
+
+    Command::cargo_bin("toy-payments-engine")?
+        .arg("tests/resources/example_transactions.csv")
+        .arg("--journal").arg(&journal)
+        .assert()
+        .code(1);
+    let journal_csv = std::fs::read_to_string(&journal)?;
+    assert!(journal_csv.starts_with("sequence,tx,debit,credit,amount\n"));
+    assert!(journal_csv.contains("4,4,client:1:available,cash,1.5\n"));
+    // The failed withdrawal is not posted
+    assert_eq!(5, journal_csv.lines().count());
+
+    std::fs::remove_file(&journal)?;
+    Ok(())
+}
+
+#[test]
+fn reconcile_lists_mismatching_clients() -> Result<(), Box<dyn Error>> {
+    let expected = std::env::temp_dir()
+        .join(format!("toy-payments-engine-statement-{}.csv", std::process::id()));
+    std::fs::write(&expected, "client,available,held,total,locked\n\
+                               1,1.5,0,1.5,false\n2,3,0,3,false\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.args(["reconcile", "tests/resources/example_transactions.csv", "--expected"])
+        .arg(&expected);
+    cmd.assert()
+        .failure()
+        .stdout("client,available_delta,held_delta,total_delta,expected_locked,actual_locked\n\
+                 2,-1,0,-1,false,false\n")
+        .stderr(predicates::str::contains("1 of 2 clients do not match the statement"));
+
+    std::fs::remove_file(&expected)?;
+    Ok(())
+}
+
+#[test]
+fn diff_compares_csv_and_json_reports() -> Result<(), Box<dyn Error>> {
+    let dir = std::env::temp_dir();
+    let before = dir.join(format!("toy-payments-engine-before-{}.csv", std::process::id()));
+    let after = dir.join(format!("toy-payments-engine-after-{}.json", std::process::id()));
+    std::fs::write(&before, "client,available,held,total,locked\n\
+                             1,1.5,0,1.5,false\n2,2,0,2,false\n")?;
+    std::fs::write(&after, r#"[
+        {"client": 1, "available": "1.50", "held": "0", "total": "1.5", "locked": false},
+        {"client": 2, "available": "0", "held": "0", "total": "0", "locked": true}
+    ]"#)?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("diff").arg(&before).arg(&after);
+    cmd.assert()
+        .success()
+        .stdout("client,available_delta,held_delta,total_delta,expected_locked,actual_locked\n\
+                 2,-2,0,-2,false,true\n")
+        .stderr(predicates::str::contains("1 of 2 clients differ"));
+
+    std::fs::remove_file(&before)?;
+    std::fs::remove_file(&after)?;
+    Ok(())
+}
+
+#[test]
+fn statement_lists_transactions_in_time_range() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-timestamps-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount,timestamp\n\
+                            deposit,1,1,5,100\ndeposit,2,2,3,150\n\
+                            withdrawal,1,3,1.5,200\ndeposit,1,4,1,300\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("statement").arg(&input)
+        .args(["--client", "1", "--from", "100", "--to", "300", "--format", "csv"]);
+    cmd.assert()
+        .success()
+        .stdout("sequence,timestamp,type,tx,amount,available,held,total,locked,memo,tags\n\
+                 1,100,deposit,1,5,5,0,5,false,,\n\
+                 3,200,withdrawal,3,1.5,3.5,0,3.5,false,,\n");
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn report_summarizes_batch() -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+
+    cmd.args(["report", "tests/resources/valid_transactions.csv", "--top", "2"]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::is_match(r"deposit +7 +0 +16\.5\n")?
+            .and(predicates::str::contains("Total held funds: 4\n"))
+            .and(predicates::str::contains("Chargeback rate: 14.29%\n"))
+            .and(predicates::str::contains("Top 2 accounts by total\n"))
+            .and(predicates::str::is_match(r"\n +3 +1\.2 +4 +5\.2\n$")?));
+
+    Ok(())
+}
+
+#[test]
+fn anomaly_report_lists_flagged_transactions() -> Result<(), Box<dyn Error>> {
+    let report = std::env::temp_dir()
+        .join(format!("toy-payments-engine-anomalies-{}.csv", std::process::id()));
+
+    Command::cargo_bin("toy-payments-engine")?
+        .arg("tests/resources/valid_transactions.csv")
+        .arg("--anomaly-report").arg(&report)
+        .args(["--flag-deposits-over", "3", "--flag-dispute-rate", "0.4"])
+        .assert()
+        .success();
+    let anomalies = std::fs::read_to_string(&report)?;
+    assert!(anomalies.starts_with("rule,sequence,client,tx,description\n"));
+    assert!(anomalies.contains("\nlarge-deposit,3,3,9,"));
+    assert!(anomalies.contains("\ndispute-rate,7,1,1,1 disputes for 2 deposits\n"));
+    assert_eq!(6, anomalies.lines().count());
+
+    std::fs::remove_file(&report)?;
+    Ok(())
+}
+
+#[test]
+fn aml_threshold_flags_structured_deposits() -> Result<(), Box<dyn Error>> {
+    let dir = std::env::temp_dir();
+    let input = dir.join(format!("toy-payments-engine-aml-{}.csv", std::process::id()));
+    let report = dir.join(format!("toy-payments-engine-aml-report-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount,timestamp\n\
+                            deposit,1,1,6000,0\ndeposit,1,2,5000,3600\ndeposit,2,3,5000,3600\n")?;
+
+    Command::cargo_bin("toy-payments-engine")?
+        .arg(&input)
+        .arg("--anomaly-report").arg(&report)
+        .args(["--aml-threshold", "10000"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("1,11000,0,11000,false\n"));
+    let anomalies = std::fs::read_to_string(&report)?;
+    assert_eq!("rule,sequence,client,tx,description\n\
+                reporting-threshold,2,1,2,2 deposits totalling 11000 within 86400 reach 10000\n",
+               anomalies);
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&report)?;
+    Ok(())
+}
+
+#[test]
+fn transactions_of_denied_clients_are_rejected() -> Result<(), Box<dyn Error>> {
+    let deny_list = std::env::temp_dir()
+        .join(format!("toy-payments-engine-deny-list-{}.txt", std::process::id()));
+    std::fs::write(&deny_list, "# sanctioned clients\n2\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("tests/resources/valid_transactions.csv").arg("--deny-list").arg(&deny_list);
+    cmd.assert()
+        .code(1)
+        .stdout(predicates::str::contains("\n2,").not())
+        .stderr(predicates::str::contains("Client 2 is blocked by screening"));
+
+    std::fs::remove_file(&deny_list)?;
+    Ok(())
+}
+
+#[test]
+fn auto_lock_is_recorded_in_audit_log() -> Result<(), Box<dyn Error>> {
+    let log = std::env::temp_dir()
+        .join(format!("toy-payments-engine-auto-lock-{}.ndjson", std::process::id()));
+    let _ = std::fs::remove_file(&log);
+
+    Command::cargo_bin("toy-payments-engine")?
+        .arg("tests/resources/valid_transactions.csv")
+        .arg("--audit-log").arg(&log)
+        .args(["--auto-lock-chargebacks", "1"])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("export-audit").arg(&log);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains(",chargeback,1,1,,true,,")
+            .and(predicates::str::contains(",true,1 chargebacks,,\n"))
+            .and(predicates::function::function(|s: &str| {
+                s.matches("chargebacks,,\n").count() == 1
+            })));
+
+    std::fs::remove_file(&log)?;
+    Ok(())
+}
+
+#[test]
+fn webhook_receives_key_events() -> Result<(), Box<dyn Error>> {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/hooks", listener.local_addr()?);
+    let server = std::thread::spawn(move || {
+        let mut bodies = Vec::new();
+        for stream in listener.incoming().take(3) {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut length = 0;
+            let mut line = String::from("-");
+            while line.trim_end() != "" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim_end().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+            bodies.push(String::from_utf8(body).unwrap());
+        }
+        bodies
+    });
+
+    Command::cargo_bin("toy-payments-engine")?
+        .arg("tests/resources/valid_transactions.csv")
+        .args(["--webhook", &url, "--large-withdrawal", "1.5"])
+        .assert()
+        .success();
+
+    assert_eq!(vec![
+        r#"{"event":"large_withdrawal","client":1,"tx":4,"amount":"1.5"}"#,
+        r#"{"event":"chargeback_executed","client":1,"tx":1}"#,
+        r#"{"event":"account_locked","client":1,"tx":1,"auto_lock":null}"#,
+    ], server.join().unwrap());
+    Ok(())
+}
+
+#[test]
+fn server_replies_per_line() -> Result<(), Box<dyn Error>> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let mut child = Command::cargo_bin("toy-payments-engine")?
+        .args(["--listen", "127.0.0.1:0"])
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line)?;
+    let addr = line.trim().strip_prefix("Listening on ").expect("address is logged");
+
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.write_all(b"deposit, 1, 1, 2.0\n{\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \
+                       \"amount\": \"3\"}\ndispute, 1, 7,\n")?;
+    let replies: Vec<String> = BufReader::new(stream).lines().take(3).collect::<Result<_, _>>()?;
+    child.kill()?;
+    child.wait()?;
+
+    assert_eq!(vec!["OK", "ERR insufficient_funds", "ERR unknown_transaction"], replies);
+    Ok(())
+}
+
+#[test]
+fn server_on_network_address_requires_tokens() -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.args(["--listen", "0.0.0.0:0"]);
+    cmd.assert()
+        .code(2)
+        .stderr(predicates::str::contains("requires --listen-tokens"));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn server_flushes_report_on_sigterm() -> Result<(), Box<dyn Error>> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let output = std::env::temp_dir()
+        .join(format!("toy-payments-engine-shutdown-{}.csv", std::process::id()));
+    let mut child = Command::cargo_bin("toy-payments-engine")?
+        .args(["--listen", "127.0.0.1:0", "--report-interval", "3600", "--output"])
+        .arg(&output)
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line)?;
+    let addr = line.trim().strip_prefix("Listening on ").expect("address is logged");
+
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.write_all(b"deposit, 4, 1, 2.5\n")?;
+    BufReader::new(&stream).read_line(&mut line)?;
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status()?;
+
+    assert!(child.wait()?.success());
+    let report = std::fs::read_to_string(&output)?;
+    assert_eq!("client,available,held,total,locked\n4,2.5,0,2.5,false\n", report);
+    std::fs::remove_file(&output)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn server_replays_original_reply_for_idempotency_key() -> Result<(), Box<dyn Error>> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let audit_log = std::env::temp_dir()
+        .join(format!("toy-payments-engine-idempotent-{}.ndjson", std::process::id()));
+    let mut child = Command::cargo_bin("toy-payments-engine")?
+        .args(["--listen", "127.0.0.1:0", "--audit-log"])
+        .arg(&audit_log)
+        .stderr(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .spawn()?;
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line)?;
+    let addr = line.trim().strip_prefix("Listening on ").expect("address is logged");
+
+    let deposit = b"{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"2\", \
+                    \"idempotency_key\": \"req-1\"}\n";
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.write_all(deposit)?;
+    stream.write_all(deposit)?;
+    stream.write_all(b"deposit, 1, 1, 2\n")?;
+    stream.write_all(b"UNLOCK 1\n")?;
+    let replies: Vec<String> = BufReader::new(&stream).lines().take(4).collect::<Result<_, _>>()?;
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status()?;
+
+    assert!(child.wait()?.success());
+    assert_eq!(vec!["OK", "OK", "ERR duplicate_transaction", "ERR invalid_transaction"], replies);
+    let audit = std::fs::read_to_string(&audit_log)?;
+    assert_eq!(3, audit.lines().count());
+    assert!(audit.lines().last().unwrap().contains(r#""type":null,"client":1,"tx":0,"#));
+    assert!(audit.lines().last().unwrap().contains(r#""operation":"UNLOCK 1""#));
+    std::fs::remove_file(&audit_log)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn server_operations_are_replayed_from_write_ahead_log() -> Result<(), Box<dyn Error>> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let wal = std::env::temp_dir()
+        .join(format!("toy-payments-engine-operations-{}.wal", std::process::id()));
+    let input = wal.with_extension("csv");
+    let _ = std::fs::remove_file(&wal);
+    let mut child = Command::cargo_bin("toy-payments-engine")?
+        .args(["--listen", "127.0.0.1:0", "--wal"])
+        .arg(&wal)
+        .stderr(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .spawn()?;
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line)?;
+    let addr = line.trim().strip_prefix("Listening on ").expect("address is logged");
+
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.write_all(b"deposit, 1, 1, 2\ndispute, 1, 1,\nchargeback, 1, 1,\nUNLOCK 1\n")?;
+    let replies: Vec<String> = BufReader::new(&stream).lines().take(4).collect::<Result<_, _>>()?;
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status()?;
+    assert!(child.wait()?.success());
+    assert_eq!(vec!["OK", "OK", "OK", "OK"], replies);
+
+    // Replaying the log without further input restores the unlocked account
+    std::fs::write(&input, "type,client,tx,amount\n")?;
+    Command::cargo_bin("toy-payments-engine")?
+        .arg(&input)
+        .arg("--wal").arg(&wal)
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,0,0,0,false\n");
+    assert!(std::fs::read_to_string(&wal)?.contains(r#""operation":{"type":"unlock","client":1}"#));
+    std::fs::remove_file(&wal)?;
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn report_per_wallet() -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+
+    cmd.arg("tests/resources/wallet_transactions.csv").arg("--report-wallets");
+    cmd.assert()
+        .code(1)
+        .stderr(predicates::str::contains("insufficient funds for transaction 3"))
+        .stdout("client,wallet,available,held,total,locked\n\
+                 1,default,4,0,4,false\n\
+                 1,savings,5,0,5,false\n\
+                 2,default,0,0,0,false\n\
+                 2,trading,1,0,1,false\n");
+
+    Ok(())
+}
+
+#[test]
+fn disputes_before_their_deposit_are_retried() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-reorder-{}.csv", std::process::id()));
+    let audit_log = input.with_extension("ndjson");
+    std::fs::write(&input, "type,client,tx,amount\n\
+                            dispute,1,1,\ndeposit,1,1,5\ndeposit,1,2,3\ndispute,1,4,\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).args(["--reorder-rows", "5"]).arg("--audit-log").arg(&audit_log);
+    cmd.assert()
+        .code(1)
+        .stderr(predicates::str::contains("not seen yet, parked for retry"))
+        .stderr(predicates::str::contains("unknown deposit transaction 4"))
+        .stdout("client,available,held,total,locked\n\
+                 1,3,5,8,false\n");
+    assert_eq!(6, std::fs::read_to_string(&audit_log)?.lines().count());
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&audit_log)?;
+    Ok(())
+}
+
+#[test]
+fn rows_are_executed_in_timestamp_order() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-ordering-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount,timestamp\n\
+                            withdrawal,1,2,3,200\ndeposit,1,1,5,100\ndeposit,1,3,1,300\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).args(["--timestamp-order", "reorder", "--order-window", "150"]);
+    cmd.assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,3,0,3,false\n");
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).args(["--timestamp-order", "reject"]);
+    cmd.assert()
+        .code(1)
+        .stderr(predicates::str::contains("Transaction 1 with timestamp 100 is out of order"))
+        .stdout("client,available,held,total,locked\n1,1,0,1,false\n");
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn expired_disputes_are_recorded_in_audit_log() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-expiry-{}.csv", std::process::id()));
+    let log = input.with_extension("ndjson");
+    std::fs::write(&input, "type,client,tx,amount,timestamp\n\
+                            deposit,1,1,5,0\ndispute,1,1,,10\ndeposit,1,2,1,200\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).args(["--dispute-expiry", "100", "--expiry-policy", "chargeback"])
+        .arg("--audit-log").arg(&log);
+    cmd.assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,1,0,1,true\n");
+    let audit = std::fs::read_to_string(&log)?;
+    assert_eq!(4, audit.lines().count());
+    assert!(audit.lines().last().unwrap().contains(r#""memo":"dispute expired""#));
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&log)?;
+    Ok(())
+}
+
+#[test]
+fn report_shows_overdrawn_amounts() -> Result<(), Box<dyn Error>> {
+    let limits = std::env::temp_dir()
+        .join(format!("toy-payments-engine-overdraft-{}.csv", std::process::id()));
+    std::fs::write(&limits, "client,limit\n2,2\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("tests/resources/example_transactions.csv").arg("--overdraft-limits").arg(&limits);
+    cmd.assert()
+        .success()
+        .stderr("Summary: rows=5 invalid=0 rejected=0 exit_code=0\n")
+        .stdout(predicates::str::starts_with("client,available,held,total,locked,overdrawn\n")
+            .and(predicates::str::contains("1,1.5,0,1.5,false,0\n"))
+            .and(predicates::str::contains("2,-1,0,-1,false,1\n")));
+
+    std::fs::remove_file(&limits)?;
+    Ok(())
+}
+
+#[test]
+fn minimum_balance_is_kept_and_dust_swept() -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("tests/resources/example_transactions.csv")
+        .args(["--min-balance", "2", "--sweep-dust", "2.5"]);
+    cmd.assert()
+        .code(1)
+        .stderr(predicates::str::contains("less than the minimum balance 2"))
+        .stdout(predicates::str::contains("1,3,0,3,false\n")
+            .and(predicates::str::contains("2,0,0,0,false\n")));
+    Ok(())
+}
+
+#[test]
+fn standing_orders_are_executed_when_due() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-standing-{}.csv", std::process::id()));
+    let orders = input.with_extension("orders.csv");
+    std::fs::write(&input, "type,client,tx,amount,timestamp
+\
+                            deposit,1,1,1,0\nwithdrawal,1,2,1,150\ndeposit,2,3,1,250\n")?;
+    std::fs::write(&orders, "client,type,amount,interval,start\n1,deposit,5,100,0\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--standing-orders").arg(&orders);
+    cmd.assert()
+        .success()
+        .stderr("Summary: rows=3 invalid=0 rejected=0 exit_code=0\n")
+        .stdout(predicates::str::contains("1,15,0,15,false\n"));
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&orders)?;
+    Ok(())
+}
+
+#[test]
+fn closed_day_is_written_as_settlement_report() -> Result<(), Box<dyn Error>> {
+    let report = std::env::temp_dir()
+        .join(format!("toy-payments-engine-settlement-{}.csv", std::process::id()));
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("tests/resources/example_transactions.csv")
+        .args(["--close-day", "2024-01-31", "--settlement-report"]).arg(&report);
+    cmd.assert().code(1);
+    assert_eq!("date,client,opening,deposits,withdrawals,fees,chargebacks,adjustments,net,closing\n\
+                2024-01-31,1,0,3,1.5,0,0,0,1.5,1.5\n\
+                2024-01-31,2,0,2,0,0,0,0,2,2\n", std::fs::read_to_string(&report)?);
+
+    std::fs::remove_file(&report)?;
+    Ok(())
+}
+
+#[test]
+fn house_accounts_are_appended_to_report() -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("tests/resources/valid_transactions.csv").arg("--report-house-accounts");
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains("1,3.5,0,3.5,true\n")
+            .and(predicates::str::ends_with("fee-income,0,0,0,false\n\
+                                             chargeback-write-off,1,0,1,false\n\
+                                             dust,0,0,0,false\n")));
+    Ok(())
+}
+
+#[test]
+fn refunds_credit_part_of_withdrawals() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-refund-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,6\n\
+                            refund,1,2,4\nrefund,1,2,3\nrefund,1,3,1\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input);
+    cmd.assert()
+        .code(1)
+        .stderr(predicates::str::contains("exceeds the refundable amount (refundable: 2, \
+                                           refund: 3)")
+            .and(predicates::str::contains("unknown withdrawal transaction 3")))
+        .stdout("client,available,held,total,locked\n1,8,0,8,false\n");
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn disputes_of_withdrawn_funds_may_go_negative() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-withdrawn-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,7\n\
+                            dispute,1,1,\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).args(["--withdrawn-funds", "negative"]);
+    cmd.assert()
+        .success()
+        .stderr("Summary: rows=3 invalid=0 rejected=0 exit_code=0\n")
+        .stdout("client,available,held,total,locked\n1,-7,10,3,false\n");
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn adjustments_are_marked_in_audit_log() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-adjust-{}.csv", std::process::id()));
+    let log = input.with_extension("ndjson");
+    std::fs::write(&input, "type,client,tx,amount,memo\ndeposit,1,1,5,\ndispute,1,1,,\n\
+                            chargeback,1,1,,\nadjustment,1,2,-1,\n\
+                            adjustment,1,3,2.5,reversed fee\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--audit-log").arg(&log);
+    cmd.assert()
+        .code(1)
+        .stdout("client,available,held,total,locked\n1,2.5,0,2.5,true\n");
+    let audit = std::fs::read_to_string(&log)?;
+    assert!(audit.contains(r#""tx":2,"amount":"-1","accepted":false"#));
+    assert!(audit.lines().last().unwrap()
+        .contains(r#""type":"adjustment","client":1,"tx":3,"amount":"2.5","accepted":true"#));
+    assert!(audit.lines().last().unwrap().contains(r#""memo":"reversed fee""#));
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&log)?;
+    Ok(())
+}
+
+#[test]
+fn rows_past_checkpoint_are_replayed_from_write_ahead_log() -> Result<(), Box<dyn Error>> {
+    let checkpoint = std::env::temp_dir()
+        .join(format!("toy-payments-engine-wal-{}.checkpoint", std::process::id()));
+    let wal = checkpoint.with_extension("wal");
+    let input = checkpoint.with_extension("csv");
+    let _ = std::fs::remove_file(&wal);
+
+    // Checkpoint after 10 of 13 rows, then resume with the last 3 rows missing from the input
+    Command::cargo_bin("toy-payments-engine")?
+        .arg("tests/resources/valid_transactions.csv")
+        .arg("--checkpoint").arg(&checkpoint)
+        .arg("--checkpoint-every").arg("5")
+        .arg("--wal").arg(&wal)
+        .arg("--wal-sync").arg("4")
+        .assert()
+        .success();
+    // The checkpoint truncated the log, leaving only the rows past it
+    assert_eq!(3, std::fs::read_to_string(&wal)?.lines().count());
+    let rows = std::fs::read_to_string("tests/resources/valid_transactions.csv")?;
+    std::fs::write(&input, rows.lines().take(11).collect::<Vec<_>>().join("\n"))?;
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--resume").arg(&checkpoint).arg("--wal").arg(&wal);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains("1,3.5,0,3.5,true\n")
+            .and(predicates::str::contains("2,5.3,0,5.3,false\n"))
+            .and(predicates::str::contains("3,1.2,4,5.2,false\n")));
+    assert_eq!(3, std::fs::read_to_string(&wal)?.lines().count());
+
+    std::fs::remove_file(&checkpoint)?;
+    std::fs::remove_file(&wal)?;
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn extended_report_summarizes_clients() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-extended-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount,timestamp\ndeposit,1,1,5,100\n\
+                            deposit,1,2,2,200\ndispute,1,1,,300\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--extended");
+    cmd.assert()
+        .success()
+        .stdout("client,available,held,total,locked,open_disputes,chargebacks,last_timestamp\n\
+                 1,2,5,7,false,1,0,300\n");
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn account_attributes_are_added_to_report() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-attrs-{}.csv", std::process::id()));
+    let attrs = input.with_extension("attrs.csv");
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,5\n")?;
+    std::fs::write(&attrs, "client,key,value\n1,external_id,acct-0042\n1,region,EU\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--account-attrs").arg(&attrs).arg("--report-attrs").arg("external_id");
+    cmd.assert()
+        .success()
+        .stdout("client,available,held,total,locked,external_id\n1,5,0,5,false,acct-0042\n");
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&attrs)?;
+    Ok(())
+}
+
+#[test]
+fn external_client_identifiers_are_mapped_with_aliases() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-aliases-{}.csv", std::process::id()));
+    let aliases = input.with_extension("aliases.csv");
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,alice@example.com,1,5\n\
+                            deposit,bob@example.com,2,3\nwithdrawal,alice@example.com,3,1\n")?;
+    std::fs::write(&aliases, "alias,client\nalice@example.com,4\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--aliases").arg(&aliases);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("4,4,0,4,false\n"))
+        .stdout(predicate::str::contains("5,3,0,3,false\n"));
+    assert_eq!("alias,client\nalice@example.com,4\nbob@example.com,5\n",
+               std::fs::read_to_string(&aliases)?);
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&aliases)?;
+    Ok(())
+}
+
+#[test]
+fn policy_file_configures_limits_and_fees() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-policy-{}.csv", std::process::id()));
+    let policy = input.with_extension("toml");
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,1000\n\
+                            withdrawal,1,3,4\n")?;
+    std::fs::write(&policy, "[amounts]\nmax = \"100\"\n\n[fees]\nwithdrawal = \"0.5\"\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--policy").arg(&policy);
+    cmd.assert()
+        .code(1)
+        .stdout("client,available,held,total,locked\n1,5.5,0,5.5,false\n");
+
+    std::fs::write(&policy, "[amounts]\nmaximum = \"100\"\n")?;
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--policy").arg(&policy);
+    cmd.assert().failure().stderr(predicate::str::contains("Could not read policy"));
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&policy)?;
+    Ok(())
+}
+
+#[test]
+fn options_are_read_from_config_file_unless_given() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-config-{}.csv", std::process::id()));
+    let config = input.with_extension("toml");
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,20\n\
+                            withdrawal,1,3,25\n")?;
+    std::fs::write(&config, format!("input_csv = {:?}\nextended = true\nmin_balance = 10\n",
+                                    input.to_str().unwrap()))?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("--config").arg(&config);
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::starts_with("client,available,held,total,locked,open_disputes"))
+        .stdout(predicate::str::contains("\n1,30,0,30,false,0,0,\n"));
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("--config").arg(&config).arg("--min-balance").arg("5");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\n1,5,0,5,false,0,0,\n"));
+
+    std::fs::write(&config, "min_balanse = 10\n")?;
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--config").arg(&config);
+    cmd.assert().failure().stderr(predicate::str::contains("Unknown option \"min_balanse\""));
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&config)?;
+    Ok(())
+}
+
+#[test]
+fn progress_bar_does_not_change_report() -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("tests/resources/example_transactions.csv").arg("--progress");
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::starts_with("client,available,held,total,locked\n"));
+    Ok(())
+}
+
+#[test]
+fn log_events_are_written_as_json() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-log-json-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,9\nfoo,1,3,1\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--log-json");
+    let output = cmd.output()?;
+    assert_eq!(Some(1), output.status.code());
+    let events: Vec<serde_json::Value> = String::from_utf8(output.stderr)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(3, events.len());
+    assert_eq!("rejected", events[0]["event"]);
+    assert_eq!("insufficient_funds", events[0]["code"]);
+    assert_eq!(2, events[0]["tx"]);
+    assert_eq!("invalid_row", events[1]["event"]);
+    assert_eq!(4, events[1]["line"]);
+    assert_eq!(serde_json::json!({
+        "event": "summary", "rows": 3, "invalid": 1, "rejected": 1, "exit_code": 1,
+    }),
+               events[2]);
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn verbosity_controls_logged_events() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-verbosity-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,9\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--quiet");
+    cmd.assert().code(1).stderr("Summary: rows=2 invalid=0 rejected=1 exit_code=1\n");
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("-vv");
+    cmd.assert()
+        .code(1)
+        .stderr(predicate::str::starts_with("Accepted Deposit transaction 1 of client 1\n\
+                                             Client 1 has insufficient funds"))
+        .stderr(predicate::str::ends_with("Summary: rows=2 invalid=0 rejected=1 exit_code=1\n"));
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn fail_fast_aborts_at_first_rejection() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-fail-fast-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,9\n\
+                            deposit,1,3,1\ndeposit,1,4,1\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--fail-fast");
+    cmd.assert()
+        .code(3)
+        .stdout("")
+        .stderr(predicate::str::ends_with("Summary: rows=2 invalid=0 rejected=1 exit_code=3\n"));
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg("nonexistent/file").arg("--fail-fast");
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::ends_with("Summary: rows=0 invalid=0 rejected=0 exit_code=2\n"));
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn run_summary_breaks_down_outcomes() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-run-summary-{}.csv", std::process::id()));
+    let summary = input.with_extension("summary.txt");
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,9\n\
+                            withdrawal,2,3,1\nfoo,1,4,1\ndispute,1,1,\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg(format!("--summary={}", summary.display()));
+    cmd.assert().code(1);
+    let lines: Vec<Vec<String>> = std::fs::read_to_string(&summary)?
+        .lines()
+        .map(|line| line.split_whitespace().map(String::from).collect())
+        .collect();
+    assert_eq!(vec![
+        vec!["rows_parsed", "4"],
+        vec!["parse_failures", "1"],
+        vec!["accepted.deposit", "1"],
+        vec!["accepted.withdrawal", "0"],
+        vec!["accepted.dispute", "1"],
+        vec!["rejected.insufficient_funds", "1"],
+        vec!["rejected.invalid_transaction", "1"],
+    ], lines);
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&summary)?;
+    Ok(())
+}
+
+#[test]
+fn lenient_mode_salvages_malformed_rows() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-lenient-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount\nDeposit,1,1,5,extra\nWithdraw,1,2,1\n\
+                            deposit,1,3,abc\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--lenient");
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::contains("1,4,0,4,false"))
+        .stderr(predicate::str::contains("line 4, column \"amount\" with value \"abc\""));
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input);
+    cmd.assert().code(1).stdout(predicate::str::contains("1,").not());
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
+
+#[test]
+fn tolerant_amounts_accept_separators_and_exponents() -> Result<(), Box<dyn Error>> {
+    let input = std::env::temp_dir()
+        .join(format!("toy-payments-engine-tolerant-amounts-{}.csv", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,\"1.234,5\"\ndeposit,1,2,1e3\n\
+                            deposit,1,3,\"1,000\"\n")?;
+
+    let mut cmd = Command::cargo_bin("toy-payments-engine")?;
+    cmd.arg(&input).arg("--tolerant-amounts");
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::contains("1,2234.5,0,2234.5,false"))
+        .stderr(predicate::str::contains("`,` may separate thousands or decimals"));
+
+    std::fs::remove_file(&input)?;
+    Ok(())
+}
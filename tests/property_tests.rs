@@ -0,0 +1,44 @@
+// Property-based tests of engine invariants, run with `cargo test --features proptest`
+#![cfg(feature = "proptest")]
+use proptest::prelude::*;
+
+use toy_payments_engine::testing::invariants::InvariantChecker;
+use toy_payments_engine::testing::reference::compare;
+use toy_payments_engine::{PaymentsEngine, Transaction};
+
+proptest! {
+    #[test]
+    fn invariants_hold_for_arbitrary_transactions(
+        transactions in prop::collection::vec(any::<Transaction>(), 0..200)
+    ) {
+        let mut engine = PaymentsEngine::new();
+        let mut checker = InvariantChecker::new();
+        for transaction in transactions {
+            let _ = engine.execute(transaction);
+            if let Err(violation) = checker.check(&engine) {
+                prop_assert!(false, "{}", violation);
+            }
+        }
+    }
+
+    #[test]
+    fn explain_agrees_with_execute(
+        transactions in prop::collection::vec(any::<Transaction>(), 0..200)
+    ) {
+        let mut engine = PaymentsEngine::new();
+        for transaction in transactions {
+            let reasons = engine.explain(&transaction);
+            let result = engine.execute(transaction);
+            prop_assert_eq!(reasons.is_empty(), result.is_ok(), "{:?} vs {:?}", reasons, result);
+        }
+    }
+
+    #[test]
+    fn engine_agrees_with_reference_engine(
+        transactions in prop::collection::vec(any::<Transaction>(), 0..100)
+    ) {
+        if let Err(divergence) = compare(transactions) {
+            prop_assert!(false, "{}", divergence);
+        }
+    }
+}